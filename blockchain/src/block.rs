@@ -19,6 +19,13 @@ pub trait Keyed<Key> {
     fn key(&self) -> Key;
 }
 
+/// A block with a weight, such as its difficulty, for a fork choice favoring
+/// the heaviest chain.
+pub trait Weighted {
+    /// Get the block weight.
+    fn weight(&self) -> u64;
+}
+
 /// A block where we can derive a header from.
 ///
 /// The block can be thought as with a header and a body. However, in many
@@ -33,3 +40,30 @@ pub trait Headered {
     /// Get the block header.
     fn header(&self) -> Self::Header;
 }
+
+/// A block or a header whose header carries a seal, such as the signature
+/// of its author, over the rest of the header.
+///
+/// Consensus hashes the unsealed header for signing, and attaches the
+/// signature as the seal. To verify, the seal is checked against the hash of
+/// the unsealed header.
+pub trait Sealed: Headered {
+    /// Seal type.
+    type Seal;
+
+    /// Get the header without its seal, as it is signed.
+    fn unsealed_header(&self) -> Self::Header;
+    /// Get the seal.
+    fn seal(&self) -> Self::Seal;
+    /// Attach a seal to an unsealed header.
+    fn with_seal(header: Self::Header, seal: Self::Seal) -> Self::Header;
+}
+
+/// A block with a body of extrinsics.
+pub trait Bodied {
+    /// Extrinsic type.
+    type Extrinsic;
+
+    /// Get the extrinsics of the block, in order.
+    fn extrinsics(&self) -> Vec<Self::Extrinsic>;
+}
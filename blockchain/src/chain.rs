@@ -1,13 +1,15 @@
-use crate::Identified;
+use std::{cmp::Ordering, collections::HashSet};
+
+use crate::{tree_route, DigestItems, ForkChoiceRule, Identified, TreeRoute, TreeRouteError};
 
 /// Fork tree.
 ///
-/// A fork tree tracks blocks of forks. However, it does not track the best
-/// block. Instead, that is supposed to be handled by the `Chain` directly. The
-/// reason for this is that, except storing the best block hash, the majority of
-/// best block tracking is used for optimizations -- rebalancing the trees,
-/// moving non-canon blocks out of cache, pruning nodes, etc. They are not only
-/// needed in a fork tree, but also in states, as well as other related structs.
+/// A fork tree tracks blocks of forks. Telling the best block among them is
+/// left to [`ForkTreeBest`], as the fork choice rule is up to the
+/// implementation, and the optimizations it drives -- rebalancing the trees,
+/// moving non-canon blocks out of cache, pruning nodes, etc. -- are not only
+/// needed in a fork tree, but also in states, as well as other related
+/// structs.
 pub trait ForkTree {
     /// The type of the identified. It can be a block or a header.
     type Block: Identified;
@@ -20,6 +22,18 @@ pub trait ForkTree {
         id: &<Self::Block as Identified>::Identifier,
     ) -> Result<Self::Block, Self::QueryError>;
 
+    /// Get the id of the parent of a block by its id, `None` for a genesis
+    /// block.
+    ///
+    /// By default, the block is fetched. Implementations holding the blocks
+    /// should look the parent up without copying the block.
+    fn parent_id(
+        &self,
+        id: &<Self::Block as Identified>::Identifier,
+    ) -> Result<Option<<Self::Block as Identified>::Identifier>, Self::QueryError> {
+        Ok(self.block(id)?.parent_id())
+    }
+
     /// Get a block depth by its id.
     fn block_depth(
         &self,
@@ -29,6 +43,7 @@ pub trait ForkTree {
     /// Find an ancestor block at given depth.
     ///
     /// If ancestor depth equals the provided block's depth, return the provided block ID.
+    /// Any depth from the genesis up to the block's own is valid, deeper ones fail.
     fn ancestor_id_at_depth(
         &self,
         id: &<Self::Block as Identified>::Identifier,
@@ -47,6 +62,101 @@ pub trait ForkTree {
 
         Ok(self.ancestor_id_at_depth(id, ancestor_depth)? == *ancestor_id)
     }
+
+    /// Blocks to roll back and to roll forward when the block `from` is
+    /// replaced by the block `to`, as `(retracted, enacted)`, both ordered
+    /// from the fork point, their common ancestor (exclusive). Rolling back
+    /// goes through the retracted blocks in reverse.
+    ///
+    /// If `from` is an ancestor of `to`, the retracted blocks are empty. Fails
+    /// with [`TreeRouteError::NoCommonAncestor`] if they are under different
+    /// genesis blocks.
+    #[allow(clippy::type_complexity)]
+    fn reorg(
+        &self,
+        from: &<Self::Block as Identified>::Identifier,
+        to: &<Self::Block as Identified>::Identifier,
+    ) -> Result<
+        (
+            Vec<<Self::Block as Identified>::Identifier>,
+            Vec<<Self::Block as Identified>::Identifier>,
+        ),
+        TreeRouteError<Self::QueryError>,
+    >
+    where
+        Self: Sized,
+    {
+        let TreeRoute {
+            mut retracted,
+            enacted,
+            ..
+        } = tree_route(self, from, to)?;
+        retracted.reverse();
+        Ok((retracted, enacted))
+    }
+}
+
+/// A fork tree that can enumerate its blocks by depth.
+pub trait ForkTreeDepths: ForkTree {
+    /// Get the ids of all blocks at the given depth, across all forks. Empty if
+    /// there's no block at the depth.
+    fn blocks_at_depth(
+        &self,
+        depth: usize,
+    ) -> Result<Vec<<Self::Block as Identified>::Identifier>, Self::QueryError>;
+
+    /// Get the ids of the blocks without children, the tips of all forks, in
+    /// no particular order.
+    ///
+    /// By default, the blocks are walked depth by depth from the genesis, up
+    /// to the first empty depth: a block is a leaf if no block at the next
+    /// depth has it as parent.
+    fn leaves(&self) -> Result<Vec<<Self::Block as Identified>::Identifier>, Self::QueryError> {
+        let mut leaves = Vec::new();
+        let mut ids = self.blocks_at_depth(0)?;
+        let mut depth = 0;
+        while !ids.is_empty() {
+            depth += 1;
+            let children = self.blocks_at_depth(depth)?;
+            let mut parents = HashSet::new();
+            for child_id in &children {
+                parents.extend(self.parent_id(child_id)?);
+            }
+            leaves.extend(ids.into_iter().filter(|id| !parents.contains(id)));
+            ids = children;
+        }
+
+        Ok(leaves)
+    }
+}
+
+/// A fork tree that can tell its best block.
+///
+/// The fork choice rule is up to the implementation, such as the deepest
+/// block or the most finalized one. Ties must be broken deterministically,
+/// such as by the smallest identifier, so that nodes with the same tree
+/// agree on the best block whatever the order they imported it in.
+pub trait ForkTreeBest: ForkTree {
+    /// Get the id of the best block.
+    fn best_id(&self) -> Result<<Self::Block as Identified>::Identifier, Self::QueryError>;
+}
+
+/// A fork tree that can finalize blocks. A finalized block is irreversible:
+/// blocks conflicting with it, neither its ancestors nor its descendants, are
+/// rejected on insert.
+pub trait ForkTreeFinalize: ForkTreeBest {
+    /// Finalize error type.
+    type FinalizeError;
+
+    /// Get the id of the finalized block. None until a block is finalized.
+    fn finalized_id(&self) -> Option<<Self::Block as Identified>::Identifier>;
+
+    /// Finalize a block. It must be an ancestor of the best block, and a
+    /// descendant of the block finalized before.
+    fn finalize(
+        &mut self,
+        id: &<Self::Block as Identified>::Identifier,
+    ) -> Result<(), Self::FinalizeError>;
 }
 
 /// A structure representing a chain with possible forks.
@@ -56,32 +166,213 @@ pub trait ForkTreeMut: ForkTree {
 
     /// Insert a new block.
     fn insert(&mut self, block: Self::Block) -> Result<(), Self::InsertError>;
+
+    /// Insert a new block, and tell whether it is the new best block by the
+    /// rule, against the current best block tracked by the caller. None if
+    /// there is none yet, such as for the genesis.
+    fn insert_with_choice<R: ForkChoiceRule<Self>>(
+        &mut self,
+        block: Self::Block,
+        current_best: Option<&<Self::Block as Identified>::Identifier>,
+        rule: &R,
+    ) -> Result<bool, Self::InsertError>
+    where
+        Self: Sized,
+        Self::InsertError: From<Self::QueryError>,
+    {
+        let id = block.id();
+        self.insert(block)?;
+        let Some(current_best) = current_best else {
+            return Ok(true);
+        };
+
+        let (current_best, candidate) = (self.block(current_best)?, self.block(&id)?);
+        Ok(rule.compare(self, &current_best, &candidate)? == Ordering::Less)
+    }
+}
+
+/// A fork tree that can remove leaf blocks, such as blocks failing a deferred
+/// check after import.
+pub trait ForkTreeRemoveLeaf: ForkTree {
+    /// Remove error type.
+    type RemoveError;
+
+    /// Remove a block without children, and get it back.
+    fn remove_leaf(
+        &mut self,
+        id: &<Self::Block as Identified>::Identifier,
+    ) -> Result<Self::Block, Self::RemoveError>;
+}
+
+/// A fork tree that can prune the forks competing with the finalized block.
+///
+/// Pruning is planned first, which may fail, and then applied, which can not,
+/// so that it can be applied together with the pruning of other stores.
+pub trait ForkTreePrune: ForkTree {
+    /// Get the blocks that are neither ancestors nor descendants of the
+    /// finalized block.
+    fn non_canonical(
+        &self,
+        finalized_id: &<Self::Block as Identified>::Identifier,
+    ) -> Result<Vec<<Self::Block as Identified>::Identifier>, Self::QueryError>;
+
+    /// Remove blocks given by [`ForkTreePrune::non_canonical`].
+    fn prune(&mut self, ids: &[<Self::Block as Identified>::Identifier]);
 }
 
 /// Transactional fork tree.
+///
+/// Blocks are inserted into a transaction, and only become part of the fork
+/// tree once it is committed. Dropping the transaction rolls it back. All
+/// checks happen on insert, and again on commit in case the fork tree changed
+/// since, in which case nothing is committed.
 pub trait ForkTreeTransactional: ForkTree {
     /// Transaction type.
     type Transaction;
     /// Insert error type.
     type InsertError;
 
+    /// Begin a new transaction.
+    fn begin(&self) -> Self::Transaction;
+
     /// Insert a new block.
     fn insert(
         &self,
         transaction: &mut Self::Transaction,
         block: Self::Block,
     ) -> Result<(), Self::InsertError>;
+
+    /// Get a block depth by its id, including blocks inserted in the
+    /// transaction.
+    fn transaction_block_depth(
+        &self,
+        transaction: &Self::Transaction,
+        id: &<Self::Block as Identified>::Identifier,
+    ) -> Result<usize, Self::QueryError>;
+
+    /// Get a block by its id, including blocks inserted in the transaction.
+    ///
+    /// By default, only the blocks already in the fork tree are found.
+    /// Implementations should look up the blocks of the transaction too.
+    fn transaction_block(
+        &self,
+        _transaction: &Self::Transaction,
+        id: &<Self::Block as Identified>::Identifier,
+    ) -> Result<Self::Block, Self::QueryError> {
+        self.block(id)
+    }
+
+    /// Get the id of the parent of a block by its id, including blocks
+    /// inserted in the transaction.
+    ///
+    /// By default, the block is fetched with
+    /// [`ForkTreeTransactional::transaction_block`].
+    fn transaction_parent_id(
+        &self,
+        transaction: &Self::Transaction,
+        id: &<Self::Block as Identified>::Identifier,
+    ) -> Result<Option<<Self::Block as Identified>::Identifier>, Self::QueryError> {
+        Ok(self.transaction_block(transaction, id)?.parent_id())
+    }
+
+    /// Commit the transaction, or none of it if a block no longer fits the
+    /// fork tree, such as after its parent was pruned.
+    fn commit(&mut self, transaction: Self::Transaction) -> Result<(), Self::InsertError>;
+}
+
+/// Status of an imported block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStatus {
+    /// The block is imported.
+    Imported,
+    /// A block with the same id is already imported, such as when received
+    /// from several peers. Nothing changed.
+    AlreadyImported,
+    /// The block is buffered until it can be imported, such as until its
+    /// parent is. Nothing changed yet.
+    Queued,
+}
+
+/// Outcome of importing a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportOutcome<Id> {
+    /// Status of the block.
+    pub status: ImportStatus,
+    /// Whether the best block changed.
+    pub new_best: bool,
+    /// Route from the previous best block to the new one, if the best block
+    /// changed and blocks left the best chain.
+    pub reorg: Option<TreeRoute<Id>>,
+}
+
+impl<Id> ImportOutcome<Id> {
+    /// Outcome of an import that left the best block unchanged.
+    pub fn unchanged(status: ImportStatus) -> Self {
+        Self {
+            status,
+            new_best: false,
+            reorg: None,
+        }
+    }
+}
+
+impl<Id: Copy + Eq> ImportOutcome<Id> {
+    /// Outcome of an import into the fork tree, whose best block was
+    /// `old_best` before, or None if the tree was empty. If the best block
+    /// moved under another genesis block, there is no route between them,
+    /// and the outcome has no reorg.
+    pub fn imported<F>(fork_tree: &F, old_best: Option<Id>) -> Result<Self, F::QueryError>
+    where
+        F: ForkTreeBest,
+        F::Block: Identified<Identifier = Id>,
+    {
+        let new_best = fork_tree.best_id()?;
+        let Some(old_best) = old_best else {
+            return Ok(Self {
+                status: ImportStatus::Imported,
+                new_best: true,
+                reorg: None,
+            });
+        };
+
+        let reorg = match tree_route(fork_tree, &old_best, &new_best) {
+            Ok(route) => Some(route).filter(TreeRoute::is_reorg),
+            Err(TreeRouteError::NoCommonAncestor) => None,
+            Err(TreeRouteError::Query(err)) => return Err(err),
+        };
+        Ok(Self {
+            status: ImportStatus::Imported,
+            new_best: old_best != new_best,
+            reorg,
+        })
+    }
 }
 
 /// A chain that can import external blocks.
 pub trait ImportBlock {
     /// Type of the block.
-    type Block;
+    type Block: Identified;
     /// Error type.
     type Error;
 
-    /// Import a new block, given a fork tree.
-    fn import(&mut self, block: Self::Block) -> Result<(), Self::Error>;
+    /// Import a new block, given a fork tree. Importing an already imported
+    /// block is a no-op, with [`ImportStatus::AlreadyImported`].
+    fn import(
+        &mut self,
+        block: Self::Block,
+    ) -> Result<ImportOutcome<<Self::Block as Identified>::Identifier>, Self::Error>;
+}
+
+/// A chain that can import headers alone, deferring their bodies, such as
+/// for a light client or a header-first sync.
+pub trait ImportHeader {
+    /// Type of the header.
+    type Header;
+    /// Error type.
+    type Error;
+
+    /// Import a new header.
+    fn import_header(&mut self, header: Self::Header) -> Result<(), Self::Error>;
 }
 
 /// Block builder.
@@ -94,19 +385,42 @@ pub trait BlockBuilder<'chain>: Sized {
     type Extrinsic;
     /// Type of error.
     type Error;
-    /// Type of pre-log.
-    type PreLog;
-    /// Type of post-log.
-    type PostLog;
+    /// Type of digest item, shared by all engines contributing to the block.
+    type DigestItem;
 
-    /// Initialize a new block.
+    /// Initialize a new block, with its pre-digest items.
     fn initialize(
         chain: &'chain Self::Chain,
         parent_id: <Self::Block as Identified>::Identifier,
-        pre_log: Self::PreLog,
+        pre_digests: DigestItems<Self::DigestItem>,
     ) -> Result<Self, Self::Error>;
     /// Apply a new extrinsic.
     fn apply_extrinsic(&mut self, extrinsic: Self::Extrinsic) -> Result<(), Self::Error>;
-    /// Finalize the current block.
-    fn finalize(self, post_log: Self::PostLog) -> Result<Self::Block, Self::Error>;
+    /// Apply a batch of extrinsics, transactionally. Returns the number of
+    /// extrinsics applied, or the index and error of the first failing one,
+    /// in which case none of the batch is applied.
+    fn apply_extrinsics(
+        &mut self,
+        batch: Vec<Self::Extrinsic>,
+    ) -> Result<usize, (usize, Self::Error)>
+    where
+        Self: Clone,
+    {
+        let checkpoint = self.clone();
+        let count = batch.len();
+
+        for (index, extrinsic) in batch.into_iter().enumerate() {
+            if let Err(err) = self.apply_extrinsic(extrinsic) {
+                *self = checkpoint;
+                return Err((index, err));
+            }
+        }
+
+        Ok(count)
+    }
+    /// Finalize the current block, appending the post-digest items.
+    fn finalize(
+        self,
+        post_digests: DigestItems<Self::DigestItem>,
+    ) -> Result<Self::Block, Self::Error>;
 }
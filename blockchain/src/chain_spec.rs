@@ -0,0 +1,113 @@
+use std::{fs, io, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{FlatStateMut, ForkTreeMut, Identified};
+
+/// Chain spec, describing the genesis of a chain.
+///
+/// It carries the genesis block and the genesis key-value state, and can be
+/// loaded from a JSON file, so that genesis does not need to be hand-built.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainSpec<Block, Key, Value> {
+    /// Genesis block.
+    pub genesis: Block,
+    /// Genesis state.
+    pub state: Vec<(Key, Value)>,
+}
+
+/// Error initializing a chain from a chain spec.
+#[derive(Debug, Clone)]
+pub enum ChainSpecError<InsertError, ApplyError> {
+    /// Inserting the genesis block into the fork tree failed.
+    Insert(InsertError),
+    /// Applying the genesis state failed.
+    Apply(ApplyError),
+}
+
+/// Error loading or storing a chain spec file.
+#[derive(Debug)]
+pub enum ChainSpecFileError {
+    /// Reading or writing the file failed.
+    Io(io::Error),
+    /// The file is not a valid chain spec.
+    Json(serde_json::Error),
+}
+
+impl From<io::Error> for ChainSpecFileError {
+    fn from(err: io::Error) -> ChainSpecFileError {
+        ChainSpecFileError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ChainSpecFileError {
+    fn from(err: serde_json::Error) -> ChainSpecFileError {
+        ChainSpecFileError::Json(err)
+    }
+}
+
+impl<Block, Key, Value> ChainSpec<Block, Key, Value> {
+    /// Create a new chain spec.
+    pub fn new(genesis: Block, state: Vec<(Key, Value)>) -> Self {
+        Self { genesis, state }
+    }
+
+    /// Build genesis, by inserting the genesis block into the fork tree and
+    /// applying the genesis state to it. Returns the genesis block id.
+    pub fn initialize<FT, FS>(
+        &self,
+        fork_tree: &mut FT,
+        state: &mut FS,
+    ) -> Result<Block::Identifier, ChainSpecError<FT::InsertError, FS::ApplyError>>
+    where
+        Block: Identified + Clone,
+        Key: Clone,
+        Value: Clone,
+        FT: ForkTreeMut<Block = Block>,
+        FS: FlatStateMut<FT, Key = Key, Value = Value>,
+    {
+        let genesis_id = self.genesis.id();
+
+        fork_tree
+            .insert(self.genesis.clone())
+            .map_err(ChainSpecError::Insert)?;
+        state
+            .apply(
+                self.state
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Some(value.clone()))),
+                genesis_id,
+                fork_tree,
+            )
+            .map_err(ChainSpecError::Apply)?;
+
+        Ok(genesis_id)
+    }
+}
+
+impl<Block, Key, Value> ChainSpec<Block, Key, Value>
+where
+    Block: DeserializeOwned,
+    Key: DeserializeOwned,
+    Value: DeserializeOwned,
+{
+    /// Load a chain spec from a JSON file.
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self, ChainSpecFileError> {
+        let file = fs::File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+}
+
+impl<Block, Key, Value> ChainSpec<Block, Key, Value>
+where
+    Block: Serialize,
+    Key: Serialize,
+    Value: Serialize,
+{
+    /// Store the chain spec as a JSON file.
+    pub fn to_json_file(&self, path: impl AsRef<Path>) -> Result<(), ChainSpecFileError> {
+        let file = fs::File::create(path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
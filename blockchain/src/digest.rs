@@ -0,0 +1,135 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Phase of a digest item.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, core::hash::Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DigestPhase {
+    /// Added before any extrinsic is applied, such as a slot claim.
+    Pre,
+    /// Added after all extrinsics are applied, such as a seal.
+    Post,
+}
+
+/// Error of adding digest items out of phase order.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DigestItemsError {
+    /// A pre-digest item was added after a post-digest item.
+    PreAfterPost,
+}
+
+/// Access a digest item as a specific engine's digest.
+///
+/// The digest item type is usually an enum defined by the chain, with a
+/// variant per engine. Each engine only requires the item to convert from and
+/// to its own digest, without knowing about the other engines.
+pub trait AsDigest<T> {
+    /// Get the engine digest, if the item is one.
+    fn as_digest(&self) -> Option<&T>;
+}
+
+/// Ordered digest items of a block, contributed by possibly multiple engines.
+///
+/// The pre-digest items all come before the post-digest items, as they are
+/// added before the extrinsics are applied.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DigestItems<Item> {
+    items: Vec<(DigestPhase, Item)>,
+}
+
+impl<Item> Default for DigestItems<Item> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Item> DigestItems<Item> {
+    /// Create an empty digest.
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    /// Push a pre-digest item. Fails if a post-digest item was pushed
+    /// already.
+    pub fn push_pre<T>(&mut self, item: T) -> Result<(), DigestItemsError>
+    where
+        Item: From<T>,
+    {
+        if self.has_post() {
+            return Err(DigestItemsError::PreAfterPost);
+        }
+        self.items.push((DigestPhase::Pre, item.into()));
+        Ok(())
+    }
+
+    /// Push a post-digest item.
+    pub fn push_post<T>(&mut self, item: T)
+    where
+        Item: From<T>,
+    {
+        self.items.push((DigestPhase::Post, item.into()));
+    }
+
+    /// Append all items of another digest, keeping their order. Fails,
+    /// appending nothing, if the other digest has pre-digest items while
+    /// this one has post-digest items already.
+    pub fn append(&mut self, other: DigestItems<Item>) -> Result<(), DigestItemsError> {
+        let other_has_pre = other
+            .items
+            .first()
+            .map_or(false, |(phase, _)| *phase == DigestPhase::Pre);
+        if other_has_pre && self.has_post() {
+            return Err(DigestItemsError::PreAfterPost);
+        }
+        self.items.extend(other.items);
+        Ok(())
+    }
+
+    /// Iterate over all items, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (DigestPhase, &Item)> {
+        self.items.iter().map(|(phase, item)| (*phase, item))
+    }
+
+    /// Iterate over the pre-digest items of type `T`, in order.
+    pub fn pre<T>(&self) -> impl Iterator<Item = &T>
+    where
+        Item: AsDigest<T>,
+    {
+        self.of_phase(DigestPhase::Pre)
+    }
+
+    /// Iterate over the post-digest items of type `T`, in order.
+    pub fn post<T>(&self) -> impl Iterator<Item = &T>
+    where
+        Item: AsDigest<T>,
+    {
+        self.of_phase(DigestPhase::Post)
+    }
+
+    /// Number of items.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Whether there's no item.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    fn has_post(&self) -> bool {
+        self.items
+            .last()
+            .map_or(false, |(phase, _)| *phase == DigestPhase::Post)
+    }
+
+    fn of_phase<T>(&self, phase: DigestPhase) -> impl Iterator<Item = &T>
+    where
+        Item: AsDigest<T>,
+    {
+        self.items
+            .iter()
+            .filter(move |(item_phase, _)| *item_phase == phase)
+            .filter_map(|(_, item)| item.as_digest())
+    }
+}
@@ -0,0 +1,131 @@
+use futures::{channel::mpsc, stream::Stream};
+
+use crate::{tree_route, ForkTreeFinalize, Identified, TreeRouteError};
+
+/// Notification of newly finalized blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalityNotification<Id> {
+    /// The new finalized block.
+    pub finalized_id: Id,
+    /// Blocks finalized since the last notification, in order, ending with
+    /// `finalized_id`. Finalizing a descendant several blocks ahead finalizes
+    /// all blocks in between.
+    pub newly_finalized: Vec<Id>,
+}
+
+/// Error of finalizing a block.
+#[derive(Debug, Clone)]
+pub enum FinalizeError<E, F = core::convert::Infallible> {
+    /// The block does not descend from the finalized block, or it is the
+    /// finalized block itself.
+    NotDescendant,
+    /// Querying the fork tree failed.
+    Query(E),
+    /// The fork tree refused to finalize the block, such as one that is not
+    /// an ancestor of its best block.
+    ForkTree(F),
+}
+
+/// Finality notifications over a fork tree.
+///
+/// The finalized block is the one of the fork tree, which finality only moves
+/// forward along its descendants. Components acting only on finalized blocks,
+/// such as indexers or bridges, subscribe to finality notifications, which
+/// unlike import events never report speculative best block changes.
+#[derive(Debug)]
+pub struct Finality<Id> {
+    subscribers: Vec<mpsc::UnboundedSender<FinalityNotification<Id>>>,
+}
+
+impl<Id: Clone + Copy + Eq> Finality<Id> {
+    /// Start notifying finality, from the finalized block of the fork tree,
+    /// if any, such as genesis.
+    pub fn new() -> Self {
+        Self {
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Subscribe to notifications of blocks finalized from now on.
+    pub fn finality_notifications(&mut self) -> impl Stream<Item = FinalityNotification<Id>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Finalize a descendant of the finalized block in the fork tree, along
+    /// with all blocks in between, and notify subscribers.
+    #[allow(clippy::type_complexity)]
+    pub fn finalize<F>(
+        &mut self,
+        fork_tree: &mut F,
+        id: &Id,
+    ) -> Result<FinalityNotification<Id>, FinalizeError<F::QueryError, F::FinalizeError>>
+    where
+        F: ForkTreeFinalize,
+        F::Block: Identified<Identifier = Id>,
+    {
+        let newly_finalized = Self::newly_finalized(fork_tree, id).map_err(|err| match err {
+            FinalizeError::NotDescendant => FinalizeError::NotDescendant,
+            FinalizeError::Query(err) => FinalizeError::Query(err),
+            FinalizeError::ForkTree(never) => match never {},
+        })?;
+        fork_tree.finalize(id).map_err(FinalizeError::ForkTree)?;
+        Ok(self.notify(*id, newly_finalized))
+    }
+
+    /// Blocks that finalizing the block would finalize after the finalized
+    /// block of the fork tree, in order. With no block finalized yet, the
+    /// whole chain up to the block is, from its genesis.
+    pub(crate) fn newly_finalized<F>(
+        fork_tree: &F,
+        id: &Id,
+    ) -> Result<Vec<Id>, FinalizeError<F::QueryError>>
+    where
+        F: ForkTreeFinalize,
+        F::Block: Identified<Identifier = Id>,
+    {
+        let Some(finalized) = fork_tree.finalized_id() else {
+            let depth = fork_tree.block_depth(id).map_err(FinalizeError::Query)?;
+            return (0..=depth)
+                .map(|depth| {
+                    fork_tree
+                        .ancestor_id_at_depth(id, depth)
+                        .map_err(FinalizeError::Query)
+                })
+                .collect();
+        };
+
+        let route = tree_route(fork_tree, &finalized, id).map_err(|err| match err {
+            TreeRouteError::NoCommonAncestor => FinalizeError::NotDescendant,
+            TreeRouteError::Query(err) => FinalizeError::Query(err),
+        })?;
+        if route.is_reorg() || route.enacted.is_empty() {
+            return Err(FinalizeError::NotDescendant);
+        }
+
+        Ok(route.enacted)
+    }
+
+    /// Notify subscribers of newly finalized blocks, dropping those gone.
+    pub(crate) fn notify(
+        &mut self,
+        finalized_id: Id,
+        newly_finalized: Vec<Id>,
+    ) -> FinalityNotification<Id> {
+        let notification = FinalityNotification {
+            finalized_id,
+            newly_finalized,
+        };
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(notification.clone()).is_ok());
+
+        notification
+    }
+}
+
+impl<Id: Clone + Copy + Eq> Default for Finality<Id> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
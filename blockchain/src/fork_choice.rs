@@ -0,0 +1,45 @@
+use std::cmp::{Ordering, Reverse};
+
+use crate::{ForkTree, Identified};
+
+/// Rule picking the best of two blocks of a fork tree, such as the longest
+/// chain or a GHOST-style rule, for callers tracking the best block
+/// themselves with [`ForkTreeMut::insert_with_choice`].
+///
+/// [`ForkTreeMut::insert_with_choice`]: crate::ForkTreeMut::insert_with_choice
+pub trait ForkChoiceRule<F: ForkTree> {
+    /// Compare the blocks as `current_best.cmp(candidate)`: [`Ordering::Less`]
+    /// if the candidate is better, making it the new best block. Ties must
+    /// be broken deterministically, as for [`ForkTreeBest`].
+    ///
+    /// [`ForkTreeBest`]: crate::ForkTreeBest
+    fn compare(
+        &self,
+        fork_tree: &F,
+        current_best: &F::Block,
+        candidate: &F::Block,
+    ) -> Result<Ordering, F::QueryError>;
+}
+
+/// The deepest block is the best, or the one with the smallest identifier
+/// among the equally deep, as the memory fork tree's
+/// [`ForkChoice::Longest`](crate::memory::ForkChoice::Longest).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LongestChain;
+
+impl<F: ForkTree> ForkChoiceRule<F> for LongestChain
+where
+    <F::Block as Identified>::Identifier: Ord,
+{
+    fn compare(
+        &self,
+        fork_tree: &F,
+        current_best: &F::Block,
+        candidate: &F::Block,
+    ) -> Result<Ordering, F::QueryError> {
+        let rank =
+            |block: &F::Block| Ok((fork_tree.block_depth(&block.id())?, Reverse(block.id())));
+
+        Ok(rank(current_best)?.cmp(&rank(candidate)?))
+    }
+}
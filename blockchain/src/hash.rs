@@ -0,0 +1,63 @@
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A 256-bit hash, ready to be used as a block identifier.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BlockHash(pub [u8; 32]);
+
+impl BlockHash {
+    /// The all-zero hash, such as for a placeholder parent.
+    pub const ZERO: Self = Self([0; 32]);
+
+    /// Create a hash from its raw bytes.
+    pub const fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Hash the data with Blake2b-256.
+    pub fn digest(data: impl AsRef<[u8]>) -> Self {
+        Self(Blake2b::<U32>::digest(data).into())
+    }
+
+    /// Raw bytes of the hash.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for BlockHash {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<BlockHash> for [u8; 32] {
+    fn from(hash: BlockHash) -> Self {
+        hash.0
+    }
+}
+
+impl AsRef<[u8]> for BlockHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,163 @@
+use futures::{
+    channel::mpsc,
+    sink::SinkExt,
+    stream::{Stream, StreamExt},
+};
+
+use crate::{ForkTree, ForkTreeBest, Identified, ImportBlock, ImportStatus, TreeRoute};
+
+/// Event of the import queue, one per pushed block, in import order.
+#[derive(Debug, Clone)]
+pub enum ImportEvent<Id, Error> {
+    /// The block is imported, or was already.
+    Imported {
+        /// The imported block.
+        id: Id,
+        /// The best block, after the import.
+        best: Id,
+        /// Whether the best block changed.
+        new_best: bool,
+        /// Route from the previous best block to the new one, if the best
+        /// block changed and blocks left the best chain.
+        reorg: Option<TreeRoute<Id>>,
+    },
+    /// The block is buffered by the importer until it can be imported, such
+    /// as until its parent is.
+    Queued {
+        /// The buffered block.
+        id: Id,
+    },
+    /// The block failed to import.
+    Failed {
+        /// The failing block.
+        id: Id,
+        /// The import error.
+        error: Error,
+    },
+}
+
+/// The import queue worker is gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportQueueClosed;
+
+/// Handle pushing blocks into an import queue.
+#[derive(Debug)]
+pub struct ImportQueue<Block> {
+    sender: mpsc::Sender<Block>,
+}
+
+impl<Block> Clone for ImportQueue<Block> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<Block> ImportQueue<Block> {
+    /// Push a block, waiting while the queue is full.
+    pub async fn push(&mut self, block: Block) -> Result<(), ImportQueueClosed> {
+        self.sender.send(block).await.map_err(|_| ImportQueueClosed)
+    }
+
+    /// Push a block if the queue has room, or get it back.
+    pub fn try_push(&mut self, block: Block) -> Result<(), Block> {
+        self.sender.try_send(block).map_err(|err| err.into_inner())
+    }
+}
+
+type BlockId<F> = <<F as ForkTree>::Block as Identified>::Identifier;
+
+/// Worker of an import queue, importing pushed blocks in order and tracking
+/// the best block of the importer, by its fork choice rule.
+///
+/// Importing can be CPU-heavy, so the worker is meant to run on its own task
+/// or thread, away from the network and production loops.
+pub struct ImportQueueWorker<Import: ImportBlock + ForkTree> {
+    import: Import,
+    best: BlockId<Import>,
+    blocks: mpsc::Receiver<<Import as ForkTree>::Block>,
+    events: mpsc::UnboundedSender<ImportEvent<BlockId<Import>, <Import as ImportBlock>::Error>>,
+}
+
+/// Create an import queue over the importer, with room for `capacity` blocks
+/// before pushes wait. Fails if the importer has no best block yet.
+#[allow(clippy::type_complexity)]
+pub fn import_queue<Import, Block>(
+    import: Import,
+    capacity: usize,
+) -> Result<
+    (
+        ImportQueue<Block>,
+        ImportQueueWorker<Import>,
+        impl Stream<Item = ImportEvent<Block::Identifier, <Import as ImportBlock>::Error>>,
+    ),
+    <Import as ForkTree>::QueryError,
+>
+where
+    Import: ImportBlock<Block = Block> + ForkTreeBest<Block = Block>,
+    Block: Identified,
+{
+    let best = import.best_id()?;
+    let (sender, blocks) = mpsc::channel(capacity);
+    let (events, event_receiver) = mpsc::unbounded();
+
+    Ok((
+        ImportQueue { sender },
+        ImportQueueWorker {
+            import,
+            best,
+            blocks,
+            events,
+        },
+        event_receiver,
+    ))
+}
+
+impl<Import, Block> ImportQueueWorker<Import>
+where
+    Import: ImportBlock<Block = Block> + ForkTreeBest<Block = Block>,
+    Block: Identified,
+{
+    /// The current best block.
+    pub fn best(&self) -> Block::Identifier {
+        self.best
+    }
+
+    /// Import blocks until all queue handles are dropped, and get the importer
+    /// back. A failing block is reported, and does not stop the queue.
+    pub async fn run(mut self) -> Import {
+        while let Some(block) = self.blocks.next().await {
+            let event = self.import_one(block);
+            // Nobody listening for events is not a reason to stop importing.
+            let _ = self.events.unbounded_send(event);
+        }
+
+        self.import
+    }
+
+    fn import_one(&mut self, block: Block) -> ImportEvent<Block::Identifier, Import::Error> {
+        let id = block.id();
+        let outcome = match self.import.import(block) {
+            Ok(outcome) => outcome,
+            Err(error) => return ImportEvent::Failed { id, error },
+        };
+        if outcome.status == ImportStatus::Queued {
+            return ImportEvent::Queued { id };
+        }
+
+        if outcome.new_best {
+            // The importer just moved its best block, so that it has one.
+            if let Ok(best) = self.import.best_id() {
+                self.best = best;
+            }
+        }
+
+        ImportEvent::Imported {
+            id,
+            best: self.best,
+            new_best: outcome.new_best,
+            reorg: outcome.reorg,
+        }
+    }
+}
@@ -4,9 +4,48 @@
 
 mod block;
 mod chain;
+#[cfg(feature = "serde")]
+mod chain_spec;
+mod digest;
+mod finality;
+mod fork_choice;
+mod hash;
+mod import_queue;
 pub mod memory;
+mod merkle;
+mod orphan;
+mod pool;
+mod pruning;
+mod route;
+mod seal;
+#[cfg(feature = "sled")]
+pub mod sled;
 mod state;
+mod transaction;
 
-pub use crate::block::{Headered, Identified, Keyed};
-pub use crate::chain::{BlockBuilder, ForkTree, ForkTreeMut, ForkTreeTransactional, ImportBlock};
-pub use crate::state::{FlatState, FlatStateMut, FlatStateTransactional, OverlayedFlatState};
+pub use crate::block::{Bodied, Headered, Identified, Keyed, Sealed, Weighted};
+pub use crate::chain::{
+    BlockBuilder, ForkTree, ForkTreeBest, ForkTreeDepths, ForkTreeFinalize, ForkTreeMut,
+    ForkTreePrune, ForkTreeRemoveLeaf, ForkTreeTransactional, ImportBlock, ImportHeader,
+    ImportOutcome, ImportStatus,
+};
+#[cfg(feature = "serde")]
+pub use crate::chain_spec::{ChainSpec, ChainSpecError, ChainSpecFileError};
+pub use crate::digest::{AsDigest, DigestItems, DigestItemsError, DigestPhase};
+pub use crate::finality::{Finality, FinalityNotification, FinalizeError};
+pub use crate::fork_choice::{ForkChoiceRule, LongestChain};
+pub use crate::hash::BlockHash;
+pub use crate::import_queue::{
+    import_queue, ImportEvent, ImportQueue, ImportQueueClosed, ImportQueueWorker,
+};
+pub use crate::merkle::{Merkleizer, StateRootCache};
+pub use crate::orphan::OrphanPool;
+pub use crate::pool::{EmptyBlockPolicy, Pool};
+pub use crate::pruning::{Chain, ChainFinalizeError, StateChange};
+pub use crate::route::{tree_route, TreeRoute, TreeRouteError};
+pub use crate::seal::{PendingSeals, SealResolution, SealResolveError, SealVerdict};
+pub use crate::state::{
+    FlatState, FlatStateEntries, FlatStateMut, FlatStatePrune, FlatStateTransactional,
+    OverlayedFlatState,
+};
+pub use crate::transaction::{ChainTransaction, ChainTransactionError};
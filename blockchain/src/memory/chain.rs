@@ -1,21 +1,71 @@
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fmt::Write,
+};
 
-use crate::{ForkTree, ForkTreeMut, Identified};
+#[cfg(feature = "serde")]
+use serde::{
+    de::Error as _, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    ForkTree, ForkTreeBest, ForkTreeDepths, ForkTreeFinalize, ForkTreeMut, ForkTreePrune,
+    ForkTreeRemoveLeaf, ForkTreeTransactional, Headered, Identified, ImportBlock, ImportOutcome,
+    ImportStatus, Keyed, TreeRoute, Weighted,
+};
 
 #[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(bound(
+        serialize = "Block: Serialize, Block::Identifier: Serialize",
+        deserialize = "Block: Deserialize<'de>, Block::Identifier: Deserialize<'de>"
+    ))
+)]
 struct MemoryForkTreeItem<Block: Identified> {
     block: Block,
     depth: usize,
+    /// Weight of the chain up to and including the block.
+    cumulative_weight: u128,
     children: Vec<Block::Identifier>,
     ancestors: Vec<(usize, Block::Identifier)>,
 }
 
+/// Rule picking the best block of a memory fork tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ForkChoice {
+    /// The deepest block.
+    #[default]
+    Longest,
+    /// The block with the heaviest chain, by
+    /// [`MemoryForkTree::cumulative_weight`], or the deepest among the
+    /// heaviest.
+    Heaviest,
+}
+
 /// A fork tree that resides entirely in memory. Useful for testing.
 #[derive(Debug, Clone)]
 pub struct MemoryForkTree<Block: Identified> {
     blocks: HashMap<Block::Identifier, MemoryForkTreeItem<Block>>,
     depths: HashMap<usize, Vec<Block::Identifier>>,
+    /// Blocks without children.
+    leaves: HashSet<Block::Identifier>,
+    /// Whether a child block is keyed after its parent, if checked.
+    key_check: Option<fn(&Block, &Block) -> bool>,
+    /// Weight of a block, if weighted.
+    weight: Option<fn(&Block) -> u64>,
+    fork_choice: ForkChoice,
+    finalized: Option<Block::Identifier>,
+    /// The equally good best blocks by the fork choice, among the descendants
+    /// of the finalized block, kept up to date on insert, removal, prune and
+    /// finalization. The best block is the smallest of them, so that only
+    /// [`ForkTreeBest`] needs ordered identifiers.
+    best: Vec<Block::Identifier>,
 }
 
 impl<Block: Identified> MemoryForkTree<Block> {
@@ -24,8 +74,210 @@ impl<Block: Identified> MemoryForkTree<Block> {
         Self {
             blocks: HashMap::new(),
             depths: HashMap::new(),
+            leaves: HashSet::new(),
+            key_check: None,
+            weight: None,
+            fork_choice: ForkChoice::Longest,
+            finalized: None,
+            best: Vec::new(),
         }
     }
+
+    /// Create a new fork tree, with space preallocated for the expected
+    /// number of blocks, in the blocks as well as in the leaves and the depth
+    /// index.
+    pub fn with_capacity(expected_blocks: usize) -> Self {
+        Self {
+            blocks: HashMap::with_capacity(expected_blocks),
+            depths: HashMap::with_capacity(expected_blocks),
+            leaves: HashSet::with_capacity(expected_blocks),
+            key_check: None,
+            weight: None,
+            fork_choice: ForkChoice::Longest,
+            finalized: None,
+            best: Vec::new(),
+        }
+    }
+
+    /// Reject inserting blocks whose key is not strictly greater than the
+    /// key of their parent, such as a child with the same number as its
+    /// parent, with [`MemoryForkTreeInsertError::NonMonotonicKey`].
+    pub fn with_monotonic_keys<K: Ord>(mut self) -> Self
+    where
+        Block: Keyed<K>,
+    {
+        self.key_check = Some(|block, parent| block.key() > parent.key());
+        self
+    }
+
+    /// Track the cumulative weight of chains by [`Weighted::weight`], rather
+    /// than counting each block as one. Must be set before inserting blocks,
+    /// or again on a deserialized tree that had it.
+    pub fn with_weights(mut self) -> Self
+    where
+        Block: Weighted,
+    {
+        self.weight = Some(|block| block.weight());
+        self
+    }
+
+    /// Weight of the chain up to and including the block, computed once on
+    /// insert. Without [`MemoryForkTree::with_weights`], each block weighs
+    /// one, so that it is the depth plus one.
+    pub fn cumulative_weight(
+        &self,
+        id: &Block::Identifier,
+    ) -> Result<u128, MemoryForkTreeQueryError> {
+        Ok(self
+            .blocks
+            .get(id)
+            .ok_or(MemoryForkTreeQueryError::UnknownBlock)?
+            .cumulative_weight)
+    }
+
+    /// Identifiers of the blocks with a depth in `start..end`, shallowest
+    /// first, and in insertion order within a depth, so that it is the same
+    /// on nodes importing in the same order, such as for snapshot sync.
+    pub fn blocks_in_depth_range(&self, start: usize, end: usize) -> Vec<Block::Identifier> {
+        (start..end.min(self.max_depth().map_or(0, |max| max + 1)))
+            .filter_map(|depth| self.depths.get(&depth))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Depth of the deepest block. None if the tree is empty.
+    pub fn max_depth(&self) -> Option<usize> {
+        self.depths.keys().max().copied()
+    }
+
+    /// Read a block in place, without the clone of [`ForkTree::block`], such
+    /// as for large blocks on hot paths.
+    pub fn with_block<R>(
+        &self,
+        id: &Block::Identifier,
+        f: impl FnOnce(&Block) -> R,
+    ) -> Result<R, MemoryForkTreeQueryError> {
+        Ok(f(&self
+            .blocks
+            .get(id)
+            .ok_or(MemoryForkTreeQueryError::UnknownBlock)?
+            .block))
+    }
+
+    /// Whether a child of the parent, or a new genesis block, conflicts with
+    /// the finalized block: a child can only extend the finalized chain
+    /// beyond the finalized block.
+    fn conflicts_finalized(
+        &self,
+        parent_id: Option<&Block::Identifier>,
+    ) -> Result<bool, MemoryForkTreeQueryError>
+    where
+        Block: Clone,
+    {
+        let Some(finalized_id) = self.finalized else {
+            return Ok(false);
+        };
+        let Some(parent_id) = parent_id else {
+            return Ok(true);
+        };
+
+        Ok(
+            self.block_depth(parent_id)? < self.block_depth(&finalized_id)?
+                || !self.is_ancestor(parent_id, &finalized_id)?,
+        )
+    }
+
+    /// Reserve space for at least `additional` more blocks, in the blocks as
+    /// well as in the leaves and the depth index.
+    pub fn reserve(&mut self, additional: usize) {
+        self.blocks.reserve(additional);
+        self.depths.reserve(additional);
+        self.leaves.reserve(additional);
+    }
+}
+
+impl<Block: Identified> Default for MemoryForkTree<Block> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serialized as its blocks, shallowest first, with their depth, children
+/// and ancestors, so that deserializing does not insert them again, along
+/// with the fork choice and the finalized block. The key check and weights
+/// are functions, and are set again on the deserialized tree with
+/// [`MemoryForkTree::with_monotonic_keys`] and
+/// [`MemoryForkTree::with_weights`].
+#[cfg(feature = "serde")]
+impl<Block> Serialize for MemoryForkTree<Block>
+where
+    Block: Identified + Serialize,
+    Block::Identifier: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let blocks = self
+            .blocks_in_depth_range(0, usize::MAX)
+            .iter()
+            .map(|id| &self.blocks[id])
+            .collect::<Vec<_>>();
+
+        let mut state = serializer.serialize_struct("MemoryForkTree", 3)?;
+        state.serialize_field("blocks", &blocks)?;
+        state.serialize_field("fork_choice", &self.fork_choice)?;
+        state.serialize_field("finalized", &self.finalized)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(
+    rename = "MemoryForkTree",
+    bound = "Block: Deserialize<'de>, Block::Identifier: Deserialize<'de>"
+)]
+struct SerializedMemoryForkTree<Block: Identified> {
+    blocks: Vec<MemoryForkTreeItem<Block>>,
+    fork_choice: ForkChoice,
+    finalized: Option<Block::Identifier>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Block> Deserialize<'de> for MemoryForkTree<Block>
+where
+    Block: Identified + Clone + Deserialize<'de>,
+    Block::Identifier: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = SerializedMemoryForkTree::<Block>::deserialize(deserializer)?;
+        let mut fork_tree = Self {
+            fork_choice: serialized.fork_choice,
+            finalized: serialized.finalized,
+            ..Self::new()
+        };
+        for item in serialized.blocks {
+            let id = item.block.id();
+            fork_tree.depths.entry(item.depth).or_default().push(id);
+            if item.children.is_empty() {
+                fork_tree.leaves.insert(id);
+            }
+            fork_tree.blocks.insert(id, item);
+        }
+
+        // Queries index the blocks by the ids they link to.
+        let known = |id: &Block::Identifier| fork_tree.blocks.contains_key(id);
+        let linked = fork_tree.blocks.values().all(|item| {
+            item.block.parent_id().iter().all(known)
+                && item.children.iter().all(known)
+                && item.ancestors.iter().all(|(_, id)| known(id))
+        });
+        if !linked || !fork_tree.finalized.iter().all(known) {
+            return Err(D::Error::custom("block links to an unknown block"));
+        }
+
+        fork_tree.best = fork_tree.choose_best();
+        Ok(fork_tree)
+    }
 }
 
 /// Query error for memory fork tree.
@@ -35,6 +287,8 @@ pub enum MemoryForkTreeQueryError {
     UnknownBlock,
     /// Ancestor depth provided is greater than current block depth.
     InvalidAncestorDepth,
+    /// Block is not an ancestor of the other block.
+    NotAncestor,
 }
 
 impl<Block: Identified + Clone> ForkTree for MemoryForkTree<Block> {
@@ -44,16 +298,23 @@ impl<Block: Identified + Clone> ForkTree for MemoryForkTree<Block> {
     fn block(&self, id: &Block::Identifier) -> Result<Block, Self::QueryError> {
         Ok(self
             .blocks
-            .get(&id)
+            .get(id)
             .ok_or(MemoryForkTreeQueryError::UnknownBlock)?
             .block
             .clone())
     }
 
+    fn parent_id(
+        &self,
+        id: &Block::Identifier,
+    ) -> Result<Option<Block::Identifier>, Self::QueryError> {
+        self.with_block(id, Identified::parent_id)
+    }
+
     fn block_depth(&self, id: &Block::Identifier) -> Result<usize, Self::QueryError> {
         Ok(self
             .blocks
-            .get(&id)
+            .get(id)
             .ok_or(MemoryForkTreeQueryError::UnknownBlock)?
             .depth)
     }
@@ -65,13 +326,9 @@ impl<Block: Identified + Clone> ForkTree for MemoryForkTree<Block> {
     ) -> Result<Block::Identifier, Self::QueryError> {
         let mut current_block = self
             .blocks
-            .get(&id)
+            .get(id)
             .ok_or(MemoryForkTreeQueryError::UnknownBlock)?;
 
-        if current_block.depth > ancestor_depth {
-            return Err(MemoryForkTreeQueryError::InvalidAncestorDepth);
-        }
-
         loop {
             if current_block.depth < ancestor_depth {
                 return Err(MemoryForkTreeQueryError::InvalidAncestorDepth);
@@ -114,11 +371,29 @@ impl<Block: Identified + Clone> ForkTree for MemoryForkTree<Block> {
     }
 }
 
+impl<Block: Identified + Clone> ForkTreeDepths for MemoryForkTree<Block> {
+    fn blocks_at_depth(&self, depth: usize) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        Ok(self.depths.get(&depth).cloned().unwrap_or_default())
+    }
+
+    /// The leaves, kept up to date on insert and removal.
+    fn leaves(&self) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        Ok(self.leaves.iter().copied().collect())
+    }
+}
+
 /// Insert error for memory fork tree.
 #[derive(Debug, Clone)]
 pub enum MemoryForkTreeInsertError {
     /// Parent is unknown.
     UnknownParent,
+    /// The block key is not greater than the key of its parent.
+    NonMonotonicKey,
+    /// The block is neither an ancestor nor a descendant of the finalized
+    /// block.
+    ConflictsFinalized,
+    /// A block with the same id is already inserted.
+    AlreadyInserted,
     /// Encounted a query issue in insertion.
     Query(MemoryForkTreeQueryError),
 }
@@ -129,8 +404,51 @@ impl From<MemoryForkTreeQueryError> for MemoryForkTreeInsertError {
     }
 }
 
+/// Finalize error for memory fork tree.
+#[derive(Debug, Clone)]
+pub enum MemoryForkTreeFinalizeError {
+    /// The block is not an ancestor of the best block.
+    NotBestAncestor,
+    /// The block is not a descendant of the block finalized before.
+    NotFinalizedDescendant,
+    /// Encounted a query issue in finalization.
+    Query(MemoryForkTreeQueryError),
+}
+
+impl From<MemoryForkTreeQueryError> for MemoryForkTreeFinalizeError {
+    fn from(query: MemoryForkTreeQueryError) -> MemoryForkTreeFinalizeError {
+        MemoryForkTreeFinalizeError::Query(query)
+    }
+}
+
+/// Remove error for memory fork tree.
+#[derive(Debug, Clone)]
+pub enum MemoryForkTreeRemoveError {
+    /// Block is unknown.
+    UnknownBlock,
+    /// Block has children.
+    NotLeaf,
+    /// Block is finalized.
+    Finalized,
+}
+
+/// Prune error for memory fork tree.
+#[derive(Debug, Clone)]
+pub enum MemoryForkTreePruneError {
+    /// Block is neither the finalized block nor one of its ancestors.
+    NotFinalized,
+    /// Encounted a query issue in pruning.
+    Query(MemoryForkTreeQueryError),
+}
+
+impl From<MemoryForkTreeQueryError> for MemoryForkTreePruneError {
+    fn from(query: MemoryForkTreeQueryError) -> MemoryForkTreePruneError {
+        MemoryForkTreePruneError::Query(query)
+    }
+}
+
 /// Skip depths for ancestor list.
-const SKIP_DEPTHS: [usize; 16] = [
+pub(crate) const SKIP_DEPTHS: [usize; 16] = [
     4usize.pow(1),
     4usize.pow(2),
     4usize.pow(3),
@@ -149,21 +467,461 @@ const SKIP_DEPTHS: [usize; 16] = [
     4usize.pow(16),
 ];
 
+impl<Block: Identified + Clone> ForkTreeBest for MemoryForkTree<Block>
+where
+    Block::Identifier: Ord,
+{
+    /// The best block by the fork choice among the descendants of the
+    /// finalized block, or the one with the smallest identifier among the
+    /// equally good. Fails with [`MemoryForkTreeQueryError::UnknownBlock`] if
+    /// the tree is empty.
+    fn best_id(&self) -> Result<Block::Identifier, Self::QueryError> {
+        self.best
+            .iter()
+            .min()
+            .copied()
+            .ok_or(MemoryForkTreeQueryError::UnknownBlock)
+    }
+}
+
+impl<Block: Identified + Clone> ForkTreeFinalize for MemoryForkTree<Block>
+where
+    Block::Identifier: Ord,
+{
+    type FinalizeError = MemoryForkTreeFinalizeError;
+
+    fn finalized_id(&self) -> Option<Block::Identifier> {
+        self.finalized
+    }
+
+    /// Finalize a block. The blocks already inserted conflicting with it are
+    /// kept, until pruned with [`ForkTreePrune`].
+    fn finalize(&mut self, id: &Block::Identifier) -> Result<(), Self::FinalizeError> {
+        let best_id = self.best_id()?;
+        if self.block_depth(id)? > self.block_depth(&best_id)? || !self.is_ancestor(&best_id, id)? {
+            return Err(MemoryForkTreeFinalizeError::NotBestAncestor);
+        }
+        if let Some(finalized_id) = self.finalized {
+            if self.block_depth(id)? < self.block_depth(&finalized_id)?
+                || !self.is_ancestor(id, &finalized_id)?
+            {
+                return Err(MemoryForkTreeFinalizeError::NotFinalizedDescendant);
+            }
+        }
+
+        self.finalized = Some(*id);
+        // The best block descends from it, but the equally good ones may not.
+        let depth = self.blocks[id].depth;
+        let best = std::mem::take(&mut self.best);
+        self.best = best
+            .into_iter()
+            .filter(|best_id| {
+                self.blocks[best_id].depth >= depth
+                    && self.is_ancestor(best_id, id).unwrap_or(false)
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+impl<Block: Identified + Clone> MemoryForkTree<Block>
+where
+    Block::Identifier: Ord,
+{
+    /// Replace the fork choice, returning the route from the best block
+    /// under the old rule to the best block under the new one. None if the
+    /// tree is empty, or if the best blocks are under different genesis
+    /// blocks.
+    pub fn set_fork_choice(
+        &mut self,
+        fork_choice: ForkChoice,
+    ) -> Option<TreeRoute<Block::Identifier>> {
+        let old_best = self.best_id().ok();
+        self.fork_choice = fork_choice;
+        self.best = self.choose_best();
+        let new_best = self.best_id().ok();
+
+        old_best
+            .zip(new_best)
+            .and_then(|(old_best, new_best)| crate::tree_route(self, &old_best, &new_best).ok())
+    }
+
+    /// The current fork choice.
+    pub fn fork_choice(&self) -> ForkChoice {
+        self.fork_choice
+    }
+
+    /// Iterate the canonical chain, from the best block back to the genesis.
+    /// Empty if the tree is empty.
+    pub fn canonical_chain(&self) -> impl Iterator<Item = Block> + '_ {
+        let best_id = self.best_id().ok();
+        best_id
+            .into_iter()
+            .flat_map(move |best_id| self.ancestry(&best_id))
+    }
+}
+
 impl<Block: Identified + Clone> ForkTreeMut for MemoryForkTree<Block> {
     type InsertError = MemoryForkTreeInsertError;
 
     fn insert(&mut self, block: Block) -> Result<(), Self::InsertError> {
+        let weight = self.weight.map_or(1, |weight| weight(&block));
+        self.insert_with_weight(block, weight)
+    }
+}
+
+/// Transaction of a memory fork tree, holding the blocks inserted in it.
+#[derive(Debug, Clone)]
+pub struct MemoryForkTreeTransaction<Block: Identified> {
+    blocks: HashMap<Block::Identifier, (Block, usize)>,
+    order: Vec<Block::Identifier>,
+}
+
+impl<Block: Identified> MemoryForkTreeTransaction<Block> {
+    /// Identifiers of the blocks inserted in the transaction, in order.
+    pub(super) fn ids(&self) -> &[Block::Identifier] {
+        &self.order
+    }
+}
+
+impl<Block: Identified + Clone> ForkTreeTransactional for MemoryForkTree<Block> {
+    type Transaction = MemoryForkTreeTransaction<Block>;
+    type InsertError = MemoryForkTreeInsertError;
+
+    fn begin(&self) -> Self::Transaction {
+        MemoryForkTreeTransaction {
+            blocks: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    fn insert(
+        &self,
+        transaction: &mut Self::Transaction,
+        block: Block,
+    ) -> Result<(), Self::InsertError> {
+        if transaction.blocks.contains_key(&block.id()) {
+            return Err(MemoryForkTreeInsertError::AlreadyInserted);
+        }
+        self.check_transaction_block(transaction, &block)?;
+
+        let depth = match block.parent_id() {
+            Some(parent_id) => match transaction.blocks.get(&parent_id) {
+                Some((_, depth)) => *depth + 1,
+                None => self.blocks[&parent_id].depth + 1,
+            },
+            None => 0,
+        };
+
+        transaction.order.push(block.id());
+        transaction.blocks.insert(block.id(), (block, depth));
+        Ok(())
+    }
+
+    fn transaction_block_depth(
+        &self,
+        transaction: &Self::Transaction,
+        id: &Block::Identifier,
+    ) -> Result<usize, Self::QueryError> {
+        match transaction.blocks.get(id) {
+            Some((_, depth)) => Ok(*depth),
+            None => self.block_depth(id),
+        }
+    }
+
+    fn transaction_block(
+        &self,
+        transaction: &Self::Transaction,
+        id: &Block::Identifier,
+    ) -> Result<Block, Self::QueryError> {
+        match transaction.blocks.get(id) {
+            Some((block, _)) => Ok(block.clone()),
+            None => self.block(id),
+        }
+    }
+
+    fn transaction_parent_id(
+        &self,
+        transaction: &Self::Transaction,
+        id: &Block::Identifier,
+    ) -> Result<Option<Block::Identifier>, Self::QueryError> {
+        match transaction.blocks.get(id) {
+            Some((block, _)) => Ok(block.parent_id()),
+            None => self.parent_id(id),
+        }
+    }
+
+    /// Check the blocks again before inserting any, as the fork tree may have
+    /// changed since they were inserted in the transaction.
+    fn commit(&mut self, mut transaction: Self::Transaction) -> Result<(), Self::InsertError> {
+        for id in &transaction.order {
+            self.check_transaction_block(&transaction, &transaction.blocks[id].0)?;
+        }
+
+        for id in transaction.order {
+            if let Some((block, _)) = transaction.blocks.remove(&id) {
+                ForkTreeMut::insert(self, block)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<Block: Identified + Clone> MemoryForkTree<Block> {
+    /// Rank of a block by the fork choice, the best blocks ranking highest.
+    fn best_rank(&self, id: &Block::Identifier) -> (u128, usize) {
+        let item = &self.blocks[id];
+        match self.fork_choice {
+            ForkChoice::Longest => (0, item.depth),
+            ForkChoice::Heaviest => (item.cumulative_weight, item.depth),
+        }
+    }
+
+    /// Track an inserted block as a best one, if it ranks as high as them,
+    /// or as the only one, if it ranks higher. Inserted blocks never conflict
+    /// with the finalized block.
+    fn update_best(&mut self, id: &Block::Identifier) {
+        let Some(best_id) = self.best.first() else {
+            self.best.push(*id);
+            return;
+        };
+        match self.best_rank(id).cmp(&self.best_rank(best_id)) {
+            Ordering::Greater => self.best = vec![*id],
+            Ordering::Equal => self.best.push(*id),
+            Ordering::Less => (),
+        }
+    }
+
+    /// Choose the best blocks from scratch, such as after the last of them
+    /// is removed. A block ranks higher than its ancestors, so that only the
+    /// leaves descending from the finalized block are candidates.
+    fn choose_best(&self) -> Vec<Block::Identifier> {
+        let candidates = self
+            .leaves
+            .iter()
+            .filter(|id| match &self.finalized {
+                Some(finalized_id) => {
+                    self.blocks[*id].depth >= self.blocks[finalized_id].depth
+                        && self.is_ancestor(id, finalized_id).unwrap_or(false)
+                }
+                None => true,
+            })
+            .map(|id| (self.best_rank(id), *id))
+            .collect::<Vec<_>>();
+        let Some(best_rank) = candidates.iter().map(|(rank, _)| *rank).max() else {
+            return Vec::new();
+        };
+
+        candidates
+            .into_iter()
+            .filter(|(rank, _)| *rank == best_rank)
+            .map(|(_, id)| id)
+            .collect()
+    }
+
+    /// Check a block of a transaction against the fork tree: it must be new
+    /// to the tree, and build on a block either of the transaction or of the
+    /// tree, without conflicting with the finalized block.
+    fn check_transaction_block(
+        &self,
+        transaction: &MemoryForkTreeTransaction<Block>,
+        block: &Block,
+    ) -> Result<(), MemoryForkTreeInsertError> {
+        if self.blocks.contains_key(&block.id()) {
+            return Err(MemoryForkTreeInsertError::AlreadyInserted);
+        }
+
+        let Some(parent_id) = block.parent_id() else {
+            if self.conflicts_finalized(None)? {
+                return Err(MemoryForkTreeInsertError::ConflictsFinalized);
+            }
+            return Ok(());
+        };
+        let parent = match transaction.blocks.get(&parent_id) {
+            Some((parent, _)) => parent,
+            None => {
+                let item = self
+                    .blocks
+                    .get(&parent_id)
+                    .ok_or(MemoryForkTreeInsertError::UnknownParent)?;
+                if self.conflicts_finalized(Some(&parent_id))? {
+                    return Err(MemoryForkTreeInsertError::ConflictsFinalized);
+                }
+                &item.block
+            }
+        };
+        if let Some(key_check) = self.key_check {
+            if !key_check(block, parent) {
+                return Err(MemoryForkTreeInsertError::NonMonotonicKey);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<Block: Identified + Clone> ForkTreeRemoveLeaf for MemoryForkTree<Block> {
+    type RemoveError = MemoryForkTreeRemoveError;
+
+    fn remove_leaf(&mut self, id: &Block::Identifier) -> Result<Block, Self::RemoveError> {
+        let item = self
+            .blocks
+            .get(id)
+            .ok_or(MemoryForkTreeRemoveError::UnknownBlock)?;
+        if !item.children.is_empty() {
+            return Err(MemoryForkTreeRemoveError::NotLeaf);
+        }
+        if self.finalized == Some(*id) {
+            return Err(MemoryForkTreeRemoveError::Finalized);
+        }
+
+        let item = self.blocks.remove(id).expect("block exists; qed");
+        if let Some(ids) = self.depths.get_mut(&item.depth) {
+            ids.retain(|depth_id| depth_id != id);
+            if ids.is_empty() {
+                self.depths.remove(&item.depth);
+            }
+        }
+        if let Some(parent) = item
+            .block
+            .parent_id()
+            .and_then(|parent_id| self.blocks.get_mut(&parent_id))
+        {
+            parent.children.retain(|child_id| child_id != id);
+            if parent.children.is_empty() {
+                self.leaves.insert(parent.block.id());
+            }
+        }
+        self.leaves.remove(id);
+        if let Some(index) = self.best.iter().position(|best_id| best_id == id) {
+            self.best.swap_remove(index);
+            if self.best.is_empty() {
+                self.best = self.choose_best();
+            }
+        }
+
+        Ok(item.block)
+    }
+}
+
+impl<Block: Identified + Clone> ForkTreePrune for MemoryForkTree<Block> {
+    fn non_canonical(
+        &self,
+        finalized_id: &Block::Identifier,
+    ) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        let mut canonical = HashSet::new();
+        let mut current_id = Some(*finalized_id);
+        while let Some(id) = current_id {
+            let item = self
+                .blocks
+                .get(&id)
+                .ok_or(MemoryForkTreeQueryError::UnknownBlock)?;
+            canonical.insert(id);
+            current_id = item.block.parent_id();
+        }
+
+        let mut descendants = self.blocks[finalized_id].children.clone();
+        while let Some(id) = descendants.pop() {
+            canonical.insert(id);
+            if let Some(item) = self.blocks.get(&id) {
+                descendants.extend(item.children.iter().copied());
+            }
+        }
+
+        Ok(self
+            .blocks
+            .keys()
+            .filter(|id| !canonical.contains(*id))
+            .copied()
+            .collect())
+    }
+
+    fn prune(&mut self, ids: &[Block::Identifier]) {
+        for id in ids {
+            let Some(item) = self.blocks.remove(id) else {
+                continue;
+            };
+            if let Some(depth_ids) = self.depths.get_mut(&item.depth) {
+                depth_ids.retain(|depth_id| depth_id != id);
+                if depth_ids.is_empty() {
+                    self.depths.remove(&item.depth);
+                }
+            }
+            if let Some(parent) = item
+                .block
+                .parent_id()
+                .and_then(|parent_id| self.blocks.get_mut(&parent_id))
+            {
+                parent.children.retain(|child_id| child_id != id);
+                if parent.children.is_empty() {
+                    self.leaves.insert(parent.block.id());
+                }
+            }
+            self.leaves.remove(id);
+        }
+        self.best.retain(|best_id| !ids.contains(best_id));
+        if self.best.is_empty() {
+            self.best = self.choose_best();
+        }
+    }
+}
+
+impl<Block: Identified + Clone> ImportBlock for MemoryForkTree<Block>
+where
+    Block::Identifier: Ord,
+{
+    type Block = Block;
+    type Error = MemoryForkTreeInsertError;
+
+    fn import(&mut self, block: Block) -> Result<ImportOutcome<Block::Identifier>, Self::Error> {
+        if self.blocks.contains_key(&block.id()) {
+            return Ok(ImportOutcome::unchanged(ImportStatus::AlreadyImported));
+        }
+
+        let old_best = self.best_id().ok();
+        ForkTreeMut::insert(self, block)?;
+        Ok(ImportOutcome::imported(self, old_best)?)
+    }
+}
+
+impl<Block: Identified + Clone> MemoryForkTree<Block> {
+    /// Insert a block weighing `weight` in the chain weights, rather than by
+    /// [`MemoryForkTree::with_weights`], such as the stake of the validators
+    /// voting for it, for [`ForkChoice::Heaviest`].
+    pub fn insert_with_weight(
+        &mut self,
+        block: Block,
+        weight: u64,
+    ) -> Result<(), MemoryForkTreeInsertError> {
         let block_id = block.id();
+        if self.blocks.contains_key(&block_id) {
+            return Err(MemoryForkTreeInsertError::AlreadyInserted);
+        }
 
-        let depth = if let Some(parent_id) = block.parent_id() {
+        let parent_id = block.parent_id();
+        if parent_id.map_or(true, |parent_id| self.blocks.contains_key(&parent_id))
+            && self.conflicts_finalized(parent_id.as_ref())?
+        {
+            return Err(MemoryForkTreeInsertError::ConflictsFinalized);
+        }
+
+        let (depth, cumulative_weight) = if let Some(parent_id) = block.parent_id() {
             let parent = self
                 .blocks
                 .get_mut(&parent_id)
                 .ok_or(MemoryForkTreeInsertError::UnknownParent)?;
+            if let Some(key_check) = self.key_check {
+                if !key_check(&block, &parent.block) {
+                    return Err(MemoryForkTreeInsertError::NonMonotonicKey);
+                }
+            }
             parent.children.push(block.id());
-            parent.depth + 1
+            self.leaves.remove(&parent_id);
+            (
+                parent.depth + 1,
+                parent.cumulative_weight.saturating_add(weight.into()),
+            )
         } else {
-            0
+            (0, weight.into())
         };
 
         let ancestors = if let Some(parent_id) = block.parent_id() {
@@ -190,16 +948,185 @@ impl<Block: Identified + Clone> ForkTreeMut for MemoryForkTree<Block> {
         };
 
         self.depths.entry(depth).or_default().push(block_id);
+        self.leaves.insert(block_id);
         self.blocks.insert(
             block_id,
             MemoryForkTreeItem {
                 block,
                 depth,
+                cumulative_weight,
                 children: Vec::new(),
                 ancestors,
             },
         );
+        self.update_best(&block_id);
+
+        Ok(())
+    }
+
+    /// Remove the branches discarded by a finalized block, either the
+    /// finalized block or one of its ancestors: the blocks below its depth
+    /// that are not its ancestors, along with everything built on them. The
+    /// block, its ancestors and its descendants are kept. Returns the removed
+    /// blocks, such as to prune their state. Fails with
+    /// [`MemoryForkTreePruneError::NotFinalized`] for any other block, which
+    /// could discard the finalized chain.
+    pub fn prune_below(
+        &mut self,
+        id: &Block::Identifier,
+    ) -> Result<HashSet<Block::Identifier>, MemoryForkTreePruneError> {
+        let removed = self.pruned_below(id)?;
+        self.prune(&removed);
+        Ok(removed.into_iter().collect())
+    }
+
+    /// The blocks [`MemoryForkTree::prune_below`] removes, without removing
+    /// them.
+    pub(super) fn pruned_below(
+        &self,
+        id: &Block::Identifier,
+    ) -> Result<Vec<Block::Identifier>, MemoryForkTreePruneError> {
+        let finalized_id = self
+            .finalized
+            .ok_or(MemoryForkTreePruneError::NotFinalized)?;
+        if self.block_depth(id)? > self.block_depth(&finalized_id)?
+            || !self.is_ancestor(&finalized_id, id)?
+        {
+            return Err(MemoryForkTreePruneError::NotFinalized);
+        }
+
+        Ok(self.non_canonical(id)?)
+    }
+
+    /// Iterate the ancestry of a block, from the block itself back to the
+    /// genesis, cloning each block as it is reached. Empty if the block is
+    /// unknown.
+    pub fn ancestry(&self, id: &Block::Identifier) -> impl Iterator<Item = Block> + '_ {
+        let mut next_id = Some(*id);
+        std::iter::from_fn(move || {
+            let block = &self.blocks.get(&next_id.take()?)?.block;
+            next_id = block.parent_id();
+            Some(block.clone())
+        })
+    }
+
+    /// Insert a batch of blocks, in order.
+    ///
+    /// Stops at the first failing block. Blocks before it stay inserted.
+    pub fn insert_batch<I: IntoIterator<Item = Block>>(
+        &mut self,
+        blocks: I,
+    ) -> Result<(), MemoryForkTreeInsertError> {
+        let blocks = blocks.into_iter();
+        self.reserve(blocks.size_hint().0);
+
+        for block in blocks {
+            ForkTreeMut::insert(self, block)?;
+        }
 
         Ok(())
     }
 }
+
+impl<Block: Identified + Headered + Clone> MemoryForkTree<Block> {
+    /// Get the headers from a block down to one of its ancestors, both
+    /// included, child first. Each header is the child of the next one, so
+    /// that a light client can link a claimed block to a trusted one, such as
+    /// the finalized block.
+    pub fn header_chain(
+        &self,
+        from_id: &Block::Identifier,
+        to_ancestor_id: &Block::Identifier,
+    ) -> Result<Vec<Block::Header>, MemoryForkTreeQueryError> {
+        let from_depth = self.block_depth(from_id)?;
+        let to_depth = self.block_depth(to_ancestor_id)?;
+        if to_depth > from_depth || self.ancestor_id_at_depth(from_id, to_depth)? != *to_ancestor_id
+        {
+            return Err(MemoryForkTreeQueryError::NotAncestor);
+        }
+
+        let mut headers = Vec::with_capacity(from_depth - to_depth + 1);
+        let mut current_id = *from_id;
+        loop {
+            let block = &self
+                .blocks
+                .get(&current_id)
+                .ok_or(MemoryForkTreeQueryError::UnknownBlock)?
+                .block;
+            headers.push(block.header());
+
+            if current_id == *to_ancestor_id {
+                return Ok(headers);
+            }
+            current_id = block
+                .parent_id()
+                .ok_or(MemoryForkTreeQueryError::NotAncestor)?;
+        }
+    }
+}
+
+impl<Block: Identified> MemoryForkTree<Block>
+where
+    Block::Identifier: Debug,
+{
+    /// Export the fork tree as a Graphviz DOT graph, for debugging, with its
+    /// finalized block and its best blocks highlighted, all of the equally
+    /// good ones, as identifiers may have no order to pick one by.
+    pub fn to_dot(&self) -> String {
+        self.dot(|id| self.best.contains(id), self.finalized.as_ref())
+    }
+
+    /// Export the fork tree as a Graphviz DOT graph, with the given best and
+    /// finalized blocks highlighted, such as those of another fork choice.
+    ///
+    /// Nodes are labeled by identifier and depth, and edges go from parent to
+    /// child. The output is deterministic for a given insertion order.
+    pub fn to_dot_highlighted(
+        &self,
+        best: Option<&Block::Identifier>,
+        finalized: Option<&Block::Identifier>,
+    ) -> String {
+        self.dot(|id| best == Some(id), finalized)
+    }
+
+    /// Export the fork tree as a Graphviz DOT graph, with the blocks matching
+    /// `is_best` and the finalized block highlighted.
+    fn dot(
+        &self,
+        is_best: impl Fn(&Block::Identifier) -> bool,
+        finalized: Option<&Block::Identifier>,
+    ) -> String {
+        fn escape<Id: Debug>(id: &Id) -> String {
+            format!("{:?}", id)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+        }
+
+        let mut dot = String::from("digraph ForkTree {\n");
+        let ids = self
+            .depths
+            .iter()
+            .sorted_by_key(|(depth, _)| **depth)
+            .flat_map(|(_, ids)| ids);
+
+        for id in ids.clone() {
+            let mut attributes = format!("label=\"{}\\n#{}\"", escape(id), self.blocks[id].depth);
+            if is_best(id) {
+                attributes.push_str(", color=red, penwidth=2");
+            }
+            if finalized == Some(id) {
+                attributes.push_str(", style=filled, fillcolor=lightgrey");
+            }
+            let _ = writeln!(dot, "  \"{}\" [{}];", escape(id), attributes);
+        }
+
+        for id in ids {
+            for child_id in &self.blocks[id].children {
+                let _ = writeln!(dot, "  \"{}\" -> \"{}\";", escape(id), escape(child_id));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
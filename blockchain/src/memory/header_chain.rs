@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use super::{MemoryForkTree, MemoryForkTreeInsertError};
+use crate::{
+    ForkTreeBest, ForkTreeDepths, ForkTreeMut, Headered, Identified, ImportBlock, ImportHeader,
+    ImportOutcome, ImportStatus,
+};
+
+/// Import error for memory header chain.
+#[derive(Debug, Clone)]
+pub enum MemoryHeaderChainError {
+    /// Inserting the header into the header tree failed.
+    Insert(MemoryForkTreeInsertError),
+    /// The header derived from the body does not match the imported header.
+    HeaderMismatch,
+}
+
+impl From<MemoryForkTreeInsertError> for MemoryHeaderChainError {
+    fn from(insert: MemoryForkTreeInsertError) -> MemoryHeaderChainError {
+        MemoryHeaderChainError::Insert(insert)
+    }
+}
+
+/// A header-first chain that resides entirely in memory, such as for a light
+/// client. Headers are imported into a header tree, and the bodies of the
+/// blocks are imported later, if at all.
+#[derive(Clone)]
+pub struct MemoryHeaderChain<Block: Headered>
+where
+    Block::Header: Identified,
+{
+    headers: MemoryForkTree<Block::Header>,
+    bodies: HashMap<<Block::Header as Identified>::Identifier, Block>,
+}
+
+impl<Block> MemoryHeaderChain<Block>
+where
+    Block: Identified + Headered + Clone,
+    Block::Header: Identified<Identifier = Block::Identifier> + Clone + PartialEq,
+{
+    /// Create a new header chain.
+    pub fn new() -> Self {
+        Self {
+            headers: MemoryForkTree::new(),
+            bodies: HashMap::new(),
+        }
+    }
+
+    /// The header tree, with both the headers imported alone and the headers
+    /// of the imported blocks.
+    pub fn headers(&self) -> &MemoryForkTree<Block::Header> {
+        &self.headers
+    }
+
+    /// Get an imported block, if its body was imported.
+    pub fn block(&self, id: &Block::Identifier) -> Option<&Block> {
+        self.bodies.get(id)
+    }
+
+    /// Identifiers of the imported headers whose body is not yet imported,
+    /// shallowest first.
+    pub fn missing_bodies(&self) -> Vec<Block::Identifier> {
+        let mut missing = Vec::new();
+        let mut depth = 0;
+        while let Ok(ids) = self.headers.blocks_at_depth(depth) {
+            if ids.is_empty() {
+                break;
+            }
+            missing.extend(ids.into_iter().filter(|id| !self.bodies.contains_key(id)));
+            depth += 1;
+        }
+        missing
+    }
+}
+
+impl<Block> Default for MemoryHeaderChain<Block>
+where
+    Block: Identified + Headered + Clone,
+    Block::Header: Identified<Identifier = Block::Identifier> + Clone + PartialEq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Block> ImportHeader for MemoryHeaderChain<Block>
+where
+    Block: Identified + Headered + Clone,
+    Block::Header: Identified<Identifier = Block::Identifier> + Clone + PartialEq,
+    Block::Identifier: Ord,
+{
+    type Header = Block::Header;
+    type Error = MemoryHeaderChainError;
+
+    fn import_header(&mut self, header: Block::Header) -> Result<(), Self::Error> {
+        Ok(self.headers.insert(header)?)
+    }
+}
+
+impl<Block> ImportBlock for MemoryHeaderChain<Block>
+where
+    Block: Identified + Headered + Clone,
+    Block::Header: Identified<Identifier = Block::Identifier> + Clone + PartialEq,
+    Block::Identifier: Ord,
+{
+    type Block = Block;
+    type Error = MemoryHeaderChainError;
+
+    /// Import a block. If its header is already imported, the body is
+    /// reconciled with it: the header derived from the block must match,
+    /// and the best block is unchanged. Otherwise, the derived header is
+    /// imported along with the body.
+    fn import(&mut self, block: Block) -> Result<ImportOutcome<Block::Identifier>, Self::Error> {
+        let header = block.header();
+        let outcome = match self
+            .headers
+            .with_block(&block.id(), |imported| *imported == header)
+        {
+            Ok(false) => return Err(MemoryHeaderChainError::HeaderMismatch),
+            Ok(true) if self.bodies.contains_key(&block.id()) => {
+                return Ok(ImportOutcome::unchanged(ImportStatus::AlreadyImported))
+            }
+            Ok(true) => ImportOutcome::unchanged(ImportStatus::Imported),
+            Err(_) => {
+                let old_best = self.headers.best_id().ok();
+                self.headers.insert(header)?;
+                ImportOutcome::imported(&self.headers, old_best)
+                    .map_err(MemoryForkTreeInsertError::from)?
+            }
+        };
+
+        self.bodies.insert(block.id(), block);
+        Ok(outcome)
+    }
+}
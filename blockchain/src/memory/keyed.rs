@@ -0,0 +1,357 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{
+    MemoryForkTree, MemoryForkTreeFinalizeError, MemoryForkTreeInsertError,
+    MemoryForkTreePruneError, MemoryForkTreeQueryError, MemoryForkTreeRemoveError,
+    MemoryForkTreeTransaction,
+};
+use crate::{
+    ForkTree, ForkTreeBest, ForkTreeDepths, ForkTreeFinalize, ForkTreeMut, ForkTreePrune,
+    ForkTreeRemoveLeaf, ForkTreeTransactional, Identified, ImportBlock, ImportOutcome,
+    ImportStatus, Keyed,
+};
+
+/// A memory fork tree indexing its blocks by their key of type `K`, such as
+/// their number, for [`KeyedMemoryForkTree::block_by_key`].
+///
+/// The index is kept up to date on every change going through the wrapper,
+/// so the inner tree is only lent immutably, with
+/// [`KeyedMemoryForkTree::tree`].
+#[derive(Debug, Clone)]
+pub struct KeyedMemoryForkTree<Block: Identified, K> {
+    tree: MemoryForkTree<Block>,
+    keys: HashMap<K, Vec<Block::Identifier>>,
+}
+
+impl<Block, K> KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K>,
+    K: Hash + Eq,
+{
+    /// Index the blocks of the fork tree by their key, such as a tree built
+    /// with [`MemoryForkTree::with_weights`], or a deserialized one.
+    pub fn new(tree: MemoryForkTree<Block>) -> Self {
+        let mut keys = HashMap::<K, Vec<_>>::new();
+        for id in tree.blocks_in_depth_range(0, usize::MAX) {
+            let key = tree
+                .with_block(&id, |block| block.key())
+                .expect("block is in the tree; qed");
+            keys.entry(key).or_default().push(id);
+        }
+
+        Self { tree, keys }
+    }
+
+    /// The indexed fork tree.
+    pub fn tree(&self) -> &MemoryForkTree<Block> {
+        &self.tree
+    }
+
+    /// The indexed fork tree, without its index.
+    pub fn into_inner(self) -> MemoryForkTree<Block> {
+        self.tree
+    }
+
+    /// Identifiers of the blocks with the key, one per fork at most for a
+    /// key such as the number, in insertion order.
+    pub fn block_by_key(&self, key: &K) -> Vec<Block::Identifier> {
+        self.keys.get(key).cloned().unwrap_or_default()
+    }
+
+    fn index(&mut self, key: K, id: Block::Identifier) {
+        self.keys.entry(key).or_default().push(id);
+    }
+
+    fn unindex(&mut self, key: K, id: &Block::Identifier) {
+        if let Some(key_ids) = self.keys.get_mut(&key) {
+            key_ids.retain(|key_id| key_id != id);
+            if key_ids.is_empty() {
+                self.keys.remove(&key);
+            }
+        }
+    }
+
+    /// Reserve space for at least `additional` more blocks, in the tree as
+    /// well as in the key index.
+    pub fn reserve(&mut self, additional: usize) {
+        self.tree.reserve(additional);
+        self.keys.reserve(additional);
+    }
+}
+
+impl<Block, K> KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K> + Clone,
+    K: Hash + Eq,
+{
+    /// Insert a block weighing `weight`, see
+    /// [`MemoryForkTree::insert_with_weight`].
+    pub fn insert_with_weight(
+        &mut self,
+        block: Block,
+        weight: u64,
+    ) -> Result<(), MemoryForkTreeInsertError> {
+        let (key, id) = (block.key(), block.id());
+        self.tree.insert_with_weight(block, weight)?;
+        self.index(key, id);
+        Ok(())
+    }
+
+    /// Insert a batch of blocks, in order.
+    ///
+    /// Stops at the first failing block. Blocks before it stay inserted.
+    pub fn insert_batch<I: IntoIterator<Item = Block>>(
+        &mut self,
+        blocks: I,
+    ) -> Result<(), MemoryForkTreeInsertError> {
+        let blocks = blocks.into_iter();
+        self.reserve(blocks.size_hint().0);
+
+        for block in blocks {
+            ForkTreeMut::insert(self, block)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the branches discarded by a finalized block, see
+    /// [`MemoryForkTree::prune_below`].
+    pub fn prune_below(
+        &mut self,
+        id: &Block::Identifier,
+    ) -> Result<HashSet<Block::Identifier>, MemoryForkTreePruneError> {
+        let removed = self.tree.pruned_below(id)?;
+        self.prune(&removed);
+        Ok(removed.into_iter().collect())
+    }
+}
+
+impl<Block, K> Default for KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K>,
+    K: Hash + Eq,
+{
+    fn default() -> Self {
+        Self::new(MemoryForkTree::new())
+    }
+}
+
+impl<Block: Identified + Clone, K> ForkTree for KeyedMemoryForkTree<Block, K> {
+    type Block = Block;
+    type QueryError = MemoryForkTreeQueryError;
+
+    fn block(&self, id: &Block::Identifier) -> Result<Block, Self::QueryError> {
+        self.tree.block(id)
+    }
+
+    fn parent_id(
+        &self,
+        id: &Block::Identifier,
+    ) -> Result<Option<Block::Identifier>, Self::QueryError> {
+        self.tree.parent_id(id)
+    }
+
+    fn block_depth(&self, id: &Block::Identifier) -> Result<usize, Self::QueryError> {
+        self.tree.block_depth(id)
+    }
+
+    fn ancestor_id_at_depth(
+        &self,
+        id: &Block::Identifier,
+        ancestor_depth: usize,
+    ) -> Result<Block::Identifier, Self::QueryError> {
+        self.tree.ancestor_id_at_depth(id, ancestor_depth)
+    }
+}
+
+impl<Block: Identified + Clone, K> ForkTreeDepths for KeyedMemoryForkTree<Block, K> {
+    fn blocks_at_depth(&self, depth: usize) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        self.tree.blocks_at_depth(depth)
+    }
+
+    fn leaves(&self) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        self.tree.leaves()
+    }
+}
+
+impl<Block: Identified + Clone, K> ForkTreeBest for KeyedMemoryForkTree<Block, K>
+where
+    Block::Identifier: Ord,
+{
+    fn best_id(&self) -> Result<Block::Identifier, Self::QueryError> {
+        self.tree.best_id()
+    }
+}
+
+impl<Block: Identified + Clone, K> ForkTreeFinalize for KeyedMemoryForkTree<Block, K>
+where
+    Block::Identifier: Ord,
+{
+    type FinalizeError = MemoryForkTreeFinalizeError;
+
+    fn finalized_id(&self) -> Option<Block::Identifier> {
+        self.tree.finalized_id()
+    }
+
+    fn finalize(&mut self, id: &Block::Identifier) -> Result<(), Self::FinalizeError> {
+        self.tree.finalize(id)
+    }
+}
+
+impl<Block, K> ForkTreeMut for KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K> + Clone,
+    K: Hash + Eq,
+{
+    type InsertError = MemoryForkTreeInsertError;
+
+    fn insert(&mut self, block: Block) -> Result<(), Self::InsertError> {
+        let (key, id) = (block.key(), block.id());
+        ForkTreeMut::insert(&mut self.tree, block)?;
+        self.index(key, id);
+        Ok(())
+    }
+}
+
+impl<Block, K> ForkTreeTransactional for KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K> + Clone,
+    K: Hash + Eq,
+{
+    type Transaction = MemoryForkTreeTransaction<Block>;
+    type InsertError = MemoryForkTreeInsertError;
+
+    fn begin(&self) -> Self::Transaction {
+        self.tree.begin()
+    }
+
+    fn insert(
+        &self,
+        transaction: &mut Self::Transaction,
+        block: Block,
+    ) -> Result<(), Self::InsertError> {
+        ForkTreeTransactional::insert(&self.tree, transaction, block)
+    }
+
+    fn transaction_block_depth(
+        &self,
+        transaction: &Self::Transaction,
+        id: &Block::Identifier,
+    ) -> Result<usize, Self::QueryError> {
+        self.tree.transaction_block_depth(transaction, id)
+    }
+
+    fn transaction_block(
+        &self,
+        transaction: &Self::Transaction,
+        id: &Block::Identifier,
+    ) -> Result<Block, Self::QueryError> {
+        self.tree.transaction_block(transaction, id)
+    }
+
+    fn transaction_parent_id(
+        &self,
+        transaction: &Self::Transaction,
+        id: &Block::Identifier,
+    ) -> Result<Option<Block::Identifier>, Self::QueryError> {
+        self.tree.transaction_parent_id(transaction, id)
+    }
+
+    /// Index the blocks of the transaction that made it into the tree, all
+    /// of them unless committing fails.
+    fn commit(&mut self, transaction: Self::Transaction) -> Result<(), Self::InsertError> {
+        let ids = transaction.ids().to_vec();
+        let result = self.tree.commit(transaction);
+        for id in ids {
+            if let Ok(key) = self.tree.with_block(&id, |block| block.key()) {
+                self.index(key, id);
+            }
+        }
+        result
+    }
+}
+
+impl<Block, K> ForkTreeRemoveLeaf for KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K> + Clone,
+    K: Hash + Eq,
+{
+    type RemoveError = MemoryForkTreeRemoveError;
+
+    fn remove_leaf(&mut self, id: &Block::Identifier) -> Result<Block, Self::RemoveError> {
+        let block = self.tree.remove_leaf(id)?;
+        self.unindex(block.key(), id);
+        Ok(block)
+    }
+}
+
+impl<Block, K> ForkTreePrune for KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K> + Clone,
+    K: Hash + Eq,
+{
+    fn non_canonical(
+        &self,
+        finalized_id: &Block::Identifier,
+    ) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        self.tree.non_canonical(finalized_id)
+    }
+
+    fn prune(&mut self, ids: &[Block::Identifier]) {
+        for id in ids {
+            if let Ok(key) = self.tree.with_block(id, |block| block.key()) {
+                self.unindex(key, id);
+            }
+        }
+        self.tree.prune(ids);
+    }
+}
+
+impl<Block, K> ImportBlock for KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K> + Clone,
+    Block::Identifier: Ord,
+    K: Hash + Eq,
+{
+    type Block = Block;
+    type Error = MemoryForkTreeInsertError;
+
+    fn import(&mut self, block: Block) -> Result<ImportOutcome<Block::Identifier>, Self::Error> {
+        let (key, id) = (block.key(), block.id());
+        let outcome = self.tree.import(block)?;
+        if outcome.status == ImportStatus::Imported {
+            self.index(key, id);
+        }
+        Ok(outcome)
+    }
+}
+
+/// Serialized as the inner tree, whose index is built again on deserializing.
+#[cfg(feature = "serde")]
+impl<Block, K> Serialize for KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Serialize,
+    Block::Identifier: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.tree.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Block, K> Deserialize<'de> for KeyedMemoryForkTree<Block, K>
+where
+    Block: Identified + Keyed<K> + Clone + Deserialize<'de>,
+    Block::Identifier: Deserialize<'de>,
+    K: Hash + Eq,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::new(MemoryForkTree::deserialize(deserializer)?))
+    }
+}
@@ -1,10 +1,23 @@
 //! Memory-only implementations.
 
 mod chain;
+mod header_chain;
+mod keyed;
 mod state;
 
-pub use self::chain::{MemoryForkTree, MemoryForkTreeInsertError, MemoryForkTreeQueryError};
-pub use self::state::MemoryFlatState;
+#[cfg(feature = "sled")]
+pub(crate) use self::chain::SKIP_DEPTHS;
+pub use self::chain::{
+    ForkChoice, MemoryForkTree, MemoryForkTreeFinalizeError, MemoryForkTreeInsertError,
+    MemoryForkTreePruneError, MemoryForkTreeQueryError, MemoryForkTreeRemoveError,
+    MemoryForkTreeTransaction,
+};
+pub use self::header_chain::{MemoryHeaderChain, MemoryHeaderChainError};
+pub use self::keyed::KeyedMemoryForkTree;
+pub use self::state::{
+    MemoryFlatState, MemoryFlatStateApplyError, MemoryFlatStatePruning, MemoryFlatStateQueryError,
+    MemoryFlatStateTransaction,
+};
 
 use core::ops::{Deref, DerefMut};
 
@@ -12,9 +25,10 @@ use core::ops::{Deref, DerefMut};
 ///
 /// The struct `MemoryTransactional` allows memory-only implementations (such as
 /// memory fork tree and memory state) to be transactional. We do this by
-/// keeping two copies of the state. When an operation happens, we try to apply
-/// it to both. If either fails, the operation halts immediately and the other
-/// state is copied back.
+/// keeping two copies of the state: the committed one, and a scratch copy.
+/// When an operation happens, it is applied once to the scratch copy, which
+/// becomes the committed state only if the operation succeeds. The committed
+/// state is therefore never observed half-applied.
 #[derive(Debug, Clone)]
 pub struct MemoryTransactional<Inner: Clone> {
     first: Inner,
@@ -31,20 +45,23 @@ impl<Inner: Clone> MemoryTransactional<Inner> {
     }
 
     /// Apply some changes.
-    pub fn apply<R, E, F: Fn(&mut Inner) -> Result<R, E>>(&mut self, f: F) -> Result<R, E> {
-        match f(&mut self.first) {
-            Ok(first_ret) => match f(&mut self.second) {
-                Ok(_second_ret) => Ok(first_ret),
-                Err(_second_err) => {
-                    self.second = self.first.clone();
-                    Ok(first_ret)
-                }
-            },
-            Err(first_err) => {
-                self.first = self.second.clone();
-                Err(first_err)
-            }
-        }
+    pub fn apply<R, E, F: FnOnce(&mut Inner) -> Result<R, E>>(&mut self, f: F) -> Result<R, E> {
+        // The committed state may have been changed through `DerefMut`.
+        self.second.clone_from(&self.first);
+
+        let ret = f(&mut self.second)?;
+        core::mem::swap(&mut self.first, &mut self.second);
+        Ok(ret)
+    }
+
+    /// Get a copy of the committed state.
+    pub fn snapshot(&self) -> Inner {
+        self.first.clone()
+    }
+
+    /// Replace the committed state, such as with an earlier snapshot.
+    pub fn restore(&mut self, inner: Inner) {
+        self.first = inner;
     }
 }
 
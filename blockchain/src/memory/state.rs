@@ -1,13 +1,85 @@
+use core::fmt;
 use core::hash::Hash;
 use core::ops::Bound;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 
-use crate::{FlatState, FlatStateMut, ForkTree, Identified};
+use crate::state::diff_entries;
+use crate::{
+    FlatState, FlatStateEntries, FlatStateMut, FlatStatePrune, FlatStateTransactional, ForkTree,
+    ForkTreeTransactional, Identified,
+};
 
 /// A flat state that is stored in memory.
 #[derive(Debug, Clone)]
 pub struct MemoryFlatState<K, V, Identifier> {
     state: HashMap<K, BTreeMap<usize, HashMap<Identifier, Option<V>>>>,
+    max_fork_scan_depth: Option<usize>,
+    /// Loader of the value of a key at a block, when no ancestor held in
+    /// memory set it.
+    on_missing_ancestor: Option<MissingAncestorLoader<K, V, Identifier>>,
+    /// Depth and id of the finalized block the state was last pruned at.
+    /// The changes up to it are compacted into its state, which its
+    /// ancestors then read too, and only its descendants can still be read
+    /// above its depth.
+    pruned_below: Option<(usize, Identifier)>,
+    /// Whether applying at a block requires the state of its parent.
+    strict_parents: bool,
+    /// Blocks whose state was applied, if strict about parents.
+    applied: HashSet<Identifier>,
+}
+
+/// Loader of [`MemoryFlatState::with_missing_ancestor_loader`], shared by the
+/// clones of the state.
+#[allow(clippy::type_complexity)]
+struct MissingAncestorLoader<K, V, Identifier>(
+    Arc<dyn Fn(&K, &Identifier) -> Option<V> + Send + Sync>,
+);
+
+impl<K, V, Identifier> Clone for MissingAncestorLoader<K, V, Identifier> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K, V, Identifier> fmt::Debug for MissingAncestorLoader<K, V, Identifier> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MissingAncestorLoader")
+    }
+}
+
+/// Query error for memory flat state.
+#[derive(Debug, Clone)]
+pub enum MemoryFlatStateQueryError<E> {
+    /// The fork tree query failed.
+    ForkTree(E),
+    /// The read skipped over more changes on sibling forks than allowed.
+    ScanLimitExceeded,
+    /// The block is on a fork pruned off the finalized block the state was
+    /// pruned at, so that its state is gone.
+    Pruned,
+}
+
+impl<E> From<E> for MemoryFlatStateQueryError<E> {
+    fn from(err: E) -> MemoryFlatStateQueryError<E> {
+        MemoryFlatStateQueryError::ForkTree(err)
+    }
+}
+
+/// Apply error for memory flat state.
+#[derive(Debug, Clone)]
+pub enum MemoryFlatStateApplyError<E> {
+    /// The fork tree query failed.
+    ForkTree(E),
+    /// The state of the parent block was not applied, with
+    /// [`MemoryFlatState::with_strict_parents`].
+    ParentStateMissing,
+}
+
+impl<E> From<E> for MemoryFlatStateApplyError<E> {
+    fn from(err: E) -> MemoryFlatStateApplyError<E> {
+        MemoryFlatStateApplyError::ForkTree(err)
+    }
 }
 
 impl<K, V, Identifier> MemoryFlatState<K, V, Identifier>
@@ -20,8 +92,132 @@ where
     pub fn new() -> Self {
         Self {
             state: HashMap::new(),
+            max_fork_scan_depth: None,
+            on_missing_ancestor: None,
+            pruned_below: None,
+            strict_parents: false,
+            applied: HashSet::new(),
         }
     }
+
+    /// Limit the number of changes on sibling forks a read may skip over to
+    /// find the last change of a key on the ancestor chain, before failing
+    /// with [`MemoryFlatStateQueryError::ScanLimitExceeded`]. Ancestors
+    /// without changes to the key are not counted, so that a key unchanged
+    /// for long is read as usual without forks. Defaults to unlimited.
+    pub fn with_max_fork_scan_depth(mut self, max_fork_scan_depth: usize) -> Self {
+        self.max_fork_scan_depth = Some(max_fork_scan_depth);
+        self
+    }
+
+    /// Load the value of a key at a block from elsewhere, such as for a
+    /// fetch-on-demand backend, when no ancestor held in memory set it. By
+    /// default, the key is then unset. Only reads of a key consult the
+    /// loader, not [`FlatState::entries`]. The loader may hold its own
+    /// state, such as a handle to the backend. Pruning then keeps the
+    /// canonical deletions up to the finalized block, so that a deleted key
+    /// is not loaded back.
+    pub fn with_missing_ancestor_loader<F>(mut self, on_missing_ancestor: F) -> Self
+    where
+        F: Fn(&K, &Identifier) -> Option<V> + Send + Sync + 'static,
+    {
+        self.on_missing_ancestor = Some(MissingAncestorLoader(Arc::new(on_missing_ancestor)));
+        self
+    }
+
+    /// Fail applying at a block whose parent state was not applied, with
+    /// [`MemoryFlatStateApplyError::ParentStateMissing`], rather than have
+    /// its reads skip over the missing changes, such as to catch a sync
+    /// applying out of order. Genesis blocks need no parent state. Must be
+    /// set before applying, which then keeps track of the blocks applied,
+    /// including by committed transactions.
+    pub fn with_strict_parents(mut self) -> Self {
+        self.strict_parents = true;
+        self
+    }
+
+    /// Number of changes stored, across all keys and blocks.
+    pub fn changes(&self) -> usize {
+        self.state
+            .values()
+            .flat_map(|depth_to_id_value| depth_to_id_value.values())
+            .map(HashMap::len)
+            .sum()
+    }
+
+    /// The changeset applied at the block: the keys written by the block
+    /// itself, with their value or `None` for a deletion, but none of the
+    /// keys inherited from its ancestors. Sorted by key. Only the changes at
+    /// the depth of the block are looked at. After a prune, the ancestors of
+    /// the finalized block have none, as they read its state.
+    #[allow(clippy::type_complexity)]
+    pub fn changes_at<FT, B>(
+        &self,
+        block_id: &Identifier,
+        fork_tree: &FT,
+    ) -> Result<Vec<(K, Option<V>)>, MemoryFlatStateQueryError<FT::QueryError>>
+    where
+        K: Clone + Ord,
+        FT: ForkTree<Block = B>,
+        B: Identified<Identifier = Identifier>,
+    {
+        let (depth, read_id) = self.read_at(block_id, fork_tree)?;
+        if read_id != *block_id {
+            return Ok(Vec::new());
+        }
+
+        let mut changes = self
+            .state
+            .iter()
+            .filter_map(|(key, depth_to_id_value)| {
+                depth_to_id_value
+                    .get(&depth)?
+                    .get(block_id)
+                    .map(|value| (key.clone(), value.clone()))
+            })
+            .collect::<Vec<_>>();
+        changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(changes)
+    }
+
+    /// Depth and id of the block whose state a read at the block sees. Once
+    /// pruned, the canonical blocks up to the finalized one read the state
+    /// it was compacted into, and blocks not on its chain fail, at any depth.
+    fn read_at<FT, B>(
+        &self,
+        block_id: &Identifier,
+        fork_tree: &FT,
+    ) -> Result<(usize, Identifier), MemoryFlatStateQueryError<FT::QueryError>>
+    where
+        FT: ForkTree<Block = B>,
+        B: Identified<Identifier = Identifier>,
+    {
+        let depth = fork_tree.block_depth(block_id)?;
+        let Some((finalized_depth, finalized_id)) = &self.pruned_below else {
+            return Ok((depth, block_id.clone()));
+        };
+
+        if depth <= *finalized_depth {
+            if fork_tree.ancestor_id_at_depth(finalized_id, depth)? == *block_id {
+                return Ok((*finalized_depth, finalized_id.clone()));
+            }
+        } else if fork_tree.ancestor_id_at_depth(block_id, *finalized_depth)? == *finalized_id {
+            return Ok((depth, block_id.clone()));
+        }
+
+        Err(MemoryFlatStateQueryError::Pruned)
+    }
+}
+
+impl<K, V, Identifier> Default for MemoryFlatState<K, V, Identifier>
+where
+    K: Eq + PartialEq + Hash,
+    V: Clone,
+    Identifier: Eq + PartialEq + Hash + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<K, V, Identifier, FT, B> FlatState<FT> for MemoryFlatState<K, V, Identifier>
@@ -34,7 +230,7 @@ where
 {
     type Key = K;
     type Value = V;
-    type QueryError = FT::QueryError;
+    type QueryError = MemoryFlatStateQueryError<FT::QueryError>;
 
     fn get(
         &self,
@@ -42,22 +238,113 @@ where
         block_id: &<FT::Block as Identified>::Identifier,
         fork_tree: &FT,
     ) -> Result<Option<Self::Value>, Self::QueryError> {
+        // Blocks on pruned forks are gone rather than stale.
+        let (depth, block_id) = self.read_at(block_id, fork_tree)?;
+
         if let Some(depth_to_id_value) = self.state.get(key) {
-            let depth = fork_tree.block_depth(block_id)?;
             let search_range = depth_to_id_value
                 .range((Bound::Unbounded, Bound::Included(depth)))
                 .rev();
 
-            for (_, search_id_to_value) in search_range {
-                for (search_id, search_value) in search_id_to_value {
-                    if fork_tree.is_ancestor(block_id, search_id)? {
-                        return Ok(search_value.clone());
-                    }
+            // Each depth with changes to the key is checked against the
+            // ancestor of the block at that depth. The changes of a depth
+            // without one of the ancestor are all on sibling forks.
+            let mut skipped = 0;
+            for (search_depth, search_id_to_value) in search_range {
+                let ancestor_id = fork_tree
+                    .ancestor_id_at_depth(&block_id, *search_depth)
+                    .map_err(MemoryFlatStateQueryError::ForkTree)?;
+                if let Some(search_value) = search_id_to_value.get(&ancestor_id) {
+                    return Ok(search_value.clone());
+                }
+
+                skipped += search_id_to_value.len();
+                if self.max_fork_scan_depth.map_or(false, |max| skipped > max) {
+                    return Err(MemoryFlatStateQueryError::ScanLimitExceeded);
                 }
             }
         }
 
-        Ok(None)
+        Ok(self
+            .on_missing_ancestor
+            .as_ref()
+            .and_then(|on_missing_ancestor| (on_missing_ancestor.0)(key, &block_id)))
+    }
+}
+
+impl<K, V, Identifier, FT, B> FlatStateEntries<FT> for MemoryFlatState<K, V, Identifier>
+where
+    K: Eq + PartialEq + Hash,
+    V: Clone,
+    Identifier: Eq + PartialEq + Hash + Clone,
+    FT: ForkTree<Block = B>,
+    B: Identified<Identifier = Identifier>,
+{
+    #[allow(clippy::type_complexity)]
+    fn entries(
+        &self,
+        block_id: &<FT::Block as Identified>::Identifier,
+        fork_tree: &FT,
+    ) -> Result<Vec<(Self::Key, Self::Value)>, Self::QueryError>
+    where
+        Self::Key: Clone,
+    {
+        let mut entries = Vec::new();
+        for key in self.state.keys() {
+            if let Some(value) = self.get(key, block_id, fork_tree)? {
+                entries.push((key.clone(), value));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Between a block and its parent, in either order, only the keys
+    /// changed at the child are read at the parent, rather than comparing
+    /// all the entries of both.
+    #[allow(clippy::type_complexity)]
+    fn diff(
+        &self,
+        from: &<FT::Block as Identified>::Identifier,
+        to: &<FT::Block as Identified>::Identifier,
+        fork_tree: &FT,
+    ) -> Result<Vec<(Self::Key, Option<Self::Value>, Option<Self::Value>)>, Self::QueryError>
+    where
+        Self::Key: Clone + Ord,
+        Self::Value: PartialEq,
+    {
+        let (from_depth, to_depth) = (fork_tree.block_depth(from)?, fork_tree.block_depth(to)?);
+        let (parent, child) = if to_depth == from_depth + 1 {
+            (from, to)
+        } else if from_depth == to_depth + 1 {
+            (to, from)
+        } else {
+            return Ok(diff_entries(
+                self.entries(from, fork_tree)?,
+                self.entries(to, fork_tree)?,
+            ));
+        };
+        if fork_tree.ancestor_id_at_depth(child, fork_tree.block_depth(parent)?)? != *parent {
+            return Ok(diff_entries(
+                self.entries(from, fork_tree)?,
+                self.entries(to, fork_tree)?,
+            ));
+        }
+
+        let mut diff = Vec::new();
+        for (key, child_value) in self.changes_at(child, fork_tree)? {
+            let parent_value = self.get(&key, parent, fork_tree)?;
+            if parent_value == child_value {
+                continue;
+            }
+            diff.push(if child == to {
+                (key, parent_value, child_value)
+            } else {
+                (key, child_value, parent_value)
+            });
+        }
+
+        Ok(diff)
     }
 }
 
@@ -69,7 +356,7 @@ where
     FT: ForkTree<Block = B>,
     B: Identified<Identifier = Identifier>,
 {
-    type ApplyError = FT::QueryError;
+    type ApplyError = MemoryFlatStateApplyError<FT::QueryError>;
 
     fn apply<I: Iterator<Item = (Self::Key, Option<Self::Value>)>>(
         &mut self,
@@ -78,6 +365,14 @@ where
         fork_tree: &FT,
     ) -> Result<(), Self::ApplyError> {
         let depth = fork_tree.block_depth(&block_id)?;
+        if self.strict_parents {
+            if let Some(parent_id) = fork_tree.parent_id(&block_id)? {
+                if !self.applied.contains(&parent_id) {
+                    return Err(MemoryFlatStateApplyError::ParentStateMissing);
+                }
+            }
+            self.applied.insert(block_id.clone());
+        }
 
         for (key, value) in changeset {
             self.state
@@ -91,3 +386,163 @@ where
         Ok(())
     }
 }
+
+/// Planned pruning of a memory flat state, holding the changes to remove.
+#[derive(Debug, Clone)]
+pub struct MemoryFlatStatePruning<K, Identifier> {
+    removals: Vec<(K, usize, Identifier)>,
+    finalized: (usize, Identifier),
+    pruned: Vec<Identifier>,
+}
+
+impl<K, V, Identifier, FT, B> FlatStatePrune<FT> for MemoryFlatState<K, V, Identifier>
+where
+    K: Eq + PartialEq + Hash + Clone,
+    V: Clone,
+    Identifier: Eq + PartialEq + Hash + Clone,
+    FT: ForkTree<Block = B>,
+    B: Identified<Identifier = Identifier>,
+{
+    type Pruning = MemoryFlatStatePruning<K, Identifier>;
+
+    fn plan_prune(
+        &self,
+        finalized_id: &Identifier,
+        pruned_ids: &[Identifier],
+        fork_tree: &FT,
+    ) -> Result<Self::Pruning, Self::QueryError> {
+        let finalized_depth = fork_tree.block_depth(finalized_id)?;
+        let pruned_ids = pruned_ids.iter().collect::<HashSet<_>>();
+        let mut canonical = HashSet::new();
+        let mut current_id = Some(finalized_id.clone());
+        while let Some(id) = current_id {
+            current_id = fork_tree.parent_id(&id)?;
+            canonical.insert(id);
+        }
+
+        // Without a loader, a key with no change is unset, so that a deletion
+        // need not be kept. With one, it is kept, so that the deleted key is
+        // not loaded back.
+        let keep_deletions = self.on_missing_ancestor.is_some();
+        let mut removals = Vec::new();
+        for (key, depth_to_id_value) in &self.state {
+            // Up to the finalized block, only the latest canonical change is
+            // visible from the blocks kept.
+            let mut visible = true;
+            for (depth, id_to_value) in depth_to_id_value.iter().rev() {
+                for (id, value) in id_to_value {
+                    let removed = if *depth > finalized_depth {
+                        pruned_ids.contains(id)
+                    } else if canonical.contains(id) {
+                        let removed = !visible || (value.is_none() && !keep_deletions);
+                        visible = false;
+                        removed
+                    } else {
+                        true
+                    };
+
+                    if removed {
+                        removals.push((key.clone(), *depth, id.clone()));
+                    }
+                }
+            }
+        }
+
+        Ok(MemoryFlatStatePruning {
+            removals,
+            finalized: (finalized_depth, finalized_id.clone()),
+            pruned: pruned_ids.into_iter().cloned().collect(),
+        })
+    }
+
+    fn prune(&mut self, pruning: Self::Pruning) {
+        self.pruned_below = Some(pruning.finalized);
+        for id in &pruning.pruned {
+            self.applied.remove(id);
+        }
+        for (key, depth, id) in pruning.removals {
+            let Some(depth_to_id_value) = self.state.get_mut(&key) else {
+                continue;
+            };
+            if let Some(id_to_value) = depth_to_id_value.get_mut(&depth) {
+                id_to_value.remove(&id);
+                if id_to_value.is_empty() {
+                    depth_to_id_value.remove(&depth);
+                }
+            }
+            if depth_to_id_value.is_empty() {
+                self.state.remove(&key);
+            }
+        }
+    }
+}
+
+/// Transaction of a memory flat state, holding the changes applied in it.
+#[derive(Debug, Clone)]
+pub struct MemoryFlatStateTransaction<K, V, Identifier> {
+    changes: Vec<(K, usize, Identifier, Option<V>)>,
+    blocks: Vec<Identifier>,
+}
+
+impl<K, V, Identifier, FT, B> FlatStateTransactional<FT> for MemoryFlatState<K, V, Identifier>
+where
+    K: Eq + PartialEq + Hash,
+    V: Clone,
+    Identifier: Eq + PartialEq + Hash + Clone,
+    FT: ForkTreeTransactional<Block = B>,
+    B: Identified<Identifier = Identifier>,
+{
+    type Transaction = MemoryFlatStateTransaction<K, V, Identifier>;
+    type ApplyError = MemoryFlatStateApplyError<FT::QueryError>;
+
+    fn begin(&self) -> Self::Transaction {
+        MemoryFlatStateTransaction {
+            changes: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn apply<I: Iterator<Item = (Self::Key, Option<Self::Value>)>>(
+        &self,
+        transaction: &mut Self::Transaction,
+        changeset: I,
+        block_id: &<FT::Block as Identified>::Identifier,
+        fork_tree: &FT,
+        fork_tree_transaction: &FT::Transaction,
+    ) -> Result<(), Self::ApplyError> {
+        let depth = fork_tree.transaction_block_depth(fork_tree_transaction, block_id)?;
+        if self.strict_parents {
+            // The parent state may also be applied earlier in the transaction.
+            if let Some(parent_id) =
+                fork_tree.transaction_parent_id(fork_tree_transaction, block_id)?
+            {
+                if !self.applied.contains(&parent_id) && !transaction.blocks.contains(&parent_id) {
+                    return Err(MemoryFlatStateApplyError::ParentStateMissing);
+                }
+            }
+        }
+        transaction.blocks.push(block_id.clone());
+
+        for (key, value) in changeset {
+            transaction
+                .changes
+                .push((key, depth, block_id.clone(), value));
+        }
+
+        Ok(())
+    }
+
+    fn commit(&mut self, transaction: Self::Transaction) {
+        if self.strict_parents {
+            self.applied.extend(transaction.blocks);
+        }
+        for (key, depth, block_id, value) in transaction.changes {
+            self.state
+                .entry(key)
+                .or_default()
+                .entry(depth)
+                .or_default()
+                .insert(block_id, value);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use crate::{FlatStateEntries, ForkTree, Identified};
+
+/// Hashing of a binary Merkle tree over key-value pairs.
+pub trait Merkleizer<Key, Value> {
+    /// Hash a key-value pair into a leaf.
+    fn leaf(&self, key: &Key, value: &Value) -> [u8; 32];
+    /// Hash two child nodes into their parent.
+    fn node(&self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32];
+    /// Hash the odd node out of a level, without a sibling, into its parent.
+    ///
+    /// Leaves, nodes and odd nodes must hash under distinct domains, such as
+    /// distinct prefixes, so that no tree has the root of another one whose
+    /// leaves are its inner nodes.
+    fn odd(&self, node: &[u8; 32]) -> [u8; 32];
+
+    /// Root of the tree over the leaves, in order. Nodes are paired level
+    /// by level, and the odd node out of a level is hashed alone with
+    /// [`Merkleizer::odd`], rather than carried up as is, or paired with
+    /// itself, either of which gives trees of different leaves the same
+    /// root. The root of no leaves is all zeros.
+    fn root(&self, leaves: Vec<[u8; 32]>) -> [u8; 32] {
+        let mut level = leaves;
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => self.node(left, right),
+                    [odd] => self.odd(odd),
+                    _ => unreachable!("chunks are of one or two nodes; qed"),
+                })
+                .collect();
+        }
+
+        level.pop().unwrap_or([0; 32])
+    }
+}
+
+/// Cache of state roots, by block id.
+///
+/// The state of a block is assumed not to change once applied, so its root is
+/// only computed once. The cache does not see the flat state change: a block
+/// whose state is applied again, such as on a retried import, must be
+/// [`removed`](Self::remove) first. Opt-in, and separate from the flat state,
+/// so that states not needing roots do not pay for them.
+#[derive(Debug, Clone)]
+pub struct StateRootCache<Id> {
+    roots: HashMap<Id, [u8; 32]>,
+}
+
+impl<Id> Default for StateRootCache<Id> {
+    fn default() -> Self {
+        Self {
+            roots: HashMap::new(),
+        }
+    }
+}
+
+impl<Id: Clone + Copy + Eq + core::hash::Hash> StateRootCache<Id> {
+    /// Create a new empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the state root of the block, computing it if not cached.
+    pub fn state_root<FS, FT, M>(
+        &mut self,
+        flat_state: &FS,
+        block_id: &Id,
+        fork_tree: &FT,
+        merkleizer: &M,
+    ) -> Result<[u8; 32], FS::QueryError>
+    where
+        FT: ForkTree,
+        FT::Block: Identified<Identifier = Id>,
+        FS: FlatStateEntries<FT>,
+        FS::Key: Clone + Ord,
+        M: Merkleizer<FS::Key, FS::Value>,
+    {
+        if let Some(root) = self.roots.get(block_id) {
+            return Ok(*root);
+        }
+
+        let root = flat_state.state_root(block_id, fork_tree, merkleizer)?;
+        self.roots.insert(*block_id, root);
+        Ok(root)
+    }
+
+    /// The cached state root of the block.
+    pub fn get(&self, block_id: &Id) -> Option<[u8; 32]> {
+        self.roots.get(block_id).copied()
+    }
+
+    /// Forget the state root of the block, such as once it is pruned.
+    pub fn remove(&mut self, block_id: &Id) -> Option<[u8; 32]> {
+        self.roots.remove(block_id)
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    ForkTree, ForkTreeBest, ForkTreeDepths, Identified, ImportBlock, ImportOutcome, ImportStatus,
+};
+
+/// A pool of orphan blocks in front of an importer.
+///
+/// Blocks whose parent is not yet known are buffered instead of being
+/// imported. Once the parent is imported, the buffered children are replayed,
+/// recursively. The pool is bounded, and when it is full the oldest orphan is
+/// evicted.
+#[derive(Debug, Clone)]
+pub struct OrphanPool<Import, Block: Identified> {
+    inner: Import,
+    capacity: usize,
+    orphans: HashMap<Block::Identifier, Block>,
+    children: HashMap<Block::Identifier, Vec<Block::Identifier>>,
+    order: VecDeque<Block::Identifier>,
+}
+
+impl<Import, Block: Identified> OrphanPool<Import, Block> {
+    /// Create a new orphan pool buffering at most `capacity` orphans.
+    pub fn new(inner: Import, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            orphans: HashMap::new(),
+            children: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// The wrapped importer.
+    pub fn inner(&self) -> &Import {
+        &self.inner
+    }
+
+    /// Take the wrapped importer, dropping all buffered orphans.
+    pub fn into_inner(self) -> Import {
+        self.inner
+    }
+
+    /// Number of buffered orphans.
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Whether no orphan is buffered.
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    /// Whether the block is buffered as an orphan.
+    pub fn contains(&self, id: &Block::Identifier) -> bool {
+        self.orphans.contains_key(id)
+    }
+
+    fn buffer(&mut self, parent_id: Block::Identifier, block: Block) {
+        let id = block.id();
+        if self.orphans.contains_key(&id) {
+            return;
+        }
+
+        if self.orphans.len() >= self.capacity {
+            match self.order.pop_front() {
+                Some(oldest_id) => self.evict(&oldest_id),
+                None => return,
+            }
+        }
+
+        self.orphans.insert(id, block);
+        self.children.entry(parent_id).or_default().push(id);
+        self.order.push_back(id);
+    }
+
+    fn evict(&mut self, id: &Block::Identifier) {
+        if let Some(block) = self.orphans.remove(id) {
+            if let Some(parent_id) = block.parent_id() {
+                if let Some(siblings) = self.children.get_mut(&parent_id) {
+                    siblings.retain(|sibling_id| sibling_id != id);
+                    if siblings.is_empty() {
+                        self.children.remove(&parent_id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn take(&mut self, id: &Block::Identifier) -> Option<Block> {
+        let block = self.orphans.remove(id)?;
+        self.order.retain(|order_id| order_id != id);
+        Some(block)
+    }
+}
+
+/// The fork tree of the wrapped importer, without the buffered orphans.
+impl<Import, Block> ForkTree for OrphanPool<Import, Block>
+where
+    Import: ForkTree<Block = Block>,
+    Block: Identified,
+{
+    type Block = Block;
+    type QueryError = Import::QueryError;
+
+    fn block(&self, id: &Block::Identifier) -> Result<Block, Self::QueryError> {
+        self.inner.block(id)
+    }
+
+    fn block_depth(&self, id: &Block::Identifier) -> Result<usize, Self::QueryError> {
+        self.inner.block_depth(id)
+    }
+
+    fn ancestor_id_at_depth(
+        &self,
+        id: &Block::Identifier,
+        ancestor_depth: usize,
+    ) -> Result<Block::Identifier, Self::QueryError> {
+        self.inner.ancestor_id_at_depth(id, ancestor_depth)
+    }
+
+    fn is_ancestor(
+        &self,
+        id: &Block::Identifier,
+        ancestor_id: &Block::Identifier,
+    ) -> Result<bool, Self::QueryError> {
+        self.inner.is_ancestor(id, ancestor_id)
+    }
+}
+
+impl<Import, Block> ForkTreeDepths for OrphanPool<Import, Block>
+where
+    Import: ForkTreeDepths<Block = Block>,
+    Block: Identified,
+{
+    fn blocks_at_depth(&self, depth: usize) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        self.inner.blocks_at_depth(depth)
+    }
+
+    fn leaves(&self) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        self.inner.leaves()
+    }
+}
+
+impl<Import, Block> ForkTreeBest for OrphanPool<Import, Block>
+where
+    Import: ForkTreeBest<Block = Block>,
+    Block: Identified,
+{
+    fn best_id(&self) -> Result<Block::Identifier, Self::QueryError> {
+        self.inner.best_id()
+    }
+}
+
+impl<Import, Block> ImportBlock for OrphanPool<Import, Block>
+where
+    Import: ImportBlock<Block = Block> + ForkTreeBest<Block = Block>,
+    <Import as ImportBlock>::Error: From<Import::QueryError>,
+    Block: Identified,
+{
+    type Block = Block;
+    type Error = <Import as ImportBlock>::Error;
+
+    /// Import a block, or buffer it until its parent is imported, with
+    /// [`ImportStatus::Queued`]. A buffered block counts as imported, so
+    /// that buffering it again is [`ImportStatus::AlreadyImported`]. The
+    /// outcome of an import releasing buffered children covers them all,
+    /// from the best block before to the best block after. Importing an
+    /// already imported block releases its buffered children left over, such
+    /// as after one of them failed to import.
+    fn import(&mut self, block: Block) -> Result<ImportOutcome<Block::Identifier>, Self::Error> {
+        if self.orphans.contains_key(&block.id()) {
+            return Ok(ImportOutcome::unchanged(ImportStatus::AlreadyImported));
+        }
+
+        if let Some(parent_id) = block.parent_id() {
+            if self.capacity > 0 && self.inner.block(&parent_id).is_err() {
+                self.buffer(parent_id, block);
+                return Ok(ImportOutcome::unchanged(ImportStatus::Queued));
+            }
+        }
+
+        let id = block.id();
+        let old_best = self.inner.best_id().ok();
+        let outcome = self.inner.import(block)?;
+        if outcome.status == ImportStatus::Queued || !self.children.contains_key(&id) {
+            return Ok(outcome);
+        }
+
+        let mut imported = VecDeque::from([id]);
+        while let Some(parent_id) = imported.pop_front() {
+            let mut children = self.children.remove(&parent_id).unwrap_or_default();
+
+            while let Some(child_id) = children.pop() {
+                let Some(child) = self.take(&child_id) else {
+                    continue;
+                };
+
+                if let Err(err) = self.inner.import(child) {
+                    // Keep the remaining siblings for a later retry.
+                    if !children.is_empty() {
+                        self.children.insert(parent_id, children);
+                    }
+                    return Err(err);
+                }
+
+                imported.push_back(child_id);
+            }
+        }
+
+        Ok(ImportOutcome::imported(&self.inner, old_best)?)
+    }
+}
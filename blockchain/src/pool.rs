@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use crate::{Bodied, ForkTree, Identified, Keyed, TreeRoute};
+
+/// Whether a block producer authors a block with no extrinsic to include,
+/// to keep the chain advancing, or skips its slot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptyBlockPolicy {
+    /// Author a block on every slot, empty or not.
+    #[default]
+    AlwaysAuthor,
+    /// Author nothing when there is no extrinsic.
+    SkipIfEmpty,
+    /// Author an empty block only on the slots that are a multiple of the
+    /// interval, so that the chain still advances at that pace. An interval
+    /// of 0 never authors empty blocks.
+    AuthorEveryNSlots(u64),
+}
+
+impl EmptyBlockPolicy {
+    /// Whether to author a block on the slot, with `extrinsics` extrinsics
+    /// to include.
+    pub fn should_author(&self, slot: u64, extrinsics: usize) -> bool {
+        if extrinsics > 0 {
+            return true;
+        }
+
+        match self {
+            EmptyBlockPolicy::AlwaysAuthor => true,
+            EmptyBlockPolicy::SkipIfEmpty => false,
+            EmptyBlockPolicy::AuthorEveryNSlots(interval) => slot.checked_rem(*interval) == Some(0),
+        }
+    }
+}
+
+/// A fork-aware pool of pending extrinsics.
+///
+/// The pool maintains a ready set valid against the current best block.
+/// When the best block changes, extrinsics of enacted blocks are pruned, and
+/// extrinsics of retracted blocks are re-queued. Extrinsics are deduplicated
+/// by their hash.
+#[derive(Debug, Clone)]
+pub struct Pool<Extrinsic, Hash> {
+    ready: HashMap<Hash, Extrinsic>,
+    order: Vec<Hash>,
+}
+
+impl<Extrinsic, Hash> Default for Pool<Extrinsic, Hash> {
+    fn default() -> Self {
+        Self {
+            ready: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+}
+
+impl<Extrinsic, Hash> Pool<Extrinsic, Hash>
+where
+    Extrinsic: Keyed<Hash>,
+    Hash: Clone + Eq + core::hash::Hash,
+{
+    /// Create a new empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of ready extrinsics.
+    pub fn len(&self) -> usize {
+        self.ready.len()
+    }
+
+    /// Whether no extrinsic is ready.
+    pub fn is_empty(&self) -> bool {
+        self.ready.is_empty()
+    }
+
+    /// Whether the extrinsic is ready.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.ready.contains_key(hash)
+    }
+
+    /// Ready extrinsics, in submission order.
+    pub fn ready(&self) -> impl Iterator<Item = &Extrinsic> {
+        self.order.iter().filter_map(|hash| self.ready.get(hash))
+    }
+
+    /// Submit an extrinsic. Returns false if it is already in the pool.
+    pub fn submit(&mut self, extrinsic: Extrinsic) -> bool {
+        let hash = extrinsic.key();
+        if self.ready.contains_key(&hash) {
+            return false;
+        }
+
+        self.order.push(hash.clone());
+        self.ready.insert(hash, extrinsic);
+        true
+    }
+
+    /// Whether to author a block on the slot from the ready extrinsics, by
+    /// the policy for empty blocks.
+    pub fn should_author(&self, policy: EmptyBlockPolicy, slot: u64) -> bool {
+        policy.should_author(slot, self.len())
+    }
+
+    /// Remove an extrinsic from the pool.
+    pub fn remove(&mut self, hash: &Hash) -> Option<Extrinsic> {
+        let extrinsic = self.ready.remove(hash)?;
+        self.order.retain(|order_hash| order_hash != hash);
+        Some(extrinsic)
+    }
+
+    /// Update the pool to a change of the best block. Extrinsics of retracted
+    /// blocks are re-queued, unless an enacted block includes them too, and
+    /// extrinsics of enacted blocks are pruned.
+    pub fn handle_route<F>(
+        &mut self,
+        fork_tree: &F,
+        route: &TreeRoute<<F::Block as Identified>::Identifier>,
+    ) -> Result<(), F::QueryError>
+    where
+        F: ForkTree,
+        F::Block: Bodied<Extrinsic = Extrinsic>,
+    {
+        for id in &route.retracted {
+            for extrinsic in fork_tree.block(id)?.extrinsics() {
+                self.submit(extrinsic);
+            }
+        }
+
+        for id in &route.enacted {
+            for extrinsic in fork_tree.block(id)?.extrinsics() {
+                self.remove(&extrinsic.key());
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use futures::{channel::mpsc, stream::Stream};
+
+use crate::{
+    Finality, FinalityNotification, FinalizeError, FlatStateEntries, FlatStatePrune,
+    ForkTreeFinalize, ForkTreePrune, Identified,
+};
+
+/// Change of a key by a finalized block, against its parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateChange<Id, K, V> {
+    /// The finalized block.
+    pub block_id: Id,
+    /// The changed key.
+    pub key: K,
+    /// Value at the parent, `None` if unset.
+    pub old: Option<V>,
+    /// Value at the block, `None` if deleted.
+    pub new: Option<V>,
+}
+
+type ChainStateChange<FT, FS> = StateChange<
+    <<FT as crate::ForkTree>::Block as Identified>::Identifier,
+    <FS as crate::FlatState<FT>>::Key,
+    <FS as crate::FlatState<FT>>::Value,
+>;
+
+/// Error finalizing a block of a [`Chain`].
+#[derive(Debug, Clone)]
+pub enum ChainFinalizeError<QueryError, StateQueryError, ForkTreeFinalizeError> {
+    /// The block does not descend from the finalized block, or planning to
+    /// prune the fork tree failed.
+    Finalize(FinalizeError<QueryError>),
+    /// Planning to prune the flat state failed.
+    State(StateQueryError),
+    /// The fork tree refused to finalize the block, such as one that is not
+    /// an ancestor of its best block.
+    ForkTree(ForkTreeFinalizeError),
+}
+
+/// A fork tree and its flat state, pruned as blocks are finalized, so that
+/// memory stays bounded. The finalized block is the one of the fork tree, and
+/// finality is notified through a [`Finality`].
+pub struct Chain<FT, FS>
+where
+    FT: ForkTreePrune + ForkTreeFinalize,
+    FS: FlatStatePrune<FT>,
+{
+    fork_tree: FT,
+    state: FS,
+    finality: Finality<<FT::Block as Identified>::Identifier>,
+    state_change_subscribers: Vec<mpsc::UnboundedSender<ChainStateChange<FT, FS>>>,
+}
+
+impl<FT, FS> Chain<FT, FS>
+where
+    FT: ForkTreePrune + ForkTreeFinalize,
+    FS: FlatStatePrune<FT>,
+{
+    /// Create a new chain, finalized up to the finalized block of the fork
+    /// tree, if any, such as genesis.
+    pub fn new(fork_tree: FT, state: FS) -> Self {
+        Self {
+            fork_tree,
+            state,
+            finality: Finality::new(),
+            state_change_subscribers: Vec::new(),
+        }
+    }
+
+    /// The fork tree.
+    pub fn fork_tree(&self) -> &FT {
+        &self.fork_tree
+    }
+
+    /// The fork tree, such as to insert blocks.
+    pub fn fork_tree_mut(&mut self) -> &mut FT {
+        &mut self.fork_tree
+    }
+
+    /// The flat state.
+    pub fn state(&self) -> &FS {
+        &self.state
+    }
+
+    /// The flat state, such as to apply changesets.
+    pub fn state_mut(&mut self) -> &mut FS {
+        &mut self.state
+    }
+
+    /// Subscribe to notifications of blocks finalized from now on.
+    pub fn finality_notifications(
+        &mut self,
+    ) -> impl Stream<Item = FinalityNotification<<FT::Block as Identified>::Identifier>> {
+        self.finality.finality_notifications()
+    }
+
+    /// Subscribe to the state changes of the blocks finalized from now on,
+    /// such as for an indexer. Each newly finalized block yields its diff
+    /// against its parent, blocks in order, including the blocks in between
+    /// when finalization jumps several blocks ahead.
+    ///
+    /// The diffs are taken on finalization, before the changes below the
+    /// finalized block are pruned.
+    pub fn finalized_state_changes(&mut self) -> impl Stream<Item = ChainStateChange<FT, FS>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.state_change_subscribers.push(sender);
+        receiver
+    }
+
+    /// Finalize a descendant of the finalized block in the fork tree, prune
+    /// the forks competing with it from the fork tree, and prune their
+    /// changes along with the changes below it from the flat state.
+    ///
+    /// All three are planned before any is changed, so that on error none is.
+    #[allow(clippy::type_complexity)]
+    pub fn finalize(
+        &mut self,
+        id: &<FT::Block as Identified>::Identifier,
+    ) -> Result<
+        FinalityNotification<<FT::Block as Identified>::Identifier>,
+        ChainFinalizeError<FT::QueryError, FS::QueryError, FT::FinalizeError>,
+    >
+    where
+        FS: FlatStateEntries<FT>,
+        FS::Key: Clone + Ord,
+        FS::Value: Clone + PartialEq,
+    {
+        let old_finalized = self.fork_tree.finalized_id();
+        let newly_finalized =
+            Finality::newly_finalized(&self.fork_tree, id).map_err(ChainFinalizeError::Finalize)?;
+        self.state_change_subscribers
+            .retain(|subscriber| !subscriber.is_closed());
+        let mut state_changes = Vec::new();
+        if !self.state_change_subscribers.is_empty() {
+            // Without a block finalized before, the genesis is diffed against
+            // the empty state of no block.
+            let mut parent_id = old_finalized;
+            for block_id in &newly_finalized {
+                let diff = match parent_id {
+                    Some(parent_id) => self.state.diff(&parent_id, block_id, &self.fork_tree),
+                    None => self
+                        .state
+                        .snapshot(block_id, &self.fork_tree)
+                        .map(|entries| {
+                            entries
+                                .into_iter()
+                                .map(|(key, value)| (key, None, Some(value)))
+                                .collect()
+                        }),
+                }
+                .map_err(ChainFinalizeError::State)?;
+                state_changes.extend(diff.into_iter().map(|(key, old, new)| StateChange {
+                    block_id: *block_id,
+                    key,
+                    old,
+                    new,
+                }));
+                parent_id = Some(*block_id);
+            }
+        }
+        let pruned_ids = self
+            .fork_tree
+            .non_canonical(id)
+            .map_err(|err| ChainFinalizeError::Finalize(FinalizeError::Query(err)))?;
+        let state_pruning = self
+            .state
+            .plan_prune(id, &pruned_ids, &self.fork_tree)
+            .map_err(ChainFinalizeError::State)?;
+        // Finalizing in the fork tree checks the block last, and changes
+        // nothing if it fails.
+        self.fork_tree
+            .finalize(id)
+            .map_err(ChainFinalizeError::ForkTree)?;
+
+        self.state.prune(state_pruning);
+        self.fork_tree.prune(&pruned_ids);
+        for state_change in state_changes {
+            self.state_change_subscribers
+                .retain(|subscriber| subscriber.unbounded_send(state_change.clone()).is_ok());
+        }
+        Ok(self.finality.notify(*id, newly_finalized))
+    }
+
+    /// Get back the fork tree and the flat state.
+    pub fn into_inner(self) -> (FT, FS) {
+        (self.fork_tree, self.state)
+    }
+}
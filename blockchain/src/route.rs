@@ -0,0 +1,87 @@
+use crate::{ForkTree, Identified};
+
+/// Route between two blocks of a fork tree, such as the old and the new best
+/// block of a reorg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeRoute<Id> {
+    /// The common ancestor of both blocks.
+    pub common: Id,
+    /// Blocks leaving the route, from the old block down to the common
+    /// ancestor (exclusive).
+    pub retracted: Vec<Id>,
+    /// Blocks entering the route, from the common ancestor (exclusive) up to
+    /// the new block.
+    pub enacted: Vec<Id>,
+}
+
+impl<Id> TreeRoute<Id> {
+    /// Whether any block leaves the route. That is, the route is a reorg
+    /// rather than an extension.
+    pub fn is_reorg(&self) -> bool {
+        !self.retracted.is_empty()
+    }
+}
+
+/// Error of finding a tree route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeRouteError<E> {
+    /// The blocks descend from different genesis blocks, so no route joins
+    /// them.
+    NoCommonAncestor,
+    /// Querying the fork tree failed.
+    Query(E),
+}
+
+impl<E> From<E> for TreeRouteError<E> {
+    fn from(query: E) -> TreeRouteError<E> {
+        TreeRouteError::Query(query)
+    }
+}
+
+/// Find the route from block `from` to block `to`. Fails with
+/// [`TreeRouteError::NoCommonAncestor`] if they are under different genesis
+/// blocks.
+pub fn tree_route<F: ForkTree>(
+    fork_tree: &F,
+    from: &<F::Block as Identified>::Identifier,
+    to: &<F::Block as Identified>::Identifier,
+) -> Result<TreeRoute<<F::Block as Identified>::Identifier>, TreeRouteError<F::QueryError>> {
+    let mut from_depth = fork_tree.block_depth(from)?;
+    let mut to_depth = fork_tree.block_depth(to)?;
+    let mut from = *from;
+    let mut to = *to;
+
+    let mut retracted = Vec::new();
+    let mut enacted = Vec::new();
+
+    while from_depth > to_depth {
+        retracted.push(from);
+        from_depth -= 1;
+        from = fork_tree.ancestor_id_at_depth(&from, from_depth)?;
+    }
+
+    while to_depth > from_depth {
+        enacted.push(to);
+        to_depth -= 1;
+        to = fork_tree.ancestor_id_at_depth(&to, to_depth)?;
+    }
+
+    while from != to {
+        if from_depth == 0 {
+            return Err(TreeRouteError::NoCommonAncestor);
+        }
+        retracted.push(from);
+        enacted.push(to);
+        from_depth -= 1;
+        from = fork_tree.ancestor_id_at_depth(&from, from_depth)?;
+        to = fork_tree.ancestor_id_at_depth(&to, from_depth)?;
+    }
+
+    enacted.reverse();
+
+    Ok(TreeRoute {
+        common: from,
+        retracted,
+        enacted,
+    })
+}
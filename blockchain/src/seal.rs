@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::{ForkTreeDepths, ForkTreeRemoveLeaf, Identified};
+
+/// Verdict of a deferred seal verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealVerdict {
+    /// The seal is valid.
+    Valid,
+    /// The seal is invalid.
+    Invalid,
+}
+
+/// Resolution of a seal-pending block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SealResolution<Id, Source> {
+    /// The block was not seal-pending.
+    NotPending,
+    /// The seal is valid, and the block stays.
+    Confirmed,
+    /// The seal is invalid, and the block was removed along with its
+    /// descendants. The source announced the block, and should be penalized
+    /// by the network.
+    Rejected {
+        /// Source of the block, such as the announcing peer.
+        source: Source,
+        /// Descendants removed along with the block, from the deepest. Those
+        /// still seal-pending are no longer, and their sources are not
+        /// penalized, as they may not have known the block was invalid.
+        removed: Vec<Id>,
+    },
+}
+
+/// Error resolving a seal-pending block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SealResolveError<QueryError, RemoveError> {
+    /// Finding the descendants of the block failed.
+    Query(QueryError),
+    /// Removing the block or a descendant failed.
+    Remove(RemoveError),
+}
+
+/// Blocks imported before their seal is verified, such as when signature
+/// verification is offloaded to a pool.
+///
+/// Each seal-pending block is tracked with its source. Once verification
+/// completes, the block is either confirmed, or removed from the fork tree
+/// with its source handed back for penalizing.
+#[derive(Debug, Clone)]
+pub struct PendingSeals<Id, Source> {
+    pending: HashMap<Id, Source>,
+}
+
+impl<Id, Source> Default for PendingSeals<Id, Source> {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<Id: Eq + core::hash::Hash, Source> PendingSeals<Id, Source> {
+    /// Create a new empty tracker.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of seal-pending blocks.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether no block is seal-pending.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Whether the block is seal-pending.
+    pub fn is_pending(&self, id: &Id) -> bool {
+        self.pending.contains_key(id)
+    }
+
+    /// Mark an imported block as seal-pending, received from `source`.
+    pub fn mark_pending(&mut self, id: Id, source: Source) {
+        self.pending.insert(id, source);
+    }
+
+    /// Resolve a seal-pending block, once verification completes. An invalid
+    /// block is removed from the fork tree, along with its descendants. If
+    /// removal fails, the block stays seal-pending, while the descendants
+    /// removed until then stay removed.
+    #[allow(clippy::type_complexity)]
+    pub fn resolve<F>(
+        &mut self,
+        fork_tree: &mut F,
+        id: &Id,
+        verdict: SealVerdict,
+    ) -> Result<SealResolution<Id, Source>, SealResolveError<F::QueryError, F::RemoveError>>
+    where
+        Id: Copy,
+        F: ForkTreeRemoveLeaf + ForkTreeDepths,
+        F::Block: Identified<Identifier = Id>,
+    {
+        if !self.pending.contains_key(id) {
+            return Ok(SealResolution::NotPending);
+        }
+
+        let mut removed = Vec::new();
+        if verdict == SealVerdict::Invalid {
+            removed = descendants(fork_tree, id).map_err(SealResolveError::Query)?;
+            removed.reverse();
+            for descendant_id in &removed {
+                fork_tree
+                    .remove_leaf(descendant_id)
+                    .map_err(SealResolveError::Remove)?;
+                self.pending.remove(descendant_id);
+            }
+            fork_tree
+                .remove_leaf(id)
+                .map_err(SealResolveError::Remove)?;
+        }
+
+        let source = self.pending.remove(id).expect("block is pending; qed");
+        Ok(match verdict {
+            SealVerdict::Valid => SealResolution::Confirmed,
+            SealVerdict::Invalid => SealResolution::Rejected { source, removed },
+        })
+    }
+}
+
+/// Descendants of the block, depth by depth.
+fn descendants<F, Id>(fork_tree: &F, id: &Id) -> Result<Vec<Id>, F::QueryError>
+where
+    F: ForkTreeDepths,
+    F::Block: Identified<Identifier = Id>,
+    Id: Copy + Eq,
+{
+    let mut descendants = Vec::new();
+    let mut parents = vec![*id];
+    let mut depth = fork_tree.block_depth(id)?;
+    while !parents.is_empty() {
+        depth += 1;
+        let mut children = Vec::new();
+        for child_id in fork_tree.blocks_at_depth(depth)? {
+            let parent_id = fork_tree.parent_id(&child_id)?;
+            if parent_id.is_some_and(|parent_id| parents.contains(&parent_id)) {
+                children.push(child_id);
+            }
+        }
+        descendants.extend(children.iter().copied());
+        parents = children;
+    }
+
+    Ok(descendants)
+}
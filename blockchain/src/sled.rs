@@ -0,0 +1,416 @@
+//! Fork tree persisted in a sled database, so that the chain survives a
+//! restart.
+
+use std::marker::PhantomData;
+
+use ::sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Db, Transactional, Tree,
+};
+use itertools::Itertools;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    memory::SKIP_DEPTHS, ForkTree, ForkTreeBest, ForkTreeDepths, ForkTreeMut,
+    ForkTreeTransactional, Identified,
+};
+
+/// Metadata key of the best block.
+const BEST_KEY: &[u8] = b"best";
+
+/// Error of a sled fork tree.
+#[derive(Debug)]
+pub enum SledForkTreeError {
+    /// The database failed.
+    Sled(::sled::Error),
+    /// A block or an identifier failed to (de)serialize.
+    Codec(serde_json::Error),
+    /// Block is unknown.
+    UnknownBlock,
+    /// Ancestor depth provided is greater than current block depth.
+    InvalidAncestorDepth,
+    /// Parent is unknown.
+    UnknownParent,
+    /// Block is already inserted.
+    AlreadyInserted,
+}
+
+impl From<::sled::Error> for SledForkTreeError {
+    fn from(err: ::sled::Error) -> SledForkTreeError {
+        SledForkTreeError::Sled(err)
+    }
+}
+
+impl From<serde_json::Error> for SledForkTreeError {
+    fn from(err: serde_json::Error) -> SledForkTreeError {
+        SledForkTreeError::Codec(err)
+    }
+}
+
+/// A block as stored, with its depth and a skip list of its ancestors, as in
+/// the memory fork tree.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "Block: Serialize, Block::Identifier: Serialize",
+    deserialize = "Block: Deserialize<'de>, Block::Identifier: Deserialize<'de>"
+))]
+struct StoredBlock<Block: Identified> {
+    depth: usize,
+    ancestors: Vec<(usize, Block::Identifier)>,
+    block: Block,
+}
+
+impl<Block: Identified> StoredBlock<Block> {
+    /// The next block on the way down to the ancestor at the depth, as far
+    /// as the skip list reaches, or None if the block is that ancestor.
+    fn towards(
+        &self,
+        ancestor_depth: usize,
+    ) -> Result<Option<Block::Identifier>, SledForkTreeError> {
+        if self.depth < ancestor_depth {
+            return Err(SledForkTreeError::InvalidAncestorDepth);
+        }
+        if self.depth == ancestor_depth {
+            return Ok(None);
+        }
+
+        self.ancestors
+            .iter()
+            .filter(|(depth, _)| *depth >= ancestor_depth)
+            .min_by_key(|(depth, _)| *depth)
+            .map(|(_, id)| *id)
+            .or_else(|| self.block.parent_id())
+            .map(Some)
+            .ok_or(SledForkTreeError::InvalidAncestorDepth)
+    }
+}
+
+/// A fork tree stored in a sled database.
+///
+/// Blocks are serialized with their depth and skip list of ancestors under
+/// their identifier, an index maps each depth to the ids at it, and a
+/// metadata key holds the best block, the deepest one or the one with the
+/// smallest identifier among the deepest.
+pub struct SledForkTree<Block> {
+    blocks: Tree,
+    depths: Tree,
+    metadata: Tree,
+    _marker: PhantomData<Block>,
+}
+
+impl<Block> SledForkTree<Block>
+where
+    Block: Identified + Serialize + DeserializeOwned,
+    Block::Identifier: Serialize + DeserializeOwned,
+{
+    /// Open the fork tree of the database, empty if it was never written.
+    pub fn open(db: &Db) -> Result<Self, SledForkTreeError> {
+        Ok(Self {
+            blocks: db.open_tree("fork_tree/blocks")?,
+            depths: db.open_tree("fork_tree/depths")?,
+            metadata: db.open_tree("fork_tree/metadata")?,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Flush the inserted blocks to disk, returning once they are durable.
+    pub fn flush(&self) -> Result<(), SledForkTreeError> {
+        for tree in [&self.blocks, &self.depths, &self.metadata] {
+            tree.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Get a block with its depth and ancestors.
+    fn stored(&self, id: &Block::Identifier) -> Result<StoredBlock<Block>, SledForkTreeError> {
+        let stored = self
+            .blocks
+            .get(serde_json::to_vec(id)?)?
+            .ok_or(SledForkTreeError::UnknownBlock)?;
+        Ok(serde_json::from_slice(&stored)?)
+    }
+
+    /// Get the best block with its depth, if any block is inserted.
+    fn best(&self) -> Result<Option<(usize, Block::Identifier)>, SledForkTreeError> {
+        self.metadata
+            .get(BEST_KEY)?
+            .map(|best| Ok(serde_json::from_slice(&best)?))
+            .transpose()
+    }
+
+    /// Get the ancestor at the depth of either a staged block or one of the
+    /// fork tree, following the skip lists.
+    fn staged_ancestor_id_at_depth(
+        &self,
+        staged: &[StoredBlock<Block>],
+        id: &Block::Identifier,
+        ancestor_depth: usize,
+    ) -> Result<Block::Identifier, SledForkTreeError> {
+        let mut id = *id;
+        loop {
+            let next = match staged.iter().find(|stored| stored.block.id() == id) {
+                Some(stored) => stored.towards(ancestor_depth)?,
+                None => self.stored(&id)?.towards(ancestor_depth)?,
+            };
+            match next {
+                Some(next) => id = next,
+                None => return Ok(id),
+            }
+        }
+    }
+}
+
+/// Key of the depth index: the big-endian depth, so that the ids at a depth
+/// share a prefix, followed by the id.
+fn depth_key(depth: usize, id: &[u8]) -> Vec<u8> {
+    let mut key = (depth as u64).to_be_bytes().to_vec();
+    key.extend_from_slice(id);
+    key
+}
+
+impl<Block> ForkTree for SledForkTree<Block>
+where
+    Block: Identified + Serialize + DeserializeOwned,
+    Block::Identifier: Serialize + DeserializeOwned,
+{
+    type Block = Block;
+    type QueryError = SledForkTreeError;
+
+    fn block(&self, id: &Block::Identifier) -> Result<Block, Self::QueryError> {
+        Ok(self.stored(id)?.block)
+    }
+
+    fn block_depth(&self, id: &Block::Identifier) -> Result<usize, Self::QueryError> {
+        Ok(self.stored(id)?.depth)
+    }
+
+    fn ancestor_id_at_depth(
+        &self,
+        id: &Block::Identifier,
+        ancestor_depth: usize,
+    ) -> Result<Block::Identifier, Self::QueryError> {
+        self.staged_ancestor_id_at_depth(&[], id, ancestor_depth)
+    }
+}
+
+impl<Block> ForkTreeDepths for SledForkTree<Block>
+where
+    Block: Identified + Serialize + DeserializeOwned,
+    Block::Identifier: Serialize + DeserializeOwned,
+{
+    fn blocks_at_depth(&self, depth: usize) -> Result<Vec<Block::Identifier>, Self::QueryError> {
+        let prefix = (depth as u64).to_be_bytes();
+        self.depths
+            .scan_prefix(prefix)
+            .keys()
+            .map(|key| Ok(serde_json::from_slice(&key?[prefix.len()..])?))
+            .collect()
+    }
+}
+
+impl<Block> ForkTreeBest for SledForkTree<Block>
+where
+    Block: Identified + Serialize + DeserializeOwned,
+    Block::Identifier: Serialize + DeserializeOwned,
+{
+    fn best_id(&self) -> Result<Block::Identifier, Self::QueryError> {
+        Ok(self.best()?.ok_or(SledForkTreeError::UnknownBlock)?.1)
+    }
+}
+
+impl<Block> SledForkTree<Block>
+where
+    Block: Identified + Serialize + DeserializeOwned,
+    Block::Identifier: Ord + Serialize + DeserializeOwned,
+{
+    /// Depth of a new block, building on either a staged block or one of the
+    /// fork tree.
+    fn new_block_depth(
+        &self,
+        staged: &[StoredBlock<Block>],
+        block: &Block,
+    ) -> Result<usize, SledForkTreeError> {
+        let id = block.id();
+        if staged.iter().any(|staged| staged.block.id() == id) {
+            return Err(SledForkTreeError::AlreadyInserted);
+        }
+        match self.block_depth(&id) {
+            Ok(_) => return Err(SledForkTreeError::AlreadyInserted),
+            Err(SledForkTreeError::UnknownBlock) => (),
+            Err(err) => return Err(err),
+        }
+
+        let Some(parent_id) = block.parent_id() else {
+            return Ok(0);
+        };
+        if let Some(parent) = staged.iter().find(|staged| staged.block.id() == parent_id) {
+            return Ok(parent.depth + 1);
+        }
+        match self.block_depth(&parent_id) {
+            Ok(parent_depth) => Ok(parent_depth + 1),
+            Err(SledForkTreeError::UnknownBlock) => Err(SledForkTreeError::UnknownParent),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Stage a new block, with its depth and skip list of ancestors.
+    fn stage(
+        &self,
+        staged: &[StoredBlock<Block>],
+        block: Block,
+    ) -> Result<StoredBlock<Block>, SledForkTreeError> {
+        let depth = self.new_block_depth(staged, &block)?;
+        let mut ancestors = Vec::new();
+        if let Some(parent_id) = block.parent_id() {
+            let ancestor_depths = SKIP_DEPTHS
+                .iter()
+                .filter(|skip_depth| depth >= **skip_depth && depth % **skip_depth == 0)
+                .map(|skip_depth| depth - skip_depth)
+                .unique();
+            for ancestor_depth in ancestor_depths {
+                ancestors.push((
+                    ancestor_depth,
+                    self.staged_ancestor_id_at_depth(staged, &parent_id, ancestor_depth)?,
+                ));
+            }
+        }
+
+        Ok(StoredBlock {
+            depth,
+            ancestors,
+            block,
+        })
+    }
+
+    /// Write blocks, along with their depth index and the best block, in a
+    /// single transaction. The best block is read in the transaction too, so
+    /// that concurrent writes do not overwrite each other's best block.
+    fn write(&self, blocks: &[StoredBlock<Block>]) -> Result<(), SledForkTreeError> {
+        let mut entries = Vec::with_capacity(blocks.len());
+        for stored in blocks {
+            let id = stored.block.id();
+            entries.push((
+                serde_json::to_vec(&id)?,
+                serde_json::to_vec(stored)?,
+                stored.depth,
+                id,
+            ));
+        }
+        let codec_error = |err| ConflictableTransactionError::Abort(SledForkTreeError::Codec(err));
+
+        (&self.blocks, &self.depths, &self.metadata)
+            .transaction(|(blocks, depths, metadata)| {
+                let mut best = metadata
+                    .get(BEST_KEY)?
+                    .map(|best| serde_json::from_slice::<(usize, Block::Identifier)>(&best))
+                    .transpose()
+                    .map_err(codec_error)?;
+                for (key, stored, depth, id) in &entries {
+                    blocks.insert(key.as_slice(), stored.as_slice())?;
+                    depths.insert(depth_key(*depth, key), &[])?;
+
+                    let is_best = match &best {
+                        Some((best_depth, best_id)) => {
+                            depth > best_depth || (depth == best_depth && id < best_id)
+                        }
+                        None => true,
+                    };
+                    if is_best {
+                        best = Some((*depth, *id));
+                    }
+                }
+                if let Some(best) = &best {
+                    let best = serde_json::to_vec(best).map_err(codec_error)?;
+                    metadata.insert(BEST_KEY, best)?;
+                }
+                Ok(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Storage(err) => SledForkTreeError::Sled(err),
+                TransactionError::Abort(err) => err,
+            })
+    }
+}
+
+impl<Block> ForkTreeMut for SledForkTree<Block>
+where
+    Block: Identified + Serialize + DeserializeOwned,
+    Block::Identifier: Ord + Serialize + DeserializeOwned,
+{
+    type InsertError = SledForkTreeError;
+
+    /// Insert a block, along with its depth index and the best block, in a
+    /// single transaction.
+    fn insert(&mut self, block: Block) -> Result<(), Self::InsertError> {
+        let stored = self.stage(&[], block)?;
+        self.write(&[stored])
+    }
+}
+
+/// Blocks staged in a transaction of a sled fork tree, with their depth and
+/// ancestors, in insertion order.
+pub struct SledForkTreeTransaction<Block: Identified> {
+    blocks: Vec<StoredBlock<Block>>,
+}
+
+impl<Block> ForkTreeTransactional for SledForkTree<Block>
+where
+    Block: Identified + Clone + Serialize + DeserializeOwned,
+    Block::Identifier: Ord + Serialize + DeserializeOwned,
+{
+    type Transaction = SledForkTreeTransaction<Block>;
+    type InsertError = SledForkTreeError;
+
+    fn begin(&self) -> Self::Transaction {
+        SledForkTreeTransaction { blocks: Vec::new() }
+    }
+
+    fn insert(
+        &self,
+        transaction: &mut Self::Transaction,
+        block: Block,
+    ) -> Result<(), Self::InsertError> {
+        let stored = self.stage(&transaction.blocks, block)?;
+        transaction.blocks.push(stored);
+        Ok(())
+    }
+
+    fn transaction_block_depth(
+        &self,
+        transaction: &Self::Transaction,
+        id: &Block::Identifier,
+    ) -> Result<usize, Self::QueryError> {
+        match transaction
+            .blocks
+            .iter()
+            .find(|stored| stored.block.id() == *id)
+        {
+            Some(stored) => Ok(stored.depth),
+            None => self.block_depth(id),
+        }
+    }
+
+    fn transaction_block(
+        &self,
+        transaction: &Self::Transaction,
+        id: &Block::Identifier,
+    ) -> Result<Block, Self::QueryError> {
+        match transaction
+            .blocks
+            .iter()
+            .find(|stored| stored.block.id() == *id)
+        {
+            Some(stored) => Ok(stored.block.clone()),
+            None => self.block(id),
+        }
+    }
+
+    /// Check the blocks again, as the fork tree may have changed since they
+    /// were staged, then write all of them in a single sled transaction.
+    fn commit(&mut self, transaction: Self::Transaction) -> Result<(), Self::InsertError> {
+        for (index, stored) in transaction.blocks.iter().enumerate() {
+            self.new_block_depth(&transaction.blocks[..index], &stored.block)?;
+        }
+        self.write(&transaction.blocks)
+    }
+}
@@ -1,6 +1,30 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::{ForkTree, Identified};
+use crate::{ForkTree, ForkTreeBest, ForkTreeTransactional, Identified, Merkleizer};
+
+/// Keys whose value differs between two sets of entries, with their value in
+/// each, sorted by key.
+#[allow(clippy::type_complexity)]
+pub(crate) fn diff_entries<K: Ord, V: PartialEq>(
+    from: Vec<(K, V)>,
+    to: Vec<(K, V)>,
+) -> Vec<(K, Option<V>, Option<V>)> {
+    let mut old = from.into_iter().collect::<BTreeMap<_, _>>();
+    let mut diff = BTreeMap::new();
+    for (key, new) in to {
+        match old.remove(&key) {
+            Some(old) if old == new => (),
+            old => {
+                diff.insert(key, (old, Some(new)));
+            }
+        }
+    }
+    diff.extend(old.into_iter().map(|(key, old)| (key, (Some(old), None))));
+
+    diff.into_iter()
+        .map(|(key, (old, new))| (key, old, new))
+        .collect()
+}
 
 /// Flat state.
 ///
@@ -23,6 +47,20 @@ pub trait FlatState<FT: ForkTree> {
         fork_tree: &FT,
     ) -> Result<Option<Self::Value>, Self::QueryError>;
 
+    /// Get the value at the best block of the fork tree.
+    fn get_best(
+        &self,
+        key: &Self::Key,
+        fork_tree: &FT,
+    ) -> Result<Option<Self::Value>, Self::QueryError>
+    where
+        FT: ForkTreeBest,
+        Self::QueryError: From<FT::QueryError>,
+    {
+        let best_id = fork_tree.best_id()?;
+        self.get(key, &best_id, fork_tree)
+    }
+
     /// Overlayed state.
     fn overlayed<'fs, 'ft>(
         &'fs self,
@@ -52,21 +90,138 @@ pub trait FlatStateMut<FT: ForkTree>: FlatState<FT> {
     ) -> Result<(), Self::ApplyError>;
 }
 
+/// Flat state whose entries at a block can be listed, for snapshots, diffs
+/// and state roots. Separate from [`FlatState`], as not every backend can
+/// list its keys.
+pub trait FlatStateEntries<FT: ForkTree>: FlatState<FT> {
+    /// Get all keys with a value at particular block id, with their values,
+    /// in no particular order.
+    #[allow(clippy::type_complexity)]
+    fn entries(
+        &self,
+        block_id: &<FT::Block as Identified>::Identifier,
+        fork_tree: &FT,
+    ) -> Result<Vec<(Self::Key, Self::Value)>, Self::QueryError>
+    where
+        Self::Key: Clone;
+
+    /// Get all keys with a value at particular block id, with their values,
+    /// sorted by key, so that nodes with the same state export the same
+    /// snapshot whatever the order it was built in.
+    #[allow(clippy::type_complexity)]
+    fn snapshot(
+        &self,
+        block_id: &<FT::Block as Identified>::Identifier,
+        fork_tree: &FT,
+    ) -> Result<Vec<(Self::Key, Self::Value)>, Self::QueryError>
+    where
+        Self::Key: Clone + Ord,
+    {
+        let mut entries = self.entries(block_id, fork_tree)?;
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(entries)
+    }
+
+    /// Get the keys whose value differs between two blocks, such as a block
+    /// and its parent, with their value at `from` and at `to`, `None` where
+    /// unset. Sorted by key.
+    ///
+    /// By default, all the entries of both blocks are compared. Implementations
+    /// keeping changesets per block should diff a block and its parent from the
+    /// changes of the block.
+    #[allow(clippy::type_complexity)]
+    fn diff(
+        &self,
+        from: &<FT::Block as Identified>::Identifier,
+        to: &<FT::Block as Identified>::Identifier,
+        fork_tree: &FT,
+    ) -> Result<Vec<(Self::Key, Option<Self::Value>, Option<Self::Value>)>, Self::QueryError>
+    where
+        Self::Key: Clone + Ord,
+        Self::Value: PartialEq,
+    {
+        Ok(diff_entries(
+            self.entries(from, fork_tree)?,
+            self.entries(to, fork_tree)?,
+        ))
+    }
+
+    /// Get the Merkle root of the state at particular block id, for clients
+    /// expecting Merkle proofs. The flat state itself is not Merkleized, so
+    /// the root is built over all entries, with leaves in key order, so that
+    /// it does not depend on the order of the entries. Use a
+    /// [`StateRootCache`](crate::StateRootCache) to compute it once per block.
+    fn state_root<M>(
+        &self,
+        block_id: &<FT::Block as Identified>::Identifier,
+        fork_tree: &FT,
+        merkleizer: &M,
+    ) -> Result<[u8; 32], Self::QueryError>
+    where
+        Self::Key: Clone + Ord,
+        M: Merkleizer<Self::Key, Self::Value>,
+    {
+        let leaves = self
+            .snapshot(block_id, fork_tree)?
+            .into_iter()
+            .map(|(key, value)| merkleizer.leaf(&key, &value))
+            .collect::<Vec<_>>();
+
+        Ok(merkleizer.root(leaves))
+    }
+}
+
 /// Transactional flat state.
-pub trait FlatStateTransactional<FT: ForkTree>: FlatState<FT> {
+///
+/// Changesets are applied to a transaction, and only become part of the state
+/// once it is committed. Dropping the transaction rolls it back. Unlike for
+/// [`ForkTreeTransactional`], committing can not fail, so that it can follow
+/// a successful commit of the fork tree transaction.
+pub trait FlatStateTransactional<FT: ForkTreeTransactional>: FlatState<FT> {
     /// Transaction type.
     type Transaction;
     /// Apply error type.
     type ApplyError;
 
-    /// Apply a changeset to a particular block id.
+    /// Begin a new transaction.
+    fn begin(&self) -> Self::Transaction;
+
+    /// Apply a changeset to a particular block id. The block may be one
+    /// inserted in the fork tree transaction.
     fn apply<I: Iterator<Item = (Self::Key, Option<Self::Value>)>>(
         &self,
         transaction: &mut Self::Transaction,
         changeset: I,
         block_id: &<FT::Block as Identified>::Identifier,
         fork_tree: &FT,
+        fork_tree_transaction: &FT::Transaction,
     ) -> Result<(), Self::ApplyError>;
+
+    /// Commit the transaction.
+    fn commit(&mut self, transaction: Self::Transaction);
+}
+
+/// Flat state that can be pruned on finalization. Like for
+/// [`ForkTreePrune`](crate::ForkTreePrune), pruning is planned first, and
+/// then applied without failing.
+pub trait FlatStatePrune<FT: ForkTree>: FlatState<FT> {
+    /// Planned pruning type.
+    type Pruning;
+
+    /// Plan removing the changes of pruned blocks, and compacting the changes
+    /// up to the finalized block into the state at it. Reads at its ancestors
+    /// then see the state at it, and reads at blocks off its chain are no
+    /// longer supported.
+    fn plan_prune(
+        &self,
+        finalized_id: &<FT::Block as Identified>::Identifier,
+        pruned_ids: &[<FT::Block as Identified>::Identifier],
+        fork_tree: &FT,
+    ) -> Result<Self::Pruning, Self::QueryError>;
+
+    /// Apply a planned pruning.
+    fn prune(&mut self, pruning: Self::Pruning);
 }
 
 /// Convinence function for building a changeset of a flat state.
@@ -77,6 +232,23 @@ pub struct OverlayedFlatState<'fs, 'ft, FS: FlatState<FT> + ?Sized, FT: ForkTree
     changeset: HashMap<FS::Key, Option<FS::Value>>,
 }
 
+impl<'fs, 'ft, FS, FT> Clone for OverlayedFlatState<'fs, 'ft, FS, FT>
+where
+    FS: FlatState<FT> + ?Sized,
+    FS::Key: Clone,
+    FS::Value: Clone,
+    FT: ForkTree,
+{
+    fn clone(&self) -> Self {
+        Self {
+            flat_state: self.flat_state,
+            fork_tree: self.fork_tree,
+            block_id: self.block_id,
+            changeset: self.changeset.clone(),
+        }
+    }
+}
+
 impl<'fs, 'ft, FS, FT> OverlayedFlatState<'fs, 'ft, FS, FT>
 where
     FS: FlatState<FT> + ?Sized,
@@ -0,0 +1,118 @@
+use crate::{FlatStateTransactional, ForkTreeTransactional, Identified};
+
+/// Error importing a block in a chain transaction.
+#[derive(Debug, Clone)]
+pub enum ChainTransactionError<InsertError, ApplyError> {
+    /// Inserting the block into the fork tree failed.
+    Insert(InsertError),
+    /// Applying the state changeset of the block failed.
+    Apply(ApplyError),
+    /// An earlier import failed after staging its block, so the transaction
+    /// can no longer be committed.
+    Poisoned,
+}
+
+/// Transaction across a fork tree and a flat state.
+///
+/// Blocks and their state changesets are staged in a transaction of each
+/// store, and committed to both at once. Dropping the chain transaction, such
+/// as on the first error, rolls both back, so that a block is never inserted
+/// without its state. An import that fails once its block is staged poisons
+/// the transaction, and it can then only be rolled back.
+pub struct ChainTransaction<'a, FT, FS>
+where
+    FT: ForkTreeTransactional,
+    FS: FlatStateTransactional<FT>,
+{
+    fork_tree: &'a mut FT,
+    state: &'a mut FS,
+    fork_tree_transaction: FT::Transaction,
+    state_transaction: FS::Transaction,
+    poisoned: bool,
+}
+
+impl<'a, FT, FS> ChainTransaction<'a, FT, FS>
+where
+    FT: ForkTreeTransactional,
+    FS: FlatStateTransactional<FT>,
+{
+    /// Begin a new transaction over the fork tree and the flat state.
+    pub fn begin(fork_tree: &'a mut FT, state: &'a mut FS) -> Self {
+        let fork_tree_transaction = fork_tree.begin();
+        let state_transaction = state.begin();
+
+        Self {
+            fork_tree,
+            state,
+            fork_tree_transaction,
+            state_transaction,
+            poisoned: false,
+        }
+    }
+
+    /// The fork tree, without the blocks of the transaction.
+    pub fn fork_tree(&self) -> &FT {
+        self.fork_tree
+    }
+
+    /// The flat state, without the changesets of the transaction.
+    pub fn state(&self) -> &FS {
+        self.state
+    }
+
+    /// Insert a block into the transaction.
+    pub fn insert(&mut self, block: FT::Block) -> Result<(), FT::InsertError> {
+        self.fork_tree
+            .insert(&mut self.fork_tree_transaction, block)
+    }
+
+    /// Apply a changeset to a block, either already in the fork tree or
+    /// inserted in the transaction.
+    pub fn apply<I: Iterator<Item = (FS::Key, Option<FS::Value>)>>(
+        &mut self,
+        changeset: I,
+        block_id: &<FT::Block as Identified>::Identifier,
+    ) -> Result<(), FS::ApplyError> {
+        self.state.apply(
+            &mut self.state_transaction,
+            changeset,
+            block_id,
+            self.fork_tree,
+            &self.fork_tree_transaction,
+        )
+    }
+
+    /// Insert a block along with its state changeset. If the changeset fails
+    /// to apply, the block is left staged without its state, and the
+    /// transaction is poisoned.
+    pub fn import<I: Iterator<Item = (FS::Key, Option<FS::Value>)>>(
+        &mut self,
+        block: FT::Block,
+        changeset: I,
+    ) -> Result<(), ChainTransactionError<FT::InsertError, FS::ApplyError>> {
+        let block_id = block.id();
+        self.insert(block).map_err(ChainTransactionError::Insert)?;
+        self.apply(changeset, &block_id).map_err(|err| {
+            self.poisoned = true;
+            ChainTransactionError::Apply(err)
+        })
+    }
+
+    /// Commit the transaction to both the fork tree and the flat state. If
+    /// the transaction is poisoned or the fork tree fails to commit, neither
+    /// is changed.
+    pub fn commit(self) -> Result<(), ChainTransactionError<FT::InsertError, FS::ApplyError>> {
+        if self.poisoned {
+            return Err(ChainTransactionError::Poisoned);
+        }
+        self.fork_tree
+            .commit(self.fork_tree_transaction)
+            .map_err(ChainTransactionError::Insert)?;
+        self.state.commit(self.state_transaction);
+        Ok(())
+    }
+
+    /// Roll back the transaction, discarding its blocks and changesets. Same
+    /// as dropping it.
+    pub fn rollback(self) {}
+}
@@ -0,0 +1,101 @@
+//! Allocations of the memory fork tree queries on a deep chain of large
+//! blocks. A test of its own, as the allocator counts for the whole process.
+
+use blockchain::memory::{MemoryFlatState, MemoryForkTree};
+use blockchain::{FlatState, FlatStateMut, ForkTree, ForkTreeMut, Identified};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The system allocator, counting allocations.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of allocations made by `f`.
+fn allocations<R>(f: impl FnOnce() -> R) -> (usize, R) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let result = f();
+    (ALLOCATIONS.load(Ordering::Relaxed) - before, result)
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    number: u32,
+    payload: Vec<u8>,
+}
+
+impl Identified for Block {
+    type Identifier = u32;
+
+    fn id(&self) -> u32 {
+        self.number
+    }
+
+    fn parent_id(&self) -> Option<u32> {
+        self.number.checked_sub(1)
+    }
+}
+
+#[test]
+fn queries_on_deep_chain_do_not_clone_blocks() {
+    const DEPTH: u32 = 2000;
+
+    let mut fork_tree = MemoryForkTree::new();
+    let mut state = MemoryFlatState::new();
+    for number in 0..DEPTH {
+        fork_tree
+            .insert(Block {
+                number,
+                payload: vec![0; 4096],
+            })
+            .unwrap();
+    }
+    state
+        .apply([(1u32, Some(1u32))].into_iter(), 0, &fork_tree)
+        .unwrap();
+    let tip = DEPTH - 1;
+
+    let (cloning, payloads) = allocations(|| {
+        (0..DEPTH)
+            .map(|number| fork_tree.block(&number).unwrap().payload.len())
+            .sum::<usize>()
+    });
+    assert!(cloning >= DEPTH as usize);
+
+    let (in_place, in_place_payloads) = allocations(|| {
+        (0..DEPTH)
+            .map(|number| {
+                fork_tree
+                    .with_block(&number, |block| block.payload.len())
+                    .unwrap()
+            })
+            .sum::<usize>()
+    });
+    assert_eq!(in_place, 0);
+    assert_eq!(in_place_payloads, payloads);
+
+    let (queries, ()) = allocations(|| {
+        for number in (0..DEPTH).step_by(7) {
+            assert!(fork_tree.is_ancestor(&tip, &number).unwrap());
+            assert_eq!(fork_tree.block_depth(&number).unwrap(), number as usize);
+        }
+        assert_eq!(state.get(&1, &tip, &fork_tree).unwrap(), Some(1));
+    });
+    assert_eq!(queries, 0);
+}
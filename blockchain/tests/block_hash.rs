@@ -0,0 +1,35 @@
+//! Tests of the block hash identifier.
+
+use blockchain::BlockHash;
+use std::collections::HashSet;
+
+#[test]
+fn distinct_contents_yield_distinct_hashes() {
+    let hashes = [b"block 1".as_slice(), b"block 2", b"", b"block 1 "]
+        .iter()
+        .map(BlockHash::digest)
+        .collect::<HashSet<_>>();
+    assert_eq!(hashes.len(), 4);
+
+    assert_eq!(BlockHash::digest(b"block 1"), BlockHash::digest(b"block 1"));
+    assert!(!hashes.contains(&BlockHash::ZERO));
+}
+
+#[test]
+fn conversions_and_display() {
+    let mut bytes = [0; 32];
+    bytes[0] = 0xab;
+    bytes[31] = 0x01;
+
+    let hash = BlockHash::new(bytes);
+    assert_eq!(BlockHash::from(bytes), hash);
+    assert_eq!(<[u8; 32]>::from(hash), bytes);
+    assert_eq!(hash.as_bytes(), &bytes);
+    assert_eq!(hash.as_ref(), bytes.as_slice());
+
+    let display = hash.to_string();
+    assert_eq!(display.len(), 2 + 64);
+    assert!(display.starts_with("0xab00"));
+    assert!(display.ends_with("0001"));
+    assert_eq!(format!("{:?}", hash), display);
+}
@@ -0,0 +1,63 @@
+//! Tests of building genesis from a chain spec file.
+
+use blockchain::memory::{MemoryFlatState, MemoryForkTree};
+use blockchain::{ChainSpec, FlatState, ForkTree, Identified};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    number: u32,
+    extra: String,
+}
+
+impl Identified for Block {
+    type Identifier = u32;
+
+    fn id(&self) -> u32 {
+        self.number
+    }
+
+    fn parent_id(&self) -> Option<u32> {
+        self.number.checked_sub(1)
+    }
+}
+
+#[test]
+fn genesis_initialized_from_reloaded_spec() {
+    let spec = ChainSpec::new(
+        Block {
+            number: 0,
+            extra: "testnet".to_string(),
+        },
+        vec![(100u32, 1u64), (200, 2)],
+    );
+
+    let path = std::env::temp_dir().join(format!("chain_spec_{}.json", std::process::id()));
+    spec.to_json_file(&path).unwrap();
+    let reloaded = ChainSpec::<Block, u32, u64>::from_json_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(reloaded, spec);
+
+    let mut fork_tree = MemoryForkTree::new();
+    let mut state = MemoryFlatState::new();
+    let genesis_id = reloaded.initialize(&mut fork_tree, &mut state).unwrap();
+
+    assert_eq!(genesis_id, 0);
+    assert_eq!(fork_tree.block(&genesis_id).unwrap(), spec.genesis);
+    assert_eq!(state.get(&100, &genesis_id, &fork_tree).unwrap(), Some(1));
+    assert_eq!(state.get(&200, &genesis_id, &fork_tree).unwrap(), Some(2));
+    assert_eq!(state.get(&300, &genesis_id, &fork_tree).unwrap(), None);
+}
+
+#[test]
+fn invalid_spec_file_rejected() {
+    let path = std::env::temp_dir().join(format!("invalid_spec_{}.json", std::process::id()));
+    std::fs::write(&path, "{\"genesis\": 0}").unwrap();
+    let result = ChainSpec::<Block, u32, u64>::from_json_file(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(
+        result,
+        Err(blockchain::ChainSpecFileError::Json(_))
+    ));
+}
@@ -0,0 +1,241 @@
+//! Tests of transactions across the fork tree and the flat state.
+
+use blockchain::memory::{
+    MemoryFlatState, MemoryFlatStateApplyError, MemoryForkTree, MemoryForkTreeInsertError,
+    MemoryForkTreeQueryError,
+};
+use blockchain::{
+    ChainTransaction, ChainTransactionError, FlatState, ForkTree, ForkTreeDepths, ForkTreeMut,
+    ForkTreeRemoveLeaf, ForkTreeTransactional,
+};
+
+mod common;
+use common::{fork, id, Block, BlockId};
+
+fn genesis() -> (MemoryForkTree<Block>, MemoryFlatState<u32, u32, BlockId>) {
+    let mut fork_tree = MemoryForkTree::new();
+    for block in fork(None, 0, 0, 0) {
+        ForkTreeMut::insert(&mut fork_tree, block).expect("insert succeeds");
+    }
+
+    (fork_tree, MemoryFlatState::new())
+}
+
+#[test]
+fn failed_state_apply_rolls_back_block() -> Result<(), MemoryForkTreeQueryError> {
+    let (mut fork_tree, mut state) = genesis();
+    let main = fork(Some(id(0, 0)), 0, 1, 1);
+
+    let mut transaction = ChainTransaction::begin(&mut fork_tree, &mut state);
+    transaction
+        .insert(main[0].clone())
+        .expect("insert succeeds");
+    // The changeset is for a block that is neither in the fork tree nor in
+    // the transaction.
+    assert!(matches!(
+        transaction.apply(vec![(10, Some(1))].into_iter(), &id(0, 2)),
+        Err(MemoryFlatStateApplyError::ForkTree(
+            MemoryForkTreeQueryError::UnknownBlock
+        ))
+    ));
+    drop(transaction);
+
+    assert!(matches!(
+        fork_tree.block(&id(0, 1)),
+        Err(MemoryForkTreeQueryError::UnknownBlock)
+    ));
+    assert_eq!(fork_tree.blocks_at_depth(1)?, Vec::<BlockId>::new());
+
+    Ok(())
+}
+
+#[test]
+fn commit_refused_after_failed_import() -> Result<(), MemoryForkTreeQueryError> {
+    let (mut fork_tree, _) = genesis();
+    let mut state = MemoryFlatState::new().with_strict_parents();
+    let main = fork(Some(id(0, 0)), 0, 1, 1);
+
+    // Genesis has no state, so the block is staged but its changeset is not.
+    let mut transaction = ChainTransaction::begin(&mut fork_tree, &mut state);
+    assert!(matches!(
+        transaction.import(main[0].clone(), vec![(10, Some(1))].into_iter()),
+        Err(ChainTransactionError::Apply(
+            MemoryFlatStateApplyError::ParentStateMissing
+        ))
+    ));
+    assert!(matches!(
+        transaction.commit(),
+        Err(ChainTransactionError::Poisoned)
+    ));
+
+    assert!(matches!(
+        fork_tree.block(&id(0, 1)),
+        Err(MemoryForkTreeQueryError::UnknownBlock)
+    ));
+    assert_eq!(fork_tree.blocks_at_depth(1)?, Vec::<BlockId>::new());
+
+    Ok(())
+}
+
+#[test]
+fn committed_import_visible_in_both_stores() -> Result<(), MemoryForkTreeQueryError> {
+    let (mut fork_tree, mut state) = genesis();
+    let main = fork(Some(id(0, 0)), 0, 1, 4);
+
+    let mut transaction = ChainTransaction::begin(&mut fork_tree, &mut state);
+    transaction
+        .import(main[0].clone(), vec![(10, Some(1))].into_iter())
+        .expect("import succeeds");
+    transaction
+        .import(main[1].clone(), vec![(10, Some(2))].into_iter())
+        .expect("import succeeds");
+    // Not visible until committed.
+    assert!(transaction.fork_tree().block(&id(0, 1)).is_err());
+    assert!(matches!(
+        transaction.import(main[3].clone(), Vec::new().into_iter()),
+        Err(ChainTransactionError::Insert(
+            MemoryForkTreeInsertError::UnknownParent
+        ))
+    ));
+    transaction.commit().expect("commit succeeds");
+
+    assert_eq!(fork_tree.block_depth(&id(0, 2))?, 2);
+    assert_eq!(
+        state.get(&10, &id(0, 1), &fork_tree).ok().flatten(),
+        Some(1)
+    );
+    assert_eq!(
+        state.get(&10, &id(0, 2), &fork_tree).ok().flatten(),
+        Some(2)
+    );
+    assert!(fork_tree.block(&id(0, 4)).is_err());
+
+    // A rolled back transaction leaves both untouched.
+    let mut transaction = ChainTransaction::begin(&mut fork_tree, &mut state);
+    transaction
+        .import(main[2].clone(), vec![(10, Some(3))].into_iter())
+        .expect("import succeeds");
+    transaction.rollback();
+    assert!(fork_tree.block(&id(0, 3)).is_err());
+    assert_eq!(
+        state.get(&10, &id(0, 2), &fork_tree).ok().flatten(),
+        Some(2)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn transactional_inserts_reject_known_blocks() -> Result<(), MemoryForkTreeQueryError> {
+    let (mut fork_tree, _) = genesis();
+    let main = fork(Some(id(0, 0)), 0, 1, 2);
+    let side = fork(Some(id(0, 0)), 1, 1, 1);
+    for block in main.iter().cloned() {
+        ForkTreeMut::insert(&mut fork_tree, block).expect("insert succeeds");
+    }
+
+    let mut transaction = fork_tree.begin();
+    assert!(matches!(
+        ForkTreeTransactional::insert(&fork_tree, &mut transaction, main[0].clone()),
+        Err(MemoryForkTreeInsertError::AlreadyInserted)
+    ));
+    ForkTreeTransactional::insert(&fork_tree, &mut transaction, side[0].clone())
+        .expect("insert succeeds");
+    assert!(matches!(
+        ForkTreeTransactional::insert(&fork_tree, &mut transaction, side[0].clone()),
+        Err(MemoryForkTreeInsertError::AlreadyInserted)
+    ));
+    fork_tree.commit(transaction).expect("commit succeeds");
+
+    assert_eq!(fork_tree.blocks_at_depth(1)?, vec![id(0, 1), id(1, 1)]);
+    assert!(fork_tree.is_ancestor(&id(0, 2), &id(0, 1))?);
+
+    Ok(())
+}
+
+#[test]
+fn commit_fails_whole_if_tree_changed() -> Result<(), MemoryForkTreeQueryError> {
+    let (mut fork_tree, _) = genesis();
+    let main = fork(Some(id(0, 0)), 0, 1, 2);
+    let side = fork(Some(id(0, 0)), 1, 1, 1);
+    ForkTreeMut::insert(&mut fork_tree, main[0].clone()).expect("insert succeeds");
+
+    // The parent of the transaction is removed before the commit.
+    let mut transaction = fork_tree.begin();
+    ForkTreeTransactional::insert(&fork_tree, &mut transaction, side[0].clone())
+        .expect("insert succeeds");
+    ForkTreeTransactional::insert(&fork_tree, &mut transaction, main[1].clone())
+        .expect("insert succeeds");
+    fork_tree.remove_leaf(&id(0, 1)).expect("remove succeeds");
+    assert!(matches!(
+        fork_tree.commit(transaction),
+        Err(MemoryForkTreeInsertError::UnknownParent)
+    ));
+    assert!(fork_tree.block(&id(1, 1)).is_err());
+
+    // So is a block inserted meanwhile.
+    let mut transaction = fork_tree.begin();
+    ForkTreeTransactional::insert(&fork_tree, &mut transaction, side[0].clone())
+        .expect("insert succeeds");
+    ForkTreeMut::insert(&mut fork_tree, side[0].clone()).expect("insert succeeds");
+    assert!(matches!(
+        fork_tree.commit(transaction),
+        Err(MemoryForkTreeInsertError::AlreadyInserted)
+    ));
+    assert_eq!(fork_tree.blocks_at_depth(1)?, vec![id(1, 1)]);
+
+    Ok(())
+}
+
+#[test]
+fn strict_parents_apply_in_transaction_order() -> Result<(), MemoryForkTreeQueryError> {
+    let (mut fork_tree, _) = genesis();
+    let mut state = MemoryFlatState::new().with_strict_parents();
+    let main = fork(Some(id(0, 0)), 0, 1, 3);
+
+    // Genesis has no state, so none of its descendants can be applied.
+    let mut transaction = ChainTransaction::begin(&mut fork_tree, &mut state);
+    assert!(matches!(
+        transaction.import(main[0].clone(), vec![(10, Some(1))].into_iter()),
+        Err(ChainTransactionError::Apply(
+            MemoryFlatStateApplyError::ParentStateMissing
+        ))
+    ));
+    transaction.rollback();
+
+    // Applied earlier in the transaction, the parent state is there.
+    let mut transaction = ChainTransaction::begin(&mut fork_tree, &mut state);
+    transaction
+        .apply(Vec::new().into_iter(), &id(0, 0))
+        .expect("apply succeeds");
+    transaction
+        .import(main[0].clone(), vec![(10, Some(1))].into_iter())
+        .expect("import succeeds");
+    transaction.commit().expect("commit succeeds");
+
+    let mut transaction = ChainTransaction::begin(&mut fork_tree, &mut state);
+    transaction
+        .insert(main[1].clone())
+        .expect("insert succeeds");
+    transaction
+        .insert(main[2].clone())
+        .expect("insert succeeds");
+    assert!(matches!(
+        transaction.apply(vec![(10, Some(3))].into_iter(), &id(0, 3)),
+        Err(MemoryFlatStateApplyError::ParentStateMissing)
+    ));
+    transaction
+        .apply(vec![(10, Some(2))].into_iter(), &id(0, 2))
+        .expect("apply succeeds");
+    transaction
+        .apply(vec![(10, Some(3))].into_iter(), &id(0, 3))
+        .expect("apply succeeds");
+    transaction.commit().expect("commit succeeds");
+
+    assert_eq!(
+        state.get(&10, &id(0, 3), &fork_tree).ok().flatten(),
+        Some(3)
+    );
+
+    Ok(())
+}
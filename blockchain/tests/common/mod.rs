@@ -0,0 +1,52 @@
+//! Block fixture shared by the tests.
+
+// Each test binary uses its own subset of the fixture.
+#![allow(dead_code)]
+
+use blockchain::Identified;
+use serde::{Deserialize, Serialize};
+
+/// A block identified by its fork and number.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct BlockId {
+    pub fork: u32,
+    pub number: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    pub id: BlockId,
+    pub parent_id: Option<BlockId>,
+}
+
+impl Identified for Block {
+    type Identifier = BlockId;
+
+    fn id(&self) -> BlockId {
+        self.id
+    }
+
+    fn parent_id(&self) -> Option<BlockId> {
+        self.parent_id
+    }
+}
+
+pub fn id(fork: u32, number: u32) -> BlockId {
+    BlockId { fork, number }
+}
+
+/// Build blocks of a fork numbered from `start` to `end` (inclusive), with the
+/// first block building on `parent_id`.
+pub fn fork(parent_id: Option<BlockId>, fork: u32, start: u32, end: u32) -> Vec<Block> {
+    let mut parent_id = parent_id;
+    (start..=end)
+        .map(|number| {
+            let block = Block {
+                id: id(fork, number),
+                parent_id,
+            };
+            parent_id = Some(block.id);
+            block
+        })
+        .collect()
+}
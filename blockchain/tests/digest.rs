@@ -0,0 +1,29 @@
+//! Tests of the phase order of digest items.
+
+use blockchain::{DigestItems, DigestItemsError, DigestPhase};
+
+#[test]
+fn pre_digest_items_rejected_after_post() {
+    let mut digests = DigestItems::<u32>::new();
+    digests.push_pre(1u32).unwrap();
+    digests.push_post(2u32);
+    assert_eq!(digests.push_pre(3u32), Err(DigestItemsError::PreAfterPost));
+
+    let mut late = DigestItems::new();
+    late.push_pre(4u32).unwrap();
+    late.push_post(5u32);
+    assert_eq!(digests.append(late), Err(DigestItemsError::PreAfterPost));
+
+    let mut post = DigestItems::new();
+    post.push_post(6u32);
+    digests.append(post).unwrap();
+
+    assert_eq!(
+        digests.iter().collect::<Vec<_>>(),
+        vec![
+            (DigestPhase::Pre, &1),
+            (DigestPhase::Post, &2),
+            (DigestPhase::Post, &6)
+        ]
+    );
+}
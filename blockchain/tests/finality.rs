@@ -0,0 +1,99 @@
+//! Tests of finality notifications over the memory fork tree.
+
+use blockchain::memory::MemoryForkTree;
+use blockchain::{Finality, FinalityNotification, FinalizeError, ForkTreeFinalize};
+use futures::{executor::block_on, StreamExt};
+
+mod common;
+use common::{fork, id, Block};
+
+/// A main chain up to 6, and a fork off block 2 up to 4, finalized at the
+/// genesis.
+fn fork_tree() -> MemoryForkTree<Block> {
+    let mut tree = MemoryForkTree::new();
+    tree.insert_batch(fork(None, 0, 0, 6)).unwrap();
+    tree.insert_batch(fork(Some(id(0, 2)), 1, 3, 4)).unwrap();
+    tree.finalize(&id(0, 0)).unwrap();
+    tree
+}
+
+#[test]
+fn jumps_report_all_finalized_blocks_in_order() {
+    let mut tree = fork_tree();
+    let mut finality = Finality::new();
+    let notifications = finality.finality_notifications();
+
+    finality.finalize(&mut tree, &id(0, 1)).unwrap();
+    finality.finalize(&mut tree, &id(0, 4)).unwrap();
+    finality.finalize(&mut tree, &id(0, 6)).unwrap();
+    assert_eq!(tree.finalized_id(), Some(id(0, 6)));
+    drop(finality);
+
+    assert_eq!(
+        block_on(notifications.collect::<Vec<_>>()),
+        vec![
+            FinalityNotification {
+                finalized_id: id(0, 1),
+                newly_finalized: vec![id(0, 1)],
+            },
+            FinalityNotification {
+                finalized_id: id(0, 4),
+                newly_finalized: vec![id(0, 2), id(0, 3), id(0, 4)],
+            },
+            FinalityNotification {
+                finalized_id: id(0, 6),
+                newly_finalized: vec![id(0, 5), id(0, 6)],
+            },
+        ]
+    );
+}
+
+#[test]
+fn only_descendants_can_be_finalized() {
+    let mut tree = fork_tree();
+    let mut finality = Finality::new();
+    let mut notifications = finality.finality_notifications();
+
+    finality.finalize(&mut tree, &id(0, 3)).unwrap();
+    assert!(matches!(
+        finality.finalize(&mut tree, &id(1, 4)),
+        Err(FinalizeError::NotDescendant)
+    ));
+    assert!(matches!(
+        finality.finalize(&mut tree, &id(0, 2)),
+        Err(FinalizeError::NotDescendant)
+    ));
+    assert!(matches!(
+        finality.finalize(&mut tree, &id(0, 3)),
+        Err(FinalizeError::NotDescendant)
+    ));
+    assert!(matches!(
+        finality.finalize(&mut tree, &id(9, 9)),
+        Err(FinalizeError::Query(_))
+    ));
+    assert_eq!(tree.finalized_id(), Some(id(0, 3)));
+
+    drop(finality);
+    assert_eq!(
+        block_on(notifications.next()).unwrap().finalized_id,
+        id(0, 3)
+    );
+    assert_eq!(block_on(notifications.next()), None);
+}
+
+#[test]
+fn fork_tree_refusals_notify_nothing() {
+    let mut tree = fork_tree();
+    let mut finality = Finality::new();
+    let mut notifications = finality.finality_notifications();
+
+    // Fork 1 descends from the genesis, but not the best block.
+    assert!(matches!(
+        finality.finalize(&mut tree, &id(1, 3)),
+        Err(FinalizeError::ForkTree(_))
+    ));
+    assert_eq!(tree.finalized_id(), Some(id(0, 0)));
+
+    drop(finality);
+    assert_eq!(block_on(notifications.next()), None);
+}
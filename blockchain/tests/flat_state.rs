@@ -0,0 +1,480 @@
+//! Tests of the memory flat state.
+
+use blockchain::memory::{
+    MemoryFlatState, MemoryFlatStateApplyError, MemoryFlatStateQueryError, MemoryForkTree,
+    MemoryForkTreeQueryError,
+};
+use blockchain::{
+    BlockHash, FlatState, FlatStateEntries, FlatStateMut, FlatStatePrune, ForkTreeBest,
+    ForkTreeMut, Merkleizer, StateRootCache,
+};
+use std::collections::HashMap;
+
+mod common;
+use common::{id, Block, BlockId};
+
+/// A main chain up to 60, and a deep sibling fork off genesis up to 50 writing
+/// key 1 in every block. Key 1 is otherwise only set in genesis, and key 2 in
+/// block 58 of the main chain.
+fn deep_fork_state() -> (MemoryForkTree<Block>, MemoryFlatState<u32, u32, BlockId>) {
+    let mut fork_tree = MemoryForkTree::new();
+    let mut state = MemoryFlatState::new();
+
+    fork_tree
+        .insert(Block {
+            id: id(0, 0),
+            parent_id: None,
+        })
+        .unwrap();
+    state
+        .apply([(1, Some(0))].into_iter(), id(0, 0), &fork_tree)
+        .unwrap();
+
+    for fork in 0..=1 {
+        let end = if fork == 0 { 60 } else { 50 };
+        for number in 1..=end {
+            fork_tree
+                .insert(Block {
+                    id: id(fork, number),
+                    parent_id: Some(id(if number == 1 { 0 } else { fork }, number - 1)),
+                })
+                .unwrap();
+        }
+    }
+
+    for number in 1..=50 {
+        state
+            .apply([(1, Some(number))].into_iter(), id(1, number), &fork_tree)
+            .unwrap();
+    }
+    state
+        .apply([(2, Some(58))].into_iter(), id(0, 58), &fork_tree)
+        .unwrap();
+
+    (fork_tree, state)
+}
+
+#[test]
+fn unlimited_scan_by_default() {
+    let (fork_tree, state) = deep_fork_state();
+
+    assert_eq!(state.get(&1, &id(0, 60), &fork_tree).unwrap(), Some(0));
+    assert_eq!(state.get(&1, &id(1, 50), &fork_tree).unwrap(), Some(50));
+}
+
+#[test]
+fn max_fork_scan_depth_limits_deep_fork_reads() {
+    let (fork_tree, state) = deep_fork_state();
+    let state = state.with_max_fork_scan_depth(10);
+
+    assert!(matches!(
+        state.get(&1, &id(0, 60), &fork_tree),
+        Err(MemoryFlatStateQueryError::ScanLimitExceeded)
+    ));
+
+    // Reads finding the key within the limit are unaffected.
+    assert_eq!(state.get(&2, &id(0, 60), &fork_tree).unwrap(), Some(58));
+    assert_eq!(state.get(&1, &id(1, 50), &fork_tree).unwrap(), Some(50));
+    assert_eq!(state.get(&1, &id(0, 10), &fork_tree).unwrap(), Some(0));
+    assert!(matches!(
+        state.get(&1, &id(0, 11), &fork_tree),
+        Err(MemoryFlatStateQueryError::ScanLimitExceeded)
+    ));
+}
+
+#[test]
+fn max_fork_scan_depth_counts_siblings_not_ancestors() {
+    let (mut fork_tree, state) = deep_fork_state();
+    let mut state = state.with_max_fork_scan_depth(1);
+
+    // A key unchanged since genesis is read however deep, without forks.
+    state
+        .apply([(3, Some(0))].into_iter(), id(0, 0), &fork_tree)
+        .unwrap();
+    assert_eq!(state.get(&3, &id(0, 60), &fork_tree).unwrap(), Some(0));
+    assert_eq!(state.get(&2, &id(0, 60), &fork_tree).unwrap(), Some(58));
+
+    // Many sibling forks change the key next to the main chain.
+    for fork in 2..20 {
+        let sibling = id(fork, 59);
+        ForkTreeMut::insert(
+            &mut fork_tree,
+            Block {
+                id: sibling,
+                parent_id: Some(id(0, 58)),
+            },
+        )
+        .unwrap();
+        state
+            .apply([(3, Some(fork))].into_iter(), sibling, &fork_tree)
+            .unwrap();
+    }
+    assert!(matches!(
+        state.get(&3, &id(0, 60), &fork_tree),
+        Err(MemoryFlatStateQueryError::ScanLimitExceeded)
+    ));
+    assert_eq!(state.get(&3, &id(7, 59), &fork_tree).unwrap(), Some(7));
+
+    // Found before any sibling change is skipped.
+    state
+        .apply([(3, Some(59))].into_iter(), id(0, 59), &fork_tree)
+        .unwrap();
+    assert_eq!(state.get(&3, &id(0, 60), &fork_tree).unwrap(), Some(59));
+}
+
+#[test]
+fn get_best_reads_at_deepest_block() {
+    let (mut fork_tree, mut state) = deep_fork_state();
+    assert_eq!(fork_tree.best_id().unwrap(), id(0, 60));
+    assert_eq!(state.get_best(&2, &fork_tree).unwrap(), Some(58));
+
+    // The sibling fork overtakes the main chain.
+    for number in 51..=61 {
+        fork_tree
+            .insert(Block {
+                id: id(1, number),
+                parent_id: Some(id(1, number - 1)),
+            })
+            .unwrap();
+    }
+    let best_id = fork_tree.best_id().unwrap();
+    assert_eq!(best_id, id(1, 61));
+    state
+        .apply([(3, Some(61))].into_iter(), best_id, &fork_tree)
+        .unwrap();
+
+    assert_eq!(state.get_best(&3, &fork_tree).unwrap(), Some(61));
+    assert_eq!(state.get_best(&2, &fork_tree).unwrap(), None);
+    assert_eq!(state.get_best(&1, &fork_tree).unwrap(), Some(50));
+}
+
+#[test]
+fn changes_at_only_returns_own_changeset() {
+    let (fork_tree, mut state) = deep_fork_state();
+    state
+        .apply(
+            [(2, None), (3, Some(59))].into_iter(),
+            id(0, 59),
+            &fork_tree,
+        )
+        .unwrap();
+
+    assert_eq!(
+        state.changes_at(&id(0, 59), &fork_tree).unwrap(),
+        [(2, None), (3, Some(59))]
+    );
+
+    // Key 1 is inherited from genesis, and key 2 from block 58.
+    assert_eq!(
+        state.changes_at(&id(0, 58), &fork_tree).unwrap(),
+        [(2, Some(58))]
+    );
+    assert_eq!(
+        state.changes_at(&id(1, 7), &fork_tree).unwrap(),
+        [(1, Some(7))]
+    );
+    assert!(state.changes_at(&id(0, 60), &fork_tree).unwrap().is_empty());
+}
+
+#[test]
+fn diffs_are_sorted_by_key() {
+    let (fork_tree, mut state) = deep_fork_state();
+    state
+        .apply(
+            [(3, Some(59)), (1, Some(0)), (2, None)].into_iter(),
+            id(0, 59),
+            &fork_tree,
+        )
+        .unwrap();
+
+    // A block and its parent are diffed from the changes of the block, where
+    // writing the value already set is no change.
+    assert_eq!(
+        state.diff(&id(0, 58), &id(0, 59), &fork_tree).unwrap(),
+        [(2, Some(58), None), (3, None, Some(59))]
+    );
+    assert_eq!(
+        state.diff(&id(0, 59), &id(0, 58), &fork_tree).unwrap(),
+        [(2, None, Some(58)), (3, Some(59), None)]
+    );
+
+    // Other blocks are diffed from all their entries, even one depth apart.
+    assert_eq!(
+        state.diff(&id(0, 57), &id(0, 59), &fork_tree).unwrap(),
+        [(3, None, Some(59))]
+    );
+    assert_eq!(
+        state.diff(&id(1, 50), &id(0, 59), &fork_tree).unwrap(),
+        [(1, Some(50), Some(0)), (3, None, Some(59))]
+    );
+    assert_eq!(
+        state.diff(&id(1, 49), &id(0, 50), &fork_tree).unwrap(),
+        [(1, Some(49), Some(0))]
+    );
+}
+
+#[test]
+fn reads_on_pruned_forks_fail() {
+    let (fork_tree, mut state) = deep_fork_state();
+    let pruning = state.plan_prune(&id(0, 5), &[], &fork_tree).unwrap();
+    FlatStatePrune::<MemoryForkTree<Block>>::prune(&mut state, pruning);
+
+    // Fork 1 split off at genesis, so its blocks lost their state, even
+    // above the finalized depth where its own changes are kept.
+    for number in [1, 5, 6, 50] {
+        assert!(matches!(
+            state.get(&1, &id(1, number), &fork_tree),
+            Err(MemoryFlatStateQueryError::Pruned)
+        ));
+        assert!(matches!(
+            state.changes_at(&id(1, number), &fork_tree),
+            Err(MemoryFlatStateQueryError::Pruned)
+        ));
+    }
+
+    // Canonical blocks read the compacted value.
+    for number in [0, 3, 5, 60] {
+        assert_eq!(state.get(&1, &id(0, number), &fork_tree).unwrap(), Some(0));
+    }
+}
+
+#[test]
+fn reads_below_pruned_finalized_see_its_state() {
+    let (fork_tree, mut state) = deep_fork_state();
+    state
+        .apply([(2, None)].into_iter(), id(0, 59), &fork_tree)
+        .unwrap();
+    let pruning = state.plan_prune(&id(0, 59), &[], &fork_tree).unwrap();
+    FlatStatePrune::<MemoryForkTree<Block>>::prune(&mut state, pruning);
+
+    // Block 58 set key 2, but the change is compacted into the deletion of
+    // block 59, which its ancestors read.
+    for number in [0, 58, 59] {
+        assert_eq!(state.get(&2, &id(0, number), &fork_tree).unwrap(), None);
+    }
+    assert_eq!(state.changes_at(&id(0, 58), &fork_tree).unwrap(), []);
+    assert_eq!(state.diff(&id(0, 57), &id(0, 58), &fork_tree).unwrap(), []);
+    assert_eq!(state.get(&1, &id(0, 60), &fork_tree).unwrap(), Some(0));
+}
+
+#[test]
+fn missing_ancestor_state_loaded_on_demand() {
+    let (fork_tree, state) = deep_fork_state();
+    assert_eq!(state.get(&9, &id(0, 60), &fork_tree).unwrap(), None);
+
+    // Stub of a fetch-on-demand backend, holding the values of keys 2 and 9
+    // set by ancestors whose state was not loaded in memory.
+    let backend = HashMap::from([(2, 200), (9, 900)]);
+    let mut state =
+        state.with_missing_ancestor_loader(move |key: &u32, _: &BlockId| backend.get(key).copied());
+    assert_eq!(state.get(&9, &id(0, 60), &fork_tree).unwrap(), Some(900));
+    assert_eq!(state.get(&2, &id(0, 40), &fork_tree).unwrap(), Some(200));
+
+    // Values held in memory, including deletions, shadow the loaded ones.
+    assert_eq!(state.get(&2, &id(0, 58), &fork_tree).unwrap(), Some(58));
+    state
+        .apply([(2, None)].into_iter(), id(0, 59), &fork_tree)
+        .unwrap();
+    assert_eq!(state.get(&2, &id(0, 60), &fork_tree).unwrap(), None);
+    assert_eq!(state.get(&1, &id(0, 60), &fork_tree).unwrap(), Some(0));
+}
+
+#[test]
+fn pruned_deletions_are_not_loaded_back() {
+    let (fork_tree, state) = deep_fork_state();
+    let backend = HashMap::from([(2, 200)]);
+    let mut state =
+        state.with_missing_ancestor_loader(move |key: &u32, _: &BlockId| backend.get(key).copied());
+    state
+        .apply([(2, None)].into_iter(), id(0, 59), &fork_tree)
+        .unwrap();
+    let pruning = state.plan_prune(&id(0, 59), &[], &fork_tree).unwrap();
+    FlatStatePrune::<MemoryForkTree<Block>>::prune(&mut state, pruning);
+
+    // The deletion at the finalized block still shadows the backend value.
+    for number in [59, 60] {
+        assert_eq!(state.get(&2, &id(0, number), &fork_tree).unwrap(), None);
+    }
+    assert_eq!(state.get(&1, &id(0, 60), &fork_tree).unwrap(), Some(0));
+}
+
+/// Blake2b-256 Merkleizer over little-endian encoded pairs, with a prefix
+/// byte per domain.
+struct Blake2Merkleizer;
+
+impl Merkleizer<u32, u32> for Blake2Merkleizer {
+    fn leaf(&self, key: &u32, value: &u32) -> [u8; 32] {
+        let mut encoded = vec![0];
+        encoded.extend(key.to_le_bytes());
+        encoded.extend(value.to_le_bytes());
+        BlockHash::digest(encoded).0
+    }
+
+    fn node(&self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        BlockHash::digest([[1].as_slice(), left.as_slice(), right.as_slice()].concat()).0
+    }
+
+    fn odd(&self, node: &[u8; 32]) -> [u8; 32] {
+        BlockHash::digest([[2].as_slice(), node.as_slice()].concat()).0
+    }
+}
+
+#[test]
+fn odd_nodes_are_hashed() {
+    let merkleizer = Blake2Merkleizer;
+    let leaves = (0..3)
+        .map(|key| merkleizer.leaf(&key, &key))
+        .collect::<Vec<_>>();
+    let inner = merkleizer.node(&leaves[0], &leaves[1]);
+
+    let root = merkleizer.root(leaves.clone());
+    assert_eq!(root, merkleizer.node(&inner, &merkleizer.odd(&leaves[2])));
+    // Neither the tree with its inner node as a leaf, nor the one with its
+    // odd leaf paired with itself, has the same root.
+    assert_ne!(root, merkleizer.root(vec![inner, leaves[2]]));
+    assert_ne!(
+        root,
+        merkleizer.root(vec![leaves[0], leaves[1], leaves[2], leaves[2]])
+    );
+}
+
+#[test]
+fn state_roots_follow_visible_state(
+) -> Result<(), MemoryFlatStateQueryError<MemoryForkTreeQueryError>> {
+    let mut fork_tree = MemoryForkTree::new();
+    let mut state = MemoryFlatState::new();
+
+    let genesis = id(0, 0);
+    fork_tree
+        .insert(Block {
+            id: genesis,
+            parent_id: None,
+        })
+        .unwrap();
+    state
+        .apply(
+            [(1, Some(1)), (2, Some(2))].into_iter(),
+            genesis,
+            &fork_tree,
+        )
+        .unwrap();
+
+    // Forks 1 and 2 make the same change, fork 3 a different one, and fork 4
+    // only removes a key that is not set.
+    let changes = [(1, Some(5)), (1, Some(5)), (1, Some(6)), (3, None)];
+    for (fork, (key, value)) in (1..).zip(changes) {
+        fork_tree
+            .insert(Block {
+                id: id(fork, 1),
+                parent_id: Some(genesis),
+            })
+            .unwrap();
+        state
+            .apply([(key, value)].into_iter(), id(fork, 1), &fork_tree)
+            .unwrap();
+    }
+
+    let mut cache = StateRootCache::new();
+    let mut root = |id| cache.state_root(&state, &id, &fork_tree, &Blake2Merkleizer);
+    let genesis_root = root(genesis)?;
+    assert_eq!(root(id(1, 1))?, root(id(2, 1))?);
+    assert_ne!(root(id(1, 1))?, root(id(3, 1))?);
+    assert_ne!(root(id(1, 1))?, genesis_root);
+    assert_eq!(root(id(4, 1))?, genesis_root);
+
+    assert_eq!(cache.get(&genesis), Some(genesis_root));
+    assert_eq!(
+        state.state_root(&genesis, &fork_tree, &Blake2Merkleizer)?,
+        genesis_root
+    );
+    // Leaves are in key order.
+    let merkleizer = Blake2Merkleizer;
+    assert_eq!(
+        genesis_root,
+        merkleizer.root(vec![merkleizer.leaf(&1, &1), merkleizer.leaf(&2, &2)])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn snapshots_do_not_depend_on_insertion_order(
+) -> Result<(), MemoryFlatStateQueryError<MemoryForkTreeQueryError>> {
+    let mut fork_tree = MemoryForkTree::new();
+    let genesis = id(0, 0);
+    let child = id(0, 1);
+    for (id, parent_id) in [(genesis, None), (child, Some(genesis))] {
+        fork_tree.insert(Block { id, parent_id }).unwrap();
+    }
+
+    // The same changes applied in opposite orders on two nodes.
+    let genesis_changes = (0..64).map(|key| (key, Some(key * 2))).collect::<Vec<_>>();
+    let child_changes = (0..64)
+        .step_by(3)
+        .map(|key| (key, (key % 2 == 0).then_some(key)))
+        .collect::<Vec<_>>();
+    let mut states = [MemoryFlatState::new(), MemoryFlatState::new()];
+    for (state, reversed) in states.iter_mut().zip([false, true]) {
+        for (id, changes) in [(genesis, &genesis_changes), (child, &child_changes)] {
+            let mut changes = changes.clone();
+            if reversed {
+                changes.reverse();
+            }
+            state.apply(changes.into_iter(), id, &fork_tree).unwrap();
+        }
+    }
+
+    let [first, second] = &states;
+    let snapshot = first.snapshot(&child, &fork_tree)?;
+    assert!(snapshot.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(snapshot, second.snapshot(&child, &fork_tree)?);
+    assert_eq!(
+        first.changes_at(&child, &fork_tree).unwrap(),
+        second.changes_at(&child, &fork_tree).unwrap()
+    );
+    assert_eq!(
+        first.state_root(&child, &fork_tree, &Blake2Merkleizer)?,
+        second.state_root(&child, &fork_tree, &Blake2Merkleizer)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn strict_parents_reject_apply_without_parent_state() {
+    let mut fork_tree = MemoryForkTree::new();
+    for number in 0..3 {
+        fork_tree
+            .insert(Block {
+                id: id(0, number),
+                parent_id: number.checked_sub(1).map(|parent| id(0, parent)),
+            })
+            .unwrap();
+    }
+
+    let mut strict = MemoryFlatState::<u32, u32, BlockId>::new().with_strict_parents();
+    strict
+        .apply([(1, Some(0))].into_iter(), id(0, 0), &fork_tree)
+        .unwrap();
+    // Block 1 is skipped, so block 2 has no parent state.
+    assert!(matches!(
+        strict.apply([(1, Some(2))].into_iter(), id(0, 2), &fork_tree),
+        Err(MemoryFlatStateApplyError::ParentStateMissing)
+    ));
+    assert_eq!(strict.changes_at(&id(0, 2), &fork_tree).unwrap(), []);
+
+    // An empty changeset still counts as the state of the block.
+    strict.apply([].into_iter(), id(0, 1), &fork_tree).unwrap();
+    strict
+        .apply([(1, Some(2))].into_iter(), id(0, 2), &fork_tree)
+        .unwrap();
+
+    let mut lenient = MemoryFlatState::<u32, u32, BlockId>::new();
+    lenient
+        .apply([(1, Some(0))].into_iter(), id(0, 0), &fork_tree)
+        .unwrap();
+    lenient
+        .apply([(1, Some(2))].into_iter(), id(0, 2), &fork_tree)
+        .unwrap();
+    assert_eq!(lenient.get(&1, &id(0, 1), &fork_tree).unwrap(), Some(0));
+}
@@ -0,0 +1,1222 @@
+//! Tests of the memory fork tree.
+
+use blockchain::memory::{
+    ForkChoice, KeyedMemoryForkTree, MemoryForkTree, MemoryForkTreeFinalizeError,
+    MemoryForkTreeInsertError, MemoryForkTreePruneError, MemoryForkTreeQueryError,
+    MemoryForkTreeRemoveError,
+};
+use blockchain::{
+    tree_route, ForkChoiceRule, ForkTree, ForkTreeBest, ForkTreeDepths, ForkTreeFinalize,
+    ForkTreeMut, ForkTreeRemoveLeaf, Headered, Identified, ImportBlock, ImportStatus, Keyed,
+    LongestChain, TreeRouteError, Weighted,
+};
+use std::{cmp::Ordering, collections::HashSet};
+
+mod common;
+use common::{fork, Block, BlockId};
+
+/// Header linking a block to its parent.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Header {
+    id: BlockId,
+    parent_id: Option<BlockId>,
+}
+
+impl Headered for Block {
+    type Header = Header;
+
+    fn header(&self) -> Header {
+        Header {
+            id: self.id,
+            parent_id: self.parent_id,
+        }
+    }
+}
+
+impl Keyed<u32> for Block {
+    fn key(&self) -> u32 {
+        self.id.number
+    }
+}
+
+impl Weighted for Block {
+    fn weight(&self) -> u64 {
+        (self.id.fork * 10 + self.id.number % 3 + 1) as u64
+    }
+}
+
+/// A canonical chain 0..=20, a fork 1 off block 5 up to 12, and a fork 2 off
+/// block 10 of fork 1 up to 15.
+fn forked_blocks() -> Vec<Block> {
+    let mut blocks = fork(None, 0, 0, 20);
+    blocks.extend(fork(Some(BlockId { fork: 0, number: 5 }), 1, 6, 12));
+    blocks.extend(fork(
+        Some(BlockId {
+            fork: 1,
+            number: 10,
+        }),
+        2,
+        11,
+        15,
+    ));
+    blocks
+}
+
+#[test]
+fn with_capacity_bulk_insert_matches_incremental() -> Result<(), MemoryForkTreeQueryError> {
+    let blocks = forked_blocks();
+
+    let mut incremental = MemoryForkTree::new();
+    for block in blocks.clone() {
+        incremental.insert(block).expect("insert succeeds");
+    }
+
+    let mut bulk = MemoryForkTree::with_capacity(blocks.len());
+    bulk.insert_batch(blocks.clone())
+        .expect("insert batch succeeds");
+
+    for block in &blocks {
+        let depth = incremental.block_depth(&block.id)?;
+        assert_eq!(bulk.block_depth(&block.id)?, depth);
+        assert_eq!(bulk.block(&block.id)?.parent_id, block.parent_id);
+
+        for ancestor_depth in 0..=depth {
+            assert_eq!(
+                bulk.ancestor_id_at_depth(&block.id, ancestor_depth)?,
+                incremental.ancestor_id_at_depth(&block.id, ancestor_depth)?,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn with_capacity_large_chain() -> Result<(), MemoryForkTreeQueryError> {
+    let count = 100_000;
+    let blocks = fork(None, 0, 0, count - 1);
+
+    let mut tree = MemoryForkTree::with_capacity(blocks.len());
+    tree.insert_batch(blocks).expect("insert batch succeeds");
+
+    let tip = BlockId {
+        fork: 0,
+        number: count - 1,
+    };
+    assert_eq!(tree.block_depth(&tip)?, (count - 1) as usize);
+    assert_eq!(
+        tree.ancestor_id_at_depth(&tip, 12345)?,
+        BlockId {
+            fork: 0,
+            number: 12345
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn ancestors_found_at_every_depth_below() -> Result<(), MemoryForkTreeQueryError> {
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert_batch(forked_blocks()).unwrap();
+
+    // The tip of fork 2 descends from fork 1 above block 5, and from fork 2
+    // above block 10.
+    let tip = BlockId {
+        fork: 2,
+        number: 15,
+    };
+    for depth in 0..=15 {
+        let fork = match depth {
+            0..=5 => 0,
+            6..=10 => 1,
+            _ => 2,
+        };
+        assert_eq!(
+            fork_tree.ancestor_id_at_depth(&tip, depth)?,
+            BlockId {
+                fork,
+                number: depth as u32
+            }
+        );
+    }
+    assert!(matches!(
+        fork_tree.ancestor_id_at_depth(&tip, 16),
+        Err(MemoryForkTreeQueryError::InvalidAncestorDepth)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn blocks_at_depth_across_forks() -> Result<(), MemoryForkTreeQueryError> {
+    let mut blocks = fork(None, 0, 0, 5);
+    blocks.extend(fork(Some(BlockId { fork: 0, number: 2 }), 1, 3, 4));
+
+    let mut tree = MemoryForkTree::new();
+    tree.insert_batch(blocks).expect("insert batch succeeds");
+
+    let mut at_three = tree.blocks_at_depth(3)?;
+    at_three.sort_by_key(|id| id.fork);
+    assert_eq!(
+        at_three,
+        vec![
+            BlockId { fork: 0, number: 3 },
+            BlockId { fork: 1, number: 3 }
+        ]
+    );
+    assert_eq!(
+        tree.blocks_at_depth(5)?,
+        vec![BlockId { fork: 0, number: 5 }]
+    );
+    assert!(tree.blocks_at_depth(6)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn to_dot_highlights_best() {
+    let mut blocks = fork(None, 0, 0, 2);
+    blocks.extend(fork(Some(BlockId { fork: 0, number: 1 }), 1, 2, 2));
+
+    let mut tree = MemoryForkTree::new();
+    tree.insert_batch(blocks).expect("insert batch succeeds");
+
+    let best = BlockId { fork: 1, number: 2 };
+    let finalized = BlockId { fork: 0, number: 1 };
+    let dot = tree.to_dot_highlighted(Some(&best), Some(&finalized));
+
+    let node = |id: BlockId| format!("\"{:?}\"", id);
+    let genesis = BlockId { fork: 0, number: 0 };
+    for child in [finalized, BlockId { fork: 0, number: 2 }, best] {
+        let parent = if child == finalized {
+            genesis
+        } else {
+            finalized
+        };
+        assert!(dot.contains(&format!("{} -> {};", node(parent), node(child))));
+    }
+    assert_eq!(dot.matches(" -> ").count(), 3);
+
+    let best_line = dot
+        .lines()
+        .find(|line| line.trim_start().starts_with(&format!("{} [", node(best))))
+        .expect("best node exists");
+    assert!(best_line.contains("#2"));
+    assert!(best_line.contains("color=red"));
+    assert_eq!(dot.matches("color=red").count(), 1);
+    assert_eq!(dot.matches("fillcolor=lightgrey").count(), 1);
+
+    // Without arguments, the equally good best blocks of the tree are
+    // highlighted, and no block is finalized yet.
+    let dot = tree.to_dot();
+    assert_eq!(dot.matches("color=red").count(), 2);
+    for tip in [BlockId { fork: 0, number: 2 }, best] {
+        assert!(dot.contains(&format!(
+            "{} [label=\"{:?}\\n#2\", color=red",
+            node(tip),
+            tip
+        )));
+    }
+    assert!(!dot.contains("fillcolor=lightgrey"));
+}
+
+#[test]
+fn monotonic_keys_reject_child_with_parent_number() {
+    let genesis = BlockId { fork: 0, number: 0 };
+    let repeated = Block {
+        id: BlockId { fork: 1, number: 0 },
+        parent_id: Some(genesis),
+    };
+
+    let mut tree = MemoryForkTree::new().with_monotonic_keys::<u32>();
+    tree.insert_batch(fork(None, 0, 0, 3))
+        .expect("insert succeeds");
+    assert!(matches!(
+        tree.insert(repeated.clone()),
+        Err(MemoryForkTreeInsertError::NonMonotonicKey)
+    ));
+    assert!(tree.block(&repeated.id).is_err());
+    assert!(tree.block_depth(&BlockId { fork: 0, number: 3 }).is_ok());
+
+    // Unchecked by default.
+    let mut tree = MemoryForkTree::new();
+    tree.insert_batch(fork(None, 0, 0, 3))
+        .expect("insert succeeds");
+    tree.insert(repeated).expect("insert succeeds");
+}
+
+#[test]
+fn header_chain_links_child_to_ancestor() -> Result<(), MemoryForkTreeQueryError> {
+    let mut tree = MemoryForkTree::new();
+    tree.insert_batch(forked_blocks()).expect("insert succeeds");
+
+    let from = BlockId {
+        fork: 2,
+        number: 15,
+    };
+    let ancestor = BlockId { fork: 0, number: 3 };
+    let headers = tree.header_chain(&from, &ancestor)?;
+
+    assert_eq!(headers.len(), 13);
+    assert_eq!(headers.first().map(|header| header.id), Some(from));
+    assert_eq!(headers.last().map(|header| header.id), Some(ancestor));
+    for pair in headers.windows(2) {
+        assert_eq!(pair[0].parent_id, Some(pair[1].id));
+    }
+
+    assert_eq!(tree.header_chain(&from, &from)?.len(), 1);
+
+    // Block 8 of the canonical chain is past the fork point.
+    assert!(matches!(
+        tree.header_chain(&from, &BlockId { fork: 0, number: 8 }),
+        Err(MemoryForkTreeQueryError::NotAncestor)
+    ));
+    assert!(matches!(
+        tree.header_chain(&ancestor, &from),
+        Err(MemoryForkTreeQueryError::NotAncestor)
+    ));
+
+    Ok(())
+}
+
+/// Weight of the chain up to a block, by walking its ancestors.
+fn reference_cumulative_weight(
+    fork_tree: &MemoryForkTree<Block>,
+    id: &BlockId,
+) -> Result<u128, MemoryForkTreeQueryError> {
+    let mut weight = 0;
+    let mut current = Some(*id);
+    while let Some(id) = current {
+        let block = fork_tree.block(&id)?;
+        weight += u128::from(block.weight());
+        current = block.parent_id();
+    }
+    Ok(weight)
+}
+
+#[test]
+fn cumulative_weights_match_recomputed() -> Result<(), MemoryForkTreeQueryError> {
+    let blocks = forked_blocks();
+    let mut fork_tree = MemoryForkTree::new().with_weights();
+    fork_tree.insert_batch(blocks.clone()).unwrap();
+
+    for block in &blocks {
+        assert_eq!(
+            fork_tree.cumulative_weight(&block.id)?,
+            reference_cumulative_weight(&fork_tree, &block.id)?
+        );
+    }
+
+    // Fork 2 is shorter than the canonical chain, but heavier.
+    let canonical_tip = BlockId {
+        fork: 0,
+        number: 20,
+    };
+    let fork_tip = BlockId {
+        fork: 2,
+        number: 15,
+    };
+    assert!(fork_tree.cumulative_weight(&fork_tip)? > fork_tree.cumulative_weight(&canonical_tip)?);
+
+    // Pruning the fork tip and building another one on its parent keeps the
+    // weights of the remaining blocks.
+    fork_tree.remove_leaf(&fork_tip).unwrap();
+    assert!(matches!(
+        fork_tree.cumulative_weight(&fork_tip),
+        Err(MemoryForkTreeQueryError::UnknownBlock)
+    ));
+    let reorg = fork(
+        Some(BlockId {
+            fork: 2,
+            number: 14,
+        }),
+        3,
+        15,
+        16,
+    );
+    fork_tree.insert_batch(reorg.clone()).unwrap();
+    for block in blocks
+        .iter()
+        .filter(|block| block.id != fork_tip)
+        .chain(&reorg)
+    {
+        assert_eq!(
+            fork_tree.cumulative_weight(&block.id)?,
+            reference_cumulative_weight(&fork_tree, &block.id)?
+        );
+    }
+
+    // Unweighted, a chain weighs its number of blocks.
+    let mut unweighted = MemoryForkTree::new();
+    unweighted.insert_batch(blocks).unwrap();
+    assert_eq!(unweighted.cumulative_weight(&canonical_tip)?, 21);
+
+    Ok(())
+}
+
+#[test]
+fn best_tie_broken_by_smallest_id() -> Result<(), MemoryForkTreeQueryError> {
+    // Two tips at depth 8: fork 0 and fork 1 off block 5.
+    let mut blocks = fork(None, 0, 0, 8);
+    blocks.extend(fork(Some(BlockId { fork: 0, number: 5 }), 1, 6, 8));
+    let mut reversed_forks = fork(None, 0, 0, 5);
+    reversed_forks.extend(fork(Some(BlockId { fork: 0, number: 5 }), 1, 6, 8));
+    reversed_forks.extend(fork(Some(BlockId { fork: 0, number: 5 }), 0, 6, 8));
+
+    // Nodes importing the same tree in different orders agree.
+    for blocks in [blocks, reversed_forks] {
+        let mut fork_tree = MemoryForkTree::new();
+        fork_tree.insert_batch(blocks).unwrap();
+        assert_eq!(fork_tree.best_id()?, BlockId { fork: 0, number: 8 });
+    }
+
+    Ok(())
+}
+
+#[test]
+fn finalizing_drops_conflicting_ties() -> Result<(), MemoryForkTreeQueryError> {
+    let id = |fork, number| BlockId { fork, number };
+    // Two tips at depth 3: fork 0, and fork 1 off block 1.
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert_batch(fork(None, 0, 0, 3)).unwrap();
+    fork_tree
+        .insert_batch(fork(Some(id(0, 1)), 1, 2, 3))
+        .unwrap();
+    assert_eq!(fork_tree.best_id()?, id(0, 3));
+
+    // Once fork 0 is finalized past the fork point, removing its tip leaves
+    // its parent as the best block, not the tip of fork 1.
+    fork_tree.finalize(&id(0, 2)).unwrap();
+    fork_tree.remove_leaf(&id(0, 3)).unwrap();
+    assert_eq!(fork_tree.best_id()?, id(0, 2));
+
+    Ok(())
+}
+
+/// A block whose identifier has no order.
+#[derive(Debug, Clone)]
+struct UnorderedBlock {
+    id: u32,
+    parent_id: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct UnorderedId(u32);
+
+impl Identified for UnorderedBlock {
+    type Identifier = UnorderedId;
+
+    fn id(&self) -> UnorderedId {
+        UnorderedId(self.id)
+    }
+
+    fn parent_id(&self) -> Option<UnorderedId> {
+        self.parent_id.map(UnorderedId)
+    }
+}
+
+#[test]
+fn unordered_identifiers_insert_and_remove() {
+    let mut fork_tree = MemoryForkTree::new();
+    for (id, parent_id) in [(0, None), (1, Some(0)), (2, Some(0))] {
+        ForkTreeMut::insert(&mut fork_tree, UnorderedBlock { id, parent_id }).unwrap();
+    }
+    fork_tree.remove_leaf(&UnorderedId(2)).unwrap();
+    assert_eq!(fork_tree.leaves().unwrap(), [UnorderedId(1)]);
+}
+
+#[test]
+fn switching_fork_choice_reorgs_to_new_best() {
+    let mut fork_tree = MemoryForkTree::new().with_weights();
+    assert_eq!(fork_tree.set_fork_choice(ForkChoice::Heaviest), None);
+    fork_tree.set_fork_choice(ForkChoice::Longest);
+    fork_tree.insert_batch(forked_blocks()).unwrap();
+
+    let id = |fork, number| BlockId { fork, number };
+    let canonical_tip = id(0, 20);
+    let fork_tip = id(2, 15);
+    assert_eq!(fork_tree.best_id().unwrap(), canonical_tip);
+
+    // Fork 2 is shorter than the canonical chain, but heavier.
+    let route = fork_tree
+        .set_fork_choice(ForkChoice::Heaviest)
+        .expect("tree is not empty");
+    assert_eq!(fork_tree.best_id().unwrap(), fork_tip);
+    assert_eq!(route.common, id(0, 5));
+    assert_eq!(
+        route.retracted,
+        (6..=20)
+            .rev()
+            .map(|number| id(0, number))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        route.enacted,
+        (6..=10)
+            .map(|number| id(1, number))
+            .chain((11..=15).map(|number| id(2, number)))
+            .collect::<Vec<_>>()
+    );
+
+    // Switching back reverses the reorg, and switching to the same rule
+    // changes nothing.
+    let back = fork_tree
+        .set_fork_choice(ForkChoice::Longest)
+        .expect("tree is not empty");
+    assert_eq!(fork_tree.best_id().unwrap(), canonical_tip);
+    assert_eq!(
+        back.retracted,
+        route.enacted.into_iter().rev().collect::<Vec<_>>()
+    );
+    let unchanged = fork_tree
+        .set_fork_choice(ForkChoice::Longest)
+        .expect("tree is not empty");
+    assert!(!unchanged.is_reorg() && unchanged.enacted.is_empty());
+}
+
+#[test]
+fn finalized_block_rejects_conflicting_inserts() {
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert_batch(forked_blocks()).unwrap();
+    let id = |fork, number| BlockId { fork, number };
+    let block = |fork, number, parent_id| Block {
+        id: id(fork, number),
+        parent_id: Some(parent_id),
+    };
+    assert_eq!(fork_tree.finalized_id(), None);
+
+    // Fork 1 is not on the way to the best block.
+    assert!(matches!(
+        fork_tree.finalize(&id(1, 8)),
+        Err(MemoryForkTreeFinalizeError::NotBestAncestor)
+    ));
+    fork_tree.finalize(&id(0, 8)).unwrap();
+    assert_eq!(fork_tree.finalized_id(), Some(id(0, 8)));
+    assert!(matches!(
+        fork_tree.finalize(&id(0, 6)),
+        Err(MemoryForkTreeFinalizeError::NotFinalizedDescendant)
+    ));
+
+    // Forks off the finalized chain, before or at the finalized depth, and
+    // new genesis blocks conflict.
+    for (block, parent_id) in [
+        (block(3, 6, id(0, 5)), id(0, 5)),
+        (block(3, 8, id(0, 7)), id(0, 7)),
+        (block(3, 13, id(1, 12)), id(1, 12)),
+    ] {
+        assert!(
+            matches!(
+                fork_tree.insert(block),
+                Err(MemoryForkTreeInsertError::ConflictsFinalized)
+            ),
+            "block off {parent_id:?}"
+        );
+    }
+    assert!(matches!(
+        fork_tree.insert(Block {
+            id: id(4, 0),
+            parent_id: None,
+        }),
+        Err(MemoryForkTreeInsertError::ConflictsFinalized)
+    ));
+
+    // Descendants of the finalized block are accepted, and it stays.
+    fork_tree.insert(block(5, 9, id(0, 8))).unwrap();
+    fork_tree.insert(block(0, 21, id(0, 20))).unwrap();
+    fork_tree.finalize(&id(0, 21)).unwrap();
+    assert!(matches!(
+        fork_tree.remove_leaf(&id(0, 21)),
+        Err(MemoryForkTreeRemoveError::Finalized)
+    ));
+}
+
+#[test]
+fn prune_below_removes_losing_forks() -> Result<(), MemoryForkTreePruneError> {
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert_batch(forked_blocks()).unwrap();
+    let id = |fork, number| BlockId { fork, number };
+    // Fork 2 grows past the canonical chain, to be finalized.
+    fork_tree
+        .insert_batch(fork(Some(id(2, 15)), 2, 16, 21))
+        .unwrap();
+
+    // Only the finalized block or its ancestors can be pruned at.
+    assert!(matches!(
+        fork_tree.prune_below(&id(2, 14)),
+        Err(MemoryForkTreePruneError::NotFinalized)
+    ));
+    fork_tree.finalize(&id(2, 14)).unwrap();
+    for not_finalized in [id(2, 15), id(0, 6)] {
+        assert!(matches!(
+            fork_tree.prune_below(&not_finalized),
+            Err(MemoryForkTreePruneError::NotFinalized)
+        ));
+    }
+
+    // Finalizing block 14 of fork 2 discards the canonical chain from 6.
+    let removed = fork_tree.prune_below(&id(2, 14))?;
+    let expected = (6..=20)
+        .map(|number| id(0, number))
+        .chain([id(1, 11), id(1, 12)])
+        .collect::<HashSet<_>>();
+    assert_eq!(removed, expected);
+    for removed_id in &removed {
+        assert!(fork_tree.block(removed_id).is_err());
+    }
+
+    // Ancestors and descendants stay, and depths only list them.
+    for kept_id in (0..=5)
+        .map(|number| id(0, number))
+        .chain((6..=10).map(|number| id(1, number)))
+        .chain((11..=21).map(|number| id(2, number)))
+    {
+        assert!(fork_tree.block(&kept_id).is_ok(), "{kept_id:?} is kept");
+    }
+    assert_eq!(fork_tree.blocks_at_depth(11)?, [id(2, 11)]);
+    assert!(fork_tree.blocks_at_depth(22)?.is_empty());
+    assert_eq!(fork_tree.best_id()?, id(2, 21));
+    assert!(fork_tree.prune_below(&id(2, 14))?.is_empty());
+    assert!(fork_tree.prune_below(&id(0, 3))?.is_empty());
+
+    Ok(())
+}
+
+/// A fork tree walking the memory fork tree for its leaves, with the default
+/// of the trait.
+struct Walked<'a>(&'a MemoryForkTree<Block>);
+
+impl ForkTree for Walked<'_> {
+    type Block = Block;
+    type QueryError = MemoryForkTreeQueryError;
+
+    fn block(&self, id: &BlockId) -> Result<Block, Self::QueryError> {
+        self.0.block(id)
+    }
+
+    fn block_depth(&self, id: &BlockId) -> Result<usize, Self::QueryError> {
+        self.0.block_depth(id)
+    }
+
+    fn ancestor_id_at_depth(
+        &self,
+        id: &BlockId,
+        ancestor_depth: usize,
+    ) -> Result<BlockId, Self::QueryError> {
+        self.0.ancestor_id_at_depth(id, ancestor_depth)
+    }
+}
+
+impl ForkTreeDepths for Walked<'_> {
+    fn blocks_at_depth(&self, depth: usize) -> Result<Vec<BlockId>, Self::QueryError> {
+        self.0.blocks_at_depth(depth)
+    }
+}
+
+#[test]
+fn leaves_follow_forked_inserts() {
+    let mut fork_tree = MemoryForkTree::new();
+    let leaves = |fork_tree: &MemoryForkTree<Block>| {
+        let mut leaves = fork_tree.leaves().unwrap();
+        leaves.sort();
+        let mut walked = Walked(fork_tree).leaves().unwrap();
+        walked.sort();
+        assert_eq!(leaves, walked);
+        leaves
+    };
+    assert!(leaves(&fork_tree).is_empty());
+
+    for block in forked_blocks() {
+        fork_tree.insert(block).unwrap();
+    }
+    assert_eq!(
+        leaves(&fork_tree),
+        [
+            BlockId {
+                fork: 0,
+                number: 20
+            },
+            BlockId {
+                fork: 1,
+                number: 12
+            },
+            BlockId {
+                fork: 2,
+                number: 15
+            },
+        ]
+    );
+
+    // Removing the tip of fork 1 makes its parent a leaf.
+    let tip = BlockId {
+        fork: 1,
+        number: 12,
+    };
+    fork_tree.remove_leaf(&tip).unwrap();
+    assert_eq!(
+        leaves(&fork_tree),
+        [
+            BlockId {
+                fork: 0,
+                number: 20
+            },
+            BlockId {
+                fork: 1,
+                number: 11
+            },
+            BlockId {
+                fork: 2,
+                number: 15
+            },
+        ]
+    );
+
+    // Extending a leaf replaces it.
+    fork_tree
+        .insert(Block {
+            id: BlockId {
+                fork: 3,
+                number: 21,
+            },
+            parent_id: Some(BlockId {
+                fork: 0,
+                number: 20,
+            }),
+        })
+        .unwrap();
+    assert!(!leaves(&fork_tree).contains(&BlockId {
+        fork: 0,
+        number: 20
+    }));
+}
+
+/// A rule preferring the blocks of a fork, then the longest chain.
+struct PreferFork(u32);
+
+impl ForkChoiceRule<MemoryForkTree<Block>> for PreferFork {
+    fn compare(
+        &self,
+        fork_tree: &MemoryForkTree<Block>,
+        current_best: &Block,
+        candidate: &Block,
+    ) -> Result<Ordering, MemoryForkTreeQueryError> {
+        let preferred = |block: &Block| block.id.fork == self.0;
+        Ok(match (preferred(current_best), preferred(candidate)) {
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            _ => LongestChain.compare(fork_tree, current_best, candidate)?,
+        })
+    }
+}
+
+/// Insert the forked blocks, tracking the best block by the rule. Returns
+/// the tree, the best block, and the blocks that were the new best block.
+fn track_best<R>(rule: &R) -> (MemoryForkTree<Block>, BlockId, Vec<BlockId>)
+where
+    R: ForkChoiceRule<MemoryForkTree<Block>>,
+{
+    let mut fork_tree = MemoryForkTree::new();
+    let mut best = None;
+    let mut new_bests = Vec::new();
+    for block in forked_blocks() {
+        let id = block.id;
+        if fork_tree
+            .insert_with_choice(block, best.as_ref(), rule)
+            .unwrap()
+        {
+            best = Some(id);
+            new_bests.push(id);
+        }
+    }
+    (fork_tree, best.unwrap(), new_bests)
+}
+
+#[test]
+fn insert_with_choice_tracks_best_by_rule() {
+    // The longest chain agrees with the tree's own fork choice, and fork 1
+    // never takes over, as it is shorter.
+    let (fork_tree, best, new_bests) = track_best(&LongestChain);
+    assert_eq!(best, fork_tree.best_id().unwrap());
+    assert_eq!(new_bests.len(), 21);
+    assert!(new_bests.iter().all(|id| id.fork == 0));
+
+    // Swapping the rule moves the best block to the preferred fork as soon
+    // as it appears, however short.
+    let (_, best, new_bests) = track_best(&PreferFork(1));
+    assert_eq!(
+        best,
+        BlockId {
+            fork: 1,
+            number: 12
+        }
+    );
+    // Every block of fork 1 extends the best chain.
+    assert_eq!(new_bests.iter().filter(|id| id.fork == 1).count(), 7);
+}
+
+#[test]
+fn reorg_lists_blocks_from_fork_point() -> Result<(), TreeRouteError<MemoryForkTreeQueryError>> {
+    let mut fork_tree = MemoryForkTree::new();
+    for block in forked_blocks() {
+        fork_tree.insert(block).unwrap();
+    }
+    let id = |fork, number| BlockId { fork, number };
+    let ids = |fork, numbers: std::ops::RangeInclusive<u32>| {
+        numbers.map(|number| id(fork, number)).collect::<Vec<_>>()
+    };
+
+    // Switching from the tip of fork 2 to block 7 of the canonical chain
+    // rolls back to block 5, the fork point of fork 1.
+    let (retracted, enacted) = fork_tree.reorg(&id(2, 13), &id(0, 7))?;
+    let mut expected = ids(1, 6..=10);
+    expected.extend(ids(2, 11..=13));
+    assert_eq!(retracted, expected);
+    assert_eq!(enacted, ids(0, 6..=7));
+
+    let (retracted, enacted) = fork_tree.reorg(&id(0, 3), &id(1, 8))?;
+    assert!(retracted.is_empty());
+    let mut expected = ids(0, 4..=5);
+    expected.extend(ids(1, 6..=8));
+    assert_eq!(enacted, expected);
+
+    let (retracted, enacted) = fork_tree.reorg(&id(1, 12), &id(1, 12))?;
+    assert!(retracted.is_empty() && enacted.is_empty());
+    Ok(())
+}
+
+#[test]
+fn routes_between_genesis_blocks_fail() {
+    let id = |fork, number| BlockId { fork, number };
+    let mut fork_tree = MemoryForkTree::new();
+    for block in fork(None, 0, 0, 5) {
+        assert!(fork_tree.import(block).is_ok());
+    }
+
+    // A second genesis, whose chain only takes over once deeper.
+    for block in fork(None, 1, 0, 5) {
+        assert!(fork_tree.import(block).is_ok());
+    }
+    assert!(matches!(
+        tree_route(&fork_tree, &id(0, 5), &id(1, 3)),
+        Err(TreeRouteError::NoCommonAncestor)
+    ));
+    assert!(matches!(
+        fork_tree.reorg(&id(1, 0), &id(0, 0)),
+        Err(TreeRouteError::NoCommonAncestor)
+    ));
+    // The import moving the best block under the second genesis succeeds,
+    // with no route to report from the previous best block.
+    let outcome = fork_tree
+        .import(Block {
+            id: id(1, 6),
+            parent_id: Some(id(1, 5)),
+        })
+        .unwrap();
+    assert_eq!(outcome.status, ImportStatus::Imported);
+    assert!(outcome.new_best);
+    assert_eq!(outcome.reorg, None);
+    assert_eq!(fork_tree.best_id().unwrap(), id(1, 6));
+}
+
+#[test]
+fn canonical_chain_walks_best_back_to_genesis() {
+    fn ids(blocks: impl Iterator<Item = Block>) -> Vec<BlockId> {
+        blocks.map(|block| block.id).collect()
+    }
+
+    let mut fork_tree = MemoryForkTree::new();
+    assert!(fork_tree.canonical_chain().next().is_none());
+
+    let genesis = BlockId { fork: 0, number: 0 };
+    fork_tree.insert(fork(None, 0, 0, 0).remove(0)).unwrap();
+    assert_eq!(ids(fork_tree.canonical_chain()), [genesis]);
+
+    for block in forked_blocks().into_iter().skip(1) {
+        fork_tree.insert(block).unwrap();
+    }
+    let canonical = ids(fork_tree.canonical_chain());
+    assert_eq!(canonical.len(), 21);
+    assert_eq!(
+        canonical[0],
+        BlockId {
+            fork: 0,
+            number: 20
+        }
+    );
+    assert_eq!(canonical.last(), Some(&genesis));
+
+    let ancestry = ids(fork_tree.ancestry(&BlockId {
+        fork: 2,
+        number: 12,
+    }));
+    let mut expected = vec![
+        BlockId {
+            fork: 2,
+            number: 12,
+        },
+        BlockId {
+            fork: 2,
+            number: 11,
+        },
+    ];
+    expected.extend((6..=10).rev().map(|number| BlockId { fork: 1, number }));
+    expected.extend((0..=5).rev().map(|number| BlockId { fork: 0, number }));
+    assert_eq!(ancestry, expected);
+    assert!(fork_tree
+        .ancestry(&BlockId { fork: 9, number: 0 })
+        .next()
+        .is_none());
+}
+
+#[test]
+fn blocks_looked_up_by_key_across_forks() {
+    let mut fork_tree = KeyedMemoryForkTree::<Block, u32>::default();
+    for block in forked_blocks() {
+        fork_tree.insert(block).unwrap();
+    }
+    let by_number = |fork_tree: &KeyedMemoryForkTree<Block, u32>, number: u32| {
+        let mut ids = fork_tree.block_by_key(&number);
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(
+        by_number(&fork_tree, 11),
+        [
+            BlockId {
+                fork: 0,
+                number: 11
+            },
+            BlockId {
+                fork: 1,
+                number: 11
+            },
+            BlockId {
+                fork: 2,
+                number: 11
+            },
+        ]
+    );
+    assert_eq!(by_number(&fork_tree, 3), [BlockId { fork: 0, number: 3 }]);
+    assert!(by_number(&fork_tree, 21).is_empty());
+
+    // Removed blocks leave the index.
+    fork_tree
+        .remove_leaf(&BlockId {
+            fork: 0,
+            number: 20,
+        })
+        .unwrap();
+    assert!(by_number(&fork_tree, 20).is_empty());
+    fork_tree
+        .finalize(&BlockId {
+            fork: 0,
+            number: 19,
+        })
+        .unwrap();
+    fork_tree
+        .prune_below(&BlockId {
+            fork: 0,
+            number: 19,
+        })
+        .unwrap();
+    assert_eq!(
+        by_number(&fork_tree, 11),
+        [BlockId {
+            fork: 0,
+            number: 11
+        }]
+    );
+
+    // Blocks already in the tree are indexed on wrapping it.
+    let mut unkeyed = MemoryForkTree::new();
+    unkeyed.insert_batch(forked_blocks()).unwrap();
+    let wrapped = KeyedMemoryForkTree::<Block, u32>::new(unkeyed);
+    assert_eq!(by_number(&wrapped, 11).len(), 3);
+    assert_eq!(
+        wrapped.tree().blocks_in_depth_range(0, usize::MAX).len(),
+        33
+    );
+}
+
+#[test]
+fn committed_blocks_are_indexed_by_key() {
+    use blockchain::ForkTreeTransactional;
+
+    let mut fork_tree = KeyedMemoryForkTree::<Block, u32>::default();
+    fork_tree.insert_batch(fork(None, 0, 0, 2)).unwrap();
+
+    let mut transaction = ForkTreeTransactional::begin(&fork_tree);
+    for block in fork(Some(BlockId { fork: 0, number: 1 }), 1, 2, 3) {
+        ForkTreeTransactional::insert(&fork_tree, &mut transaction, block).unwrap();
+    }
+    assert_eq!(fork_tree.block_by_key(&3), []);
+
+    ForkTreeTransactional::commit(&mut fork_tree, transaction).unwrap();
+    assert_eq!(
+        fork_tree.block_by_key(&2),
+        [
+            BlockId { fork: 0, number: 2 },
+            BlockId { fork: 1, number: 2 }
+        ]
+    );
+    assert_eq!(fork_tree.block_by_key(&3), [BlockId { fork: 1, number: 3 }]);
+}
+
+#[test]
+fn parent_ids_include_transaction_blocks() {
+    use blockchain::ForkTreeTransactional;
+
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert_batch(fork(None, 0, 0, 1)).unwrap();
+    let mut transaction = ForkTreeTransactional::begin(&fork_tree);
+    for block in fork(Some(BlockId { fork: 0, number: 1 }), 1, 2, 2) {
+        ForkTreeTransactional::insert(&fork_tree, &mut transaction, block).unwrap();
+    }
+
+    let (genesis, tip) = (
+        BlockId { fork: 0, number: 0 },
+        BlockId { fork: 0, number: 1 },
+    );
+    let child = BlockId { fork: 1, number: 2 };
+    assert_eq!(fork_tree.parent_id(&genesis).unwrap(), None);
+    assert_eq!(fork_tree.parent_id(&tip).unwrap(), Some(genesis));
+    assert!(matches!(
+        fork_tree.parent_id(&child),
+        Err(MemoryForkTreeQueryError::UnknownBlock)
+    ));
+    assert_eq!(
+        fork_tree
+            .transaction_parent_id(&transaction, &child)
+            .unwrap(),
+        Some(tip)
+    );
+    assert_eq!(
+        fork_tree.transaction_parent_id(&transaction, &tip).unwrap(),
+        Some(genesis)
+    );
+}
+
+#[test]
+fn blocks_enumerated_by_depth_range() {
+    let mut fork_tree = MemoryForkTree::new();
+    assert_eq!(fork_tree.max_depth(), None);
+    assert!(fork_tree.blocks_in_depth_range(0, 10).is_empty());
+
+    for block in forked_blocks() {
+        fork_tree.insert(block).unwrap();
+    }
+    assert_eq!(fork_tree.max_depth(), Some(20));
+
+    let id = |fork, number| BlockId { fork, number };
+    assert_eq!(
+        fork_tree.blocks_in_depth_range(10, 12),
+        [id(0, 10), id(1, 10), id(0, 11), id(1, 11), id(2, 11)]
+    );
+    assert_eq!(fork_tree.blocks_in_depth_range(0, 1), [id(0, 0)]);
+    assert_eq!(
+        fork_tree.blocks_in_depth_range(19, usize::MAX),
+        [id(0, 19), id(0, 20)]
+    );
+    assert!(fork_tree.blocks_in_depth_range(12, 12).is_empty());
+    assert!(fork_tree.blocks_in_depth_range(21, 30).is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serialized_tree_round_trips() -> Result<(), MemoryForkTreeQueryError> {
+    let mut fork_tree = MemoryForkTree::new();
+    for block in forked_blocks() {
+        fork_tree.insert(block).unwrap();
+    }
+    fork_tree.finalize(&BlockId { fork: 0, number: 3 }).unwrap();
+
+    let json = serde_json::to_string(&fork_tree).unwrap();
+    let restored = serde_json::from_str::<MemoryForkTree<Block>>(&json).unwrap();
+
+    assert_eq!(restored.best_id()?, fork_tree.best_id()?);
+    assert_eq!(restored.finalized_id(), fork_tree.finalized_id());
+    for id in [
+        BlockId { fork: 0, number: 0 },
+        BlockId { fork: 1, number: 9 },
+        BlockId {
+            fork: 2,
+            number: 15,
+        },
+    ] {
+        assert_eq!(restored.block_depth(&id)?, fork_tree.block_depth(&id)?);
+    }
+    assert_eq!(
+        restored.blocks_in_depth_range(0, usize::MAX),
+        fork_tree.blocks_in_depth_range(0, usize::MAX)
+    );
+    let mut leaves = restored.leaves()?;
+    leaves.sort();
+    assert_eq!(
+        leaves,
+        [
+            BlockId {
+                fork: 0,
+                number: 20
+            },
+            BlockId {
+                fork: 1,
+                number: 12
+            },
+            BlockId {
+                fork: 2,
+                number: 15
+            },
+        ]
+    );
+    // Serializing is the same after the round trip, in depth order.
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+
+    // A keyed tree is serialized as its tree, and indexed again.
+    let keyed = KeyedMemoryForkTree::<Block, u32>::new(restored);
+    assert_eq!(serde_json::to_string(&keyed).unwrap(), json);
+    let keyed = serde_json::from_str::<KeyedMemoryForkTree<Block, u32>>(&json).unwrap();
+    assert_eq!(keyed.block_by_key(&11).len(), 3);
+
+    assert!(
+        serde_json::from_str::<MemoryForkTree<Block>>(&json.replacen(
+            "\"number\":20",
+            "\"number\":21",
+            1
+        ))
+        .is_err()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn best_follows_removals_down_to_an_empty_tree() {
+    let mut fork_tree = MemoryForkTree::new();
+    assert!(matches!(
+        fork_tree.best_id(),
+        Err(MemoryForkTreeQueryError::UnknownBlock)
+    ));
+    for block in fork(None, 0, 0, 2) {
+        fork_tree.insert(block).unwrap();
+    }
+    fork_tree
+        .insert(Block {
+            id: BlockId { fork: 1, number: 1 },
+            parent_id: Some(BlockId { fork: 0, number: 0 }),
+        })
+        .unwrap();
+
+    fork_tree
+        .remove_leaf(&BlockId { fork: 0, number: 2 })
+        .unwrap();
+    assert_eq!(fork_tree.best_id().unwrap(), BlockId { fork: 0, number: 1 });
+    for id in [
+        BlockId { fork: 0, number: 1 },
+        BlockId { fork: 1, number: 1 },
+        BlockId { fork: 0, number: 0 },
+    ] {
+        fork_tree.remove_leaf(&id).unwrap();
+    }
+    assert!(matches!(
+        fork_tree.best_id(),
+        Err(MemoryForkTreeQueryError::UnknownBlock)
+    ));
+
+    // Importing into the emptied tree starts over rather than panic.
+    let outcome = fork_tree
+        .import(Block {
+            id: BlockId { fork: 2, number: 0 },
+            parent_id: None,
+        })
+        .unwrap();
+    assert!(outcome.new_best);
+    assert_eq!(fork_tree.best_id().unwrap(), BlockId { fork: 2, number: 0 });
+}
+
+#[test]
+fn heaviest_by_inserted_weights() -> Result<(), MemoryForkTreeQueryError> {
+    let id = |fork, number| BlockId { fork, number };
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.set_fork_choice(ForkChoice::Heaviest);
+
+    // A long canonical chain with little stake voting for it, and a shorter
+    // fork off genesis with more.
+    for (block, weight) in fork(None, 0, 0, 5).into_iter().zip([10, 1, 1, 1, 1, 1]) {
+        fork_tree.insert_with_weight(block, weight).unwrap();
+    }
+    for (block, weight) in fork(Some(id(0, 0)), 1, 1, 2).into_iter().zip([3, 3]) {
+        fork_tree.insert_with_weight(block, weight).unwrap();
+    }
+    assert_eq!(fork_tree.cumulative_weight(&id(0, 5))?, 15);
+    assert_eq!(fork_tree.cumulative_weight(&id(1, 2))?, 16);
+    assert_eq!(fork_tree.best_id()?, id(1, 2));
+
+    // Equally heavy, the deeper chain wins.
+    fork_tree
+        .insert_with_weight(
+            Block {
+                id: id(0, 6),
+                parent_id: Some(id(0, 5)),
+            },
+            1,
+        )
+        .unwrap();
+    assert_eq!(fork_tree.cumulative_weight(&id(0, 6))?, 16);
+    assert_eq!(fork_tree.best_id()?, id(0, 6));
+
+    // Inserting a block again leaves its weight and children as they were.
+    let again = Block {
+        id: id(0, 5),
+        parent_id: Some(id(0, 4)),
+    };
+    assert!(matches!(
+        fork_tree.insert_with_weight(again.clone(), u64::MAX),
+        Err(MemoryForkTreeInsertError::AlreadyInserted)
+    ));
+    assert!(matches!(
+        ForkTreeMut::insert(&mut fork_tree, again),
+        Err(MemoryForkTreeInsertError::AlreadyInserted)
+    ));
+    assert_eq!(fork_tree.cumulative_weight(&id(0, 5))?, 15);
+    assert_eq!(fork_tree.blocks_at_depth(5)?, vec![id(0, 5)]);
+    assert_eq!(fork_tree.best_id()?, id(0, 6));
+
+    Ok(())
+}
+
+#[test]
+fn cumulative_weights_exceed_block_weights() -> Result<(), MemoryForkTreeQueryError> {
+    let mut fork_tree = MemoryForkTree::new();
+    for block in fork(None, 0, 0, 2) {
+        fork_tree.insert_with_weight(block, u64::MAX).unwrap();
+    }
+
+    let tip = BlockId { fork: 0, number: 2 };
+    assert_eq!(fork_tree.cumulative_weight(&tip)?, 3 * u128::from(u64::MAX));
+
+    Ok(())
+}
+
+#[test]
+fn best_ignores_forks_conflicting_finalized() -> Result<(), MemoryForkTreeQueryError> {
+    let id = |fork, number| BlockId { fork, number };
+    let mut fork_tree = MemoryForkTree::new();
+    for block in fork(None, 0, 0, 3) {
+        fork_tree.insert_with_weight(block, 1).unwrap();
+    }
+    for block in fork(Some(id(0, 0)), 1, 1, 2) {
+        fork_tree.insert_with_weight(block, 10).unwrap();
+    }
+    fork_tree.finalize(&id(0, 2)).unwrap();
+
+    // Fork 1 is the heaviest, but conflicts with the finalized block.
+    fork_tree.set_fork_choice(ForkChoice::Heaviest);
+    assert_eq!(fork_tree.cumulative_weight(&id(1, 2))?, 21);
+    assert_eq!(fork_tree.best_id()?, id(0, 3));
+
+    fork_tree.remove_leaf(&id(0, 3)).unwrap();
+    assert_eq!(fork_tree.best_id()?, id(0, 2));
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Tests of importing headers ahead of their bodies.
+
+use blockchain::memory::{MemoryHeaderChain, MemoryHeaderChainError};
+use blockchain::{Headered, Identified, ImportBlock, ImportHeader, ImportOutcome, ImportStatus};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    number: u32,
+    parent: Option<u32>,
+    /// Commitment to the body.
+    extrinsics_root: u64,
+}
+
+impl Identified for Header {
+    type Identifier = u32;
+
+    fn id(&self) -> u32 {
+        self.number
+    }
+
+    fn parent_id(&self) -> Option<u32> {
+        self.parent
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    number: u32,
+    extrinsics: Vec<u64>,
+}
+
+impl Identified for Block {
+    type Identifier = u32;
+
+    fn id(&self) -> u32 {
+        self.number
+    }
+
+    fn parent_id(&self) -> Option<u32> {
+        self.number.checked_sub(1)
+    }
+}
+
+impl Headered for Block {
+    type Header = Header;
+
+    fn header(&self) -> Header {
+        Header {
+            number: self.number,
+            parent: self.parent_id(),
+            extrinsics_root: self.extrinsics.iter().sum(),
+        }
+    }
+}
+
+fn block(number: u32, extrinsics: Vec<u64>) -> Block {
+    Block { number, extrinsics }
+}
+
+#[test]
+fn bodies_reconcile_with_imported_headers() {
+    let mut chain = MemoryHeaderChain::<Block>::new();
+    chain.import(block(0, Vec::new())).unwrap();
+    for number in 1..=3 {
+        chain
+            .import_header(block(number, vec![number as u64]).header())
+            .unwrap();
+    }
+    assert_eq!(chain.missing_bodies(), [1, 2, 3]);
+
+    // The header is already the best chain, so the body changes no best.
+    assert_eq!(
+        chain.import(block(2, vec![2])).unwrap(),
+        ImportOutcome::unchanged(ImportStatus::Imported)
+    );
+    assert_eq!(chain.missing_bodies(), [1, 3]);
+    assert_eq!(chain.block(&2).unwrap().extrinsics, [2]);
+    assert!(chain.block(&1).is_none());
+}
+
+#[test]
+fn mismatched_body_rejected() {
+    let mut chain = MemoryHeaderChain::<Block>::new();
+    chain.import(block(0, Vec::new())).unwrap();
+    chain.import_header(block(1, vec![1, 2]).header()).unwrap();
+
+    assert!(matches!(
+        chain.import(block(1, vec![4])),
+        Err(MemoryHeaderChainError::HeaderMismatch)
+    ));
+    assert_eq!(chain.missing_bodies(), [1]);
+    assert!(chain.block(&1).is_none());
+
+    // Headers still need a known parent.
+    assert!(matches!(
+        chain.import_header(block(5, Vec::new()).header()),
+        Err(MemoryHeaderChainError::Insert(_))
+    ));
+}
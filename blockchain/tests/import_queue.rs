@@ -0,0 +1,157 @@
+//! Tests of the import queue in front of the memory fork tree.
+
+use blockchain::memory::{MemoryForkTree, MemoryForkTreeInsertError};
+use blockchain::{import_queue, ForkTree, ForkTreeMut, Identified, ImportEvent, OrphanPool};
+use futures::{executor::block_on, StreamExt};
+
+mod common;
+use common::{id, Block, BlockId};
+
+fn block(fork: u32, number: u32, parent: BlockId) -> Block {
+    Block {
+        id: id(fork, number),
+        parent_id: Some(parent),
+    }
+}
+
+#[test]
+fn batch_imported_with_best_tracking() {
+    let mut tree = MemoryForkTree::new();
+    tree.insert(Block {
+        id: id(0, 0),
+        parent_id: None,
+    })
+    .unwrap();
+
+    let (mut queue, worker, events) = import_queue(tree, 2).unwrap();
+    let worker = std::thread::spawn(move || block_on(worker.run()));
+
+    let blocks = vec![
+        block(0, 1, id(0, 0)),
+        block(0, 2, id(0, 1)),
+        // Unknown parent.
+        block(2, 9, id(2, 8)),
+        block(1, 2, id(0, 1)),
+        block(1, 3, id(1, 2)),
+        block(0, 3, id(0, 2)),
+    ];
+    let ids = blocks.iter().map(Identified::id).collect::<Vec<_>>();
+
+    block_on(async {
+        for block in blocks {
+            queue.push(block).await.unwrap();
+        }
+    });
+    drop(queue);
+
+    let tree = worker.join().unwrap();
+    let events = block_on(events.collect::<Vec<_>>());
+    assert_eq!(events.len(), ids.len());
+
+    let mut failed = Vec::new();
+    let mut bests = Vec::new();
+    for (event, expected_id) in events.into_iter().zip(&ids) {
+        match event {
+            ImportEvent::Imported {
+                id,
+                best,
+                new_best,
+                reorg,
+            } => {
+                assert_eq!(id, *expected_id);
+                bests.push((best, new_best, reorg));
+            }
+            ImportEvent::Queued { .. } => panic!("the fork tree buffers no block"),
+            ImportEvent::Failed { id, error } => {
+                assert!(matches!(error, MemoryForkTreeInsertError::UnknownParent));
+                failed.push(id);
+            }
+        }
+    }
+
+    assert_eq!(failed, vec![id(2, 9)]);
+    assert_eq!(
+        bests.iter().map(|(best, _, _)| *best).collect::<Vec<_>>(),
+        vec![id(0, 1), id(0, 2), id(0, 2), id(1, 3), id(0, 3)]
+    );
+    assert_eq!(
+        bests
+            .iter()
+            .map(|(_, new_best, _)| *new_best)
+            .collect::<Vec<_>>(),
+        vec![true, true, false, true, true]
+    );
+
+    // Extending the best chain is no reorg.
+    assert!(bests[1].2.is_none());
+    assert!(bests[2].2.is_none());
+
+    // The switch to the longer fork is a reorg.
+    let reorg = bests[3].2.as_ref().expect("blocks left the best chain");
+    assert_eq!(reorg.retracted, vec![id(0, 2)]);
+    assert_eq!(reorg.enacted, vec![id(1, 2), id(1, 3)]);
+
+    // The fork as deep as the best one, but with a smaller id, wins the tie.
+    let tie_break = bests[4].2.as_ref().expect("blocks left the best chain");
+    assert_eq!(tie_break.retracted, vec![id(1, 3), id(1, 2)]);
+    assert_eq!(tie_break.enacted, vec![id(0, 2), id(0, 3)]);
+
+    assert!(tree.block(&id(0, 3)).is_ok());
+    assert!(tree.block(&id(2, 9)).is_err());
+}
+
+#[test]
+fn buffered_blocks_reported_as_queued() {
+    let mut tree = MemoryForkTree::new();
+    tree.insert(Block {
+        id: id(0, 0),
+        parent_id: None,
+    })
+    .unwrap();
+
+    let (mut queue, worker, events) = import_queue(OrphanPool::new(tree, 4), 2).unwrap();
+    let worker = std::thread::spawn(move || block_on(worker.run()));
+    block_on(async {
+        queue.push(block(0, 2, id(0, 1))).await.unwrap();
+        queue.push(block(0, 1, id(0, 0))).await.unwrap();
+    });
+    drop(queue);
+
+    let pool = worker.join().unwrap();
+    let events = block_on(events.collect::<Vec<_>>());
+    assert!(matches!(events[0], ImportEvent::Queued { id: queued } if queued == id(0, 2)));
+    // Importing the parent releases the buffered child, which is then best.
+    match &events[1] {
+        ImportEvent::Imported {
+            id: imported,
+            best,
+            new_best,
+            reorg,
+        } => {
+            assert_eq!((*imported, *best, *new_best), (id(0, 1), id(0, 2), true));
+            assert!(reorg.is_none());
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    assert!(pool.is_empty());
+}
+
+#[test]
+fn full_queue_applies_backpressure() {
+    let mut tree = MemoryForkTree::new();
+    tree.insert(Block {
+        id: id(0, 0),
+        parent_id: None,
+    })
+    .unwrap();
+
+    // The channel has one slot per handle on top of the capacity.
+    let (mut queue, worker, _events) = import_queue(tree, 0).unwrap();
+    assert!(queue.try_push(block(0, 1, id(0, 0))).is_ok());
+    let rejected = queue.try_push(block(0, 2, id(0, 1))).unwrap_err();
+    assert_eq!(rejected.id(), id(0, 2));
+
+    drop(queue);
+    let tree = block_on(worker.run());
+    assert!(tree.block(&id(0, 1)).is_ok());
+}
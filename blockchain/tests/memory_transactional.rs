@@ -0,0 +1,78 @@
+//! Tests of the memory transactional.
+
+use blockchain::memory::MemoryTransactional;
+use std::panic::{self, AssertUnwindSafe};
+
+#[test]
+fn restore_matches_snapshot() {
+    let mut data = MemoryTransactional::new(vec![1, 2, 3]);
+    let snapshot = data.snapshot();
+
+    data.apply(|inner| {
+        inner.push(4);
+        Ok::<_, ()>(())
+    })
+    .unwrap();
+    assert_eq!(*data, vec![1, 2, 3, 4]);
+
+    data.restore(snapshot.clone());
+    assert_eq!(*data, snapshot);
+
+    // Later changes build on the restored state.
+    data.apply(|inner| {
+        inner.push(5);
+        Ok::<_, ()>(())
+    })
+    .unwrap();
+    assert_eq!(*data, vec![1, 2, 3, 5]);
+}
+
+#[test]
+fn failed_apply_keeps_committed_state() {
+    let mut data = MemoryTransactional::new(vec![1, 2, 3]);
+    let snapshot = data.snapshot();
+
+    assert!(data
+        .apply(|inner| {
+            inner.clear();
+            Err::<(), _>("failed")
+        })
+        .is_err());
+    assert_eq!(*data, snapshot);
+}
+
+#[test]
+fn crash_mid_apply_keeps_committed_state() {
+    let mut data = MemoryTransactional::new(vec![1, 2, 3]);
+    let snapshot = data.snapshot();
+
+    let crashed = panic::catch_unwind(AssertUnwindSafe(|| {
+        data.apply(|inner| {
+            inner.push(4);
+            panic!("crash");
+            #[allow(unreachable_code)]
+            Ok::<_, ()>(())
+        })
+    }));
+    assert!(crashed.is_err());
+    assert_eq!(*data, snapshot);
+
+    data.restore(snapshot.clone());
+    assert_eq!(*data, snapshot);
+}
+
+#[test]
+fn apply_runs_once() {
+    let mut data = MemoryTransactional::new(0);
+    let mut calls = 0;
+
+    data.apply(|inner| {
+        calls += 1;
+        *inner += 1;
+        Ok::<_, ()>(())
+    })
+    .unwrap();
+
+    assert_eq!(calls, 1);
+    assert_eq!(*data, 1);
+}
@@ -0,0 +1,150 @@
+//! Tests of the orphan pool in front of the memory fork tree.
+
+use blockchain::memory::{MemoryForkTree, MemoryForkTreeInsertError};
+use blockchain::{
+    ForkTree, ForkTreeBest, Identified, ImportBlock, ImportOutcome, ImportStatus, Keyed, OrphanPool,
+};
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    number: u32,
+}
+
+impl Identified for Block {
+    type Identifier = u32;
+
+    fn id(&self) -> u32 {
+        self.number
+    }
+
+    fn parent_id(&self) -> Option<u32> {
+        self.number.checked_sub(1)
+    }
+}
+
+#[test]
+fn child_imported_after_parent() {
+    let mut pool = OrphanPool::new(MemoryForkTree::new(), 16);
+    pool.import(Block { number: 0 }).unwrap();
+
+    pool.import(Block { number: 3 }).unwrap();
+    pool.import(Block { number: 2 }).unwrap();
+    assert_eq!(pool.len(), 2);
+    assert!(pool.inner().block(&2).is_err());
+
+    pool.import(Block { number: 1 }).unwrap();
+    assert!(pool.is_empty());
+    assert_eq!(pool.inner().block_depth(&3).unwrap(), 3);
+}
+
+#[test]
+fn oldest_orphan_evicted() {
+    let mut pool = OrphanPool::new(MemoryForkTree::new(), 2);
+    pool.import(Block { number: 0 }).unwrap();
+
+    pool.import(Block { number: 2 }).unwrap();
+    pool.import(Block { number: 3 }).unwrap();
+    pool.import(Block { number: 4 }).unwrap();
+    assert_eq!(pool.len(), 2);
+    assert!(!pool.contains(&2));
+
+    pool.import(Block { number: 1 }).unwrap();
+    assert!(pool.inner().block(&1).is_ok());
+    assert!(pool.inner().block(&3).is_err());
+    assert_eq!(pool.len(), 2);
+}
+
+#[test]
+fn zero_capacity_forwards_unknown_parent() {
+    let mut pool = OrphanPool::new(MemoryForkTree::new(), 0);
+    pool.import(Block { number: 0 }).unwrap();
+
+    assert!(matches!(
+        pool.import(Block { number: 2 }),
+        Err(MemoryForkTreeInsertError::UnknownParent)
+    ));
+}
+
+#[test]
+fn orphans_queue_until_released() {
+    let mut pool = OrphanPool::new(MemoryForkTree::new(), 16);
+    let outcome = pool.import(Block { number: 0 }).unwrap();
+    assert_eq!(outcome.status, ImportStatus::Imported);
+    assert!(outcome.new_best);
+
+    for number in [3, 2] {
+        assert_eq!(
+            pool.import(Block { number }).unwrap(),
+            ImportOutcome::unchanged(ImportStatus::Queued)
+        );
+    }
+    assert_eq!(
+        pool.import(Block { number: 3 }).unwrap(),
+        ImportOutcome::unchanged(ImportStatus::AlreadyImported)
+    );
+
+    // Releasing the orphans is one extension, up to the deepest of them.
+    let outcome = pool.import(Block { number: 1 }).unwrap();
+    assert_eq!(outcome.status, ImportStatus::Imported);
+    assert!(outcome.new_best);
+    assert_eq!(outcome.reorg, None);
+    assert_eq!(pool.inner().best_id().unwrap(), 3);
+}
+
+/// A block with an explicit parent and number, which may not follow.
+#[derive(Debug, Clone)]
+pub struct NumberedBlock {
+    id: u32,
+    parent_id: u32,
+    number: u32,
+}
+
+impl Identified for NumberedBlock {
+    type Identifier = u32;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn parent_id(&self) -> Option<u32> {
+        Some(self.parent_id).filter(|_| self.id != 0)
+    }
+}
+
+impl Keyed<u32> for NumberedBlock {
+    fn key(&self) -> u32 {
+        self.number
+    }
+}
+
+fn numbered(id: u32, parent_id: u32, number: u32) -> NumberedBlock {
+    NumberedBlock {
+        id,
+        parent_id,
+        number,
+    }
+}
+
+#[test]
+fn reimport_releases_orphans_left_by_failure() {
+    let tree = MemoryForkTree::new().with_monotonic_keys();
+    let mut pool = OrphanPool::new(tree, 16);
+    pool.import(numbered(0, 0, 0)).unwrap();
+
+    pool.import(numbered(2, 1, 2)).unwrap();
+    pool.import(numbered(3, 2, 3)).unwrap();
+    // Released first, and rejected for its number.
+    pool.import(numbered(4, 1, 0)).unwrap();
+
+    assert!(matches!(
+        pool.import(numbered(1, 0, 1)),
+        Err(MemoryForkTreeInsertError::NonMonotonicKey)
+    ));
+    assert_eq!(pool.len(), 2);
+
+    let outcome = pool.import(numbered(1, 0, 1)).unwrap();
+    assert_eq!(outcome.status, ImportStatus::Imported);
+    assert!(outcome.new_best);
+    assert!(pool.is_empty());
+    assert_eq!(pool.inner().best_id().unwrap(), 3);
+}
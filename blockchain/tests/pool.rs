@@ -0,0 +1,114 @@
+//! Tests of the extrinsic pool over reorgs of the memory fork tree.
+
+use blockchain::memory::MemoryForkTree;
+use blockchain::{tree_route, Bodied, EmptyBlockPolicy, ForkTreeMut, Identified, Keyed, Pool};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extrinsic(u32);
+
+impl Keyed<u32> for Extrinsic {
+    fn key(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    id: &'static str,
+    parent_id: Option<&'static str>,
+    extrinsics: Vec<Extrinsic>,
+}
+
+impl Identified for Block {
+    type Identifier = &'static str;
+
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn parent_id(&self) -> Option<&'static str> {
+        self.parent_id
+    }
+}
+
+impl Bodied for Block {
+    type Extrinsic = Extrinsic;
+
+    fn extrinsics(&self) -> Vec<Extrinsic> {
+        self.extrinsics.clone()
+    }
+}
+
+fn block(id: &'static str, parent_id: Option<&'static str>, extrinsics: &[u32]) -> Block {
+    Block {
+        id,
+        parent_id,
+        extrinsics: extrinsics.iter().copied().map(Extrinsic).collect(),
+    }
+}
+
+#[test]
+fn reorg_requeues_retracted_and_prunes_enacted() {
+    let mut tree = MemoryForkTree::new();
+    tree.insert(block("genesis", None, &[])).unwrap();
+    tree.insert(block("a1", Some("genesis"), &[1, 3])).unwrap();
+    tree.insert(block("b1", Some("genesis"), &[2])).unwrap();
+    tree.insert(block("b2", Some("b1"), &[3])).unwrap();
+
+    let mut pool = Pool::new();
+    for extrinsic in [1, 2, 3, 4] {
+        assert!(pool.submit(Extrinsic(extrinsic)));
+    }
+    assert!(!pool.submit(Extrinsic(1)));
+
+    let route = tree_route(&tree, &"genesis", &"a1").unwrap();
+    assert!(!route.is_reorg());
+    pool.handle_route(&tree, &route).unwrap();
+    assert_eq!(
+        pool.ready().copied().collect::<Vec<_>>(),
+        vec![Extrinsic(2), Extrinsic(4)]
+    );
+
+    let route = tree_route(&tree, &"a1", &"b2").unwrap();
+    assert_eq!(route.common, "genesis");
+    assert_eq!(route.retracted, vec!["a1"]);
+    assert_eq!(route.enacted, vec!["b1", "b2"]);
+    pool.handle_route(&tree, &route).unwrap();
+
+    // 1 is retracted and back, 2 is enacted, and 3 is in both forks.
+    assert!(pool.contains(&1));
+    assert!(!pool.contains(&2));
+    assert!(!pool.contains(&3));
+    assert!(pool.contains(&4));
+    assert_eq!(pool.len(), 2);
+}
+
+#[test]
+fn empty_pool_authors_by_policy() {
+    let mut pool = Pool::<Extrinsic, u32>::new();
+    let authored = |pool: &Pool<Extrinsic, u32>, policy| {
+        (0..6)
+            .filter(|slot| pool.should_author(policy, *slot))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        authored(&pool, EmptyBlockPolicy::AlwaysAuthor),
+        [0, 1, 2, 3, 4, 5]
+    );
+    assert!(authored(&pool, EmptyBlockPolicy::SkipIfEmpty).is_empty());
+    assert_eq!(
+        authored(&pool, EmptyBlockPolicy::AuthorEveryNSlots(3)),
+        [0, 3]
+    );
+    assert!(authored(&pool, EmptyBlockPolicy::AuthorEveryNSlots(0)).is_empty());
+
+    // Any policy authors once there is something to include.
+    pool.submit(Extrinsic(1));
+    for policy in [
+        EmptyBlockPolicy::SkipIfEmpty,
+        EmptyBlockPolicy::AuthorEveryNSlots(3),
+    ] {
+        assert_eq!(authored(&pool, policy), [0, 1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,161 @@
+//! Tests of pruning the memory fork tree and flat state on finalization.
+
+use blockchain::memory::{MemoryFlatState, MemoryForkTree, MemoryForkTreeFinalizeError};
+use blockchain::{
+    Chain, ChainFinalizeError, FinalizeError, FlatState, FlatStateMut, ForkTree, ForkTreeFinalize,
+    ForkTreeMut, StateChange,
+};
+use futures::{executor::block_on, StreamExt};
+
+mod common;
+use common::{id, Block, BlockId};
+
+/// A main chain up to 10, and a fork off block 3 up to 7, with changes to
+/// keys 1 and 2 on both.
+fn chain() -> Chain<MemoryForkTree<Block>, MemoryFlatState<u32, u32, BlockId>> {
+    let mut fork_tree = MemoryForkTree::new();
+    let mut state = MemoryFlatState::new();
+    let mut insert =
+        |block_id: BlockId, parent_id: Option<BlockId>, changes: &[(u32, Option<u32>)]| {
+            fork_tree
+                .insert(Block {
+                    id: block_id,
+                    parent_id,
+                })
+                .unwrap();
+            state
+                .apply(changes.iter().copied(), block_id, &fork_tree)
+                .unwrap();
+        };
+
+    insert(id(0, 0), None, &[(1, Some(0))]);
+    for number in 1..=10 {
+        let changes = match number {
+            2 => vec![(1, Some(2))],
+            5 => vec![(1, Some(5)), (2, Some(5))],
+            6 => vec![(2, None)],
+            _ => Vec::new(),
+        };
+        insert(id(0, number), Some(id(0, number - 1)), &changes);
+    }
+    for number in 4..=7 {
+        let parent_id = if number == 4 {
+            id(0, 3)
+        } else {
+            id(1, number - 1)
+        };
+        let changes = match number {
+            4 => vec![(1, Some(40))],
+            5 => vec![(2, Some(50))],
+            _ => Vec::new(),
+        };
+        insert(id(1, number), Some(parent_id), &changes);
+    }
+
+    fork_tree.finalize(&id(0, 0)).unwrap();
+    Chain::new(fork_tree, state)
+}
+
+#[test]
+fn finalize_prunes_competing_fork_and_old_state() {
+    let mut chain = chain();
+    assert_eq!(chain.state().changes(), 7);
+
+    let notification = chain.finalize(&id(0, 6)).unwrap();
+    assert_eq!(notification.finalized_id, id(0, 6));
+    assert_eq!(notification.newly_finalized.len(), 6);
+    assert_eq!(chain.fork_tree().finalized_id(), Some(id(0, 6)));
+
+    for number in 4..=7 {
+        assert!(chain.fork_tree().block(&id(1, number)).is_err());
+    }
+    for number in 0..=10 {
+        assert!(chain.fork_tree().block(&id(0, number)).is_ok());
+    }
+
+    // Only the change of key 1 at block 5 is left: the fork changes, the
+    // older changes, and the deletion of key 2 are gone.
+    assert_eq!(chain.state().changes(), 1);
+    let (fork_tree, state) = (chain.fork_tree(), chain.state());
+    for block_id in [id(0, 6), id(0, 10)] {
+        assert_eq!(state.get(&1, &block_id, fork_tree).unwrap(), Some(5));
+        assert_eq!(state.get(&2, &block_id, fork_tree).unwrap(), None);
+    }
+}
+
+#[test]
+fn failed_finalize_changes_nothing() {
+    let mut chain = chain();
+
+    // The fork descends from the finalized genesis, but the fork tree only
+    // finalizes ancestors of its best block.
+    assert!(matches!(
+        chain.finalize(&id(1, 5)),
+        Err(ChainFinalizeError::ForkTree(
+            MemoryForkTreeFinalizeError::NotBestAncestor
+        ))
+    ));
+    assert_eq!(chain.fork_tree().finalized_id(), Some(id(0, 0)));
+    assert_eq!(chain.state().changes(), 7);
+    assert!(chain.fork_tree().block(&id(1, 7)).is_ok());
+
+    chain.finalize(&id(0, 6)).unwrap();
+
+    // The pruned fork is unknown, and the finalized block is not its own
+    // descendant.
+    assert!(matches!(
+        chain.finalize(&id(1, 7)),
+        Err(ChainFinalizeError::Finalize(FinalizeError::Query(_)))
+    ));
+    assert!(matches!(
+        chain.finalize(&id(0, 6)),
+        Err(ChainFinalizeError::Finalize(FinalizeError::NotDescendant))
+    ));
+    assert_eq!(chain.state().changes(), 1);
+    assert!(chain.fork_tree().block(&id(0, 10)).is_ok());
+
+    chain.finalize(&id(0, 10)).unwrap();
+}
+
+#[test]
+fn finalized_state_changes_stream_each_block_diff() {
+    let mut chain = chain();
+    let changes = chain.finalized_state_changes();
+    let notifications = chain.finality_notifications();
+
+    // One block, then a jump over blocks without changes.
+    chain.finalize(&id(0, 2)).unwrap();
+    chain.finalize(&id(0, 6)).unwrap();
+    drop(chain);
+
+    let notifications = block_on(notifications.collect::<Vec<_>>());
+    assert_eq!(
+        notifications
+            .iter()
+            .map(|notification| notification.newly_finalized.len())
+            .collect::<Vec<_>>(),
+        [2, 4]
+    );
+
+    let mut changes = block_on(changes.collect::<Vec<_>>());
+    // Blocks are in order, but the keys of a block are not.
+    assert!(changes
+        .windows(2)
+        .all(|pair| pair[0].block_id.number <= pair[1].block_id.number));
+    changes.sort_by_key(|change| (change.block_id.number, change.key));
+    let change = |number, key, old, new| StateChange {
+        block_id: id(0, number),
+        key,
+        old,
+        new,
+    };
+    assert_eq!(
+        changes,
+        [
+            change(2, 1, Some(0), Some(2)),
+            change(5, 1, Some(2), Some(5)),
+            change(5, 2, None, Some(5)),
+            change(6, 2, Some(5), None),
+        ]
+    );
+}
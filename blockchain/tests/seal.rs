@@ -0,0 +1,206 @@
+//! Tests of deferred seal verification over the memory fork tree.
+
+use blockchain::memory::{MemoryForkTree, MemoryForkTreeRemoveError};
+use blockchain::{
+    BlockHash, ForkTree, ForkTreeDepths, ForkTreeFinalize, ForkTreeMut, Headered, Identified,
+    PendingSeals, SealResolution, SealResolveError, SealVerdict, Sealed,
+};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    number: u32,
+}
+
+impl Identified for Block {
+    type Identifier = u32;
+
+    fn id(&self) -> u32 {
+        self.number
+    }
+
+    fn parent_id(&self) -> Option<u32> {
+        self.number.checked_sub(1)
+    }
+}
+
+/// Peer reputations, as kept by the network.
+#[derive(Default)]
+struct Reputations(HashMap<&'static str, i32>);
+
+impl Reputations {
+    fn apply(&mut self, resolution: SealResolution<u32, &'static str>) {
+        if let SealResolution::Rejected { source, .. } = resolution {
+            *self.0.entry(source).or_default() -= 100;
+        }
+    }
+}
+
+#[test]
+fn invalid_seal_removes_block_and_penalizes_source() {
+    let mut tree = MemoryForkTree::new();
+    let mut seals = PendingSeals::new();
+    let mut reputations = Reputations::default();
+
+    tree.insert(Block { number: 0 }).unwrap();
+    tree.insert(Block { number: 1 }).unwrap();
+    seals.mark_pending(1, "alice");
+    tree.insert(Block { number: 2 }).unwrap();
+    seals.mark_pending(2, "bob");
+
+    let resolution = seals.resolve(&mut tree, &2, SealVerdict::Invalid).unwrap();
+    assert_eq!(
+        resolution,
+        SealResolution::Rejected {
+            source: "bob",
+            removed: Vec::new(),
+        }
+    );
+    reputations.apply(resolution);
+    assert!(tree.block(&2).is_err());
+    assert!(tree.blocks_at_depth(2).unwrap().is_empty());
+    assert_eq!(reputations.0.get("bob"), Some(&-100));
+
+    let resolution = seals.resolve(&mut tree, &1, SealVerdict::Valid).unwrap();
+    assert_eq!(resolution, SealResolution::Confirmed);
+    reputations.apply(resolution);
+    assert!(tree.block(&1).is_ok());
+    assert_eq!(reputations.0.get("alice"), None);
+    assert!(seals.is_empty());
+
+    // The parent accepts a new child once the invalid one is gone.
+    tree.insert(Block { number: 2 }).unwrap();
+    assert_eq!(tree.blocks_at_depth(2).unwrap(), vec![2]);
+}
+
+#[test]
+fn invalid_seal_removes_descendants() {
+    let mut tree = MemoryForkTree::new();
+    let mut seals = PendingSeals::new();
+    let mut reputations = Reputations::default();
+
+    for number in 0..4 {
+        tree.insert(Block { number }).unwrap();
+    }
+    seals.mark_pending(1, "alice");
+    seals.mark_pending(3, "carol");
+
+    let resolution = seals.resolve(&mut tree, &1, SealVerdict::Invalid).unwrap();
+    assert_eq!(
+        resolution,
+        SealResolution::Rejected {
+            source: "alice",
+            removed: vec![3, 2],
+        }
+    );
+    reputations.apply(resolution);
+    assert_eq!(tree.leaves().unwrap(), vec![0]);
+    // Only the source of the invalid block is penalized.
+    assert!(seals.is_empty());
+    assert_eq!(reputations.0.get("alice"), Some(&-100));
+    assert_eq!(reputations.0.get("carol"), None);
+}
+
+#[test]
+fn failed_removal_keeps_block_pending() {
+    let mut tree = MemoryForkTree::new();
+    let mut seals = PendingSeals::new();
+
+    tree.insert(Block { number: 0 }).unwrap();
+    tree.insert(Block { number: 1 }).unwrap();
+    tree.finalize(&1).unwrap();
+    seals.mark_pending(1, "alice");
+
+    assert!(matches!(
+        seals.resolve(&mut tree, &1, SealVerdict::Invalid),
+        Err(SealResolveError::Remove(
+            MemoryForkTreeRemoveError::Finalized
+        ))
+    ));
+    assert!(seals.is_pending(&1));
+}
+
+/// A header sealed with the signature of its author.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    number: u32,
+    author: u8,
+    seal: Option<BlockHash>,
+}
+
+impl Header {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = self.number.to_le_bytes().to_vec();
+        encoded.push(self.author);
+        if let Some(seal) = &self.seal {
+            encoded.extend(seal.as_bytes());
+        }
+        encoded
+    }
+}
+
+impl Headered for Header {
+    type Header = Header;
+
+    fn header(&self) -> Header {
+        self.clone()
+    }
+}
+
+impl Sealed for Header {
+    type Seal = Option<BlockHash>;
+
+    fn unsealed_header(&self) -> Header {
+        Header {
+            seal: None,
+            ..self.clone()
+        }
+    }
+
+    fn seal(&self) -> Option<BlockHash> {
+        self.seal
+    }
+
+    fn with_seal(header: Header, seal: Option<BlockHash>) -> Header {
+        Header { seal, ..header }
+    }
+}
+
+/// Signature of a hash by the author key, standing in for a real signature
+/// scheme.
+fn sign(author: u8, pre_hash: &BlockHash) -> BlockHash {
+    BlockHash::digest([&[author], pre_hash.as_bytes().as_slice()].concat())
+}
+
+fn verify(header: &Header) -> SealVerdict {
+    let pre_hash = BlockHash::digest(header.unsealed_header().encode());
+    match header.seal() {
+        Some(seal) if seal == sign(header.author, &pre_hash) => SealVerdict::Valid,
+        _ => SealVerdict::Invalid,
+    }
+}
+
+#[test]
+fn seal_verifies_against_pre_seal_hash() {
+    let unsealed = Header {
+        number: 1,
+        author: 7,
+        seal: None,
+    };
+    let pre_hash = BlockHash::digest(unsealed.encode());
+    let sealed = Header::with_seal(unsealed.clone(), Some(sign(7, &pre_hash)));
+
+    assert_eq!(sealed.unsealed_header(), unsealed);
+    assert_ne!(BlockHash::digest(sealed.encode()), pre_hash);
+    assert_eq!(verify(&sealed), SealVerdict::Valid);
+
+    // A seal by another author, or over another header, is rejected.
+    let forged = Header::with_seal(unsealed.clone(), Some(sign(8, &pre_hash)));
+    assert_eq!(verify(&forged), SealVerdict::Invalid);
+    let moved = Header {
+        number: 2,
+        ..sealed.clone()
+    };
+    assert_eq!(verify(&moved), SealVerdict::Invalid);
+    assert_eq!(verify(&unsealed), SealVerdict::Invalid);
+}
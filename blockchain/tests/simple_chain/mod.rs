@@ -0,0 +1,633 @@
+//! A simple chain, with hash-backed block ids and a fixed seal, generic over
+//! its fork tree so that the same scenarios run against each backend.
+
+// Each test binary uses its own subset of the harness.
+#![allow(dead_code)]
+
+use blockchain::memory::{MemoryFlatState, MemoryFlatStateApplyError, MemoryFlatStateQueryError};
+use blockchain::{
+    AsDigest, BlockBuilder, BlockHash, ChainTransaction, ChainTransactionError, DigestItems,
+    DigestItemsError, FlatState, ForkTree, ForkTreeBest, ForkTreeDepths, ForkTreeTransactional,
+    Headered, Identified, ImportBlock, ImportOutcome, ImportStatus, Keyed, OverlayedFlatState,
+};
+use serde::{Deserialize, Serialize};
+
+/// A simple seal.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Seal {
+    /// Valid seal.
+    ValidSeal,
+    /// Invalid seal.
+    InvalidSeal,
+}
+
+/// Slot claimed by the block author.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Slot(pub u64);
+
+/// Digest item, with a variant per engine.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DigestItem {
+    Slot(Slot),
+    Seal(Seal),
+}
+
+impl From<Slot> for DigestItem {
+    fn from(slot: Slot) -> Self {
+        Self::Slot(slot)
+    }
+}
+
+impl From<Seal> for DigestItem {
+    fn from(seal: Seal) -> Self {
+        Self::Seal(seal)
+    }
+}
+
+impl AsDigest<Slot> for DigestItem {
+    fn as_digest(&self) -> Option<&Slot> {
+        match self {
+            Self::Slot(slot) => Some(slot),
+            _ => None,
+        }
+    }
+}
+
+impl AsDigest<Seal> for DigestItem {
+    fn as_digest(&self) -> Option<&Seal> {
+        match self {
+            Self::Seal(seal) => Some(seal),
+            _ => None,
+        }
+    }
+}
+
+/// Blocks are identified by the hash of their contents.
+pub type BlockId = BlockHash;
+
+/// Extrinsic type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Extrinsic {
+    Set(u32, u32),
+    /// Remove an existing key. Fails if the key is not set.
+    Remove(u32),
+}
+
+/// Simple block structure.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Block {
+    pub digests: DigestItems<DigestItem>,
+    pub id: BlockId,
+    pub parent_id: Option<BlockId>,
+    pub number: u32,
+    pub extrinsics: Vec<Extrinsic>,
+}
+
+/// Header is simply block with extrinsic removed.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub digests: DigestItems<DigestItem>,
+    pub id: BlockId,
+    pub parent_id: Option<BlockId>,
+    pub number: u32,
+}
+
+impl Identified for Block {
+    type Identifier = BlockId;
+
+    fn id(&self) -> BlockId {
+        self.id
+    }
+
+    fn parent_id(&self) -> Option<BlockId> {
+        self.parent_id
+    }
+}
+
+impl Block {
+    /// Genesis block, without any extrinsic.
+    pub fn genesis() -> Self {
+        let mut block = Block {
+            digests: DigestItems::new(),
+            id: BlockHash::ZERO,
+            parent_id: None,
+            number: 0,
+            extrinsics: Vec::new(),
+        };
+        block.id = block.compute_id();
+        block
+    }
+
+    /// Hash of the parent, number and extrinsics. Digests are not part of the
+    /// id, so that the seal can sign it.
+    pub fn compute_id(&self) -> BlockId {
+        let mut encoded = self.parent_id.unwrap_or(BlockHash::ZERO).0.to_vec();
+        encoded.extend(self.number.to_le_bytes());
+        for extrinsic in &self.extrinsics {
+            match extrinsic {
+                Extrinsic::Set(key, value) => {
+                    encoded.push(0);
+                    encoded.extend(key.to_le_bytes());
+                    encoded.extend(value.to_le_bytes());
+                }
+                Extrinsic::Remove(key) => {
+                    encoded.push(1);
+                    encoded.extend(key.to_le_bytes());
+                }
+            }
+        }
+        BlockHash::digest(encoded)
+    }
+
+    /// The seal of the block, the last seal post-digest.
+    pub fn seal(&self) -> Seal {
+        self.digests
+            .post::<Seal>()
+            .last()
+            .copied()
+            .unwrap_or(Seal::InvalidSeal)
+    }
+}
+
+// Block can also be keyed by its block number instead of block id.
+impl Keyed<u32> for Block {
+    fn key(&self) -> u32 {
+        self.number
+    }
+}
+
+impl Headered for Block {
+    type Header = Header;
+
+    fn header(&self) -> Header {
+        Header {
+            digests: self.digests.clone(),
+            id: self.id,
+            parent_id: self.parent_id,
+            number: self.number,
+        }
+    }
+}
+
+/// Fork tree backend of the chain.
+pub trait Backend: ForkTreeTransactional<Block = Block> + ForkTreeBest {}
+
+impl<FT: ForkTreeTransactional<Block = Block> + ForkTreeBest> Backend for FT {}
+
+/// Error of the chain over a backend.
+pub type BackendError<FT> =
+    ChainError<<FT as ForkTree>::QueryError, <FT as ForkTreeTransactional>::InsertError>;
+
+/// Define the chain.
+pub struct Chain<FT> {
+    pub fork_tree: FT,
+    pub state: MemoryFlatState<u32, u32, BlockId>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChainError<QueryError, InsertError> {
+    InvalidSeal,
+    CantImportGenesis,
+    MissingKey(u32),
+    ForkTreeInsert(InsertError),
+    ForkTreeQuery(QueryError),
+    StateQuery(MemoryFlatStateQueryError<QueryError>),
+    StateApply(MemoryFlatStateApplyError<QueryError>),
+    Digest(DigestItemsError),
+    TransactionPoisoned,
+}
+
+impl<Q, I> From<DigestItemsError> for ChainError<Q, I> {
+    fn from(err: DigestItemsError) -> Self {
+        Self::Digest(err)
+    }
+}
+
+impl<Q, I> From<MemoryFlatStateQueryError<Q>> for ChainError<Q, I> {
+    fn from(err: MemoryFlatStateQueryError<Q>) -> Self {
+        Self::StateQuery(err)
+    }
+}
+
+impl<Q, I> From<MemoryFlatStateApplyError<Q>> for ChainError<Q, I> {
+    fn from(err: MemoryFlatStateApplyError<Q>) -> Self {
+        Self::StateApply(err)
+    }
+}
+
+impl<Q, I> From<ChainTransactionError<I, MemoryFlatStateApplyError<Q>>> for ChainError<Q, I> {
+    fn from(err: ChainTransactionError<I, MemoryFlatStateApplyError<Q>>) -> Self {
+        match err {
+            ChainTransactionError::Insert(err) => Self::ForkTreeInsert(err),
+            ChainTransactionError::Apply(err) => Self::StateApply(err),
+            ChainTransactionError::Poisoned => Self::TransactionPoisoned,
+        }
+    }
+}
+
+impl<FT: Backend> Chain<FT> {
+    /// Create a chain on an empty fork tree, importing the genesis block
+    /// along with its state.
+    pub fn new(
+        fork_tree: FT,
+        genesis_block: Block,
+        genesis_state: Vec<(u32, Option<u32>)>,
+    ) -> Result<Self, BackendError<FT>> {
+        let mut chain = Chain {
+            fork_tree,
+            state: MemoryFlatState::new(),
+        };
+
+        // It's possible to handle extrinsics in a genesis, but it's a rare thing,
+        // and here we just assert that it's empty.
+        assert!(genesis_block.extrinsics.is_empty());
+
+        let mut transaction = ChainTransaction::begin(&mut chain.fork_tree, &mut chain.state);
+        transaction.import(genesis_block, genesis_state.into_iter())?;
+        transaction.commit()?;
+
+        Ok(chain)
+    }
+}
+
+impl<FT: Backend> ImportBlock for Chain<FT> {
+    type Block = Block;
+    type Error = BackendError<FT>;
+
+    fn import(&mut self, block: Block) -> Result<ImportOutcome<BlockId>, Self::Error> {
+        // The same block may be received from several peers.
+        if self.fork_tree.block(&block.id()).is_ok() {
+            return Ok(ImportOutcome::unchanged(ImportStatus::AlreadyImported));
+        }
+
+        // Verify the seal is valid.
+        if block.seal() != Seal::ValidSeal {
+            return Err(ChainError::InvalidSeal);
+        }
+
+        let parent_id = block.parent_id().ok_or(ChainError::CantImportGenesis)?;
+        let old_best = self
+            .fork_tree
+            .best_id()
+            .map_err(ChainError::ForkTreeQuery)?;
+
+        // Both the block and its state are rolled back if any step fails.
+        let mut transaction = ChainTransaction::begin(&mut self.fork_tree, &mut self.state);
+        transaction
+            .insert(block.clone())
+            .map_err(ChainError::ForkTreeInsert)?;
+
+        let mut overlay = transaction
+            .state()
+            .overlayed(parent_id, transaction.fork_tree());
+        for extrinsic in &block.extrinsics {
+            execute(&mut overlay, extrinsic)?;
+        }
+
+        let changeset = overlay.into_changeset().collect::<Vec<_>>();
+        transaction.apply(changeset.into_iter(), &block.id())?;
+        transaction.commit()?;
+
+        ImportOutcome::imported(&self.fork_tree, Some(old_best)).map_err(ChainError::ForkTreeQuery)
+    }
+}
+
+/// Execute an extrinsic against the overlay.
+fn execute<FT: Backend>(
+    overlay: &mut OverlayedFlatState<'_, '_, MemoryFlatState<u32, u32, BlockId>, FT>,
+    extrinsic: &Extrinsic,
+) -> Result<(), BackendError<FT>> {
+    match extrinsic {
+        Extrinsic::Set(key, value) => {
+            overlay.insert(*key, *value);
+        }
+        Extrinsic::Remove(key) => {
+            if overlay.get(key)?.is_none() {
+                return Err(ChainError::MissingKey(*key));
+            }
+            overlay.remove(key);
+        }
+    }
+
+    Ok(())
+}
+
+/// Chain builder.
+pub struct ChainBlockBuilder<'chain, FT: Backend> {
+    pub chain: &'chain Chain<FT>,
+    pub block: Block,
+    pub overlay: OverlayedFlatState<'chain, 'chain, MemoryFlatState<u32, u32, BlockId>, FT>,
+}
+
+// Derived, it would require the fork tree itself to be `Clone`.
+impl<'chain, FT: Backend> Clone for ChainBlockBuilder<'chain, FT> {
+    fn clone(&self) -> Self {
+        Self {
+            chain: self.chain,
+            block: self.block.clone(),
+            overlay: self.overlay.clone(),
+        }
+    }
+}
+
+impl<'chain, FT: Backend> BlockBuilder<'chain> for ChainBlockBuilder<'chain, FT> {
+    type Block = Block;
+    type Extrinsic = Extrinsic;
+    type Error = BackendError<FT>;
+    type DigestItem = DigestItem;
+    type Chain = Chain<FT>;
+
+    fn initialize(
+        chain: &'chain Chain<FT>,
+        parent_id: <Self::Block as Identified>::Identifier,
+        pre_digests: DigestItems<DigestItem>,
+    ) -> Result<Self, Self::Error> {
+        let parent_block = chain
+            .fork_tree
+            .block(&parent_id)
+            .map_err(ChainError::ForkTreeQuery)?;
+
+        let block = Block {
+            digests: pre_digests,
+            parent_id: Some(parent_id),
+            // Known once the extrinsics are, in `finalize`.
+            id: BlockHash::ZERO,
+            number: parent_block.number + 1,
+            extrinsics: Vec::new(),
+        };
+
+        Ok(ChainBlockBuilder {
+            block,
+            chain,
+            overlay: chain.state.overlayed(parent_id, &chain.fork_tree),
+        })
+    }
+
+    fn apply_extrinsic(&mut self, extrinsic: Extrinsic) -> Result<(), Self::Error> {
+        execute(&mut self.overlay, &extrinsic)?;
+        self.block.extrinsics.push(extrinsic);
+        Ok(())
+    }
+
+    fn finalize(mut self, post_digests: DigestItems<DigestItem>) -> Result<Block, Self::Error> {
+        self.block.id = self.block.compute_id();
+        self.block.digests.append(post_digests)?;
+        Ok(self.block)
+    }
+}
+
+/// Build a valid block on the parent, setting key 1 to the value.
+pub fn child<FT: Backend>(
+    chain: &Chain<FT>,
+    parent_id: BlockId,
+    value: u32,
+) -> Result<Block, BackendError<FT>> {
+    let mut builder = ChainBlockBuilder::initialize(chain, parent_id, DigestItems::new())?;
+    builder.apply_extrinsic(Extrinsic::Set(1, value))?;
+    let mut post_digests = DigestItems::new();
+    post_digests.push_post(Seal::ValidSeal);
+    builder.finalize(post_digests)
+}
+
+pub fn basic_build_and_import<FT: Backend>(fork_tree: FT) -> Result<(), BackendError<FT>> {
+    // Define a genesis block.
+    let genesis_block = Block::genesis();
+
+    // Define a genesis state.
+    let genesis_state = vec![(100, Some(100)), (200, Some(200))];
+
+    // Create a new chain, importing the genesis into the fork tree.
+    let mut chain = Chain::new(fork_tree, genesis_block.clone(), genesis_state)?;
+
+    // Build a new block.
+    let mut builder =
+        ChainBlockBuilder::initialize(&chain, genesis_block.id(), DigestItems::new())?;
+    builder.apply_extrinsic(Extrinsic::Set(100, 200))?;
+    let mut post_digests = DigestItems::new();
+    post_digests.push_post(Seal::ValidSeal);
+    let block = builder.finalize(post_digests)?;
+
+    // Import the block.
+    chain.import(block.clone())?;
+
+    // Check that the state is actually set.
+    assert_eq!(
+        chain
+            .state
+            .get(&100, &genesis_block.id(), &chain.fork_tree)?,
+        Some(100),
+    );
+    assert_eq!(
+        chain.state.get(&100, &block.id(), &chain.fork_tree)?,
+        Some(200),
+    );
+
+    // Create a fork.
+    let mut block2 = block.clone();
+    block2.extrinsics[0] = Extrinsic::Set(100, 300);
+    block2.id = block2.compute_id();
+    assert_ne!(block2.id(), block.id());
+    chain.import(block2.clone())?;
+    assert_eq!(
+        chain.state.get(&100, &block2.id(), &chain.fork_tree)?,
+        Some(300),
+    );
+
+    Ok(())
+}
+
+pub fn build_with_multiple_digests<FT: Backend>(fork_tree: FT) -> Result<(), BackendError<FT>> {
+    let genesis_block = Block::genesis();
+    let mut chain = Chain::new(fork_tree, genesis_block.clone(), Vec::new())?;
+
+    // The slot engine and the seal engine each contribute their own digest.
+    let mut pre_digests = DigestItems::new();
+    pre_digests.push_pre(Slot(7))?;
+    let builder = ChainBlockBuilder::initialize(&chain, genesis_block.id(), pre_digests)?;
+
+    let mut post_digests = DigestItems::new();
+    post_digests.push_post(Seal::ValidSeal);
+    let block = builder.finalize(post_digests)?;
+
+    assert_eq!(block.digests.len(), 2);
+    assert_eq!(
+        block.digests.pre::<Slot>().collect::<Vec<_>>(),
+        vec![&Slot(7)]
+    );
+    assert_eq!(
+        block.digests.post::<Seal>().collect::<Vec<_>>(),
+        vec![&Seal::ValidSeal]
+    );
+    assert_eq!(block.digests.post::<Slot>().count(), 0);
+    assert_eq!(block.header().digests, block.digests);
+
+    chain.import(block.clone())?;
+
+    // The digests are stored along with the block.
+    let stored = chain
+        .fork_tree
+        .block(&block.id())
+        .map_err(ChainError::ForkTreeQuery)?;
+    assert_eq!(stored.digests, block.digests);
+
+    Ok(())
+}
+
+pub fn failing_batch_leaves_builder_unchanged<FT: Backend>(
+    fork_tree: FT,
+) -> Result<(), BackendError<FT>> {
+    let genesis_block = Block::genesis();
+    let genesis_state = vec![(100, Some(100)), (200, Some(200))];
+    let mut chain = Chain::new(fork_tree, genesis_block.clone(), genesis_state)?;
+
+    let mut builder =
+        ChainBlockBuilder::initialize(&chain, genesis_block.id(), DigestItems::new())?;
+    builder.apply_extrinsic(Extrinsic::Set(100, 1))?;
+
+    let failed = builder.apply_extrinsics(vec![
+        Extrinsic::Set(100, 2),
+        Extrinsic::Remove(300),
+        Extrinsic::Remove(200),
+    ]);
+    assert!(matches!(failed, Err((1, ChainError::MissingKey(300)))));
+    assert_eq!(builder.block.extrinsics.len(), 1);
+    assert_eq!(builder.overlay.get(&100)?, Some(1));
+    assert_eq!(builder.overlay.get(&200)?, Some(200));
+
+    assert_eq!(
+        builder
+            .apply_extrinsics(vec![Extrinsic::Set(100, 2), Extrinsic::Remove(200)])
+            .map_err(|(_, err)| err)?,
+        2
+    );
+    assert_eq!(builder.overlay.get(&200)?, None);
+
+    let mut post_digests = DigestItems::new();
+    post_digests.push_post(Seal::ValidSeal);
+    let block = builder.finalize(post_digests)?;
+    assert_eq!(block.extrinsics.len(), 3);
+    chain.import(block.clone())?;
+    assert_eq!(
+        chain.state.get(&100, &block.id(), &chain.fork_tree)?,
+        Some(2),
+    );
+
+    Ok(())
+}
+
+pub fn unknown_hash_is_not_found<FT: Backend>(fork_tree: FT) -> Result<(), BackendError<FT>> {
+    let genesis_block = Block::genesis();
+    let mut chain = Chain::new(fork_tree, genesis_block.clone(), Vec::new())?;
+
+    // Building on a parent that's not in the chain fails.
+    let unknown = BlockHash::digest(b"unknown");
+    assert!(matches!(
+        ChainBlockBuilder::initialize(&chain, unknown, DigestItems::new()),
+        Err(ChainError::ForkTreeQuery(_))
+    ));
+
+    let block = child(&chain, genesis_block.id(), 1)?;
+    chain.import(block.clone())?;
+
+    let imported = chain
+        .fork_tree
+        .block(&block.id())
+        .map_err(ChainError::ForkTreeQuery)?;
+    assert_eq!(imported.number, 1);
+    assert!(chain.fork_tree.block(&unknown).is_err());
+
+    Ok(())
+}
+
+pub fn reimport_is_noop<FT: Backend + ForkTreeDepths>(
+    fork_tree: FT,
+) -> Result<(), BackendError<FT>> {
+    let genesis_block = Block::genesis();
+    let mut chain = Chain::new(fork_tree, genesis_block.clone(), Vec::new())?;
+
+    let block = child(&chain, genesis_block.id(), 1)?;
+    assert_eq!(chain.import(block.clone())?.status, ImportStatus::Imported);
+    let changes = chain.state.changes();
+
+    assert_eq!(
+        chain.import(block.clone())?,
+        ImportOutcome::unchanged(ImportStatus::AlreadyImported)
+    );
+    assert_eq!(chain.state.changes(), changes);
+    assert_eq!(
+        chain
+            .fork_tree
+            .blocks_at_depth(1)
+            .map_err(ChainError::ForkTreeQuery)?,
+        [block.id()]
+    );
+    assert_eq!(chain.state.get(&1, &block.id(), &chain.fork_tree)?, Some(1));
+
+    Ok(())
+}
+
+pub fn import_reports_best_changes_and_reorgs<FT: Backend>(
+    fork_tree: FT,
+) -> Result<(), BackendError<FT>> {
+    let genesis_block = Block::genesis();
+    let mut chain = Chain::new(fork_tree, genesis_block.clone(), Vec::new())?;
+
+    // Extending the best chain is a new best block, without a reorg.
+    let mut best_chain = vec![genesis_block.id()];
+    for value in [1, 2] {
+        let block = child(&chain, *best_chain.last().unwrap(), value)?;
+        let outcome = chain.import(block.clone())?;
+        assert_eq!(outcome.status, ImportStatus::Imported);
+        assert!(outcome.new_best);
+        assert_eq!(outcome.reorg, None);
+        best_chain.push(block.id());
+    }
+    let (a1, a2) = (best_chain[1], best_chain[2]);
+
+    // A shorter fork leaves the best block unchanged.
+    let b1 = child(&chain, genesis_block.id(), 10)?;
+    assert_eq!(
+        chain.import(b1.clone())?,
+        ImportOutcome::unchanged(ImportStatus::Imported)
+    );
+
+    // An equally long fork only wins the tie by a smaller id. The values are
+    // picked so that one block of the fork loses the tie and another wins
+    // it, whatever the hashes.
+    let mut tied = (20..).map(|value| child(&chain, b1.id(), value));
+    let b2 = tied
+        .find(|block| block.as_ref().map_or(true, |block| block.id() > a2))
+        .expect("values are unbounded")?;
+    let c2 = tied
+        .find(|block| block.as_ref().map_or(true, |block| block.id() < a2))
+        .expect("values are unbounded")?;
+    assert_eq!(
+        chain.import(b2.clone())?,
+        ImportOutcome::unchanged(ImportStatus::Imported)
+    );
+    let outcome = chain.import(c2.clone())?;
+    assert!(outcome.new_best);
+    let reorg = outcome.reorg.unwrap();
+    assert_eq!(reorg.common, genesis_block.id());
+    assert_eq!(reorg.retracted, [a2, a1]);
+    assert_eq!(reorg.enacted, [b1.id(), c2.id()]);
+
+    // A longer fork always wins.
+    let b3 = child(&chain, b2.id(), 30)?;
+    let outcome = chain.import(b3.clone())?;
+    assert!(outcome.new_best);
+    assert_eq!(
+        chain
+            .fork_tree
+            .best_id()
+            .map_err(ChainError::ForkTreeQuery)?,
+        b3.id()
+    );
+    let reorg = outcome.reorg.unwrap();
+    assert_eq!(reorg.common, b1.id());
+    assert_eq!(reorg.retracted, [c2.id()]);
+    assert_eq!(reorg.enacted, [b2.id(), b3.id()]);
+
+    Ok(())
+}
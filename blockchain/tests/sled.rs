@@ -0,0 +1,278 @@
+//! Tests of the sled fork tree.
+
+use blockchain::sled::{SledForkTree, SledForkTreeError};
+use blockchain::{
+    ForkTree, ForkTreeBest, ForkTreeDepths, ForkTreeMut, ForkTreeTransactional, Identified,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+
+mod common;
+use common::{fork, id, Block};
+mod simple_chain;
+use simple_chain::ChainError;
+
+type SledChainError = ChainError<SledForkTreeError, SledForkTreeError>;
+
+/// A canonical chain 0..=10, and a fork 1 off block 4 up to 10.
+fn forked_blocks() -> Vec<Block> {
+    let mut blocks = fork(None, 0, 0, 10);
+    blocks.extend(fork(Some(id(0, 4)), 1, 5, 10));
+    blocks
+}
+
+/// A database directory of its own per test, removed on drop.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("blockchain-sled-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        Self(path)
+    }
+
+    /// Open the database without a background flusher, which would keep it
+    /// locked for a while after the fork tree is dropped.
+    fn open<Block>(&self) -> SledForkTree<Block>
+    where
+        Block: Identified + Serialize + DeserializeOwned,
+        Block::Identifier: Serialize + DeserializeOwned,
+    {
+        let db = sled::Config::new()
+            .path(&self.0)
+            .flush_every_ms(None)
+            .open()
+            .expect("database opens");
+        SledForkTree::open(&db).expect("fork tree opens")
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn forked_inserts_are_queryable() -> Result<(), SledForkTreeError> {
+    let dir = TempDir::new("queries");
+    let mut fork_tree = dir.open();
+    assert!(matches!(
+        fork_tree.best_id(),
+        Err(SledForkTreeError::UnknownBlock)
+    ));
+
+    for block in forked_blocks() {
+        ForkTreeMut::insert(&mut fork_tree, block)?;
+    }
+
+    // Both forks are as deep, so the smallest id is the best.
+    assert_eq!(fork_tree.best_id()?, id(0, 10));
+    assert_eq!(fork_tree.block_depth(&id(1, 7))?, 7);
+    assert_eq!(fork_tree.block(&id(1, 5))?.parent_id, Some(id(0, 4)));
+    let mut at_depth = fork_tree.blocks_at_depth(6)?;
+    at_depth.sort();
+    assert_eq!(at_depth, [id(0, 6), id(1, 6)]);
+    assert!(fork_tree.blocks_at_depth(11)?.is_empty());
+
+    assert_eq!(fork_tree.ancestor_id_at_depth(&id(1, 9), 2)?, id(0, 2));
+    assert!(fork_tree.is_ancestor(&id(1, 9), &id(0, 4))?);
+    assert!(!fork_tree.is_ancestor(&id(1, 9), &id(0, 5))?);
+    assert!(matches!(
+        fork_tree.ancestor_id_at_depth(&id(1, 9), 10),
+        Err(SledForkTreeError::InvalidAncestorDepth)
+    ));
+
+    let (retracted, enacted) = fork_tree
+        .reorg(&id(0, 10), &id(1, 6))
+        .expect("both forks share the genesis");
+    assert_eq!(retracted, (5..=10).map(|n| id(0, n)).collect::<Vec<_>>());
+    assert_eq!(enacted, [id(1, 5), id(1, 6)]);
+
+    // Growing the fork makes it the best.
+    ForkTreeMut::insert(
+        &mut fork_tree,
+        Block {
+            id: id(1, 11),
+            parent_id: Some(id(1, 10)),
+        },
+    )?;
+    assert_eq!(fork_tree.best_id()?, id(1, 11));
+
+    assert!(matches!(
+        ForkTreeMut::insert(
+            &mut fork_tree,
+            Block {
+                id: id(2, 3),
+                parent_id: Some(id(2, 2)),
+            }
+        ),
+        Err(SledForkTreeError::UnknownParent)
+    ));
+    assert!(matches!(
+        ForkTreeMut::insert(
+            &mut fork_tree,
+            Block {
+                id: id(1, 11),
+                parent_id: Some(id(1, 10)),
+            }
+        ),
+        Err(SledForkTreeError::AlreadyInserted)
+    ));
+    assert!(matches!(
+        fork_tree.block(&id(2, 3)),
+        Err(SledForkTreeError::UnknownBlock)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn fork_tree_survives_reopening() -> Result<(), SledForkTreeError> {
+    let dir = TempDir::new("reopen");
+    {
+        let mut fork_tree = dir.open::<Block>();
+        for block in forked_blocks() {
+            ForkTreeMut::insert(&mut fork_tree, block)?;
+        }
+        fork_tree.flush()?;
+    }
+
+    let mut fork_tree = dir.open::<Block>();
+    assert_eq!(fork_tree.best_id()?, id(0, 10));
+    let mut leaves = fork_tree.leaves()?;
+    leaves.sort();
+    assert_eq!(leaves, [id(0, 10), id(1, 10)]);
+
+    ForkTreeMut::insert(
+        &mut fork_tree,
+        Block {
+            id: id(0, 11),
+            parent_id: Some(id(0, 10)),
+        },
+    )?;
+    assert_eq!(fork_tree.best_id()?, id(0, 11));
+    assert_eq!(fork_tree.block_depth(&id(0, 11))?, 11);
+
+    Ok(())
+}
+
+#[test]
+fn basic_build_and_import() -> Result<(), SledChainError> {
+    let dir = TempDir::new("basic");
+    simple_chain::basic_build_and_import(dir.open())
+}
+
+#[test]
+fn build_with_multiple_digests() -> Result<(), SledChainError> {
+    let dir = TempDir::new("digests");
+    simple_chain::build_with_multiple_digests(dir.open())
+}
+
+#[test]
+fn failing_batch_leaves_builder_unchanged() -> Result<(), SledChainError> {
+    let dir = TempDir::new("batch");
+    simple_chain::failing_batch_leaves_builder_unchanged(dir.open())
+}
+
+#[test]
+fn unknown_hash_is_not_found() -> Result<(), SledChainError> {
+    let dir = TempDir::new("unknown");
+    simple_chain::unknown_hash_is_not_found(dir.open())
+}
+
+#[test]
+fn reimport_is_noop() -> Result<(), SledChainError> {
+    let dir = TempDir::new("reimport");
+    simple_chain::reimport_is_noop(dir.open())
+}
+
+#[test]
+fn import_reports_best_changes_and_reorgs() -> Result<(), SledChainError> {
+    let dir = TempDir::new("reorgs");
+    simple_chain::import_reports_best_changes_and_reorgs(dir.open())
+}
+
+#[test]
+fn transaction_commits_all_blocks_or_none() -> Result<(), SledForkTreeError> {
+    let dir = TempDir::new("transaction");
+    let mut fork_tree = dir.open::<Block>();
+    let blocks = forked_blocks();
+    ForkTreeMut::insert(&mut fork_tree, blocks[0].clone())?;
+
+    // A dropped transaction leaves the fork tree unchanged.
+    let mut transaction = fork_tree.begin();
+    ForkTreeTransactional::insert(&fork_tree, &mut transaction, blocks[1].clone())?;
+    assert_eq!(
+        fork_tree.transaction_block_depth(&transaction, &blocks[1].id)?,
+        1
+    );
+    drop(transaction);
+    assert!(matches!(
+        fork_tree.block(&blocks[1].id),
+        Err(SledForkTreeError::UnknownBlock)
+    ));
+
+    // A block staged on a staged parent is committed along with it.
+    let mut transaction = fork_tree.begin();
+    for block in &blocks[1..3] {
+        ForkTreeTransactional::insert(&fork_tree, &mut transaction, block.clone())?;
+    }
+    assert!(matches!(
+        ForkTreeTransactional::insert(&fork_tree, &mut transaction, blocks[2].clone()),
+        Err(SledForkTreeError::AlreadyInserted)
+    ));
+    fork_tree.commit(transaction)?;
+    assert_eq!(fork_tree.best_id()?, id(0, 2));
+    assert_eq!(fork_tree.block_depth(&id(0, 2))?, 2);
+
+    Ok(())
+}
+
+#[test]
+fn deep_ancestors_follow_skip_lists() -> Result<(), SledForkTreeError> {
+    let dir = TempDir::new("skip-lists");
+    let mut fork_tree = dir.open::<Block>();
+    let chain = |fork, numbers: std::ops::RangeInclusive<u32>, parent_id| {
+        let mut parent_id = parent_id;
+        numbers
+            .map(|number| {
+                let block = Block {
+                    id: id(fork, number),
+                    parent_id,
+                };
+                parent_id = Some(block.id);
+                block
+            })
+            .collect::<Vec<_>>()
+    };
+    for block in chain(0, 0..=100, None) {
+        ForkTreeMut::insert(&mut fork_tree, block)?;
+    }
+    // Staged blocks list ancestors both staged and inserted.
+    let mut transaction = fork_tree.begin();
+    for block in chain(0, 101..=200, Some(id(0, 100))) {
+        ForkTreeTransactional::insert(&fork_tree, &mut transaction, block)?;
+    }
+    fork_tree.commit(transaction)?;
+    for block in chain(1, 65..=150, Some(id(0, 64))) {
+        ForkTreeMut::insert(&mut fork_tree, block)?;
+    }
+
+    for depth in 0..=200 {
+        assert_eq!(
+            fork_tree.ancestor_id_at_depth(&id(0, 200), depth)?,
+            id(0, depth as u32)
+        );
+    }
+    for depth in 0..=150 {
+        let fork = if depth <= 64 { 0 } else { 1 };
+        assert_eq!(
+            fork_tree.ancestor_id_at_depth(&id(1, 150), depth)?,
+            id(fork, depth as u32)
+        );
+    }
+
+    Ok(())
+}
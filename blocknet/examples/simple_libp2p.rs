@@ -1,5 +1,5 @@
-use blocknet::{BroadcastService, Message};
-use futures::{select, stream::StreamExt, FutureExt};
+use blocknet::{BroadcastService, Message, Service};
+use futures::{channel::mpsc, select, stream::StreamExt, FutureExt};
 use serde::{Deserialize, Serialize};
 use tokio::{io, io::AsyncBufReadExt};
 use tracing::{error, info};
@@ -24,8 +24,8 @@ impl Message for SimpleBroadcast {
     }
 }
 
-impl Into<String> for SimpleBroadcastTopic {
-    fn into(self) -> String {
+impl From<SimpleBroadcastTopic> for String {
+    fn from(_: SimpleBroadcastTopic) -> String {
         "simple_broadcast_topic".into()
     }
 }
@@ -38,6 +38,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let service = worker.service();
     let mut worker_handle = tokio::spawn(worker.run()).fuse();
 
+    // There's no chain in this example. Typing `import` simulates importing a
+    // new best block, which is pushed to peers.
+    let mut best_block = service.local_info().best_block;
+    let (best_block_sender, best_block_notifications) = mpsc::unbounded();
+    let mut follow_service = service.clone();
+    tokio::spawn(async move {
+        follow_service
+            .follow_local_info(best_block_notifications, |info, best_block| {
+                info.best_block = best_block
+            })
+            .await
+    });
+
     let mut stdin = io::BufReader::new(io::stdin()).lines();
     let mut stream_service = service.clone();
     let mut broadcast_stream = Box::pin(
@@ -55,6 +68,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     if line == "exit" {
                         break
                     }
+                    if line == "import" {
+                        best_block += 1;
+                        let _ = best_block_sender.unbounded_send(best_block);
+                        info!("Imported best block {}", best_block);
+                        continue
+                    }
                     match BroadcastService::<SimpleBroadcast>::broadcast(
                         &mut service,
                         SimpleBroadcast {
@@ -0,0 +1,168 @@
+use crate::{Request, RequestService, Service};
+use futures::{channel::oneshot, stream::Stream};
+use serde::Serialize;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+use sync_extra::MutexExtra;
+
+type Waiter = Box<dyn Any + Send>;
+type InFlight<PeerId> = Arc<Mutex<HashMap<(PeerId, TypeId, Vec<u8>), Vec<Waiter>>>>;
+
+/// A request service sharing identical in-flight requests.
+///
+/// Requests to the same peer with the same serialized request share a single
+/// round-trip, and all callers get the same response. If the shared request
+/// fails, each waiting caller retries on its own, so errors are never shared.
+pub struct Coalescing<S: Service> {
+    inner: S,
+    in_flight: InFlight<S::PeerId>,
+}
+
+impl<S: Service> Coalescing<S> {
+    /// Wrap a service.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            in_flight: Default::default(),
+        }
+    }
+
+    /// The wrapped service.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Service + Clone> Clone for Coalescing<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl<S> Service for Coalescing<S>
+where
+    S: Service,
+    S::PeerId: Send,
+{
+    type PeerId = S::PeerId;
+    type PeerInfo = S::PeerInfo;
+    type Error = S::Error;
+
+    fn local_info(&self) -> S::PeerInfo {
+        self.inner.local_info()
+    }
+
+    fn set_local_info(&mut self, info: S::PeerInfo) {
+        self.inner.set_local_info(info)
+    }
+
+    fn update_local_info(&mut self, f: impl FnOnce(&mut S::PeerInfo)) {
+        self.inner.update_local_info(f)
+    }
+
+    fn peers(&self) -> impl IntoIterator<Item = (S::PeerId, S::PeerInfo)> {
+        self.inner.peers()
+    }
+}
+
+/// Removes the in-flight entry once the leading request completes or is
+/// dropped. Waiters still registered then retry on their own.
+struct InFlightGuard<PeerId: Eq + Hash> {
+    in_flight: InFlight<PeerId>,
+    key: Option<(PeerId, TypeId, Vec<u8>)>,
+}
+
+impl<PeerId: Eq + Hash> InFlightGuard<PeerId> {
+    fn take_waiters(&mut self) -> Vec<Waiter> {
+        match self.key.take() {
+            Some(key) => self
+                .in_flight
+                .lock_unwrap()
+                .remove(&key)
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl<PeerId: Eq + Hash> Drop for InFlightGuard<PeerId> {
+    fn drop(&mut self) {
+        self.take_waiters();
+    }
+}
+
+impl<S, Req> RequestService<Req> for Coalescing<S>
+where
+    S: RequestService<Req>,
+    S::PeerId: Clone + Eq + Hash + Send,
+    Req: Request + Serialize + Send + 'static,
+    Req::Response: Clone + Send + 'static,
+{
+    type Event = S::Event;
+    type Channel = S::Channel;
+
+    fn listen(
+        &mut self,
+    ) -> impl Future<Output = Result<impl Stream<Item = (S::Channel, S::Event)> + Send, S::Error>> + Send
+    {
+        self.inner.listen()
+    }
+
+    async fn request(&mut self, peer: S::PeerId, request: Req) -> Result<Req::Response, S::Error> {
+        let Ok(serialized) = serde_json::to_vec(&request) else {
+            return self.inner.request(peer, request).await;
+        };
+        let key = (peer.clone(), TypeId::of::<Req>(), serialized);
+
+        let waiting = {
+            let mut in_flight = self.in_flight.lock_unwrap();
+            match in_flight.get_mut(&key) {
+                Some(waiters) => {
+                    let (sender, receiver) = oneshot::channel::<Req::Response>();
+                    waiters.push(Box::new(sender));
+                    Some(receiver)
+                }
+                None => {
+                    in_flight.insert(key.clone(), Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(receiver) = waiting {
+            return match receiver.await {
+                Ok(response) => Ok(response),
+                Err(oneshot::Canceled) => self.inner.request(peer, request).await,
+            };
+        }
+
+        let mut guard = InFlightGuard {
+            in_flight: self.in_flight.clone(),
+            key: Some(key),
+        };
+        let response = self.inner.request(peer, request).await?;
+        for waiter in guard.take_waiters() {
+            if let Ok(sender) = waiter.downcast::<oneshot::Sender<Req::Response>>() {
+                let _ = sender.send(response.clone());
+            }
+        }
+
+        Ok(response)
+    }
+
+    fn respond(
+        &mut self,
+        channel: S::Channel,
+        response: Req::Response,
+    ) -> impl Future<Output = Result<(), S::Error>> + Send {
+        self.inner.respond(channel, response)
+    }
+}
@@ -0,0 +1,232 @@
+//! Conformance tests of the service traits.
+//!
+//! The functions here are generic over a [`Backend`], so that all
+//! implementations run the same assertions and honor the trait contracts
+//! identically. They panic on failure, and are meant to be called from tests.
+
+use crate::{BroadcastService, Event, Message, Request, RequestService, Service};
+use futures::{
+    future::{self, Either},
+    stream::StreamExt,
+};
+use futures_timer::Delay;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for the network to deliver.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How often to check again, or to retry a broadcast.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+pub const PING_TOPIC: &str = "conformance_ping";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ping {
+    pub value: u64,
+}
+
+impl Message for Ping {
+    type Topic = &'static str;
+
+    fn topic(&self) -> &'static str {
+        PING_TOPIC
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Echo {
+    pub value: u64,
+}
+
+impl Request for Echo {
+    type Response = u64;
+
+    const PROTOCOL_ID: &'static str = "/conformance/echo/1";
+}
+
+/// A service implementation under test.
+pub trait Backend {
+    type Service: Service<PeerInfo = PeerInfo>
+        + BroadcastService<Ping>
+        + RequestService<Echo>
+        + Clone
+        + Send
+        + 'static;
+
+    /// Create the services of two peers, connected to each other.
+    fn pair(
+        &mut self,
+        first: PeerInfo,
+        second: PeerInfo,
+    ) -> impl Future<Output = (Self::Service, Self::Service)> + Send;
+}
+
+/// Wait until the condition holds, panicking after the timeout.
+async fn eventually(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        Delay::new(RETRY_INTERVAL).await;
+    }
+}
+
+/// Id of the remote peer with the given info, once the service reports it.
+async fn remote_peer_id<S>(service: &S, id: u64) -> S::PeerId
+where
+    S: Service<PeerInfo = PeerInfo>,
+{
+    let mut peer_id = None;
+    eventually("peer to be reported", || {
+        peer_id = service
+            .peers()
+            .into_iter()
+            .find(|(_, info)| info.id == id)
+            .map(|(peer_id, _)| peer_id);
+        peer_id.is_some()
+    })
+    .await;
+
+    peer_id.expect("peer is reported; qed")
+}
+
+/// Both peers report each other, with their local info.
+pub async fn test_peers_reported<B: Backend>(backend: &mut B) {
+    let (first, second) = backend.pair(PeerInfo { id: 1 }, PeerInfo { id: 2 }).await;
+
+    assert_eq!(first.local_info(), PeerInfo { id: 1 });
+    assert_eq!(second.local_info(), PeerInfo { id: 2 });
+
+    remote_peer_id(&first, 2).await;
+    remote_peer_id(&second, 1).await;
+
+    // The local peer is not reported as a remote.
+    assert!(first.peers().into_iter().all(|(_, info)| info.id != 1));
+}
+
+/// A broadcast is received by the listening peer, from its origin.
+pub async fn test_broadcast_roundtrip<B: Backend>(backend: &mut B)
+where
+    <B::Service as Service>::PeerId: PartialEq + Debug,
+    <B::Service as Service>::Error: Debug,
+{
+    let (first, mut second) = backend.pair(PeerInfo { id: 1 }, PeerInfo { id: 2 }).await;
+    let second_peer_id = remote_peer_id(&first, 2).await;
+
+    let mut listener = first.clone();
+    let mut messages = Box::pin(
+        BroadcastService::<Ping>::listen(&mut listener, PING_TOPIC)
+            .await
+            .expect("listen succeeds"),
+    );
+
+    // Gossip may need a while before the subscription propagates, so the
+    // broadcast is retried until received.
+    let deadline = Instant::now() + TIMEOUT;
+    let event = loop {
+        assert!(Instant::now() < deadline, "timed out waiting for broadcast");
+        BroadcastService::<Ping>::broadcast(&mut second, Ping { value: 7 })
+            .await
+            .expect("broadcast succeeds");
+
+        match future::select(messages.next(), Delay::new(RETRY_INTERVAL)).await {
+            Either::Left((Some(event), _)) => break event,
+            Either::Left((None, _)) => panic!("broadcast stream ended"),
+            Either::Right(_) => (),
+        }
+    };
+
+    assert_eq!(*event.origin(), second_peer_id);
+    assert_eq!(event.into_value(), Ping { value: 7 });
+}
+
+/// Once unsubscribed, the listener stream ends, without any broadcast made
+/// afterwards.
+pub async fn test_broadcast_unsubscribe<B: Backend>(backend: &mut B)
+where
+    <B::Service as Service>::PeerId: PartialEq + Debug,
+    <B::Service as Service>::Error: Debug,
+{
+    let (mut first, mut second) = backend.pair(PeerInfo { id: 1 }, PeerInfo { id: 2 }).await;
+    remote_peer_id(&first, 2).await;
+
+    let mut listener = first.clone();
+    let mut messages = Box::pin(
+        BroadcastService::<Ping>::listen(&mut listener, PING_TOPIC)
+            .await
+            .expect("listen succeeds"),
+    );
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        assert!(Instant::now() < deadline, "timed out waiting for broadcast");
+        BroadcastService::<Ping>::broadcast(&mut second, Ping { value: 7 })
+            .await
+            .expect("broadcast succeeds");
+
+        match future::select(messages.next(), Delay::new(RETRY_INTERVAL)).await {
+            Either::Left((Some(_), _)) => break,
+            Either::Left((None, _)) => panic!("broadcast stream ended"),
+            Either::Right(_) => (),
+        }
+    }
+
+    BroadcastService::<Ping>::unsubscribe(&mut first, PING_TOPIC)
+        .await
+        .expect("unsubscribe succeeds");
+    BroadcastService::<Ping>::broadcast(&mut second, Ping { value: 8 })
+        .await
+        .expect("broadcast succeeds");
+
+    // Broadcasts received before unsubscribing may still be buffered.
+    let drain = async {
+        while let Some(event) = messages.next().await {
+            assert_eq!(event.into_value(), Ping { value: 7 });
+        }
+    };
+    match future::select(Box::pin(drain), Delay::new(TIMEOUT)).await {
+        Either::Left(((), _)) => (),
+        Either::Right(_) => panic!("broadcast stream did not end"),
+    }
+}
+
+/// A request is answered by the listening peer.
+pub async fn test_request_roundtrip<B: Backend>(backend: &mut B)
+where
+    <B::Service as Service>::PeerId: PartialEq + Debug,
+    <B::Service as Service>::Error: Debug,
+{
+    let (mut first, second) = backend.pair(PeerInfo { id: 1 }, PeerInfo { id: 2 }).await;
+    let first_peer_id = remote_peer_id(&second, 1).await;
+    let second_peer_id = remote_peer_id(&first, 2).await;
+
+    let mut listener = second.clone();
+    let mut requests = Box::pin(
+        RequestService::<Echo>::listen(&mut listener)
+            .await
+            .expect("listen succeeds"),
+    );
+
+    let mut responder = second.clone();
+    let respond = async move {
+        let (channel, event) = requests.next().await.expect("request is received");
+        assert_eq!(*event.origin(), first_peer_id);
+
+        let value = event.into_value().value;
+        RequestService::<Echo>::respond(&mut responder, channel, value + 1)
+            .await
+            .expect("respond succeeds");
+    };
+    let request = RequestService::<Echo>::request(&mut first, second_peer_id, Echo { value: 41 });
+
+    let ((), response) = future::join(respond, request).await;
+    assert_eq!(response.expect("request succeeds"), 42);
+}
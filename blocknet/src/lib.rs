@@ -1,7 +1,12 @@
+mod coalesce;
 mod service;
 
+pub mod conformance;
 pub mod libp2p;
+pub mod mock;
 
+pub use crate::coalesce::Coalescing;
 pub use crate::service::{
-    BroadcastService, Event, Message, NotifyService, RequestService, Service,
+    BroadcastService, Correlated, Event, Message, NotifyService, Priority, Request, RequestService,
+    Service,
 };
@@ -0,0 +1,673 @@
+use super::{
+    codec, flow_control, peer_info, priority, rate_limit, reputation, sequence, Behaviour, Error,
+    EventRecorder, PeerFullInfo, PeerId, ProtocolVersion, TopicShards, VersionPolicy, WireCodec,
+    Worker,
+};
+use futures::{
+    channel::mpsc,
+    stream::{self, BoxStream, Stream, StreamExt},
+};
+use futures_timer::Delay;
+use libp2p::{
+    core::{
+        transport::{MemoryTransport, OptionalTransport, Transport as _},
+        upgrade,
+    },
+    gossipsub, identify,
+    identity::Keypair,
+    kad, mdns,
+    multiaddr::Protocol,
+    ping, request_response,
+    swarm::{behaviour::toggle::Toggle, StreamProtocol, Swarm},
+    Multiaddr,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::Debug,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tracing::warn;
+
+const DEFAULT_IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Well within the default provider record TTL of 48 hours.
+const DEFAULT_REPROVIDE_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// Shortest interval between gap checks of ordered topics, so that a zero gap
+/// timeout does not keep the worker busy.
+const MIN_REORDER_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Transport a worker listens on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportKind {
+    Tcp,
+    Quic,
+    /// In-process, enabled with [`WorkerBuilder::with_memory_transport`].
+    Memory,
+}
+
+impl TransportKind {
+    /// Transport of a listen address, if it is one of the supported ones.
+    fn of(addr: &Multiaddr) -> Option<Self> {
+        addr.iter().find_map(|protocol| match protocol {
+            Protocol::QuicV1 => Some(TransportKind::Quic),
+            Protocol::Tcp(_) => Some(TransportKind::Tcp),
+            Protocol::Memory(_) => Some(TransportKind::Memory),
+            _ => None,
+        })
+    }
+}
+
+type MessageIdFn = Arc<dyn Fn(&[u8]) -> gossipsub::MessageId + Send + Sync>;
+
+/// Builder of a libp2p [`Worker`], composing the optional behaviours.
+///
+/// By default, the worker gets a new identity, enables mdns, and listens on
+/// all interfaces over both QUIC and TCP. Failing to listen over QUIC, such as
+/// where UDP is blocked, only logs a warning, as long as another listen
+/// address succeeds.
+pub struct WorkerBuilder<PeerInfo> {
+    local_info: PeerInfo,
+    keypair: Option<Keypair>,
+    network_id: Option<String>,
+    mdns: bool,
+    memory_transport: bool,
+    ping: bool,
+    relay: bool,
+    bootstrap: Vec<Multiaddr>,
+    listen_addrs: Vec<Multiaddr>,
+    idle_connection_timeout: Duration,
+    request_timeout: Duration,
+    codecs: Vec<WireCodec>,
+    broadcast_codec: WireCodec,
+    inbound_rate_limit: Option<rate_limit::Config>,
+    sequenced_topics: Vec<String>,
+    ordered_topics: Vec<(String, Duration)>,
+    message_id_fn: Option<MessageIdFn>,
+    topic_shards: Option<TopicShards>,
+    max_subscriptions: Option<usize>,
+    protocol_version: (u32, u32),
+    version_policy: VersionPolicy,
+    reputation: reputation::Config,
+    reprovide_interval: Duration,
+    reprovide_timer: Option<BoxStream<'static, ()>>,
+    recorder: Option<EventRecorder>,
+}
+
+/// What the swarm is built from, kept by the worker to build it again under
+/// a new identity, with [`super::Service::rotate_identity`].
+#[derive(Clone)]
+pub(super) struct SwarmConfig {
+    protocol_version: String,
+    peer_info_protocol: StreamProtocol,
+    peer_info_push_protocol: StreamProtocol,
+    request_response_protocol: StreamProtocol,
+    mdns: bool,
+    ping: bool,
+    relay: bool,
+    memory_transport: bool,
+    idle_connection_timeout: Duration,
+    request_timeout: Duration,
+    inbound_rate_limit: Option<rate_limit::Config>,
+    message_id_fn: Option<MessageIdFn>,
+    reputation: reputation::Config,
+    /// Dialed again under a rotated identity.
+    pub(super) bootstrap: Vec<Multiaddr>,
+}
+
+impl SwarmConfig {
+    /// Build a swarm under the identity, listening nowhere and connected to
+    /// no one yet.
+    pub(super) fn build<PeerInfo>(
+        &self,
+        keypair: Keypair,
+        local_info: PeerFullInfo<PeerInfo>,
+    ) -> Result<Swarm<Behaviour<PeerInfo>>, Error>
+    where
+        PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + 'static,
+    {
+        let (mdns, ping, relay) = (self.mdns, self.ping, self.relay);
+        let memory_transport = self.memory_transport;
+        let request_timeout = self.request_timeout;
+        let rate_limit = self
+            .inbound_rate_limit
+            .clone()
+            .map(rate_limit::Behaviour::new);
+        let message_id_fn = self.message_id_fn.clone();
+        let reputation = self.reputation;
+        let protocol_version = self.protocol_version.clone();
+        let peer_info_protocol = self.peer_info_protocol.clone();
+        let peer_info_push_protocol = self.peer_info_push_protocol.clone();
+        let request_response_protocol = self.request_response_protocol.clone();
+
+        let swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_tcp(
+                libp2p::tcp::Config::default(),
+                libp2p::noise::Config::new,
+                libp2p::yamux::Config::default,
+            )?
+            .with_quic()
+            .with_other_transport(|key| {
+                Ok::<_, Box<dyn std::error::Error + Send + Sync>>(if memory_transport {
+                    OptionalTransport::some(
+                        MemoryTransport::default()
+                            .upgrade(upgrade::Version::V1)
+                            .authenticate(libp2p::noise::Config::new(key)?)
+                            .multiplex(libp2p::yamux::Config::default()),
+                    )
+                } else {
+                    OptionalTransport::none()
+                })
+            })
+            .map_err(|e| Error::Build(Box::new(e)))?
+            .with_relay_client(libp2p::noise::Config::new, libp2p::yamux::Config::default)?
+            .with_behaviour(|key, relay_client| {
+                let peer_id = PeerId::from_public_key(&key.public());
+
+                let mut gossipsub_config = gossipsub::ConfigBuilder::default();
+                if let Some(message_id_fn) = message_id_fn {
+                    gossipsub_config.message_id_fn(move |message| message_id_fn(&message.data));
+                }
+                let mut gossipsub = gossipsub::Behaviour::new(
+                    gossipsub::MessageAuthenticity::Signed(key.clone()),
+                    gossipsub_config.build()?,
+                )?;
+                // Scored by the application reputation alone, rather than
+                // also by IP, which peers behind the same NAT share.
+                if reputation.gossipsub_scoring {
+                    gossipsub.with_peer_score(
+                        gossipsub::PeerScoreParams {
+                            app_specific_weight: 1.0,
+                            ip_colocation_factor_weight: 0.0,
+                            retain_score: reputation.retention,
+                            ..Default::default()
+                        },
+                        gossipsub::PeerScoreThresholds::default(),
+                    )?;
+                }
+
+                // Provider records are republished by the worker's reprovide
+                // timer instead.
+                let mut kademlia_config = kad::Config::default();
+                kademlia_config.set_provider_publication_interval(None);
+                let kademlia = kad::Behaviour::with_config(
+                    peer_id,
+                    kad::store::MemoryStore::new(peer_id),
+                    kademlia_config,
+                );
+
+                let identify = identify::Behaviour::new(identify::Config::new(
+                    protocol_version.clone(),
+                    key.public(),
+                ));
+
+                let peer_info = peer_info::json::Behaviour::new(
+                    peer_info::Config::new(
+                        protocol_version,
+                        key.public(),
+                        peer_info_protocol,
+                        peer_info_push_protocol,
+                    ),
+                    local_info.clone(),
+                );
+
+                let mdns = if mdns {
+                    Some(mdns::Behaviour::new(mdns::Config::default(), peer_id)?)
+                } else {
+                    None
+                };
+
+                let ping = ping.then(|| ping::Behaviour::new(ping::Config::new()));
+                let relay = relay.then_some(relay_client);
+
+                let request_response = request_response::Behaviour::new(
+                    // TODO: At this moment we just allow all request/response types to be
+                    // communicated via a single protocol. It's reasonable to expect that
+                    // users will want to restrict this and negioate protocol types in
+                    // advance. In those situations, we will introduce a new `Metadata` type
+                    // that needs to be implemented by all request/response types, and default
+                    // it to `AnyMetadata`. The same is with notifications and broadcasts.
+                    vec![(
+                        request_response_protocol,
+                        request_response::ProtocolSupport::Full,
+                    )],
+                    request_response::Config::default().with_request_timeout(request_timeout),
+                );
+
+                Ok(Behaviour::<PeerInfo> {
+                    rate_limit: Toggle::from(rate_limit),
+                    bans: reputation::Bans::default(),
+                    gossipsub,
+                    kademlia,
+                    identify,
+                    peer_info,
+                    mdns: Toggle::from(mdns),
+                    ping: Toggle::from(ping),
+                    relay: Toggle::from(relay),
+                    request_response,
+                })
+            })
+            .map_err(|e| Error::Build(Box::new(e)))?
+            .with_swarm_config(|config| {
+                config.with_idle_connection_timeout(self.idle_connection_timeout)
+            })
+            .build();
+
+        Ok(swarm)
+    }
+}
+
+impl<PeerInfo> WorkerBuilder<PeerInfo>
+where
+    PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    pub fn new(local_info: PeerInfo) -> Self {
+        Self {
+            local_info,
+            keypair: None,
+            network_id: None,
+            mdns: true,
+            memory_transport: false,
+            ping: false,
+            relay: false,
+            bootstrap: Vec::new(),
+            listen_addrs: vec![
+                "/ip4/0.0.0.0/udp/0/quic-v1"
+                    .parse()
+                    .expect("address is valid; qed"),
+                "/ip4/0.0.0.0/tcp/0".parse().expect("address is valid; qed"),
+            ],
+            idle_connection_timeout: DEFAULT_IDLE_CONNECTION_TIMEOUT,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            codecs: codec::default_codecs(),
+            broadcast_codec: WireCodec::Json,
+            inbound_rate_limit: None,
+            sequenced_topics: Vec::new(),
+            ordered_topics: Vec::new(),
+            message_id_fn: None,
+            topic_shards: None,
+            max_subscriptions: None,
+            protocol_version: (0, 1),
+            version_policy: VersionPolicy::Exact,
+            reputation: Default::default(),
+            reprovide_interval: DEFAULT_REPROVIDE_INTERVAL,
+            reprovide_timer: None,
+            recorder: None,
+        }
+    }
+
+    /// Use an existing identity instead of generating a new one.
+    pub fn with_keypair(mut self, keypair: Keypair) -> Self {
+        self.keypair = Some(keypair);
+        self
+    }
+
+    /// Namespace all protocol names with the network id, so that nodes of
+    /// different networks do not talk to each other.
+    pub fn with_network_id(mut self, network_id: impl Into<String>) -> Self {
+        self.network_id = Some(network_id.into());
+        self
+    }
+
+    /// Enable or disable local peer discovery via mdns.
+    pub fn with_mdns(mut self, enabled: bool) -> Self {
+        self.mdns = enabled;
+        self
+    }
+
+    /// Also support the in-process memory transport, to listen on and dial
+    /// `/memory/<port>` addresses, such as in tests.
+    pub fn with_memory_transport(mut self) -> Self {
+        self.memory_transport = true;
+        self
+    }
+
+    /// Enable the ping protocol.
+    pub fn with_ping(mut self) -> Self {
+        self.ping = true;
+        self
+    }
+
+    /// Enable the relay client, so that the node can be reached via relays.
+    pub fn with_relay(mut self) -> Self {
+        self.relay = true;
+        self
+    }
+
+    /// Addresses to dial on start. Addresses ending with `/p2p/<peer id>` are
+    /// also added to the DHT routing table.
+    pub fn with_bootstrap(mut self, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.bootstrap.extend(addrs);
+        self
+    }
+
+    /// Replace the default listen addresses.
+    pub fn with_listen_addrs(mut self, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.listen_addrs = addrs.into_iter().collect();
+        self
+    }
+
+    /// How long a connection without any active protocol is kept open.
+    pub fn with_idle_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_connection_timeout = timeout;
+        self
+    }
+
+    /// How long an outbound request waits for its response.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Request codecs advertised to peers, in order of preference. JSON is
+    /// always supported, as the fallback when there's no common codec.
+    pub fn with_codecs(mut self, codecs: impl IntoIterator<Item = WireCodec>) -> Self {
+        self.codecs = codecs.into_iter().collect();
+        if !self.codecs.contains(&WireCodec::Json) {
+            self.codecs.push(WireCodec::Json);
+        }
+        self
+    }
+
+    /// Prefer the codec for requests as well as for the local broadcasts,
+    /// like [`Self::with_codecs`] with the codec alone, and
+    /// [`Self::with_broadcast_codec`].
+    pub fn with_codec(self, codec: WireCodec) -> Self {
+        self.with_codecs([codec]).with_broadcast_codec(codec)
+    }
+
+    /// Codec of the local broadcasts. Defaults to JSON, which all peers
+    /// support. Peers not supporting the codec drop the broadcasts, and
+    /// report them with [`super::Service::listen_unsupported_codecs`].
+    pub fn with_broadcast_codec(mut self, codec: WireCodec) -> Self {
+        self.broadcast_codec = codec;
+        self
+    }
+
+    /// Deny inbound connections from a source IP beyond `max_connections` per
+    /// `interval`.
+    pub fn with_inbound_rate_limit(mut self, max_connections: usize, interval: Duration) -> Self {
+        self.inbound_rate_limit = Some(rate_limit::Config {
+            max_connections,
+            interval,
+        });
+        self
+    }
+
+    /// Stamp broadcasts on the topic with a sequence, so that receivers drop
+    /// replayed and out of order messages.
+    pub fn with_sequenced_topic(mut self, topic: impl Into<String>) -> Self {
+        self.sequenced_topics.push(topic.into());
+        self
+    }
+
+    /// Deliver the broadcasts of each origin on the topic in sequence order,
+    /// buffering the ones arriving ahead of a missing sequence. A sequence
+    /// still missing after `gap_timeout` is skipped over, and reported to
+    /// the gap listeners of the topic. Senders must stamp the topic with
+    /// [`Self::with_sequenced_topic`]. Gaps are checked for at most every
+    /// 10ms, however short the timeout.
+    pub fn with_ordered_topic(mut self, topic: impl Into<String>, gap_timeout: Duration) -> Self {
+        self.ordered_topics.push((topic.into(), gap_timeout));
+        self
+    }
+
+    /// Compute the gossipsub id of broadcasts from their raw bytes, as read
+    /// by [`super::AnyMessage::decode`], instead of from their source and
+    /// gossipsub sequence number. Messages with the same id are deduplicated,
+    /// so that deriving it from an application field, such as a block hash,
+    /// deduplicates the same message broadcast by different peers.
+    ///
+    /// All the nodes of a network must use the same function: gossipsub
+    /// peers exchange ids to advertise and request messages, so that nodes
+    /// disagreeing on ids fail to gossip with each other.
+    pub fn with_message_id_fn(
+        mut self,
+        message_id_fn: impl Fn(&[u8]) -> gossipsub::MessageId + Send + Sync + 'static,
+    ) -> Self {
+        self.message_id_fn = Some(Arc::new(message_id_fn));
+        self
+    }
+
+    /// Broadcast the logical topics over `shards` physical gossipsub topics,
+    /// by [`TopicShards`], instead of one gossipsub topic each. Listeners only
+    /// receive the broadcasts of their logical topic. All the nodes of a
+    /// network must use the same number of shards.
+    pub fn with_topic_shards(mut self, shards: u32) -> Self {
+        self.topic_shards = Some(TopicShards::new(shards));
+        self
+    }
+
+    /// Fail listening with [`super::Error::TooManySubscriptions`] rather than
+    /// subscribe to more than `max_subscriptions` gossipsub topics. With
+    /// [`Self::with_topic_shards`], logical topics of the same shard share a
+    /// subscription.
+    pub fn with_max_subscriptions(mut self, max_subscriptions: usize) -> Self {
+        self.max_subscriptions = Some(max_subscriptions);
+        self
+    }
+
+    /// Major and minor protocol version advertised to peers. Defaults to 0.1.
+    pub fn with_protocol_version(mut self, major: u32, minor: u32) -> Self {
+        self.protocol_version = (major, minor);
+        self
+    }
+
+    /// Which peer protocol versions to stay connected to. Defaults to exact
+    /// match.
+    pub fn with_version_policy(mut self, policy: VersionPolicy) -> Self {
+        self.version_policy = policy;
+        self
+    }
+
+    /// Ban the peers whose reputation, from [`super::Service::report_peer`],
+    /// falls below `threshold`, for `duration`. Defaults to
+    /// [`super::DEFAULT_BAN_THRESHOLD`] for [`super::DEFAULT_BAN_DURATION`].
+    pub fn with_peer_bans(mut self, threshold: i32, duration: Duration) -> Self {
+        self.reputation.ban_threshold = threshold;
+        self.reputation.ban_duration = duration;
+        self
+    }
+
+    /// How long the reputation of a disconnected peer is kept, so that it
+    /// resumes on reconnecting. Defaults to
+    /// [`super::DEFAULT_REPUTATION_RETENTION`].
+    pub fn with_reputation_retention(mut self, retention: Duration) -> Self {
+        self.reputation.retention = retention;
+        self
+    }
+
+    /// Also score peers in gossipsub by their reputation, so that gossipsub
+    /// stops forwarding to and accepting from low scoring peers before they
+    /// are banned. Disabled by default, as gossipsub then also scores the
+    /// mesh delivery of the peers, with thresholds to tune per network.
+    pub fn with_gossipsub_scoring(mut self) -> Self {
+        self.reputation.gossipsub_scoring = true;
+        self
+    }
+
+    /// How often provided DHT keys are announced again. Defaults to 12 hours.
+    pub fn with_reprovide_interval(mut self, interval: Duration) -> Self {
+        self.reprovide_interval = interval;
+        self
+    }
+
+    /// Reprovide on every tick of the stream instead of every reprovide
+    /// interval, such as to drive it manually in tests.
+    pub fn with_reprovide_timer(mut self, ticks: impl Stream<Item = ()> + Send + 'static) -> Self {
+        self.reprovide_timer = Some(ticks.boxed());
+        self
+    }
+
+    /// Record the events of the worker, to replay them with
+    /// [`Worker::replay`].
+    pub fn with_event_recorder(mut self, recorder: EventRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    fn protocol_name(&self, name: &str) -> Result<StreamProtocol, Error> {
+        let name = match &self.network_id {
+            Some(network_id) => format!("/blocknet/{}/{}", network_id, name),
+            None => format!("/blocknet/{}", name),
+        };
+
+        StreamProtocol::try_from_owned(name).map_err(|e| Error::Build(Box::new(e)))
+    }
+
+    pub fn build(self) -> Result<Worker<PeerInfo>, Error> {
+        let version = ProtocolVersion {
+            network_id: self.network_id.clone(),
+            major: self.protocol_version.0,
+            minor: self.protocol_version.1,
+        };
+
+        let swarm_config = SwarmConfig {
+            protocol_version: version.to_string(),
+            peer_info_protocol: self.protocol_name("peer_info/v0.1")?,
+            peer_info_push_protocol: self.protocol_name("peer_info/push/v0.1")?,
+            request_response_protocol: self.protocol_name("request_response/v0.1")?,
+            mdns: self.mdns,
+            ping: self.ping,
+            relay: self.relay,
+            memory_transport: self.memory_transport,
+            idle_connection_timeout: self.idle_connection_timeout,
+            request_timeout: self.request_timeout,
+            inbound_rate_limit: self.inbound_rate_limit,
+            message_id_fn: self.message_id_fn,
+            reputation: self.reputation,
+            bootstrap: self.bootstrap.clone(),
+        };
+        let local_info = PeerFullInfo {
+            info: self.local_info,
+            codecs: self.codecs,
+        };
+        let keypair = self.keypair.unwrap_or_else(Keypair::generate_ed25519);
+        let mut swarm = swarm_config.build(keypair, local_info.clone())?;
+
+        let mut active_transports = Vec::new();
+        let mut listening = false;
+        let mut quic_error = None;
+        for addr in self.listen_addrs {
+            let transport = TransportKind::of(&addr);
+            match swarm.listen_on(addr.clone()) {
+                Ok(_) => {
+                    // Any listener is enough to do without QUIC, including
+                    // one of no known transport.
+                    listening = true;
+                    if let Some(transport) = transport {
+                        if !active_transports.contains(&transport) {
+                            active_transports.push(transport);
+                        }
+                    }
+                }
+                Err(err) if transport == Some(TransportKind::Quic) => {
+                    warn!("QUIC unavailable, failed to listen on {}: {:?}", addr, err);
+                    quic_error = Some(err);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if !listening {
+            if let Some(err) = quic_error {
+                return Err(err.into());
+            }
+        }
+
+        for addr in self.bootstrap {
+            if let Some(libp2p::multiaddr::Protocol::P2p(peer_id)) = addr.iter().last() {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, addr.clone());
+            }
+
+            swarm.dial(addr).map_err(|e| Error::Build(Box::new(e)))?;
+        }
+
+        let (action_sender, action_receiver) =
+            flow_control::channel(super::ACTION_CHANNEL_BUFFER_SIZE);
+        let (broadcast_sender, broadcast_receiver) =
+            priority::channel(super::BROADCAST_QUEUE_CAPACITY);
+        let (local_info_sender, local_info_receiver) = mpsc::channel(0);
+        let reprovide_interval = self.reprovide_interval;
+        let reprovide_timer = self.reprovide_timer.unwrap_or_else(|| {
+            stream::unfold((), move |()| async move {
+                Delay::new(reprovide_interval).await;
+                Some(((), ()))
+            })
+            .boxed()
+        });
+        // Gaps are checked for at a fraction of the shortest timeout, so that
+        // they are reported at most that much late.
+        let reorder_timer = match self
+            .ordered_topics
+            .iter()
+            .map(|(_, timeout)| *timeout)
+            .min()
+        {
+            Some(timeout) => {
+                let interval = (timeout / 4).max(MIN_REORDER_INTERVAL);
+                stream::unfold((), move |()| async move {
+                    Delay::new(interval).await;
+                    Some(((), ()))
+                })
+                .boxed()
+            }
+            None => stream::pending().boxed(),
+        };
+
+        Ok(Worker {
+            swarm,
+            swarm_config,
+            peers: Arc::new(RwLock::new(Default::default())),
+            local_info: Arc::new(RwLock::new(local_info)),
+            broadcast_listen_senders: Default::default(),
+            request_listen_senders: Default::default(),
+            peer_addrs: Default::default(),
+            connect_waiters: Default::default(),
+            peer_info_waiters: Default::default(),
+            topic_peer_waiters: Default::default(),
+            recorder: self.recorder,
+            broadcast_sequences: self
+                .sequenced_topics
+                .into_iter()
+                .map(|topic| (topic, sequence::initial_sequence()))
+                .collect(),
+            sequence_filter: sequence::SequenceFilter::new(),
+            reorder_buffers: self
+                .ordered_topics
+                .into_iter()
+                .map(|(topic, gap_timeout)| {
+                    (
+                        gossipsub::IdentTopic::new(topic).hash(),
+                        sequence::ReorderBuffer::new(gap_timeout),
+                    )
+                })
+                .collect(),
+            gap_listen_senders: Default::default(),
+            unsupported_codec_senders: Vec::new(),
+            unsupported_codecs: Default::default(),
+            broadcast_codec: self.broadcast_codec,
+            topic_shards: self.topic_shards,
+            max_subscriptions: self.max_subscriptions,
+            protocol_version: version,
+            version_policy: self.version_policy,
+            reputations: reputation::Reputations::new(self.reputation),
+            pending_requests: Default::default(),
+            providing: Default::default(),
+            topic_peers: Default::default(),
+            peer_protocols: Default::default(),
+            active_transports: Arc::new(active_transports),
+            reprovide_timer: reprovide_timer.fuse(),
+            reorder_timer: reorder_timer.fuse(),
+            action_sender,
+            action_receiver,
+            broadcast_sender,
+            broadcast_receiver,
+            local_info_receiver,
+            local_info_sender,
+        })
+    }
+}
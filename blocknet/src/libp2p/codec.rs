@@ -0,0 +1,129 @@
+use super::{Error, PeerId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Wire encoding of requests, responses and broadcasts.
+///
+/// Codecs are advertised in the peer info exchange, and each request uses the
+/// best codec supported by both peers. Broadcasts go to peers of unknown
+/// codecs, so that each one is tagged with the codec of its sender, and
+/// receivers drop the ones tagged with a codec they don't support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WireCodec {
+    /// Compact SCALE binary encoding.
+    Scale,
+    /// Human-readable JSON. Supported by all peers.
+    Json,
+}
+
+impl WireCodec {
+    /// Pick the codec to talk to a remote. The first local codec, in order of
+    /// preference, that the remote supports is used, falling back to JSON.
+    pub fn negotiate(local: &[WireCodec], remote: &[WireCodec]) -> WireCodec {
+        local
+            .iter()
+            .find(|codec| remote.contains(codec))
+            .copied()
+            .unwrap_or(WireCodec::Json)
+    }
+
+    /// Byte tagging broadcasts encoded with the codec.
+    pub fn tag(&self) -> u8 {
+        match self {
+            WireCodec::Json => 0,
+            WireCodec::Scale => 1,
+        }
+    }
+
+    /// Codec of a broadcast tag, if known.
+    pub fn from_tag(tag: u8) -> Option<WireCodec> {
+        match tag {
+            0 => Some(WireCodec::Json),
+            1 => Some(WireCodec::Scale),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        match self {
+            WireCodec::Scale => {
+                serde_scale::to_vec(value).map_err(|e| Error::Codec(format!("{:?}", e)))
+            }
+            WireCodec::Json => {
+                serde_json::to_vec(value).map_err(|e| Error::Codec(format!("{:?}", e)))
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error> {
+        match self {
+            WireCodec::Scale => {
+                serde_scale::from_slice(data).map_err(|e| Error::Codec(format!("{:?}", e)))
+            }
+            WireCodec::Json => {
+                serde_json::from_slice(data).map_err(|e| Error::Codec(format!("{:?}", e)))
+            }
+        }
+    }
+}
+
+pub(crate) fn default_codecs() -> Vec<WireCodec> {
+    vec![WireCodec::Json]
+}
+
+/// Codec of requests and responses from peers predating codec negotiation,
+/// which used JSON.
+pub(crate) fn default_codec() -> WireCodec {
+    WireCodec::Json
+}
+
+/// Tag of broadcasts from peers predating codec tags, which used JSON.
+pub(crate) fn default_tag() -> u8 {
+    WireCodec::Json.tag()
+}
+
+/// Broadcasts of an origin on a topic were dropped, because they are tagged
+/// with a codec not supported locally. Reported once per origin, topic and
+/// tag, rather than for each message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedCodec {
+    pub origin: PeerId,
+    pub topic: String,
+    /// Tag of the broadcasts, see [`WireCodec::tag`].
+    pub tag: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_prefers_local_order() {
+        use WireCodec::*;
+
+        assert_eq!(WireCodec::negotiate(&[Scale, Json], &[Json]), Json);
+        assert_eq!(WireCodec::negotiate(&[Scale, Json], &[Json, Scale]), Scale);
+        assert_eq!(WireCodec::negotiate(&[Json, Scale], &[Scale, Json]), Json);
+        assert_eq!(WireCodec::negotiate(&[Scale], &[]), Json);
+    }
+
+    #[test]
+    fn tags_roundtrip() {
+        for codec in [WireCodec::Json, WireCodec::Scale] {
+            assert_eq!(WireCodec::from_tag(codec.tag()), Some(codec));
+        }
+        assert_eq!(WireCodec::from_tag(default_tag()), Some(WireCodec::Json));
+        assert_eq!(WireCodec::from_tag(255), None);
+    }
+
+    #[test]
+    fn scale_roundtrip() {
+        let value = (1u64, Some("block".to_string()), vec![1u8, 2, 3]);
+        let encoded = WireCodec::Scale.encode(&value).unwrap();
+        assert_eq!(
+            WireCodec::Scale
+                .decode::<(u64, Option<String>, Vec<u8>)>(&encoded)
+                .unwrap(),
+            value
+        );
+    }
+}
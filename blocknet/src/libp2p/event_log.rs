@@ -0,0 +1,246 @@
+//! Recording of the events driving a worker, so that a misbehaving session
+//! can be replayed for post-mortem debugging.
+//!
+//! Only the events changing the state observable from a [`super::Service`]
+//! are recorded: connections, peer info, topic subscriptions, broadcast
+//! messages, and the broadcast actions of the local services. Requests are
+//! not recorded.
+
+use super::{peer_info, ActionItem, AnyMessage, BehaviourEvent, Error, PeerFullInfo};
+use libp2p::{gossipsub, swarm::SwarmEvent, PeerId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+/// An event of the worker, from the swarm or from a local service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordedEvent<PeerInfo> {
+    ConnectionEstablished {
+        peer_id: PeerId,
+    },
+    /// The last connection to the peer closed.
+    ConnectionClosed {
+        peer_id: PeerId,
+    },
+    PeerInfoReceived {
+        peer_id: PeerId,
+        info: PeerFullInfo<PeerInfo>,
+    },
+    Subscribed {
+        peer_id: PeerId,
+        /// Topic hash.
+        topic: String,
+    },
+    Unsubscribed {
+        peer_id: PeerId,
+        /// Topic hash.
+        topic: String,
+    },
+    /// A gossipsub message, as received.
+    Message {
+        source: Option<PeerId>,
+        /// Topic hash.
+        topic: String,
+        data: Vec<u8>,
+    },
+    BroadcastSend {
+        message: AnyMessage,
+    },
+    BroadcastListen {
+        topic: String,
+    },
+    BroadcastUnsubscribe {
+        topic: String,
+    },
+}
+
+impl<PeerInfo> RecordedEvent<PeerInfo> {
+    /// The recorded form of a swarm event, or the event back if it is not
+    /// recorded.
+    pub(super) fn from_swarm_event(
+        event: SwarmEvent<BehaviourEvent<PeerInfo>>,
+    ) -> Result<Self, SwarmEvent<BehaviourEvent<PeerInfo>>>
+    where
+        PeerInfo: std::fmt::Debug + Clone + Serialize + DeserializeOwned + Send + 'static,
+    {
+        Ok(match event {
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                RecordedEvent::ConnectionEstablished { peer_id }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => RecordedEvent::ConnectionClosed { peer_id },
+            SwarmEvent::Behaviour(BehaviourEvent::PeerInfo(peer_info::Event::Received {
+                peer_id,
+                info,
+            })) => RecordedEvent::PeerInfoReceived { peer_id, info },
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                peer_id,
+                topic,
+            })) => RecordedEvent::Subscribed {
+                peer_id,
+                topic: topic.into_string(),
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Unsubscribed {
+                peer_id,
+                topic,
+            })) => RecordedEvent::Unsubscribed {
+                peer_id,
+                topic: topic.into_string(),
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                message,
+                ..
+            })) => RecordedEvent::Message {
+                source: message.source,
+                topic: message.topic.into_string(),
+                data: message.data,
+            },
+            event => return Err(event),
+        })
+    }
+
+    /// The recorded form of an action, if it is recorded.
+    pub(super) fn from_action(action: &ActionItem) -> Option<Self> {
+        match action {
+            ActionItem::BroadcastSend { message } => Some(RecordedEvent::BroadcastSend {
+                message: message.clone(),
+            }),
+            ActionItem::BroadcastListen { topic, .. } => Some(RecordedEvent::BroadcastListen {
+                topic: topic.clone(),
+            }),
+            ActionItem::BroadcastUnsubscribe { topic, .. } => {
+                Some(RecordedEvent::BroadcastUnsubscribe {
+                    topic: topic.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A recorded event, with the time it was handled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry<PeerInfo> {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub event: RecordedEvent<PeerInfo>,
+}
+
+/// Recorder of the events of a worker, writing one JSON entry per line.
+///
+/// Entries are written on a thread of their own, so that a slow writer does
+/// not block the worker. Dropping the recorder waits for the entries
+/// recorded so far to be written.
+pub struct EventRecorder {
+    lines: Option<mpsc::Sender<Vec<u8>>>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl EventRecorder {
+    /// Record to the writer.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        let (lines, receiver) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("blocknet-event-recorder".into())
+            .spawn(move || write_lines(writer, receiver))
+            .expect("spawning the event recorder thread succeeds");
+
+        Self {
+            lines: Some(lines),
+            writer: Some(writer),
+        }
+    }
+
+    /// Record to a new file, truncating it if it exists.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Record an event. Failing to record only logs a warning, so that the
+    /// worker keeps running.
+    pub(super) fn record<PeerInfo: Serialize>(&mut self, event: &RecordedEvent<PeerInfo>) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+
+        #[derive(Serialize)]
+        struct Entry<'a, PeerInfo> {
+            timestamp_ms: u64,
+            event: &'a RecordedEvent<PeerInfo>,
+        }
+
+        let mut line = match serde_json::to_vec(&Entry {
+            timestamp_ms,
+            event,
+        }) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Failed to record worker event: {:?}", err);
+                return;
+            }
+        };
+        line.push(b'\n');
+        if let Some(lines) = &self.lines {
+            if lines.send(line).is_err() {
+                warn!("Failed to record worker event: the recorder thread is gone");
+            }
+        }
+    }
+}
+
+impl Drop for EventRecorder {
+    fn drop(&mut self) {
+        // Ends the thread once it wrote the entries left.
+        self.lines.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Write the lines as they are recorded. Flushed whenever no entry is
+/// left, so that the log is complete up to a crash but for the entries
+/// recorded just before it.
+fn write_lines(mut writer: impl Write, lines: mpsc::Receiver<Vec<u8>>) {
+    while let Ok(line) = lines.recv() {
+        let mut result = writer.write_all(&line);
+        while result.is_ok() {
+            match lines.try_recv() {
+                Ok(line) => result = writer.write_all(&line),
+                Err(_) => break,
+            }
+        }
+        if let Err(err) = result.and_then(|()| writer.flush()) {
+            warn!("Failed to record worker event: {:?}", err);
+        }
+    }
+}
+
+/// Events recorded by an [`EventRecorder`], to replay with
+/// [`super::Worker::replay`].
+#[derive(Debug, Clone)]
+pub struct EventLog<PeerInfo> {
+    pub entries: Vec<LogEntry<PeerInfo>>,
+}
+
+impl<PeerInfo: DeserializeOwned> EventLog<PeerInfo> {
+    /// Read a log, such as from the file of an [`EventRecorder`].
+    pub fn read(reader: impl Read) -> Result<Self, Error> {
+        let entries = serde_json::Deserializer::from_reader(reader)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .map_err(|e| Error::Codec(format!("{:?}", e)))?;
+        Ok(Self { entries })
+    }
+}
@@ -0,0 +1,178 @@
+use futures::{
+    channel::mpsc,
+    future,
+    stream::{FusedStream, Stream},
+};
+use std::{
+    fmt, mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+/// Create a bounded channel counting the items queued in it.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    let queued = Arc::new(AtomicUsize::new(0));
+
+    (
+        Sender {
+            sender,
+            queued: queued.clone(),
+            capacity,
+        },
+        Receiver { receiver, queued },
+    )
+}
+
+/// Sender of a counted channel.
+pub struct Sender<T> {
+    sender: mpsc::Sender<T>,
+    queued: Arc<AtomicUsize>,
+    capacity: usize,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            queued: self.queued.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("queued", &self.queued)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl<T> Sender<T> {
+    /// Occupancy of the channel, from 0.0 for empty to 1.0 for full.
+    pub fn pressure(&self) -> f32 {
+        if self.capacity == 0 {
+            return 1.0;
+        }
+
+        let queued = self.queued.load(Ordering::Acquire);
+        (queued as f32 / self.capacity as f32).min(1.0)
+    }
+
+    /// Whether the receiver is gone or closed, so that sending fails.
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Send an item, waiting while the channel is full.
+    pub async fn send(&mut self, item: T) -> Result<(), mpsc::SendError> {
+        // Counted before sending, so that the receiver never sees more items
+        // than counted.
+        let count = Count::new(&self.queued);
+        // Not flushing once the item is handed over, so that a send canceled
+        // after that still counts the item.
+        future::poll_fn(|cx| self.sender.poll_ready(cx)).await?;
+        self.sender.start_send(item)?;
+        count.keep();
+        Ok(())
+    }
+
+    /// Send an item if the channel has room.
+    pub fn try_send(&mut self, item: T) -> Result<(), mpsc::TrySendError<T>> {
+        let count = Count::new(&self.queued);
+        self.sender.try_send(item)?;
+        count.keep();
+        Ok(())
+    }
+}
+
+/// Count of an item being sent, taken back on drop unless kept, so that a
+/// failed or canceled send leaves the count as it was.
+struct Count<'a> {
+    queued: &'a AtomicUsize,
+}
+
+impl<'a> Count<'a> {
+    fn new(queued: &'a AtomicUsize) -> Self {
+        queued.fetch_add(1, Ordering::AcqRel);
+        Self { queued }
+    }
+
+    /// Keep the count, once the item is queued.
+    fn keep(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for Count<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Receiver of a counted channel.
+pub struct Receiver<T> {
+    receiver: mpsc::Receiver<T>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl<T> Receiver<T> {
+    /// Close the channel, so that sending fails, while the items already
+    /// queued can still be received.
+    pub fn close(&mut self) {
+        self.receiver.close();
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let poll = Pin::new(&mut self.receiver).poll_next(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+        }
+        poll
+    }
+}
+
+impl<T> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        self.receiver.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{FutureExt, StreamExt};
+
+    #[test]
+    fn canceled_send_is_not_counted() {
+        let (mut sender, mut receiver) = channel(2);
+        let mut sent = 0;
+        while sender.send(sent).now_or_never().is_some() {
+            sent += 1;
+        }
+
+        // The pending send was dropped along with its item.
+        assert_eq!(sender.queued.load(Ordering::Acquire), sent);
+        drop(sender);
+        assert_eq!(
+            receiver
+                .by_ref()
+                .collect::<Vec<_>>()
+                .now_or_never()
+                .unwrap()
+                .len(),
+            sent
+        );
+        assert_eq!(receiver.queued.load(Ordering::Acquire), 0);
+    }
+}
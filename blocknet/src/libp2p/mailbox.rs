@@ -0,0 +1,137 @@
+use super::{AnyMessage, PeerId};
+use futures::{channel::mpsc, sink::SinkExt, stream::Stream, task::AtomicWaker};
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use sync_extra::MutexExtra;
+
+type Item = (PeerId, AnyMessage);
+
+/// Create a mailbox delivering broadcasts to a listener. With `keep_latest`,
+/// a queued message is overwritten by a newer one from the same origin, and
+/// sending never waits. Otherwise, up to `capacity` messages are queued.
+pub fn channel(capacity: usize, keep_latest: bool) -> (Sender, Receiver) {
+    if keep_latest {
+        let shared = Arc::new(Shared::default());
+        (
+            Sender::Latest(LatestSender(shared.clone())),
+            Receiver::Latest(LatestReceiver(shared)),
+        )
+    } else {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (Sender::Queued(sender), Receiver::Queued(receiver))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Origins with a queued message, in the order their message was first
+    /// queued.
+    order: VecDeque<PeerId>,
+    latest: HashMap<PeerId, AnyMessage>,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    waker: AtomicWaker,
+}
+
+pub struct LatestSender(Arc<Shared>);
+
+impl Drop for LatestSender {
+    fn drop(&mut self) {
+        self.0.state.lock_unwrap().sender_closed = true;
+        self.0.waker.wake();
+    }
+}
+
+pub struct LatestReceiver(Arc<Shared>);
+
+impl Drop for LatestReceiver {
+    fn drop(&mut self) {
+        self.0.state.lock_unwrap().receiver_closed = true;
+    }
+}
+
+/// Sending half of a mailbox, kept by the worker.
+pub enum Sender {
+    Queued(mpsc::Sender<Item>),
+    Latest(LatestSender),
+}
+
+impl Sender {
+    /// Whether the listener is gone.
+    pub fn is_closed(&self) -> bool {
+        match self {
+            Sender::Queued(sender) => sender.is_closed(),
+            Sender::Latest(sender) => sender.0.state.lock_unwrap().receiver_closed,
+        }
+    }
+
+    /// Deliver a message to the listener.
+    pub async fn send(&mut self, item: Item) -> Result<(), mpsc::SendError> {
+        match self {
+            Sender::Queued(sender) => sender.send(item).await,
+            Sender::Latest(sender) => {
+                let (origin, message) = item;
+                {
+                    let mut state = sender.0.state.lock_unwrap();
+                    match state.latest.get_mut(&origin) {
+                        Some(queued) => {
+                            // On sequenced topics, newer is by the origin's
+                            // order rather than by arrival.
+                            if queued.sequence <= message.sequence {
+                                *queued = message;
+                            }
+                        }
+                        None => {
+                            state.latest.insert(origin, message);
+                            state.order.push_back(origin);
+                        }
+                    }
+                }
+                sender.0.waker.wake();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Receiving half of a mailbox, read by the listener stream.
+pub enum Receiver {
+    Queued(mpsc::Receiver<Item>),
+    Latest(LatestReceiver),
+}
+
+impl Stream for Receiver {
+    type Item = Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Item>> {
+        match self.get_mut() {
+            Receiver::Queued(receiver) => Pin::new(receiver).poll_next(cx),
+            Receiver::Latest(receiver) => {
+                let shared = &receiver.0;
+                shared.waker.register(cx.waker());
+
+                let mut state = shared.state.lock_unwrap();
+                if let Some(origin) = state.order.pop_front() {
+                    let message = state
+                        .latest
+                        .remove(&origin)
+                        .expect("queued origins have a message; qed");
+                    Poll::Ready(Some((origin, message)))
+                } else if state.sender_closed {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Pending
+                }
+            }
+        }
+    }
+}
@@ -1,35 +1,70 @@
+mod builder;
+mod codec;
+mod event_log;
+mod flow_control;
+mod mailbox;
 pub mod peer_info;
+mod priority;
+pub mod rate_limit;
+mod reputation;
+mod sequence;
+mod shard;
+#[cfg(feature = "testnet")]
+pub mod testnet;
+mod version;
+mod wire_error;
+
+pub use self::builder::{TransportKind, WorkerBuilder};
+pub use self::codec::{UnsupportedCodec, WireCodec};
+pub use self::event_log::{EventLog, EventRecorder, LogEntry, RecordedEvent};
+pub use self::reputation::{
+    PeerBehavior, DEFAULT_BAN_DURATION, DEFAULT_BAN_THRESHOLD, DEFAULT_REPUTATION_RETENTION,
+};
+pub use self::sequence::SequenceGap;
+pub use self::shard::TopicShards;
+pub use self::version::{InvalidProtocolVersion, ProtocolVersion, VersionPolicy};
+pub use self::wire_error::{WireError, WireErrorKind};
 
 use crate::{
-    BroadcastService as BroadcastServiceT, Event as EventT, Message as MessageT,
-    Service as ServiceT,
+    BroadcastService as BroadcastServiceT, Event as EventT, Message as MessageT, Priority,
+    Request as RequestT, RequestService as RequestServiceT, Service as ServiceT,
 };
 use futures::{
-    channel::mpsc,
-    select,
+    channel::{mpsc, oneshot},
+    future::{self, FutureExt},
+    select, select_biased,
     sink::SinkExt,
-    stream::{Stream, StreamExt, TryStreamExt},
+    stream::{self, BoxStream, Fuse, Stream, StreamExt},
 };
+use futures_timer::Delay;
 use libp2p::{
-    gossipsub, identify, kad, mdns, request_response,
-    swarm::{NetworkBehaviour, StreamProtocol, Swarm, SwarmEvent},
+    gossipsub, identify,
+    identity::Keypair,
+    kad, mdns, ping, relay, request_response,
+    swarm::{behaviour::toggle::Toggle, dial_opts::DialOpts, NetworkBehaviour, Swarm, SwarmEvent},
+    Multiaddr, StreamProtocol,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::Infallible,
     fmt::Debug,
     future::Future,
     ops::Deref,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
-use sync_extra::RwLockExtra;
+use sync_extra::{MutexExtra, RwLockExtra};
 use thiserror::Error;
-use tracing::error;
+use tracing::{error, warn};
 
 const MESSAGE_CHANNEL_BUFFER_SIZE: usize = 16;
 const ACTION_CHANNEL_BUFFER_SIZE: usize = 64;
+/// Capacity of the outbound broadcast queue of each priority class.
+const BROADCAST_QUEUE_CAPACITY: usize = 64;
+/// Action channel pressure from which `try_broadcast` sheds load.
+const ACTION_CHANNEL_HIGH_WATERMARK: f32 = 0.9;
 
 pub type PeerId = libp2p::PeerId;
 pub type ProtocolName = Cow<'static, str>;
@@ -37,33 +72,167 @@ pub type ProtocolName = Cow<'static, str>;
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AnyRequest {
     pub protocol_id: String,
+    #[serde(default = "codec::default_codec")]
+    pub codec: WireCodec,
     pub serialized: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AnyResponse {
     pub protocol_id: String,
+    #[serde(default = "codec::default_codec")]
+    pub codec: WireCodec,
     pub serialized: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AnyMessage {
     pub topic: String,
+    /// Sequence of the message from its origin, on sequenced topics.
+    #[serde(default)]
+    pub sequence: Option<u64>,
+    /// Codec of `serialized`, as a [`WireCodec::tag`].
+    #[serde(default = "codec::default_tag")]
+    pub codec: u8,
     pub serialized: Vec<u8>,
 }
 
+/// First byte of broadcast envelopes. Valid JSON never starts with it, which
+/// tells envelopes apart from the bare JSON payloads of peers predating them.
+const ENVELOPE_TAG: u8 = 0;
+
+impl AnyMessage {
+    /// Binary envelope of the message, as published on gossipsub. The
+    /// payload goes as is, rather than as the JSON array of its bytes.
+    ///
+    /// Only broadcasts needing the envelope, sequenced ones, the ones on
+    /// sharded topics and the ones not in JSON, are published in it. Others
+    /// are published as their bare payload, as peers predating it do.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut data = vec![ENVELOPE_TAG];
+        data.extend(WireCodec::Scale.encode(self)?);
+        Ok(data)
+    }
+
+    /// Decode a message from its envelope, see [`AnyMessage::encode`]. Data
+    /// not in an envelope is a bare JSON payload, whose topic is left empty.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        match data.split_first() {
+            Some((&ENVELOPE_TAG, envelope)) => WireCodec::Scale.decode(envelope),
+            _ => Ok(AnyMessage {
+                topic: String::new(),
+                sequence: None,
+                codec: codec::default_tag(),
+                serialized: data.to_vec(),
+            }),
+        }
+    }
+
+    /// Whether the message is published as its bare payload rather than in
+    /// an envelope, see [`AnyMessage::encode`].
+    fn is_bare(&self, sharded: bool) -> bool {
+        self.sequence.is_none() && self.codec == codec::default_tag() && !sharded
+    }
+}
+
+type BroadcastSender = mailbox::Sender;
+type RequestSender = mpsc::Sender<(
+    PeerId,
+    AnyRequest,
+    request_response::ResponseChannel<AnyResponse>,
+)>;
+type PendingRequests = HashMap<request_response::OutboundRequestId, PendingRequest>;
+
+#[derive(Debug)]
+struct PendingRequest {
+    protocol_id: String,
+    sender: oneshot::Sender<Result<AnyResponse, Error>>,
+}
+
 enum ActionItem {
     BroadcastSend {
         message: AnyMessage,
     },
     BroadcastListen {
-        sender: mpsc::Sender<(PeerId, AnyMessage)>,
+        sender: BroadcastSender,
+        topic: String,
+        done: oneshot::Sender<Result<(), Error>>,
+    },
+    BroadcastUnsubscribe {
         topic: String,
+        done: oneshot::Sender<()>,
+    },
+    GapListen {
+        sender: mpsc::Sender<SequenceGap>,
+        topic: String,
+    },
+    UnsupportedCodecListen {
+        sender: mpsc::Sender<UnsupportedCodec>,
+    },
+    MeshPeers {
+        topic: String,
+        sender: oneshot::Sender<Vec<PeerId>>,
+    },
+    LocalInfoChanged,
+    ReportPeer {
+        peer_id: PeerId,
+        behavior: PeerBehavior,
+        weight: u32,
+    },
+    Request {
+        peer_id: PeerId,
+        request: AnyRequest,
+        sender: oneshot::Sender<Result<AnyResponse, Error>>,
+    },
+    RequestListen {
+        sender: RequestSender,
+        protocol_id: String,
+    },
+    Respond {
+        channel: request_response::ResponseChannel<AnyResponse>,
+        response: AnyResponse,
+    },
+    StartProviding {
+        key: Vec<u8>,
+        done: oneshot::Sender<Result<(), Error>>,
+    },
+    StopProviding {
+        key: Vec<u8>,
+    },
+    WaitConnected {
+        peer_id: PeerId,
+        done: oneshot::Sender<Result<(), Error>>,
+    },
+    WaitPeerInfo {
+        peer_id: PeerId,
+        done: oneshot::Sender<Result<(), Error>>,
+    },
+    WaitTopicPeers {
+        topic: gossipsub::TopicHash,
+        min_peers: usize,
+        done: oneshot::Sender<Result<(), Error>>,
+    },
+    RotateIdentity {
+        keypair: Keypair,
+        done: oneshot::Sender<Result<PeerId, Error>>,
     },
 
     Error(RunError),
 }
 
+/// An input of the worker, taken by [`Worker::step`] before it is handled.
+enum StepInput<PeerInfo>
+where
+    PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    Action(ActionItem),
+    Broadcast(AnyMessage),
+    LocalInfoChanged,
+    Reprovide,
+    ReorderTimer,
+    Swarm(SwarmEvent<BehaviourEvent<PeerInfo>>),
+}
+
 #[derive(Debug, Error)]
 pub enum FatalRunError {}
 
@@ -103,6 +272,29 @@ pub enum Error {
     #[error("Build error")]
     Build(Box<dyn std::error::Error + Send + Sync + 'static>),
 
+    #[error("Outbound request failure")]
+    OutboundRequest(#[from] request_response::OutboundFailure),
+    #[error("Worker dropped the request")]
+    RequestCanceled(#[from] oneshot::Canceled),
+    #[error("Response channel is closed")]
+    ResponseChannelClosed,
+    #[error("Action channel is near full")]
+    WouldBlock,
+    #[error("Low priority broadcast dropped: its queue is full")]
+    BroadcastDropped,
+    #[error("Not enough peers on the topic before the deadline")]
+    InsufficientPeers,
+    #[error("Peer not connected before the timeout")]
+    ConnectTimeout,
+    #[error("Subscription cap reached")]
+    TooManySubscriptions,
+    #[error("Worker shut down")]
+    Shutdown,
+    #[error("Worker disconnected")]
+    WorkerDisconnected,
+    #[error("DHT record store error")]
+    RecordStore(#[from] kad::store::Error),
+
     #[error("Broadcast message with an unknown source")]
     UnknownOriginBroadcast(AnyMessage),
 }
@@ -112,11 +304,18 @@ struct Behaviour<PeerInfo>
 where
     PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + 'static,
 {
+    /// First, as the behaviours are asked in order to accept a connection,
+    /// so that a denied one never reaches the others.
+    rate_limit: Toggle<rate_limit::Behaviour>,
+    /// Second, so that banned peers never reach the protocols either.
+    bans: reputation::Bans,
     gossipsub: gossipsub::Behaviour,
     kademlia: kad::Behaviour<kad::store::MemoryStore>,
     identify: identify::Behaviour,
     peer_info: peer_info::json::Behaviour<PeerFullInfo<PeerInfo>>,
-    mdns: mdns::tokio::Behaviour,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+    ping: Toggle<ping::Behaviour>,
+    relay: Toggle<relay::client::Behaviour>,
     request_response: request_response::json::Behaviour<AnyRequest, AnyResponse>,
 }
 
@@ -125,12 +324,70 @@ where
     PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + 'static,
 {
     swarm: Swarm<Behaviour<PeerInfo>>,
+    swarm_config: builder::SwarmConfig,
     peers: Arc<RwLock<HashMap<PeerId, PeerFullInfo<PeerInfo>>>>,
     local_info: Arc<RwLock<PeerFullInfo<PeerInfo>>>,
-    broadcast_listen_senders:
-        HashMap<gossipsub::TopicHash, (String, Vec<mpsc::Sender<(PeerId, AnyMessage)>>)>,
-    action_receiver: mpsc::Receiver<ActionItem>,
-    action_sender: mpsc::Sender<ActionItem>,
+    broadcast_listen_senders: HashMap<gossipsub::TopicHash, (String, Vec<BroadcastSender>)>,
+    request_listen_senders: HashMap<String, Vec<RequestSender>>,
+    /// Listen addresses identified by the connected peers, to dial them again
+    /// under a new identity.
+    peer_addrs: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Callers waiting for each peer to connect.
+    connect_waiters: HashMap<PeerId, Vec<oneshot::Sender<Result<(), Error>>>>,
+    /// Callers waiting for the info of each peer.
+    peer_info_waiters: HashMap<PeerId, Vec<oneshot::Sender<Result<(), Error>>>>,
+    /// Callers waiting for a number of peers to subscribe to each topic.
+    #[allow(clippy::type_complexity)]
+    topic_peer_waiters:
+        HashMap<gossipsub::TopicHash, Vec<(usize, oneshot::Sender<Result<(), Error>>)>>,
+    recorder: Option<EventRecorder>,
+    /// Next sequence of each sequenced topic.
+    broadcast_sequences: HashMap<String, u64>,
+    sequence_filter: sequence::SequenceFilter,
+    /// Reordering of each ordered topic.
+    reorder_buffers: HashMap<gossipsub::TopicHash, sequence::ReorderBuffer>,
+    gap_listen_senders: HashMap<gossipsub::TopicHash, (String, Vec<mpsc::Sender<SequenceGap>>)>,
+    unsupported_codec_senders: Vec<mpsc::Sender<UnsupportedCodec>>,
+    /// Origins, topics and tags already reported as unsupported codecs.
+    unsupported_codecs: HashSet<(PeerId, gossipsub::TopicHash, u8)>,
+    /// Codec of the local broadcasts.
+    broadcast_codec: WireCodec,
+    topic_shards: Option<TopicShards>,
+    /// Maximum number of gossipsub topics subscribed to, if capped.
+    max_subscriptions: Option<usize>,
+    protocol_version: ProtocolVersion,
+    version_policy: VersionPolicy,
+    reputations: reputation::Reputations,
+    pending_requests: Arc<Mutex<PendingRequests>>,
+    /// Keys provided on the DHT, with their number of announcements.
+    providing: HashMap<Vec<u8>, usize>,
+    /// Connected peers subscribed to each topic.
+    topic_peers: Arc<RwLock<HashMap<gossipsub::TopicHash, HashSet<PeerId>>>>,
+    /// Protocols advertised by identified peers.
+    peer_protocols: Arc<RwLock<HashMap<PeerId, Vec<StreamProtocol>>>>,
+    /// Transports listened on.
+    active_transports: Arc<Vec<TransportKind>>,
+    reprovide_timer: Fuse<BoxStream<'static, ()>>,
+    reorder_timer: Fuse<BoxStream<'static, ()>>,
+    action_receiver: flow_control::Receiver<ActionItem>,
+    action_sender: flow_control::Sender<ActionItem>,
+    broadcast_receiver: priority::Receiver<AnyMessage>,
+    broadcast_sender: priority::Sender<AnyMessage>,
+    /// Notifications of a local info update. Each service has a slot of its
+    /// own, so that one is only not sent if already pending.
+    local_info_receiver: mpsc::Receiver<()>,
+    local_info_sender: mpsc::Sender<()>,
+}
+
+impl<PeerInfo> Drop for Worker<PeerInfo>
+where
+    PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn drop(&mut self) {
+        // Closed before the listeners are dropped, so that a listener stream
+        // ending can tell a gone worker from an unsubscribe.
+        self.action_receiver.close();
+    }
 }
 
 impl<PeerInfo> Worker<PeerInfo>
@@ -138,89 +395,22 @@ where
     PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + 'static,
 {
     pub fn new(local_info: PeerInfo) -> Result<Self, Error> {
-        let mut swarm = libp2p::SwarmBuilder::with_new_identity()
-            .with_tokio()
-            .with_tcp(
-                libp2p::tcp::Config::default(),
-                libp2p::noise::Config::new,
-                libp2p::yamux::Config::default,
-            )?
-            .with_quic()
-            .with_behaviour(|key| {
-                let peer_id = PeerId::from_public_key(&key.public());
-
-                let gossipsub = gossipsub::Behaviour::new(
-                    gossipsub::MessageAuthenticity::Signed(key.clone()),
-                    Default::default(),
-                )?;
-
-                let kademlia = kad::Behaviour::new(peer_id, kad::store::MemoryStore::new(peer_id));
-
-                let identify = identify::Behaviour::new(identify::Config::new(
-                    "/blocknet/v0.1".to_string(),
-                    key.public(),
-                ));
-
-                let peer_info = peer_info::json::Behaviour::new(
-                    peer_info::Config::new(
-                        "/blocknet/v0.1".to_string(),
-                        key.public(),
-                        StreamProtocol::new("/blocknet/peer_info/v0.1"),
-                        StreamProtocol::new("/blocknet/peer_info/push/v0.1"),
-                    ),
-                    PeerFullInfo {
-                        info: local_info.clone(),
-                    },
-                );
-
-                let mdns = mdns::Behaviour::new(mdns::Config::default(), peer_id.clone())?;
-
-                let request_response = request_response::Behaviour::new(
-                    // TODO: At this moment we just allow all request/response types to be
-                    // communicated via a single protocol. It's reasonable to expect that
-                    // users will want to restrict this and negioate protocol types in
-                    // advance. In those situations, we will introduce a new `Metadata` type
-                    // that needs to be implemented by all request/response types, and default
-                    // it to `AnyMetadata`. The same is with notifications and broadcasts.
-                    vec![(
-                        StreamProtocol::new("/blocknet/request_response/v0.1"),
-                        request_response::ProtocolSupport::Full,
-                    )],
-                    request_response::Config::default(),
-                );
-
-                Ok(Behaviour::<PeerInfo> {
-                    gossipsub,
-                    kademlia,
-                    identify,
-                    peer_info,
-                    mdns,
-                    request_response,
-                })
-            })
-            .map_err(|e| Error::Build(Box::new(e)))?
-            .build();
-
-        swarm.listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse()?)?;
-        swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
-
-        let (action_sender, action_receiver) = mpsc::channel(ACTION_CHANNEL_BUFFER_SIZE);
-
-        Ok(Self {
-            swarm,
-            peers: Arc::new(RwLock::new(Default::default())),
-            local_info: Arc::new(RwLock::new(PeerFullInfo { info: local_info })),
-            broadcast_listen_senders: Default::default(),
-            action_sender,
-            action_receiver,
-        })
+        WorkerBuilder::new(local_info).build()
     }
 
     pub fn service(&self) -> Service<PeerInfo> {
         Service {
             peers: self.peers.clone(),
             local_info: self.local_info.clone(),
+            pending_requests: self.pending_requests.clone(),
+            topic_peers: self.topic_peers.clone(),
+            peer_protocols: self.peer_protocols.clone(),
+            active_transports: self.active_transports.clone(),
+            broadcast_codec: self.broadcast_codec,
+            topic_shards: self.topic_shards,
             action_sender: self.action_sender.clone(),
+            broadcast_sender: self.broadcast_sender.clone(),
+            local_info_sender: self.local_info_sender.clone(),
         }
     }
 
@@ -236,63 +426,843 @@ where
         }
     }
 
+    /// Run until the shutdown future resolves, then cancel what is still in
+    /// flight: the outbound requests, the waits for a connection, and the
+    /// actions queued but not handled yet resolve with [`Error::Shutdown`]
+    /// rather than hang. Shutdown is only checked between inputs, so that
+    /// the input being handled when it resolves is handled in full first.
+    pub async fn run_until(
+        mut self,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), FatalRunError> {
+        let mut shutdown = Box::pin(shutdown.fuse());
+        loop {
+            // Shutdown only interrupts the wait for the next input, so that
+            // an input taken is always handled in full.
+            let input = select_biased! {
+                () = shutdown => break,
+                input = self.next_input().fuse() => input,
+            };
+            match self.handle_input(input).await {
+                Ok(()) => (),
+                Err(RunError::Normal(e)) => {
+                    error!("Worker run normal error: {:?}", e)
+                }
+                Err(RunError::Fatal(e)) => return Err(e),
+            }
+        }
+
+        self.cancel_in_flight();
+        Ok(())
+    }
+
+    fn cancel_in_flight(&mut self) {
+        // Services sending after this fail instead of waiting on the worker.
+        self.action_receiver.close();
+        while let Some(Some(action)) = self.action_receiver.next().now_or_never() {
+            match action {
+                ActionItem::Request { sender, .. } => {
+                    let _ = sender.send(Err(Error::Shutdown));
+                }
+                ActionItem::WaitConnected { done, .. } => {
+                    let _ = done.send(Err(Error::Shutdown));
+                }
+                ActionItem::WaitPeerInfo { done, .. } => {
+                    let _ = done.send(Err(Error::Shutdown));
+                }
+                ActionItem::WaitTopicPeers { done, .. } => {
+                    let _ = done.send(Err(Error::Shutdown));
+                }
+                ActionItem::RotateIdentity { done, .. } => {
+                    let _ = done.send(Err(Error::Shutdown));
+                }
+                ActionItem::BroadcastListen { done, .. } => {
+                    let _ = done.send(Err(Error::Shutdown));
+                }
+                ActionItem::StartProviding { done, .. } => {
+                    let _ = done.send(Err(Error::Shutdown));
+                }
+                _ => (),
+            }
+        }
+
+        for (_, pending) in self.pending_requests.lock_unwrap().drain() {
+            let _ = pending.sender.send(Err(Error::Shutdown));
+        }
+        for (_, waiters) in self
+            .connect_waiters
+            .drain()
+            .chain(self.peer_info_waiters.drain())
+        {
+            for done in waiters {
+                let _ = done.send(Err(Error::Shutdown));
+            }
+        }
+        for (_, waiters) in self.topic_peer_waiters.drain() {
+            for (_, done) in waiters {
+                let _ = done.send(Err(Error::Shutdown));
+            }
+        }
+    }
+
+    /// Wait for the next input, an action or a swarm event, and handle it.
+    ///
+    /// Not cancel safe: dropped while handling an input, the rest of its
+    /// handling is lost. Use [`Worker::run_until`] to stop the worker.
     pub async fn step(&mut self) -> Result<(), RunError> {
+        let input = self.next_input().await;
+        self.handle_input(input).await
+    }
+
+    /// Wait for the next input. Cancel safe, as it only takes the input.
+    async fn next_input(&mut self) -> StepInput<PeerInfo> {
         select! {
-            action = self.action_receiver.select_next_some() => {
-                match action {
-                    ActionItem::BroadcastSend {
-                        message
-                    } => {
-                        let topic = gossipsub::IdentTopic::new(message.topic);
-                        self.swarm.behaviour_mut().gossipsub
-                            .publish(topic, message.serialized)?;
-                    },
-                    ActionItem::BroadcastListen {
-                        sender, topic,
-                    } => {
-                        let ident_topic = gossipsub::IdentTopic::new(topic.clone());
-                        self.swarm.behaviour_mut().gossipsub
-                            .subscribe(&ident_topic)?;
-
-                        self.broadcast_listen_senders.entry(ident_topic.hash())
-                            .or_insert((topic, Vec::new()))
-                            .1
-                            .push(sender);
-                    },
-                    ActionItem::Error(err) => {
-                        return Err(err.into())
-                    },
+            action = self.action_receiver.select_next_some() => StepInput::Action(action),
+            message = self.broadcast_receiver.select_next_some() => {
+                StepInput::Broadcast(message)
+            },
+            () = self.local_info_receiver.select_next_some() => StepInput::LocalInfoChanged,
+            () = self.reprovide_timer.select_next_some() => StepInput::Reprovide,
+            () = self.reorder_timer.select_next_some() => StepInput::ReorderTimer,
+            event = self.swarm.select_next_some() => StepInput::Swarm(event),
+        }
+    }
+
+    async fn handle_input(&mut self, input: StepInput<PeerInfo>) -> Result<(), RunError> {
+        match input {
+            StepInput::Action(action) => self.handle_action(action).await?,
+            StepInput::Broadcast(message) => {
+                self.handle_action(ActionItem::BroadcastSend { message })
+                    .await?
+            }
+            StepInput::LocalInfoChanged => self.handle_action(ActionItem::LocalInfoChanged).await?,
+            StepInput::Reprovide => {
+                let keys = self.providing.keys().cloned().collect::<Vec<_>>();
+                // A key failing to be announced again does not hold back
+                // the others.
+                for key in keys {
+                    if let Err(err) = self.announce(key) {
+                        warn!("Failed to reprovide a key: {:?}", err);
+                    }
                 }
+            }
+            StepInput::ReorderTimer => self.expire_gaps().await?,
+            StepInput::Swarm(event) => match RecordedEvent::from_swarm_event(event) {
+                Ok(event) => {
+                    if let Some(recorder) = &mut self.recorder {
+                        recorder.record(&event);
+                    }
+                    self.apply(event).await?;
+                }
+                Err(event) => self.handle_swarm_event(event).await?,
             },
-            event = self.swarm.select_next_some() => {
-                match event {
-                    SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
-                        message, ..
-                    })) => {
-                        if let Some(entry) = self.broadcast_listen_senders.get_mut(&message.topic) {
-                            // TODO: Unsubscribe from topic when the entry becomes empty.
-                            entry.1.retain(|sender| sender.is_closed());
-
-                            let topic = entry.0.clone();
-                            let any_message = AnyMessage {
-                                topic,
-                                serialized: message.data,
-                            };
-
-                            if let Some(source) = message.source {
-                                for sender in &mut entry.1 {
-                                    sender.send((source, any_message.clone())).await?;
-                                }
-                            } else {
-                                return Err(Error::UnknownOriginBroadcast(any_message.clone()).into())
+        }
+
+        Ok(())
+    }
+
+    /// Replay the events of a recorded session, to reproduce the state
+    /// transitions of the recording worker, such as the peers added and
+    /// removed and the broadcasts delivered to listeners. Meant for a worker
+    /// with no listen address nor bootstrap peer, isolated from the network.
+    ///
+    /// The actions of the local services, such as listening on a topic, are
+    /// handled before each event. Recorded broadcasts are not published
+    /// again.
+    pub async fn replay(&mut self, log: EventLog<PeerInfo>) -> Result<(), RunError> {
+        for entry in log.entries {
+            self.handle_pending_actions().await?;
+            self.apply(entry.event).await?;
+        }
+
+        self.handle_pending_actions().await
+    }
+
+    async fn handle_pending_actions(&mut self) -> Result<(), RunError> {
+        while let Some(Some(action)) = self.action_receiver.next().now_or_never() {
+            self.handle_action(action).await?;
+        }
+        while let Some(Some(message)) = self.broadcast_receiver.next().now_or_never() {
+            self.handle_action(ActionItem::BroadcastSend { message })
+                .await?;
+        }
+        while let Some(Some(())) = self.local_info_receiver.next().now_or_never() {
+            self.handle_action(ActionItem::LocalInfoChanged).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_action(&mut self, action: ActionItem) -> Result<(), RunError> {
+        if let Some(recorder) = &mut self.recorder {
+            if let Some(event) = RecordedEvent::<PeerInfo>::from_action(&action) {
+                recorder.record(&event);
+            }
+        }
+
+        match action {
+            ActionItem::BroadcastSend { mut message } => {
+                if let Some(next) = self.broadcast_sequences.get_mut(&message.topic) {
+                    message.sequence = Some(*next);
+                    *next += 1;
+                }
+
+                let topic = shard::gossipsub_topic(self.topic_shards, &message.topic);
+                let data = if message.is_bare(self.topic_shards.is_some()) {
+                    message.serialized
+                } else {
+                    message.encode()?
+                };
+                self.swarm.behaviour_mut().gossipsub.publish(topic, data)?;
+            }
+            ActionItem::BroadcastListen {
+                sender,
+                topic,
+                done,
+            } => {
+                if let Err(e) = self.subscribe(&topic) {
+                    let _ = done.send(Err(e));
+                    return Ok(());
+                }
+                let _ = done.send(Ok(()));
+                self.broadcast_listen_senders
+                    .entry(gossipsub::IdentTopic::new(topic.clone()).hash())
+                    .or_insert((topic, Vec::new()))
+                    .1
+                    .push(sender);
+            }
+            ActionItem::BroadcastUnsubscribe { topic, done } => {
+                self.unsubscribe(topic)?;
+                let _ = done.send(());
+            }
+            ActionItem::GapListen { sender, topic } => {
+                self.gap_listen_senders
+                    .entry(gossipsub::IdentTopic::new(topic.clone()).hash())
+                    .or_insert((topic, Vec::new()))
+                    .1
+                    .push(sender);
+            }
+            ActionItem::UnsupportedCodecListen { sender } => {
+                self.unsupported_codec_senders.push(sender);
+            }
+            ActionItem::MeshPeers { topic, sender } => {
+                let mesh_peers = self
+                    .swarm
+                    .behaviour()
+                    .gossipsub
+                    .mesh_peers(&shard::gossipsub_topic(self.topic_shards, &topic).hash())
+                    .copied()
+                    .collect();
+                let _ = sender.send(mesh_peers);
+            }
+            ActionItem::LocalInfoChanged => {
+                let local_info = self.local_info.read_unwrap().clone();
+                self.swarm
+                    .behaviour_mut()
+                    .peer_info
+                    .set_local_info(local_info);
+            }
+            ActionItem::ReportPeer {
+                peer_id,
+                behavior,
+                weight,
+            } => {
+                let (score, banned) =
+                    self.reputations
+                        .report(peer_id, behavior, weight, Instant::now());
+                let gossipsub_scoring = self.reputations.config().gossipsub_scoring;
+                let behaviour = self.swarm.behaviour_mut();
+                if gossipsub_scoring {
+                    behaviour
+                        .gossipsub
+                        .set_application_score(&peer_id, f64::from(score));
+                }
+                if banned {
+                    let until = self
+                        .reputations
+                        .banned_until(&peer_id)
+                        .expect("peer is banned; qed");
+                    warn!(
+                        "Banning {}: reputation {} after {:?}",
+                        peer_id, score, behavior
+                    );
+                    behaviour.bans.ban(peer_id, until);
+                    behaviour.gossipsub.blacklist_peer(&peer_id);
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                }
+            }
+            ActionItem::Request {
+                peer_id,
+                request,
+                sender,
+            } => {
+                let protocol_id = request.protocol_id.clone();
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_request(&peer_id, request);
+
+                self.pending_requests.lock_unwrap().insert(
+                    request_id,
+                    PendingRequest {
+                        protocol_id,
+                        sender,
+                    },
+                );
+            }
+            ActionItem::RequestListen {
+                sender,
+                protocol_id,
+            } => {
+                self.request_listen_senders
+                    .entry(protocol_id)
+                    .or_default()
+                    .push(sender);
+            }
+            ActionItem::Respond { channel, response } => {
+                self.swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(channel, response)
+                    .map_err(|_| Error::ResponseChannelClosed)?;
+            }
+            ActionItem::StartProviding { key, done } => {
+                let _ = done.send(self.announce(key));
+            }
+            ActionItem::StopProviding { key } => {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .stop_providing(&kad::RecordKey::new(&key));
+                self.providing.remove(&key);
+            }
+            ActionItem::WaitConnected { peer_id, done } => {
+                if self.swarm.is_connected(&peer_id) {
+                    let _ = done.send(Ok(()));
+                } else {
+                    let waiters = self.connect_waiters.entry(peer_id).or_default();
+                    // Drop the waiters that timed out.
+                    waiters.retain(|waiter| !waiter.is_canceled());
+                    waiters.push(done);
+                }
+            }
+            ActionItem::WaitPeerInfo { peer_id, done } => {
+                if self.peers.read_unwrap().contains_key(&peer_id) {
+                    let _ = done.send(Ok(()));
+                } else {
+                    let waiters = self.peer_info_waiters.entry(peer_id).or_default();
+                    // Drop the waiters that timed out.
+                    waiters.retain(|waiter| !waiter.is_canceled());
+                    waiters.push(done);
+                }
+            }
+            ActionItem::WaitTopicPeers {
+                topic,
+                min_peers,
+                done,
+            } => {
+                let peers = self
+                    .topic_peers
+                    .read_unwrap()
+                    .get(&topic)
+                    .map_or(0, HashSet::len);
+                if peers >= min_peers {
+                    let _ = done.send(Ok(()));
+                } else {
+                    let waiters = self.topic_peer_waiters.entry(topic).or_default();
+                    // Drop the waiters that timed out.
+                    waiters.retain(|(_, waiter)| !waiter.is_canceled());
+                    waiters.push((min_peers, done));
+                }
+            }
+            ActionItem::RotateIdentity { keypair, done } => {
+                let _ = done.send(self.rotate_identity(keypair));
+            }
+            ActionItem::Error(err) => return Err(err),
+        }
+
+        Ok(())
+    }
+
+    /// Apply a swarm event, as handled live or replayed. Recorded actions are
+    /// only replayed.
+    async fn apply(&mut self, event: RecordedEvent<PeerInfo>) -> Result<(), RunError> {
+        match event {
+            RecordedEvent::Message {
+                source,
+                topic,
+                data,
+            } => {
+                let mut topic = gossipsub::TopicHash::from_raw(topic);
+                let mut decoded = None;
+                if let Some(shards) = self.topic_shards {
+                    // Listeners are by logical topic, which must be on the
+                    // shard it arrived on.
+                    let any_message = AnyMessage::decode(&data)?;
+                    if shard::gossipsub_topic(Some(shards), &any_message.topic).hash() != topic {
+                        return Ok(());
+                    }
+                    topic = gossipsub::IdentTopic::new(any_message.topic.clone()).hash();
+                    decoded = Some(any_message);
+                }
+                if let Some(entry) = self.broadcast_listen_senders.get_mut(&topic) {
+                    let mut any_message = match decoded {
+                        Some(any_message) => any_message,
+                        None => AnyMessage::decode(&data)?,
+                    };
+                    any_message.topic = entry.0.clone();
+
+                    let Some(source) = source else {
+                        return Err(Error::UnknownOriginBroadcast(any_message).into());
+                    };
+                    if !self.supports_codec(any_message.codec) {
+                        return self
+                            .report_unsupported_codec(source, &topic, any_message)
+                            .await;
+                    }
+                    let deliveries =
+                        match (self.reorder_buffers.get_mut(&topic), any_message.sequence) {
+                            (Some(buffer), Some(sequence)) => {
+                                buffer.push(source, sequence, any_message, Instant::now())
+                            }
+                            (None, Some(sequence))
+                                if !self.sequence_filter.accept(
+                                    source,
+                                    topic.clone(),
+                                    sequence,
+                                ) =>
+                            {
+                                return Ok(());
                             }
+                            _ => vec![sequence::Delivery::Message(any_message)],
+                        };
+                    self.deliver(&topic, source, deliveries).await?;
+                }
+            }
+            RecordedEvent::Subscribed { peer_id, topic } => {
+                let topic = gossipsub::TopicHash::from_raw(topic);
+                let peers = {
+                    let mut topic_peers = self.topic_peers.write_unwrap();
+                    let peers = topic_peers.entry(topic.clone()).or_default();
+                    peers.insert(peer_id);
+                    peers.len()
+                };
+                if let Some(waiters) = self.topic_peer_waiters.remove(&topic) {
+                    let (ready, waiting): (Vec<_>, Vec<_>) = waiters
+                        .into_iter()
+                        .partition(|(min_peers, _)| peers >= *min_peers);
+                    for (_, done) in ready {
+                        let _ = done.send(Ok(()));
+                    }
+                    if !waiting.is_empty() {
+                        self.topic_peer_waiters.insert(topic, waiting);
+                    }
+                }
+            }
+            RecordedEvent::Unsubscribed { peer_id, topic } => {
+                let topic = gossipsub::TopicHash::from_raw(topic);
+                if let Some(peers) = self.topic_peers.write_unwrap().get_mut(&topic) {
+                    peers.remove(&peer_id);
+                }
+            }
+            RecordedEvent::PeerInfoReceived { peer_id, info } => {
+                self.peers.write_unwrap().insert(peer_id, info);
+                for done in self.peer_info_waiters.remove(&peer_id).unwrap_or_default() {
+                    let _ = done.send(Ok(()));
+                }
+            }
+            RecordedEvent::ConnectionEstablished { peer_id } => {
+                // Banned peers are denied by the bans behaviour before
+                // connecting, but for a replayed session.
+                let admission = self.reputations.connected(peer_id, Instant::now());
+                let Some(score) = admission.score() else {
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                    return Ok(());
+                };
+                let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+                if admission == reputation::Admission::BanExpired {
+                    gossipsub.remove_blacklisted_peer(&peer_id);
+                }
+                // Gossipsub keeps the score of a peer banned before, unless
+                // reset along with its reputation.
+                if self.reputations.config().gossipsub_scoring {
+                    gossipsub.set_application_score(&peer_id, f64::from(score));
+                }
+                for done in self.connect_waiters.remove(&peer_id).unwrap_or_default() {
+                    let _ = done.send(Ok(()));
+                }
+            }
+            RecordedEvent::ConnectionClosed { peer_id } => {
+                self.reputations.disconnected(peer_id, Instant::now());
+                self.peers.write_unwrap().remove(&peer_id);
+                self.peer_protocols.write_unwrap().remove(&peer_id);
+                self.peer_addrs.remove(&peer_id);
+                // Gossipsub forgets the subscriptions of disconnected
+                // peers without reporting them as unsubscribed.
+                for peers in self.topic_peers.write_unwrap().values_mut() {
+                    peers.remove(&peer_id);
+                }
+            }
+            RecordedEvent::BroadcastSend { message } => {
+                if let Some(next) = self.broadcast_sequences.get_mut(&message.topic) {
+                    *next += 1;
+                }
+            }
+            RecordedEvent::BroadcastListen { topic } => {
+                // A listen that failed live was reported to its listener, and
+                // fails the same way again.
+                if let Err(e) = self.subscribe(&topic) {
+                    tracing::debug!("Replayed listen on {} failed: {:?}", topic, e);
+                }
+            }
+            RecordedEvent::BroadcastUnsubscribe { topic } => {
+                self.unsubscribe(topic)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn supports_codec(&self, tag: u8) -> bool {
+        WireCodec::from_tag(tag).map_or(false, |codec| {
+            self.local_info.read_unwrap().codecs.contains(&codec)
+        })
+    }
+
+    /// Drop a broadcast tagged with an unsupported codec, reporting it to
+    /// the listeners the first time for its origin, topic and tag.
+    async fn report_unsupported_codec(
+        &mut self,
+        origin: PeerId,
+        topic: &gossipsub::TopicHash,
+        message: AnyMessage,
+    ) -> Result<(), RunError> {
+        if !self
+            .unsupported_codecs
+            .insert((origin, topic.clone(), message.codec))
+        {
+            return Ok(());
+        }
+
+        warn!(
+            "Dropping broadcasts from {} on {} with unsupported codec tag {}",
+            origin, message.topic, message.codec
+        );
+        self.unsupported_codec_senders
+            .retain(|sender| !sender.is_closed());
+        let report = UnsupportedCodec {
+            origin,
+            topic: message.topic,
+            tag: message.codec,
+        };
+        for sender in &mut self.unsupported_codec_senders {
+            sender.send(report.clone()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deliver to the listeners of the topic what the origin's broadcasts
+    /// made deliverable, in order.
+    async fn deliver(
+        &mut self,
+        topic: &gossipsub::TopicHash,
+        origin: PeerId,
+        deliveries: Vec<sequence::Delivery>,
+    ) -> Result<(), RunError> {
+        for delivery in deliveries {
+            match delivery {
+                sequence::Delivery::Message(message) => {
+                    if let Some((_, senders)) = self.broadcast_listen_senders.get_mut(topic) {
+                        // TODO: Unsubscribe from topic when the entry becomes empty.
+                        senders.retain(|sender| !sender.is_closed());
+                        // A listener dropped since only misses the message.
+                        for sender in senders {
+                            let _ = sender.send((origin, message.clone())).await;
                         }
-                    },
-                    _ => (),
+                    }
+                }
+                sequence::Delivery::Gap(missing) => {
+                    warn!(
+                        "Sequences {:?} from {} on {} did not arrive in time",
+                        missing, origin, topic
+                    );
+                    if let Some((name, senders)) = self.gap_listen_senders.get_mut(topic) {
+                        senders.retain(|sender| !sender.is_closed());
+                        let gap = SequenceGap {
+                            origin,
+                            topic: name.clone(),
+                            missing,
+                        };
+                        for sender in senders {
+                            let _ = sender.send(gap.clone()).await;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Skip over the gaps of ordered topics that timed out.
+    async fn expire_gaps(&mut self) -> Result<(), RunError> {
+        let now = Instant::now();
+        let expired = self
+            .reorder_buffers
+            .iter_mut()
+            .map(|(topic, buffer)| (topic.clone(), buffer.expire(now)))
+            .collect::<Vec<_>>();
+        for (topic, origins) in expired {
+            for (origin, deliveries) in origins {
+                self.deliver(&topic, origin, deliveries).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_swarm_event(
+        &mut self,
+        event: SwarmEvent<BehaviourEvent<PeerInfo>>,
+    ) -> Result<(), RunError> {
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                request_response::Event::Message { peer, message },
+            )) => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    let senders = self
+                        .request_listen_senders
+                        .entry(request.protocol_id.clone())
+                        .or_default();
+                    senders.retain(|sender| !sender.is_closed());
+
+                    // The request is handled by a single listener. If there's none, the
+                    // channel is dropped and the remote is told there's no response.
+                    if let Some(sender) = senders.first_mut() {
+                        sender.send((peer, request, channel)).await?;
+                    } else {
+                        warn!("No listener for request protocol {}", request.protocol_id);
+                    }
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    let pending = self.pending_requests.lock_unwrap().remove(&request_id);
+                    if let Some(pending) = pending {
+                        let _ = pending.sender.send(Ok(response));
+                    }
                 }
             },
+            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                let pending = self.pending_requests.lock_unwrap().remove(&request_id);
+                if let Some(pending) = pending {
+                    let _ = pending.sender.send(Err(error.into()));
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+            })) => {
+                let compatible = match info.protocol_version.parse::<ProtocolVersion>() {
+                    Ok(remote) => self
+                        .version_policy
+                        .is_compatible(&self.protocol_version, &remote),
+                    Err(_) => false,
+                };
+
+                if !compatible {
+                    warn!(
+                        "Disconnecting {}: protocol version {} is incompatible with {}",
+                        peer_id, info.protocol_version, self.protocol_version,
+                    );
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                } else {
+                    self.peer_protocols
+                        .write_unwrap()
+                        .insert(peer_id, info.protocols);
+                    self.peer_addrs.insert(peer_id, info.listen_addrs);
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Subscribe to the gossipsub topic of a logical topic, unless already
+    /// subscribed through another logical topic of its shard. Fails with
+    /// [`Error::TooManySubscriptions`] beyond the subscription cap.
+    fn subscribe(&mut self, topic: &str) -> Result<(), Error> {
+        let ident_topic = shard::gossipsub_topic(self.topic_shards, topic);
+        let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+        let subscribed = gossipsub.topics().any(|hash| *hash == ident_topic.hash());
+        if !subscribed
+            && self
+                .max_subscriptions
+                .map_or(false, |max| gossipsub.topics().count() >= max)
+        {
+            return Err(Error::TooManySubscriptions);
+        }
+
+        gossipsub.subscribe(&ident_topic)?;
+        Ok(())
+    }
+
+    /// Drop the listeners of a logical topic, and unsubscribe from its
+    /// gossipsub topic unless other logical topics of its shard have some.
+    fn unsubscribe(&mut self, topic: String) -> Result<(), Error> {
+        let ident_topic = shard::gossipsub_topic(self.topic_shards, &topic);
+        let logical = gossipsub::IdentTopic::new(topic).hash();
+
+        // Dropping the senders ends the listener streams.
+        self.broadcast_listen_senders.remove(&logical);
+        self.gap_listen_senders.remove(&logical);
+
+        let shards = self.topic_shards;
+        if !self
+            .broadcast_listen_senders
+            .values()
+            .any(|(name, _)| shard::gossipsub_topic(shards, name).hash() == ident_topic.hash())
+        {
+            self.swarm
+                .behaviour_mut()
+                .gossipsub
+                .unsubscribe(&ident_topic)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the swarm with one built under the new identity, carrying over
+    /// what the old one had: the listen addresses, the gossipsub
+    /// subscriptions, the DHT routing table and provided keys, and the
+    /// connected peers, dialed again at the addresses they identified with.
+    /// Returns the new peer id.
+    fn rotate_identity(&mut self, keypair: Keypair) -> Result<PeerId, Error> {
+        let local_info = self.local_info.read_unwrap().clone();
+        let mut swarm = self.swarm_config.build(keypair, local_info)?;
+
+        // The new swarm is configured before the swap, so that a failure
+        // leaves the old identity in place.
+        for topic in self.swarm.behaviour().gossipsub.topics() {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(&gossipsub::IdentTopic::new(topic.clone().into_string()))?;
+        }
+        for bucket in self.swarm.behaviour_mut().kademlia.kbuckets() {
+            for entry in bucket.iter() {
+                let peer_id = *entry.node.key.preimage();
+                for addr in entry.node.value.iter() {
+                    swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, addr.clone());
+                }
+            }
+        }
+        for addr in &self.swarm_config.bootstrap {
+            if let Some(libp2p::multiaddr::Protocol::P2p(peer_id)) = addr.iter().last() {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, addr.clone());
+            }
+        }
+        for addr in self.swarm.external_addresses() {
+            swarm.add_external_address(addr.clone());
+        }
+        for (peer_id, until) in self.reputations.bans(Instant::now()) {
+            let behaviour = swarm.behaviour_mut();
+            behaviour.bans.ban(peer_id, until);
+            behaviour.gossipsub.blacklist_peer(&peer_id);
+        }
+
+        let listen_addrs = self.swarm.listeners().cloned().collect::<Vec<_>>();
+        let connected = self.swarm.connected_peers().copied().collect::<Vec<_>>();
+        // Dropping the old swarm closes its connections, and frees its listen
+        // addresses for the new one.
+        drop(std::mem::replace(&mut self.swarm, swarm));
+
+        // Past the swap, failures are logged rather than returned, as the
+        // old identity is gone.
+        for addr in listen_addrs {
+            if let Err(err) = self.swarm.listen_on(addr.clone()) {
+                warn!(
+                    "Failed to listen on {} under the new identity: {:?}",
+                    addr, err
+                );
+            }
+        }
+        let keys = self.providing.keys().cloned().collect::<Vec<_>>();
+        for key in keys {
+            let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+            if let Err(err) = kademlia.start_providing(kad::RecordKey::new(&key)) {
+                warn!("Failed to provide a key under the new identity: {:?}", err);
+            }
         }
 
+        // The old connections closed without their events, so the peers are
+        // forgotten here, until they connect again.
+        let now = Instant::now();
+        let mut redialed = HashSet::new();
+        for peer_id in connected {
+            self.reputations.disconnected(peer_id, now);
+            let addrs = self.peer_addrs.remove(&peer_id).unwrap_or_default();
+            if addrs.is_empty() {
+                continue;
+            }
+            redialed.insert(peer_id);
+            let opts = DialOpts::peer_id(peer_id).addresses(addrs).build();
+            if let Err(err) = self.swarm.dial(opts) {
+                warn!(
+                    "Failed to dial {} under the new identity: {:?}",
+                    peer_id, err
+                );
+            }
+        }
+        for addr in self.swarm_config.bootstrap.clone() {
+            if let Some(libp2p::multiaddr::Protocol::P2p(peer_id)) = addr.iter().last() {
+                if redialed.contains(&peer_id) {
+                    continue;
+                }
+            }
+            if let Err(err) = self.swarm.dial(addr.clone()) {
+                warn!(
+                    "Failed to dial bootstrap {} under the new identity: {:?}",
+                    addr, err
+                );
+            }
+        }
+        self.peer_addrs.clear();
+        self.peers.write_unwrap().clear();
+        self.peer_protocols.write_unwrap().clear();
+        self.topic_peers.write_unwrap().clear();
+        for (_, pending) in self.pending_requests.lock_unwrap().drain() {
+            let _ = pending.sender.send(Err(
+                request_response::OutboundFailure::ConnectionClosed.into()
+            ));
+        }
+
+        Ok(*self.swarm.local_peer_id())
+    }
+
+    /// Announce the key as provided on the DHT.
+    fn announce(&mut self, key: Vec<u8>) -> Result<(), Error> {
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .start_providing(kad::RecordKey::new(&key))?;
+        *self.providing.entry(key).or_default() += 1;
         Ok(())
     }
 }
@@ -300,16 +1270,59 @@ where
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PeerFullInfo<PeerInfo> {
     info: PeerInfo,
+    /// Supported request codecs, in order of preference.
+    #[serde(default = "codec::default_codecs")]
+    codecs: Vec<WireCodec>,
+}
+
+/// Push of a [`PeerFullInfo`], with only the fields that changed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerFullInfoPush<PeerInfo> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    info: Option<PeerInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    codecs: Option<Vec<WireCodec>>,
+}
+
+impl<PeerInfo> From<PeerFullInfo<PeerInfo>> for PeerFullInfoPush<PeerInfo> {
+    fn from(full_info: PeerFullInfo<PeerInfo>) -> Self {
+        Self {
+            info: Some(full_info.info),
+            codecs: Some(full_info.codecs),
+        }
+    }
 }
 
 impl<PeerInfo> peer_info::Info for PeerFullInfo<PeerInfo>
 where
-    PeerInfo: Debug + Clone + Send + 'static,
+    PeerInfo: Debug + Clone + Serialize + Send + 'static,
 {
-    type Push = Self;
+    type Push = PeerFullInfoPush<PeerInfo>;
 
     fn merge(&mut self, push: Self::Push) {
-        *self = push;
+        if let Some(info) = push.info {
+            self.info = info;
+        }
+        if let Some(codecs) = push.codecs {
+            self.codecs = codecs;
+        }
+    }
+
+    fn diff(&self, previous: &Self) -> Self::Push {
+        // The info is compared by its encoding, as sent to the peer, so that
+        // it does not need to be comparable.
+        let info_changed = match (
+            serde_json::to_value(&self.info),
+            serde_json::to_value(&previous.info),
+        ) {
+            (Ok(info), Ok(previous)) => info != previous,
+            // Pushed anyway, for the codec to fail on it.
+            _ => true,
+        };
+        PeerFullInfoPush {
+            info: info_changed.then(|| self.info.clone()),
+            codecs: (self.codecs != previous.codecs).then(|| self.codecs.clone()),
+        }
     }
 }
 
@@ -317,7 +1330,333 @@ where
 pub struct Service<PeerInfo> {
     peers: Arc<RwLock<HashMap<PeerId, PeerFullInfo<PeerInfo>>>>,
     local_info: Arc<RwLock<PeerFullInfo<PeerInfo>>>,
-    action_sender: mpsc::Sender<ActionItem>,
+    pending_requests: Arc<Mutex<PendingRequests>>,
+    topic_peers: Arc<RwLock<HashMap<gossipsub::TopicHash, HashSet<PeerId>>>>,
+    peer_protocols: Arc<RwLock<HashMap<PeerId, Vec<StreamProtocol>>>>,
+    active_transports: Arc<Vec<TransportKind>>,
+    broadcast_codec: WireCodec,
+    topic_shards: Option<TopicShards>,
+    action_sender: flow_control::Sender<ActionItem>,
+    broadcast_sender: priority::Sender<AnyMessage>,
+    local_info_sender: mpsc::Sender<()>,
+}
+
+impl<PeerInfo> Service<PeerInfo> {
+    /// Codec used for requests to the peer.
+    pub fn negotiated_codec(&self, peer: &PeerId) -> WireCodec {
+        let local = self.local_info.read_unwrap().codecs.clone();
+        match self.peers.read_unwrap().get(peer) {
+            Some(remote) => WireCodec::negotiate(&local, &remote.codecs),
+            None => WireCodec::Json,
+        }
+    }
+
+    /// Number of outstanding outbound requests, by request protocol id.
+    pub fn pending_requests(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for pending in self.pending_requests.lock_unwrap().values() {
+            *counts.entry(pending.protocol_id.clone()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Start providing the key on the DHT. It is announced now, and again on
+    /// every reprovide interval until stopped, so that the record does not
+    /// expire. Fails if the key can not be stored locally, such as when
+    /// too many keys are provided.
+    pub async fn start_providing(&mut self, key: Vec<u8>) -> Result<(), Error> {
+        let (done, done_receiver) = oneshot::channel();
+        self.action_sender
+            .send(ActionItem::StartProviding { key, done })
+            .await?;
+        done_receiver.await?
+    }
+
+    /// Stop providing the key on the DHT.
+    pub async fn stop_providing(&mut self, key: Vec<u8>) -> Result<(), Error> {
+        self.action_sender
+            .send(ActionItem::StopProviding { key })
+            .await?;
+        Ok(())
+    }
+
+    /// Wait until a connection to the peer is established, or fail with
+    /// [`Error::ConnectTimeout`]. Resolves immediately if already connected.
+    pub async fn wait_connected(&mut self, peer: PeerId, timeout: Duration) -> Result<(), Error> {
+        let (done, done_receiver) = oneshot::channel();
+        self.action_sender
+            .send(ActionItem::WaitConnected {
+                peer_id: peer,
+                done,
+            })
+            .await?;
+
+        select! {
+            result = done_receiver.fuse() => result?,
+            () = Delay::new(timeout).fuse() => Err(Error::ConnectTimeout),
+        }
+    }
+
+    /// Wait until the info of the peer is received, or fail with
+    /// [`Error::ConnectTimeout`]. Resolves immediately if already received.
+    pub async fn wait_peer_info(&mut self, peer: PeerId, timeout: Duration) -> Result<(), Error> {
+        let (done, done_receiver) = oneshot::channel();
+        self.action_sender
+            .send(ActionItem::WaitPeerInfo {
+                peer_id: peer,
+                done,
+            })
+            .await?;
+
+        select! {
+            result = done_receiver.fuse() => result?,
+            () = Delay::new(timeout).fuse() => Err(Error::ConnectTimeout),
+        }
+    }
+
+    /// Switch to a new identity, returning its peer id, without restarting
+    /// the worker. As libp2p ties the peer id to the keypair, the swarm is
+    /// built again under the new key, which gossipsub also signs with, and
+    /// identify advertises. The listen addresses, subscriptions, DHT routing
+    /// table, bootstrap peers, external addresses and provided keys carry
+    /// over. The new swarm is subscribed before the old one is dropped, so
+    /// that a failure leaves the old identity in place; failing to listen on
+    /// an address, provide a key or dial a peer again is only logged.
+    ///
+    /// Rotating churns every peer: all the connections close, failing the
+    /// outbound requests in flight with a closed connection. The peers that
+    /// identified with listen addresses are dialed again, and see this node
+    /// leave and a new peer join, with gossipsub meshes to form again and
+    /// their reputation of the old peer id not carried over. Dial-only peers
+    /// must dial again themselves.
+    pub async fn rotate_identity(&mut self, keypair: Keypair) -> Result<PeerId, Error> {
+        let (done, done_receiver) = oneshot::channel();
+        self.action_sender
+            .send(ActionItem::RotateIdentity { keypair, done })
+            .await?;
+        done_receiver.await?
+    }
+
+    /// Report the behavior of the peer, as judged by the application, such
+    /// as on validating its broadcasts. Below the ban threshold of
+    /// [`WorkerBuilder::with_peer_bans`], the peer is disconnected, and
+    /// denied any connection for the ban duration. With
+    /// [`WorkerBuilder::with_gossipsub_scoring`], the reputation is also the
+    /// application score of the peer in gossipsub, which stops forwarding to
+    /// and accepting from low scoring peers before they are banned.
+    pub async fn report_peer(
+        &mut self,
+        peer: PeerId,
+        behavior: PeerBehavior,
+        weight: u32,
+    ) -> Result<(), Error> {
+        self.action_sender
+            .send(ActionItem::ReportPeer {
+                peer_id: peer,
+                behavior,
+                weight,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Listen for the broadcasts on the topic, like
+    /// [`BroadcastService::listen`], but ending with a last
+    /// [`Error::WorkerDisconnected`] if the stream ends because the worker is
+    /// gone, rather than because of an unsubscribe, so that consumers can
+    /// react, such as by reconnecting.
+    ///
+    /// Resolves once the worker subscribed to the topic, failing with
+    /// [`Error::TooManySubscriptions`] beyond the subscription cap.
+    pub async fn listen_broadcasts<Msg>(
+        &mut self,
+        topic: Msg::Topic,
+    ) -> Result<impl Stream<Item = Result<Event<Msg>, Error>> + Send, Error>
+    where
+        Msg: MessageT + DeserializeOwned + Send + 'static,
+        Msg::Topic: Into<String>,
+    {
+        let (sender, receiver) = mailbox::channel(MESSAGE_CHANNEL_BUFFER_SIZE, Msg::KEEP_LATEST);
+        let (done, done_receiver) = oneshot::channel();
+        self.action_sender
+            .send(ActionItem::BroadcastListen {
+                topic: topic.into(),
+                sender,
+                done,
+            })
+            .await?;
+        done_receiver.await??;
+
+        let listening = Some((receiver, self.action_sender.clone()));
+        Ok(stream::unfold(listening, |listening| async move {
+            let (mut receiver, mut action_sender) = listening?;
+            loop {
+                let Some((origin, msg)) = receiver.next().await else {
+                    return action_sender
+                        .is_closed()
+                        .then_some((Err(Error::WorkerDisconnected), None));
+                };
+
+                let value = WireCodec::from_tag(msg.codec)
+                    .ok_or_else(|| Error::Codec(format!("Unknown tag {}", msg.codec)))
+                    .and_then(|codec| codec.decode(&msg.serialized));
+                match value {
+                    Ok(value) => {
+                        let event = Event { origin, value };
+                        return Some((Ok(event), Some((receiver, action_sender))));
+                    }
+                    // Messages failing to decode are reported to the worker.
+                    Err(e) => {
+                        if let Err(e) = action_sender.send(ActionItem::Error(e.into())).await {
+                            tracing::info!("Communicate with the worker service failed: {:?}", e);
+                            return Some((Err(Error::WorkerDisconnected), None));
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Listen for the gaps of an ordered topic, set with
+    /// [`WorkerBuilder::with_ordered_topic`]: the sequences skipped over
+    /// after their timeout. A gap is reported before the broadcasts following
+    /// it are delivered to the listeners of the topic.
+    pub async fn listen_gaps(
+        &mut self,
+        topic: impl Into<String>,
+    ) -> Result<impl Stream<Item = SequenceGap> + Send, Error> {
+        let (sender, receiver) = mpsc::channel(MESSAGE_CHANNEL_BUFFER_SIZE);
+        self.action_sender
+            .send(ActionItem::GapListen {
+                sender,
+                topic: topic.into(),
+            })
+            .await?;
+        Ok(receiver)
+    }
+
+    /// Listen for the broadcasts dropped because of their codec: an event
+    /// for each origin, topic and codec tag not supported locally, on its
+    /// first broadcast.
+    pub async fn listen_unsupported_codecs(
+        &mut self,
+    ) -> Result<impl Stream<Item = UnsupportedCodec> + Send, Error> {
+        let (sender, receiver) = mpsc::channel(MESSAGE_CHANNEL_BUFFER_SIZE);
+        self.action_sender
+            .send(ActionItem::UnsupportedCodecListen { sender })
+            .await?;
+        Ok(receiver)
+    }
+
+    /// Peers in the gossipsub mesh of the topic, which broadcasts are sent
+    /// to and forwarded by. Empty if not subscribed to the topic. The mesh
+    /// is maintained on each gossipsub heartbeat.
+    pub async fn mesh_peers(&mut self, topic: impl Into<String>) -> Result<Vec<PeerId>, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.action_sender
+            .send(ActionItem::MeshPeers {
+                topic: topic.into(),
+                sender,
+            })
+            .await?;
+        Ok(receiver.await?)
+    }
+
+    /// Peers advertising the protocol, such as to find the peers able to
+    /// sync. Peers not yet identified, or whose info is not yet received, are
+    /// excluded.
+    pub fn peers_supporting(&self, protocol: &StreamProtocol) -> Vec<(PeerId, PeerInfo)>
+    where
+        PeerInfo: Clone,
+    {
+        let peers = self.peers.read_unwrap();
+        self.peer_protocols
+            .read_unwrap()
+            .iter()
+            .filter(|(_, protocols)| protocols.contains(protocol))
+            .filter_map(|(peer_id, _)| peers.get(peer_id).map(|info| (*peer_id, info.info.clone())))
+            .collect()
+    }
+
+    /// Transports the worker listens on. Empty if it only dials.
+    pub fn active_transports(&self) -> Vec<TransportKind> {
+        self.active_transports.to_vec()
+    }
+
+    /// Number of connected peers subscribed to the topic.
+    pub fn topic_peers(&self, topic: &str) -> usize {
+        let topic = shard::gossipsub_topic(self.topic_shards, topic).hash();
+        self.topic_peers
+            .read_unwrap()
+            .get(&topic)
+            .map_or(0, HashSet::len)
+    }
+
+    /// Occupancy of the fullest channel to the worker, of actions or of
+    /// [`Priority::High`] and [`Priority::Normal`] broadcasts, from 0.0 for
+    /// empty to 1.0 for full. Calls sending to a full channel wait.
+    pub fn action_channel_pressure(&self) -> f32 {
+        [Priority::High, Priority::Normal]
+            .into_iter()
+            .map(|priority| self.broadcast_sender.pressure(priority))
+            .fold(self.action_sender.pressure(), f32::max)
+    }
+
+    /// Occupancy of the outbound queue of broadcasts of the priority class,
+    /// from 0.0 for empty to 1.0 for full.
+    pub fn broadcast_queue_pressure(&self, priority: Priority) -> f32 {
+        self.broadcast_sender.pressure(priority)
+    }
+
+    /// Broadcast a message, or fail fast with [`Error::WouldBlock`] if the
+    /// queue of its priority class is near full, so that latency-sensitive
+    /// callers can shed load instead of waiting. [`Priority::Low`] messages
+    /// fail the same way, while [`BroadcastService::broadcast`] only fails
+    /// them with [`Error::BroadcastDropped`] once their queue is full.
+    pub fn try_broadcast<Msg>(&mut self, message: Msg) -> Result<(), Error>
+    where
+        Msg: MessageT + Serialize,
+        Msg::Topic: Into<String>,
+    {
+        let message = any_message(&message, self.broadcast_codec)?;
+        if self.broadcast_queue_pressure(Msg::PRIORITY) >= ACTION_CHANNEL_HIGH_WATERMARK {
+            return Err(Error::WouldBlock);
+        }
+
+        self.broadcast_sender
+            .queue(Msg::PRIORITY)
+            .try_send(message)
+            .map_err(|err| {
+                if err.is_full() {
+                    Error::WouldBlock
+                } else {
+                    Error::ChannelSend(err.into_send_error())
+                }
+            })
+    }
+
+    /// Queue a [`Priority::Low`] broadcast, dropping it with
+    /// [`Error::BroadcastDropped`] if the queue is full.
+    fn send_low_priority(&mut self, message: AnyMessage) -> Result<(), Error> {
+        match self.broadcast_sender.queue(Priority::Low).try_send(message) {
+            Ok(()) => Ok(()),
+            Err(err) if err.is_full() => Err(Error::BroadcastDropped),
+            Err(err) => Err(Error::ChannelSend(err.into_send_error())),
+        }
+    }
+}
+
+fn any_message<Msg>(message: &Msg, codec: WireCodec) -> Result<AnyMessage, Error>
+where
+    Msg: MessageT + Serialize,
+    Msg::Topic: Into<String>,
+{
+    Ok(AnyMessage {
+        topic: message.topic().into(),
+        sequence: None,
+        codec: codec.tag(),
+        serialized: codec.encode(message)?,
+    })
 }
 
 impl<PeerInfo> ServiceT for Service<PeerInfo>
@@ -333,7 +1672,19 @@ where
     }
 
     fn set_local_info(&mut self, info: Self::PeerInfo) {
-        self.local_info.write_unwrap().info = info;
+        self.update_local_info(|local_info| *local_info = info);
+    }
+
+    fn update_local_info(&mut self, f: impl FnOnce(&mut Self::PeerInfo)) {
+        f(&mut self.local_info.write_unwrap().info);
+
+        // The worker reads the latest local info when notified, so a full
+        // channel means a pending notification already covers this update.
+        if let Err(err) = self.local_info_sender.try_send(()) {
+            if err.is_disconnected() {
+                warn!("Failed to notify worker of local info change: worker is gone");
+            }
+        }
     }
 
     fn peers(&self) -> impl IntoIterator<Item = (Self::PeerId, Self::PeerInfo)> {
@@ -376,73 +1727,240 @@ where
 {
     type Event = Event<Msg>;
 
-    fn listen(
+    /// See [`Service::listen_broadcasts`] to tell a gone worker from an
+    /// unsubscribe, which both end this stream.
+    async fn listen(
         &mut self,
         topic: Msg::Topic,
-    ) -> impl Future<Output = Result<impl Stream<Item = Self::Event> + Send, Self::Error>> + Send
-    {
+    ) -> Result<impl Stream<Item = Self::Event> + Send, Self::Error> {
+        Ok(self
+            .listen_broadcasts::<Msg>(topic)
+            .await?
+            .filter_map(|event| future::ready(event.ok())))
+    }
+
+    async fn broadcast(&mut self, message: Msg) -> Result<(), Self::Error> {
+        let message = any_message(&message, self.broadcast_codec)?;
+        if Msg::PRIORITY == Priority::Low {
+            return self.send_low_priority(message);
+        }
+
+        self.broadcast_sender
+            .queue(Msg::PRIORITY)
+            .send(message)
+            .await?;
+        Ok(())
+    }
+
+    async fn broadcast_when_ready(
+        &mut self,
+        message: Msg,
+        min_peers: usize,
+        deadline: Duration,
+    ) -> Result<(), Self::Error> {
+        let topic: String = message.topic().into();
+        if self.topic_peers(&topic) < min_peers {
+            let mut timeout = Delay::new(deadline).fuse();
+            let (done, done_receiver) = oneshot::channel();
+            let wait = self.action_sender.send(ActionItem::WaitTopicPeers {
+                topic: shard::gossipsub_topic(self.topic_shards, &topic).hash(),
+                min_peers,
+                done,
+            });
+            select! {
+                result = wait.fuse() => result?,
+                () = timeout => return Err(Error::InsufficientPeers),
+            }
+            select! {
+                result = done_receiver.fuse() => result??,
+                () = timeout => return Err(Error::InsufficientPeers),
+            }
+        }
+
+        BroadcastServiceT::<Msg>::broadcast(self, message).await
+    }
+
+    async fn unsubscribe(&mut self, topic: Msg::Topic) -> Result<(), Self::Error> {
+        let (done, done_receiver) = oneshot::channel();
+        self.action_sender
+            .send(ActionItem::BroadcastUnsubscribe {
+                topic: topic.into(),
+                done,
+            })
+            .await?;
+
+        done_receiver.await?;
+        Ok(())
+    }
+}
+
+/// Protocol id of a request type. All request types share a single libp2p
+/// protocol, and are told apart by their [`RequestT::PROTOCOL_ID`].
+pub fn request_protocol_id<Req: RequestT>() -> String {
+    Req::PROTOCOL_ID.to_string()
+}
+
+/// Channel to respond to an inbound request.
+#[derive(Debug)]
+pub struct Channel {
+    protocol_id: String,
+    codec: WireCodec,
+    inner: request_response::ResponseChannel<AnyResponse>,
+}
+
+impl Channel {
+    /// Codec of the request, which the response also uses.
+    pub fn codec(&self) -> WireCodec {
+        self.codec
+    }
+}
+
+impl<PeerExtraInfo, Req> RequestServiceT<Req> for Service<PeerExtraInfo>
+where
+    PeerExtraInfo: Clone + Send + Sync + 'static,
+    Req: RequestT + Send + Serialize + DeserializeOwned + 'static,
+    Req::Response: Send + Serialize + DeserializeOwned,
+{
+    type Event = Event<Req>;
+    type Channel = Channel;
+
+    async fn listen(&mut self) -> Result<impl Stream<Item = (Channel, Event<Req>)> + Send, Error> {
         let (sender, receiver) = mpsc::channel(MESSAGE_CHANNEL_BUFFER_SIZE);
+        let protocol_id = request_protocol_id::<Req>();
 
-        async move {
-            self.action_sender
-                .send(ActionItem::BroadcastListen {
-                    topic: topic.into(),
-                    sender,
-                })
-                .await?;
+        self.action_sender
+            .send(ActionItem::RequestListen {
+                protocol_id: protocol_id.clone(),
+                sender,
+            })
+            .await?;
 
-            let action_sender = self.action_sender.clone();
-
-            Ok(receiver
-                .map(|v| Ok(v))
-                .and_then(|(origin, msg)| async move {
-                    Ok(Event {
-                        origin,
-                        value: serde_json::from_slice(&msg.serialized)
-                            .map_err(|e| Error::Codec(format!("{:?}", e)))?,
-                    })
-                })
-                .scan((), move |(), v| {
-                    let mut action_sender = action_sender.clone();
-                    async move {
-                        match v {
-                            Ok(v) => Some(Ok(v)),
-                            Err(e) => match action_sender.send(ActionItem::Error(e)).await {
-                                Ok(()) => Some(Err(())),
-                                Err(e) => {
-                                    tracing::info!(
-                                        "Communicate with the worker service failed: {:?}",
-                                        e
-                                    );
-
-                                    // The action sender stops working. We close the stream.
-                                    None
-                                }
+        Ok(
+            receiver.filter_map(move |(origin, request, channel): (_, AnyRequest, _)| {
+                let protocol_id = protocol_id.clone();
+                async move {
+                    match request.codec.decode(&request.serialized) {
+                        Ok(value) => Some((
+                            Channel {
+                                protocol_id,
+                                codec: request.codec,
+                                inner: channel,
                             },
+                            Event { origin, value },
+                        )),
+                        Err(e) => {
+                            // Dropping the channel tells the remote there's no response.
+                            warn!("Failed to decode request from {}: {:?}", origin, e);
+                            None
                         }
                     }
-                })
-                .filter_map(|v| async move {
-                    match v {
-                        Ok(v) => Some(v),
-                        Err(()) => None,
-                    }
-                }))
-        }
+                }
+            }),
+        )
     }
 
-    fn broadcast(&mut self, message: Msg) -> impl Future<Output = Result<(), Self::Error>> + Send {
-        async move {
-            let item = ActionItem::BroadcastSend {
-                message: AnyMessage {
-                    topic: message.topic().into(),
-                    serialized: serde_json::to_vec(&message)
-                        .map_err(|e| Error::Codec(format!("{:?}", e)))?,
-                },
-            };
+    async fn request(&mut self, peer: PeerId, request: Req) -> Result<Req::Response, Error> {
+        let (sender, receiver) = oneshot::channel();
+        let codec = self.negotiated_codec(&peer);
+        let item = ActionItem::Request {
+            peer_id: peer,
+            request: AnyRequest {
+                protocol_id: request_protocol_id::<Req>(),
+                codec,
+                serialized: codec.encode(&request)?,
+            },
+            sender,
+        };
 
-            self.action_sender.send(item).await?;
-            Ok(())
-        }
+        self.action_sender.send(item).await?;
+        let response = receiver.await??;
+
+        response.codec.decode(&response.serialized)
+    }
+
+    async fn respond(&mut self, channel: Channel, response: Req::Response) -> Result<(), Error> {
+        let item = ActionItem::Respond {
+            channel: channel.inner,
+            response: AnyResponse {
+                protocol_id: channel.protocol_id,
+                codec: channel.codec,
+                serialized: channel.codec.encode(&response)?,
+            },
+        };
+
+        self.action_sender.send(item).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peer_info::Info as _;
+
+    #[test]
+    fn full_info_push_contains_only_changed_fields() {
+        let previous = PeerFullInfo {
+            info: 1u64,
+            codecs: vec![WireCodec::Scale, WireCodec::Json],
+        };
+        let mut current = previous.clone();
+        current.info = 2;
+
+        let push = current.diff(&previous);
+        assert_eq!(push.info, Some(2));
+        assert_eq!(push.codecs, None);
+        assert_eq!(
+            serde_json::to_string(&push).expect("push encodes"),
+            r#"{"info":2}"#
+        );
+        assert!(current.diff(&current).info.is_none());
+
+        let mut merged = previous.clone();
+        merged.merge(push);
+        assert_eq!(merged.info, 2);
+        assert_eq!(merged.codecs, previous.codecs);
+    }
+
+    #[tokio::test]
+    async fn provided_keys_reprovided_on_timer() {
+        let (ticks, reprovide_timer) = mpsc::unbounded();
+        let mut worker = WorkerBuilder::new(())
+            .with_mdns(false)
+            .with_listen_addrs([])
+            .with_reprovide_timer(reprovide_timer)
+            .build()
+            .expect("worker builds");
+
+        let key = b"block 1".to_vec();
+        let (done, done_receiver) = oneshot::channel();
+        worker
+            .handle_action(ActionItem::StartProviding {
+                key: key.clone(),
+                done,
+            })
+            .await
+            .expect("action is handled");
+        done_receiver
+            .await
+            .expect("worker answers")
+            .expect("start providing succeeds");
+        assert_eq!(worker.providing.get(&key), Some(&1));
+
+        // Tick the timer, as if one reprovide interval passed.
+        ticks.unbounded_send(()).expect("worker is alive");
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while worker.providing.get(&key) != Some(&2) {
+                worker.step().await.expect("worker steps");
+            }
+        })
+        .await
+        .expect("key is announced again");
+
+        worker
+            .handle_action(ActionItem::StopProviding { key: key.clone() })
+            .await
+            .expect("action is handled");
+        assert!(worker.providing.is_empty());
     }
 }
@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use super::handler::{self, Handler, InEvent};
-use super::{Codec, Info, UpgradeError};
+use super::{Codec, ErrorKind, Info, UpgradeError};
 use libp2p::core::{ConnectedPoint, Endpoint, Multiaddr};
 use libp2p::identity::PeerId;
 use libp2p::identity::PublicKey;
@@ -29,9 +29,11 @@ use libp2p::swarm::{
     StreamProtocol, StreamUpgradeError, THandlerInEvent, ToSwarm,
 };
 use libp2p::swarm::{ConnectionId, THandler, THandlerOutEvent};
+use lru::LruCache;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     marker::PhantomData,
+    num::NonZeroUsize,
     task::Context,
     task::Poll,
     time::Duration,
@@ -51,13 +53,16 @@ pub struct Behaviour<TInfo: Info, TCodec: Codec<TInfo>> {
     our_observed_addresses: HashMap<ConnectionId, Multiaddr>,
 
     /// Pending events to be emitted when polled.
-    events: VecDeque<ToSwarm<Event<TInfo>, InEvent>>,
+    events: VecDeque<ToSwarm<Event<TInfo>, InEvent<TInfo>>>,
 
     listen_addresses: ListenAddresses,
     external_addresses: ExternalAddresses,
 
     local_info: TInfo,
 
+    /// The information of all peers that we have discovered.
+    discovered_peers: PeerCache<TInfo>,
+
     _marker: PhantomData<(TInfo, TCodec)>,
 }
 
@@ -95,7 +100,7 @@ pub struct Config {
     /// How many entries of discovered peers to keep before we discard
     /// the least-recently used one.
     ///
-    /// Disabled by default.
+    /// Defaults to 100. Setting it to 0 disables the cache.
     pub cache_size: usize,
 
     /// Protocol name.
@@ -147,7 +152,7 @@ impl Config {
         self
     }
 
-    /// Configures the size of the LRU cache, caching addresses of discovered peers.
+    /// Configures the size of the LRU cache, caching information of discovered peers.
     pub fn with_cache_size(mut self, cache_size: usize) -> Self {
         self.cache_size = cache_size;
         self
@@ -157,6 +162,11 @@ impl Config {
 impl<TInfo: Info, TCodec: Codec<TInfo>> Behaviour<TInfo, TCodec> {
     /// Creates a new identify [`Behaviour`].
     pub fn new(config: Config, local_info: TInfo) -> Self {
+        let discovered_peers = match NonZeroUsize::new(config.cache_size) {
+            None => PeerCache::disabled(),
+            Some(size) => PeerCache::enabled(size),
+        };
+
         Self {
             config,
             connected: HashMap::new(),
@@ -165,10 +175,53 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> Behaviour<TInfo, TCodec> {
             listen_addresses: Default::default(),
             external_addresses: Default::default(),
             local_info,
+            discovered_peers,
             _marker: PhantomData,
         }
     }
 
+    /// Get the last received information of a discovered peer, if it is still
+    /// in the cache.
+    ///
+    /// This does not count as a use of the entry for the LRU cache.
+    pub fn discovered_peer(&self, peer: &PeerId) -> Option<&TInfo> {
+        self.discovered_peers.peek(peer)
+    }
+
+    /// Iterate over all discovered peers in the cache, from the
+    /// most-recently used to the least-recently used.
+    pub fn discovered_peers(&self) -> impl Iterator<Item = (&PeerId, &TInfo)> {
+        self.discovered_peers.iter()
+    }
+
+    /// Get the local peer information advertised to peers.
+    pub fn local_info(&self) -> &TInfo {
+        &self.local_info
+    }
+
+    /// Update the local peer information, for example to advertise a newly
+    /// supported protocol, and immediately push it to all connected peers.
+    ///
+    /// Each delivered push is confirmed by an [`Event::Pushed`].
+    pub fn set_local_info(&mut self, info: TInfo) {
+        self.local_info = info;
+
+        let change_events = self
+            .connected
+            .iter()
+            .flat_map(|(peer, map)| map.keys().map(|id| (*peer, *id)))
+            .map(|(peer_id, connection_id)| ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(connection_id),
+                event: InEvent::LocalInfoChanged(self.local_info.clone()),
+            })
+            .collect::<Vec<_>>();
+        self.events.extend(change_events);
+
+        let peers = self.connected.keys().copied().collect::<Vec<_>>();
+        self.push(peers);
+    }
+
     /// Initiates an active push of the local peer information to the given peers.
     pub fn push<I>(&mut self, peers: I)
     where
@@ -263,6 +316,8 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> NetworkBehaviour for Behaviour<TInfo, TC
     ) {
         match event {
             handler::Event::Identified(info) => {
+                self.discovered_peers.put(peer_id, info.clone());
+
                 self.events
                     .push_back(ToSwarm::GenerateEvent(Event::Received { peer_id, info }));
             }
@@ -275,8 +330,12 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> NetworkBehaviour for Behaviour<TInfo, TC
                     .push_back(ToSwarm::GenerateEvent(Event::Pushed { peer_id, info }));
             }
             handler::Event::IdentificationError(error) => {
-                self.events
-                    .push_back(ToSwarm::GenerateEvent(Event::Error { peer_id, error }));
+                let kind = ErrorKind::of(&error);
+                self.events.push_back(ToSwarm::GenerateEvent(Event::Error {
+                    peer_id,
+                    kind,
+                    error,
+                }));
             }
         }
     }
@@ -366,15 +425,241 @@ pub enum Event<TInfo: Info> {
     Pushed {
         /// The peer that the information has been sent to.
         peer_id: PeerId,
-        /// The full Info struct we pushed to the remote peer. Clients must
-        /// do some diff'ing to know what has changed since the last push.
+        /// The push we sent to the remote peer. It only contains what has
+        /// changed since the last info sent, as computed by [`Info::diff`].
         info: TInfo::Push,
     },
     /// Error while attempting to identify the remote.
     Error {
         /// The peer with whom the error originated.
         peer_id: PeerId,
+        /// The category of the error.
+        kind: ErrorKind,
         /// The error that occurred.
         error: StreamUpgradeError<UpgradeError>,
     },
 }
+
+struct PeerCache<TInfo>(Option<LruCache<PeerId, TInfo>>);
+
+impl<TInfo> PeerCache<TInfo> {
+    fn disabled() -> Self {
+        Self(None)
+    }
+
+    fn enabled(size: NonZeroUsize) -> Self {
+        Self(Some(LruCache::new(size)))
+    }
+
+    fn put(&mut self, peer: PeerId, info: TInfo) {
+        if let Some(cache) = self.0.as_mut() {
+            cache.put(peer, info);
+        }
+    }
+
+    fn peek(&self, peer: &PeerId) -> Option<&TInfo> {
+        self.0.as_ref()?.peek(peer)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&PeerId, &TInfo)> {
+        self.0.iter().flat_map(|cache| cache.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use libp2p::identity::Keypair;
+    use libp2p::swarm::{Swarm, SwarmEvent};
+    use libp2p_swarm_test::SwarmExt;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct TestInfo(usize);
+
+    impl Info for TestInfo {
+        type Push = Self;
+
+        fn merge(&mut self, push: Self) {
+            *self = push;
+        }
+    }
+
+    fn test_behaviour(cache_size: usize) -> super::super::json::Behaviour<TestInfo> {
+        let config = Config::new(
+            "/test/v0.1".to_string(),
+            Keypair::generate_ed25519().public(),
+            StreamProtocol::new("/test/peer_info/v0.1"),
+            StreamProtocol::new("/test/peer_info/push/v0.1"),
+        )
+        .with_cache_size(cache_size);
+
+        Behaviour::new(config, TestInfo(0))
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct ProtocolsInfo {
+        protocols: Vec<String>,
+    }
+
+    impl Info for ProtocolsInfo {
+        type Push = Self;
+
+        fn merge(&mut self, push: Self) {
+            *self = push;
+        }
+    }
+
+    fn protocols_behaviour(
+        key: Keypair,
+        protocols: &[&str],
+    ) -> super::super::json::Behaviour<ProtocolsInfo> {
+        let config = Config::new(
+            "/test/v0.1".to_string(),
+            key.public(),
+            StreamProtocol::new("/test/peer_info/v0.1"),
+            StreamProtocol::new("/test/peer_info/push/v0.1"),
+        );
+
+        Behaviour::new(
+            config,
+            ProtocolsInfo {
+                protocols: protocols.iter().map(|p| p.to_string()).collect(),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn set_local_info_pushes_updated_protocols() {
+        let mut swarm1 = Swarm::new_ephemeral(|key| protocols_behaviour(key, &["/sync/v1"]));
+        let mut swarm2 = Swarm::new_ephemeral(|key| protocols_behaviour(key, &[]));
+        swarm1.listen().with_memory_addr_external().await;
+        swarm2.connect(&mut swarm1).await;
+
+        // Wait for the initial identification in both directions.
+        let ([e1], [e2]): ([Event<ProtocolsInfo>; 1], [Event<ProtocolsInfo>; 1]) =
+            tokio::time::timeout(
+                Duration::from_secs(20),
+                libp2p_swarm_test::drive(&mut swarm1, &mut swarm2),
+            )
+            .await
+            .expect("peers identify each other in time");
+        assert!(matches!(e1, Event::Received { .. } | Event::Sent { .. }));
+        assert!(matches!(e2, Event::Received { .. } | Event::Sent { .. }));
+
+        let updated = ProtocolsInfo {
+            protocols: vec!["/sync/v1".to_string(), "/warp/v1".to_string()],
+        };
+        swarm1.behaviour_mut().set_local_info(updated.clone());
+
+        let swarm1_peer_id = *swarm1.local_peer_id();
+        let swarm2_peer_id = *swarm2.local_peer_id();
+        tokio::time::timeout(Duration::from_secs(20), async {
+            let mut pushed = false;
+            let mut observed = false;
+            while !(pushed && observed) {
+                futures::select! {
+                    event = swarm1.next_behaviour_event().fuse() => {
+                        if let Event::Pushed { peer_id, info } = event {
+                            assert_eq!(peer_id, swarm2_peer_id);
+                            assert_eq!(info, updated);
+                            pushed = true;
+                        }
+                    },
+                    event = swarm2.next_behaviour_event().fuse() => {
+                        if let Event::Received { peer_id, info } = event {
+                            if info == updated {
+                                assert_eq!(peer_id, swarm1_peer_id);
+                                observed = true;
+                            }
+                        }
+                    },
+                }
+            }
+        })
+        .await
+        .expect("update is pushed and observed in time");
+
+        assert_eq!(
+            swarm2.behaviour().discovered_peer(&swarm1_peer_id),
+            Some(&updated)
+        );
+    }
+
+    #[tokio::test]
+    async fn identify_not_retried_against_unsupported_peer() {
+        let mut swarm1 = Swarm::new_ephemeral(|key| {
+            let config = Config::new(
+                "/test/v0.1".to_string(),
+                key.public(),
+                StreamProtocol::new("/test/peer_info/v0.1"),
+                StreamProtocol::new("/test/peer_info/push/v0.1"),
+            )
+            .with_interval(Duration::from_millis(50));
+
+            super::super::json::Behaviour::new(config, TestInfo(0))
+        });
+        let mut swarm2 = Swarm::new_ephemeral(|_| libp2p::swarm::dummy::Behaviour);
+        swarm2.listen().with_memory_addr_external().await;
+        swarm1.connect(&mut swarm2).await;
+        tokio::spawn(swarm2.loop_on_next());
+
+        let kind = swarm1
+            .wait(|event| match event {
+                SwarmEvent::Behaviour(Event::Error { kind, .. }) => Some(kind),
+                _ => None,
+            })
+            .await;
+        assert_eq!(kind, ErrorKind::Unsupported);
+
+        // Several intervals pass without any further attempt.
+        let retried = tokio::time::timeout(
+            Duration::from_millis(300),
+            swarm1.wait(|event| match event {
+                SwarmEvent::Behaviour(Event::Error { .. }) => Some(()),
+                _ => None,
+            }),
+        )
+        .await;
+        assert!(retried.is_err());
+    }
+
+    #[test]
+    fn discovered_peers_evicts_least_recently_used() {
+        let cache_size = 3;
+        let mut behaviour = test_behaviour(cache_size);
+
+        let peers = (0..=cache_size)
+            .map(|_| PeerId::random())
+            .collect::<Vec<_>>();
+        for (i, peer) in peers.iter().enumerate() {
+            behaviour.on_connection_handler_event(
+                *peer,
+                ConnectionId::new_unchecked(i),
+                handler::Event::Identified(TestInfo(i)),
+            );
+        }
+
+        assert_eq!(behaviour.discovered_peers().count(), cache_size);
+        assert_eq!(behaviour.discovered_peer(&peers[0]), None);
+        for (i, peer) in peers.iter().enumerate().skip(1) {
+            assert_eq!(behaviour.discovered_peer(peer), Some(&TestInfo(i)));
+        }
+    }
+
+    #[test]
+    fn discovered_peers_disabled_with_zero_cache_size() {
+        let mut behaviour = test_behaviour(0);
+
+        let peer = PeerId::random();
+        behaviour.on_connection_handler_event(
+            peer,
+            ConnectionId::new_unchecked(0),
+            handler::Event::Identified(TestInfo(1)),
+        );
+
+        assert_eq!(behaviour.discovered_peer(&peer), None);
+        assert_eq!(behaviour.discovered_peers().count(), 0);
+    }
+}
@@ -18,7 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use super::{Codec, Info, UpgradeError};
+use super::{Codec, ErrorKind, Info, UpgradeError};
 use either::Either;
 use futures::prelude::*;
 use futures_bounded::Timeout;
@@ -49,10 +49,11 @@ const MAX_CONCURRENT_STREAMS_PER_CONNECTION: usize = 10;
 pub struct Handler<TInfo: Info, TCodec: Codec<TInfo>> {
     remote_peer_id: PeerId,
     /// Pending events to yield.
+    #[allow(clippy::type_complexity)]
     events: SmallVec<
         [ConnectionHandlerEvent<
             Either<ReadyUpgrade<StreamProtocol>, ReadyUpgrade<StreamProtocol>>,
-            (),
+            OutboundStream,
             Event<TInfo>,
         >; 4],
     >,
@@ -65,12 +66,24 @@ pub struct Handler<TInfo: Info, TCodec: Codec<TInfo>> {
     /// Whether we have exchanged at least one periodic identify.
     exchanged_one_periodic_identify: bool,
 
+    /// Whether the remote failed to negotiate the identify protocol. If so,
+    /// we stop sending identify requests on this connection.
+    identify_unsupported: bool,
+
+    /// Whether the remote failed to negotiate the push protocol. If so, we
+    /// stop sending pushes on this connection.
+    push_unsupported: bool,
+
     /// The interval of `trigger_next_identify`, i.e. the recurrent delay.
     interval: Duration,
 
     /// Local info.
     local_info: TInfo,
 
+    /// The last local info sent to the remote, either as a full identify or
+    /// merged from pushes. Pushes are computed as a diff against it.
+    last_sent_info: Option<TInfo>,
+
     /// Identify information about the remote peer.
     remote_info: Option<TInfo>,
 
@@ -83,10 +96,19 @@ pub struct Handler<TInfo: Info, TCodec: Codec<TInfo>> {
     _marker: PhantomData<(TInfo, TCodec)>,
 }
 
+/// Protocol of an outbound stream, so that a failed upgrade tells which one
+/// the remote does not support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboundStream {
+    Identify,
+    Push,
+}
+
 /// An event from `Behaviour` with the information requested by the `Handler`.
 #[derive(Debug)]
-pub enum InEvent {
+pub enum InEvent<TInfo> {
     AddressesChanged(HashSet<Multiaddr>),
+    LocalInfoChanged(TInfo),
     Push,
 }
 
@@ -123,11 +145,14 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> Handler<TInfo, TCodec> {
             ),
             trigger_next_identify: Delay::new(Duration::ZERO),
             exchanged_one_periodic_identify: false,
+            identify_unsupported: false,
+            push_unsupported: false,
             interval,
             local_supported_protocols: SupportedProtocols::default(),
             remote_info: Default::default(),
             external_addresses,
             local_info,
+            last_sent_info: None,
             protocol_name,
             push_protocol_name,
             _marker: PhantomData,
@@ -149,7 +174,10 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> Handler<TInfo, TCodec> {
 
                 if self
                     .active_streams
-                    .try_push(TCodec::write_info(stream, info).map_ok(|_| Success::SentIdentify))
+                    .try_push(
+                        TCodec::write_info(stream, info.clone())
+                            .map_ok(|()| Success::SentIdentify(info)),
+                    )
                     .is_err()
                 {
                     tracing::warn!("Dropping inbound stream because we are at capacity");
@@ -191,13 +219,14 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> Handler<TInfo, TCodec> {
                 }
             }
             future::Either::Right(stream) => {
-                let info: TInfo::Push = self.local_info.clone().into();
+                let info = self.local_info.clone();
+                let push = self.local_push_info();
 
                 if self
                     .active_streams
                     .try_push(
-                        TCodec::write_push_info(stream, info.clone())
-                            .map_ok(|()| Success::SentIdentifyPush(info)),
+                        TCodec::write_push_info(stream, push.clone())
+                            .map_ok(|()| Success::SentIdentifyPush(info, push)),
                     )
                     .is_err()
                 {
@@ -209,6 +238,15 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> Handler<TInfo, TCodec> {
         }
     }
 
+    /// The push to send to the remote, containing only what changed since the
+    /// last info sent to it.
+    fn local_push_info(&self) -> TInfo::Push {
+        match &self.last_sent_info {
+            Some(previous) => self.local_info.diff(previous),
+            None => self.local_info.clone().into(),
+        }
+    }
+
     fn handle_incoming_info(&mut self, info: &TInfo) {
         self.remote_info.replace(info.clone());
     }
@@ -223,12 +261,12 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> Handler<TInfo, TCodec> {
 }
 
 impl<TInfo: Info, TCodec: Codec<TInfo>> ConnectionHandler for Handler<TInfo, TCodec> {
-    type FromBehaviour = InEvent;
+    type FromBehaviour = InEvent<TInfo>;
     type ToBehaviour = Event<TInfo>;
     type InboundProtocol =
         SelectUpgrade<ReadyUpgrade<StreamProtocol>, ReadyUpgrade<StreamProtocol>>;
     type OutboundProtocol = Either<ReadyUpgrade<StreamProtocol>, ReadyUpgrade<StreamProtocol>>;
-    type OutboundOpenInfo = ();
+    type OutboundOpenInfo = OutboundStream;
     type InboundOpenInfo = ();
 
     fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
@@ -246,12 +284,21 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> ConnectionHandler for Handler<TInfo, TCo
             InEvent::AddressesChanged(addresses) => {
                 self.external_addresses = addresses;
             }
+            InEvent::LocalInfoChanged(info) => {
+                self.local_info = info;
+            }
+            InEvent::Push if self.push_unsupported => {
+                tracing::debug!(
+                    peer=%self.remote_peer_id,
+                    "Not pushing to peer because it does not support the protocol"
+                );
+            }
             InEvent::Push => {
                 self.events
                     .push(ConnectionHandlerEvent::OutboundSubstreamRequest {
                         protocol: SubstreamProtocol::new(
                             Either::Right(ReadyUpgrade::new(self.push_protocol_name.clone())),
-                            (),
+                            OutboundStream::Push,
                         ),
                     });
             }
@@ -269,12 +316,13 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> ConnectionHandler for Handler<TInfo, TCo
         }
 
         // Poll the future that fires when we need to identify the node again.
-        if let Poll::Ready(()) = self.trigger_next_identify.poll_unpin(cx) {
+        // Identify is not retried against a remote without the protocol.
+        if !self.identify_unsupported && self.trigger_next_identify.poll_unpin(cx).is_ready() {
             self.trigger_next_identify.reset(self.interval);
             let event = ConnectionHandlerEvent::OutboundSubstreamRequest {
                 protocol: SubstreamProtocol::new(
                     Either::Left(ReadyUpgrade::new(self.protocol_name.clone())),
-                    (),
+                    OutboundStream::Identify,
                 ),
             };
             return Poll::Ready(event);
@@ -288,12 +336,16 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> ConnectionHandler for Handler<TInfo, TCo
                     remote_info,
                 )));
             }
-            Poll::Ready(Ok(Ok(Success::SentIdentifyPush(info)))) => {
+            Poll::Ready(Ok(Ok(Success::SentIdentifyPush(info, push)))) => {
+                self.last_sent_info = Some(info);
+
                 return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(
-                    Event::IdentificationPushed(info),
+                    Event::IdentificationPushed(push),
                 ));
             }
-            Poll::Ready(Ok(Ok(Success::SentIdentify))) => {
+            Poll::Ready(Ok(Ok(Success::SentIdentify(info)))) => {
+                self.last_sent_info = Some(info);
+
                 return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(
                     Event::Identification,
                 ));
@@ -340,11 +392,22 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> ConnectionHandler for Handler<TInfo, TCo
             ConnectionEvent::FullyNegotiatedOutbound(fully_negotiated_outbound) => {
                 self.on_fully_negotiated_outbound(fully_negotiated_outbound)
             }
-            ConnectionEvent::DialUpgradeError(DialUpgradeError { error, .. }) => {
+            ConnectionEvent::DialUpgradeError(DialUpgradeError { info, error }) => {
+                let error = error.map_upgrade_err(|e| void::unreachable(e.into_inner()));
+                if ErrorKind::of(&error) == ErrorKind::Unsupported {
+                    tracing::debug!(
+                        peer=%self.remote_peer_id,
+                        protocol=?info,
+                        "Remote does not support the peer info protocol, stop using it"
+                    );
+                    match info {
+                        OutboundStream::Identify => self.identify_unsupported = true,
+                        OutboundStream::Push => self.push_unsupported = true,
+                    }
+                }
+
                 self.events.push(ConnectionHandlerEvent::NotifyBehaviour(
-                    Event::IdentificationError(
-                        error.map_upgrade_err(|e| void::unreachable(e.into_inner())),
-                    ),
+                    Event::IdentificationError(error),
                 ));
                 self.trigger_next_identify.reset(self.interval);
             }
@@ -357,7 +420,10 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> ConnectionHandler for Handler<TInfo, TCo
                     .then(|| self.local_protocols_to_string())
                     .unwrap_or_default();
 
-                if protocols_changed && self.exchanged_one_periodic_identify {
+                if protocols_changed
+                    && self.exchanged_one_periodic_identify
+                    && !self.push_unsupported
+                {
                     tracing::debug!(
                         peer=%self.remote_peer_id,
                         %before,
@@ -369,7 +435,7 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> ConnectionHandler for Handler<TInfo, TCo
                         .push(ConnectionHandlerEvent::OutboundSubstreamRequest {
                             protocol: SubstreamProtocol::new(
                                 Either::Right(ReadyUpgrade::new(self.push_protocol_name.clone())),
-                                (),
+                                OutboundStream::Push,
                             ),
                         });
                 }
@@ -380,8 +446,158 @@ impl<TInfo: Info, TCodec: Codec<TInfo>> ConnectionHandler for Handler<TInfo, TCo
 }
 
 enum Success<TInfo: Info> {
-    SentIdentify,
+    SentIdentify(TInfo),
     ReceivedIdentify(TInfo),
-    SentIdentifyPush(TInfo::Push),
+    SentIdentifyPush(TInfo, TInfo::Push),
     ReceivedIdentifyPush(TInfo::Push),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TestInfo {
+        best_block: u64,
+        agent: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TestPushInfo {
+        best_block: Option<u64>,
+        agent: Option<String>,
+    }
+
+    impl From<TestInfo> for TestPushInfo {
+        fn from(info: TestInfo) -> Self {
+            Self {
+                best_block: Some(info.best_block),
+                agent: Some(info.agent),
+            }
+        }
+    }
+
+    impl Info for TestInfo {
+        type Push = TestPushInfo;
+
+        fn merge(&mut self, push: TestPushInfo) {
+            if let Some(best_block) = push.best_block {
+                self.best_block = best_block;
+            }
+            if let Some(agent) = push.agent {
+                self.agent = agent;
+            }
+        }
+
+        fn diff(&self, previous: &Self) -> TestPushInfo {
+            TestPushInfo {
+                best_block: (self.best_block != previous.best_block).then_some(self.best_block),
+                agent: (self.agent != previous.agent).then(|| self.agent.clone()),
+            }
+        }
+    }
+
+    struct TestCodec;
+
+    #[async_trait::async_trait]
+    impl Codec<TestInfo> for TestCodec {
+        async fn read_info<T>(_io: T) -> Result<TestInfo, UpgradeError>
+        where
+            T: AsyncRead + Unpin + Send,
+        {
+            Err(UpgradeError::StreamClosed)
+        }
+
+        async fn read_push_info<T>(_io: T) -> Result<TestPushInfo, UpgradeError>
+        where
+            T: AsyncRead + Unpin + Send,
+        {
+            Err(UpgradeError::StreamClosed)
+        }
+
+        async fn write_info<T>(_io: T, _info: TestInfo) -> Result<(), UpgradeError>
+        where
+            T: AsyncWrite + Unpin + Send,
+        {
+            Ok(())
+        }
+
+        async fn write_push_info<T>(_io: T, _info: TestPushInfo) -> Result<(), UpgradeError>
+        where
+            T: AsyncWrite + Unpin + Send,
+        {
+            Ok(())
+        }
+    }
+
+    fn test_handler(local_info: TestInfo) -> Handler<TestInfo, TestCodec> {
+        test_handler_with_interval(local_info, Duration::from_secs(60))
+    }
+
+    fn test_handler_with_interval(
+        local_info: TestInfo,
+        interval: Duration,
+    ) -> Handler<TestInfo, TestCodec> {
+        Handler::new(
+            interval,
+            PeerId::random(),
+            HashSet::new(),
+            local_info,
+            StreamProtocol::new("/test/peer_info/v0.1"),
+            StreamProtocol::new("/test/peer_info/push/v0.1"),
+        )
+    }
+
+    #[test]
+    fn push_contains_only_changed_fields() {
+        let info = TestInfo {
+            best_block: 1,
+            agent: "test".to_string(),
+        };
+        let mut handler = test_handler(info.clone());
+
+        // Nothing was sent yet, so the full info is pushed.
+        assert_eq!(handler.local_push_info(), TestPushInfo::from(info.clone()));
+
+        handler.last_sent_info = Some(info);
+        handler.local_info.best_block = 2;
+
+        assert_eq!(
+            handler.local_push_info(),
+            TestPushInfo {
+                best_block: Some(2),
+                agent: None,
+            }
+        );
+    }
+
+    #[test]
+    fn identify_kept_when_only_push_unsupported() {
+        let info = TestInfo {
+            best_block: 1,
+            agent: "test".to_string(),
+        };
+        let mut handler = test_handler_with_interval(info, Duration::ZERO);
+
+        handler.on_connection_event(ConnectionEvent::DialUpgradeError(DialUpgradeError {
+            info: OutboundStream::Push,
+            error: StreamUpgradeError::NegotiationFailed,
+        }));
+        assert!(matches!(
+            futures::executor::block_on(future::poll_fn(|cx| handler.poll(cx))),
+            ConnectionHandlerEvent::NotifyBehaviour(Event::IdentificationError(
+                StreamUpgradeError::NegotiationFailed
+            ))
+        ));
+
+        // Pushes stop, but the remote is still identified.
+        handler.on_behaviour_event(InEvent::Push);
+        match futures::executor::block_on(future::poll_fn(|cx| handler.poll(cx))) {
+            ConnectionHandlerEvent::OutboundSubstreamRequest { protocol } => {
+                assert_eq!(*protocol.info(), OutboundStream::Identify);
+            }
+            _ => panic!("expected an identify request"),
+        }
+        assert!(handler.events.is_empty());
+    }
+}
@@ -47,7 +47,6 @@ where
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let info: TInfo = info.into();
         let data =
             serde_json::to_vec(&info).map_err(|e| UpgradeError::Codec(format!("{:?}", e)))?;
 
@@ -59,7 +58,6 @@ where
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let info: TInfo::Push = info.into();
         let data =
             serde_json::to_vec(&info).map_err(|e| UpgradeError::Codec(format!("{:?}", e)))?;
 
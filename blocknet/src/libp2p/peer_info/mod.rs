@@ -43,7 +43,7 @@
 
 pub use self::behaviour::{Behaviour, Config, Event};
 pub use self::codec::Codec;
-pub use self::protocol::{Info, UpgradeError};
+pub use self::protocol::{ErrorKind, Info, UpgradeError};
 
 mod behaviour;
 mod codec;
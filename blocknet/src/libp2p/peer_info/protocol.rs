@@ -20,6 +20,7 @@
 
 use libp2p::core::multiaddr;
 use libp2p::identity;
+use libp2p::swarm::StreamUpgradeError;
 use std::fmt::Debug;
 use thiserror::Error;
 
@@ -27,6 +28,15 @@ pub trait Info: Debug + Clone + Send + 'static {
     type Push: From<Self> + Debug + Clone + Send + 'static;
 
     fn merge(&mut self, push: Self::Push);
+
+    /// Compute the push needed to bring a peer that knows `previous` up to
+    /// date with `self`, so that merging it into `previous` yields `self`.
+    ///
+    /// Implementations with partial pushes should only set the fields that
+    /// have changed. Defaults to pushing the full info.
+    fn diff(&self, _previous: &Self) -> Self::Push {
+        self.clone().into()
+    }
 }
 
 #[derive(Debug, Error)]
@@ -42,3 +52,33 @@ pub enum UpgradeError {
     #[error("Failed decoding public key")]
     PublicKey(#[from] identity::DecodingError),
 }
+
+/// Category of a peer info stream error, so that callers can decide whether
+/// retrying makes sense.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The remote does not support the protocol. This is persistent, and the
+    /// peer will not be asked again on the same connection.
+    Unsupported,
+    /// The stream timed out.
+    Timeout,
+    /// I/O failure, or the stream was closed early.
+    Io,
+    /// The remote sent data that could not be decoded.
+    Codec,
+}
+
+impl ErrorKind {
+    /// Classify a stream upgrade error.
+    pub fn of(error: &StreamUpgradeError<UpgradeError>) -> Self {
+        match error {
+            StreamUpgradeError::NegotiationFailed => Self::Unsupported,
+            StreamUpgradeError::Timeout => Self::Timeout,
+            StreamUpgradeError::Io(_) => Self::Io,
+            StreamUpgradeError::Apply(UpgradeError::Io(_) | UpgradeError::StreamClosed) => Self::Io,
+            StreamUpgradeError::Apply(
+                UpgradeError::Codec(_) | UpgradeError::Multiaddr(_) | UpgradeError::PublicKey(_),
+            ) => Self::Codec,
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use super::flow_control;
+use crate::Priority;
+use futures::stream::{FusedStream, Stream};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Create bounded queues, one per priority class, each with the capacity.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let (high_sender, high) = flow_control::channel(capacity);
+    let (normal_sender, normal) = flow_control::channel(capacity);
+    let (low_sender, low) = flow_control::channel(capacity);
+
+    (
+        Sender {
+            high: high_sender,
+            normal: normal_sender,
+            low: low_sender,
+        },
+        Receiver {
+            queues: [high, normal, low],
+        },
+    )
+}
+
+/// Sender of prioritized queues.
+#[derive(Debug)]
+pub struct Sender<T> {
+    high: flow_control::Sender<T>,
+    normal: flow_control::Sender<T>,
+    low: flow_control::Sender<T>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            high: self.high.clone(),
+            normal: self.normal.clone(),
+            low: self.low.clone(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Queue of the priority class.
+    pub fn queue(&mut self, priority: Priority) -> &mut flow_control::Sender<T> {
+        match priority {
+            Priority::High => &mut self.high,
+            Priority::Normal => &mut self.normal,
+            Priority::Low => &mut self.low,
+        }
+    }
+
+    /// Occupancy of the queue of the priority class, from 0.0 for empty to
+    /// 1.0 for full.
+    pub fn pressure(&self, priority: Priority) -> f32 {
+        match priority {
+            Priority::High => self.high.pressure(),
+            Priority::Normal => self.normal.pressure(),
+            Priority::Low => self.low.pressure(),
+        }
+    }
+}
+
+/// Receiver of prioritized queues, yielding from a queue only once all the
+/// queues of higher priority are empty.
+pub struct Receiver<T> {
+    queues: [flow_control::Receiver<T>; 3],
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut terminated = true;
+        for queue in &mut self.queues {
+            if queue.is_terminated() {
+                continue;
+            }
+
+            match Pin::new(queue).poll_next(cx) {
+                Poll::Ready(Some(item)) => return Poll::Ready(Some(item)),
+                Poll::Ready(None) => (),
+                Poll::Pending => terminated = false,
+            }
+        }
+
+        if terminated {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        self.queues.iter().all(FusedStream::is_terminated)
+    }
+}
@@ -0,0 +1,208 @@
+//! Inbound connection rate limiting, keyed by the source IP of the remote.
+
+use libp2p::core::{multiaddr::Protocol, Endpoint, Multiaddr};
+use libp2p::identity::PeerId;
+use libp2p::swarm::{
+    dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+use void::Void;
+
+/// Configuration of the inbound connection rate limiter.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of inbound connections accepted from a single IP within
+    /// `interval`.
+    pub max_connections: usize,
+    /// Length of the sliding window.
+    pub interval: Duration,
+}
+
+/// An inbound connection was denied, because its source IP exceeded the rate.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimited {
+    pub ip: IpAddr,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Inbound connection rate exceeded for {}", self.ip)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Network behaviour denying inbound connections from IPs connecting more
+/// often than the configured rate. Remotes without an IP address are never
+/// limited.
+pub struct Behaviour {
+    config: Config,
+    recent: HashMap<IpAddr, VecDeque<Instant>>,
+    /// Time source of the sliding window.
+    clock: Box<dyn FnMut() -> Instant + Send>,
+}
+
+impl Behaviour {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            recent: HashMap::new(),
+            clock: Box::new(Instant::now),
+        }
+    }
+
+    /// Measure the sliding window on the clock rather than on
+    /// [`Instant::now`].
+    pub fn with_clock(mut self, clock: impl FnMut() -> Instant + Send + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    fn check(&mut self, ip: IpAddr, now: Instant) -> Result<(), RateLimited> {
+        let interval = self.config.interval;
+        let is_recent = |time: &Instant| now.saturating_duration_since(*time) < interval;
+
+        self.recent
+            .retain(|_, times| times.back().map_or(false, is_recent));
+
+        let times = self.recent.entry(ip).or_default();
+        while times.front().map_or(false, |time| !is_recent(time)) {
+            times.pop_front();
+        }
+
+        if times.len() >= self.config.max_connections {
+            return Err(RateLimited { ip });
+        }
+
+        times.push_back(now);
+        Ok(())
+    }
+}
+
+fn remote_ip(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Void;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        if let Some(ip) = remote_ip(remote_addr) {
+            let now = (self.clock)();
+            if let Err(err) = self.check(ip, now) {
+                warn!(
+                    "Denied inbound connection from {} at {}: {}",
+                    peer, remote_addr, err
+                );
+                return Err(ConnectionDenied::new(err));
+            }
+        }
+
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, _: FromSwarm) {}
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn connect(behaviour: &mut Behaviour, id: usize, remote_addr: &str) -> bool {
+        behaviour
+            .handle_established_inbound_connection(
+                ConnectionId::new_unchecked(id),
+                PeerId::random(),
+                &"/ip4/127.0.0.1/tcp/30333".parse().unwrap(),
+                &remote_addr.parse().unwrap(),
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn excess_connections_from_one_ip_denied() {
+        let mut behaviour = Behaviour::new(Config {
+            max_connections: 3,
+            interval: Duration::from_secs(60),
+        });
+
+        let accepted = (0..10)
+            .filter(|id| {
+                connect(
+                    &mut behaviour,
+                    *id,
+                    &format!("/ip4/10.0.0.1/tcp/{}", 1000 + id),
+                )
+            })
+            .count();
+        assert_eq!(accepted, 3);
+
+        assert!(connect(&mut behaviour, 10, "/ip4/10.0.0.2/tcp/1000"));
+        assert!(connect(&mut behaviour, 11, "/ip6/::1/tcp/1000"));
+        // Without an IP, the remote is not limited.
+        assert!(connect(&mut behaviour, 12, "/memory/1"));
+    }
+
+    #[test]
+    fn rate_recovers_after_interval() {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = now.clone();
+        let mut behaviour = Behaviour::new(Config {
+            max_connections: 1,
+            interval: Duration::from_secs(60),
+        })
+        .with_clock(move || *clock.lock().unwrap());
+
+        assert!(connect(&mut behaviour, 0, "/ip4/10.0.0.1/tcp/1000"));
+        assert!(!connect(&mut behaviour, 1, "/ip4/10.0.0.1/tcp/1001"));
+
+        *now.lock().unwrap() += Duration::from_secs(59);
+        assert!(!connect(&mut behaviour, 2, "/ip4/10.0.0.1/tcp/1002"));
+
+        *now.lock().unwrap() += Duration::from_secs(1);
+        assert!(connect(&mut behaviour, 3, "/ip4/10.0.0.1/tcp/1003"));
+    }
+}
@@ -0,0 +1,379 @@
+//! Application-level reputation of peers, fed by the validation verdicts of
+//! the protocols, such as a bad block or an invalid work report.
+
+use libp2p::core::{Endpoint, Multiaddr};
+use libp2p::identity::PeerId;
+use libp2p::swarm::{
+    dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use void::Void;
+
+/// Reputation below which a peer is banned, by default.
+pub const DEFAULT_BAN_THRESHOLD: i32 = -100;
+/// How long a ban lasts, by default.
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(3600);
+/// How long the reputation of a disconnected peer is kept, by default, so
+/// that reconnecting does not clear it.
+pub const DEFAULT_REPUTATION_RETENTION: Duration = Duration::from_secs(600);
+/// Bound of the reputation either way, so that a long record of good
+/// behavior cannot cover for bad behavior indefinitely.
+const MAX_REPUTATION: i32 = 1000;
+
+/// Behavior of a peer, as judged by the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerBehavior {
+    /// A valid message or block, raising the reputation by the weight.
+    Good,
+    /// An invalid message, lowering the reputation by the weight.
+    BadMessage,
+    /// An invalid block, lowering the reputation by twice the weight, as it
+    /// also wasted an import.
+    BadBlock,
+}
+
+impl PeerBehavior {
+    fn change(self, weight: u32) -> i64 {
+        let weight = i64::from(weight);
+        match self {
+            PeerBehavior::Good => weight,
+            PeerBehavior::BadMessage => -weight,
+            PeerBehavior::BadBlock => -2 * weight,
+        }
+    }
+}
+
+/// Configuration of the reputations.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Config {
+    pub ban_threshold: i32,
+    pub ban_duration: Duration,
+    pub retention: Duration,
+    /// Whether the reputation is also the application score of the peer in
+    /// gossipsub.
+    pub gossipsub_scoring: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ban_threshold: DEFAULT_BAN_THRESHOLD,
+            ban_duration: DEFAULT_BAN_DURATION,
+            retention: DEFAULT_REPUTATION_RETENTION,
+            gossipsub_scoring: false,
+        }
+    }
+}
+
+/// Whether a connecting peer is let in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Admission {
+    /// Connected, with the reputation kept from before.
+    Allowed(i32),
+    /// Still banned.
+    Banned,
+    /// A ban expired, clearing the reputation.
+    BanExpired,
+}
+
+impl Admission {
+    /// Score of the admitted peer, starting over at zero if its ban expired,
+    /// or None if it is still banned.
+    pub fn score(self) -> Option<i32> {
+        match self {
+            Admission::Allowed(score) => Some(score),
+            Admission::Banned => None,
+            Admission::BanExpired => Some(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Reputation {
+    score: i32,
+    disconnected_at: Option<Instant>,
+    banned_until: Option<Instant>,
+}
+
+/// Reputations of the known peers, kept across disconnects shorter than the
+/// retention.
+#[derive(Debug)]
+pub(crate) struct Reputations {
+    config: Config,
+    peers: HashMap<PeerId, Reputation>,
+}
+
+impl Reputations {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Apply the behavior of the peer, returning its new reputation, and
+    /// whether it is newly banned for falling below the threshold.
+    ///
+    /// A peer reported while not connected, such as from a broadcast
+    /// relayed by others, is retained as if it just disconnected.
+    pub fn report(
+        &mut self,
+        peer_id: PeerId,
+        behavior: PeerBehavior,
+        weight: u32,
+        now: Instant,
+    ) -> (i32, bool) {
+        self.forget_expired(now);
+        let config = self.config;
+        let reputation = self.peers.entry(peer_id).or_insert_with(|| Reputation {
+            disconnected_at: Some(now),
+            ..Default::default()
+        });
+        if reputation.banned_until.is_some() {
+            return (reputation.score, false);
+        }
+
+        reputation.score = (i64::from(reputation.score) + behavior.change(weight))
+            .clamp(-i64::from(MAX_REPUTATION), i64::from(MAX_REPUTATION))
+            as i32;
+        let banned = reputation.score < config.ban_threshold;
+        if banned {
+            reputation.banned_until = Some(now + config.ban_duration);
+        }
+        (reputation.score, banned)
+    }
+
+    /// Admit a connecting peer, unless banned.
+    pub fn connected(&mut self, peer_id: PeerId, now: Instant) -> Admission {
+        self.forget_expired(now);
+        let reputation = self.peers.entry(peer_id).or_default();
+
+        match reputation.banned_until {
+            Some(until) if until > now => Admission::Banned,
+            Some(_) => {
+                *reputation = Reputation::default();
+                Admission::BanExpired
+            }
+            None => {
+                reputation.disconnected_at = None;
+                Admission::Allowed(reputation.score)
+            }
+        }
+    }
+
+    /// Start the retention of the reputation of a disconnected peer.
+    pub fn disconnected(&mut self, peer_id: PeerId, now: Instant) {
+        if let Some(reputation) = self.peers.get_mut(&peer_id) {
+            reputation.disconnected_at.get_or_insert(now);
+        }
+    }
+
+    /// Forget the peers disconnected for longer than the retention, unless
+    /// still banned.
+    fn forget_expired(&mut self, now: Instant) {
+        let retention = self.config.retention;
+        self.peers.retain(|_, reputation| {
+            reputation.banned_until.map_or(false, |until| until > now)
+                || reputation
+                    .disconnected_at
+                    .map_or(true, |at| now.duration_since(at) < retention)
+        });
+    }
+
+    /// The peers still banned, with the end of their ban.
+    pub fn bans(&self, now: Instant) -> impl Iterator<Item = (PeerId, Instant)> + '_ {
+        self.peers.iter().filter_map(move |(peer_id, reputation)| {
+            let until = reputation.banned_until.filter(|until| *until > now)?;
+            Some((*peer_id, until))
+        })
+    }
+
+    /// End of the ban of the peer, if banned.
+    pub fn banned_until(&self, peer_id: &PeerId) -> Option<Instant> {
+        self.peers.get(peer_id)?.banned_until
+    }
+}
+
+/// A connection was denied, because the peer is banned.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Banned {
+    pub peer_id: PeerId,
+}
+
+impl fmt::Display for Banned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Peer {} is banned", self.peer_id)
+    }
+}
+
+impl std::error::Error for Banned {}
+
+/// Network behaviour denying the connections of banned peers, inbound and
+/// outbound, before they are established with the other behaviours.
+///
+/// Expired bans are lifted on the next connection of the peer, which then
+/// reaches [`Reputations::connected`] to clear its reputation.
+#[derive(Default)]
+pub(crate) struct Bans {
+    banned_until: HashMap<PeerId, Instant>,
+}
+
+impl Bans {
+    pub fn ban(&mut self, peer_id: PeerId, until: Instant) {
+        self.banned_until.insert(peer_id, until);
+    }
+
+    fn check(&mut self, peer_id: PeerId, now: Instant) -> Result<(), ConnectionDenied> {
+        match self.banned_until.get(&peer_id) {
+            Some(until) if *until > now => Err(ConnectionDenied::new(Banned { peer_id })),
+            Some(_) => {
+                self.banned_until.remove(&peer_id);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl NetworkBehaviour for Bans {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Void;
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: Option<PeerId>,
+        _: &[Multiaddr],
+        _: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if let Some(peer) = peer {
+            self.check(peer, Instant::now())?;
+        }
+        Ok(vec![])
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check(peer, Instant::now())?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check(peer, Instant::now())?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, _: FromSwarm) {}
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reputation_survives_short_disconnects() {
+        let mut reputations = Reputations::new(Config {
+            ban_threshold: -10,
+            ban_duration: Duration::from_secs(60),
+            retention: Duration::from_secs(10),
+            gossipsub_scoring: false,
+        });
+        let peer_id = PeerId::random();
+        let start = Instant::now();
+
+        assert_eq!(
+            reputations.report(peer_id, PeerBehavior::BadBlock, 3, start),
+            (-6, false)
+        );
+        reputations.disconnected(peer_id, start);
+        assert_eq!(
+            reputations.connected(peer_id, start + Duration::from_secs(5)),
+            Admission::Allowed(-6)
+        );
+        assert_eq!(
+            reputations.report(peer_id, PeerBehavior::BadMessage, 5, start),
+            (-11, true)
+        );
+        assert_eq!(
+            reputations.connected(peer_id, start + Duration::from_secs(30)),
+            Admission::Banned
+        );
+        let admission = reputations.connected(peer_id, start + Duration::from_secs(61));
+        assert_eq!(admission, Admission::BanExpired);
+        assert_eq!(admission.score(), Some(0));
+
+        reputations.report(peer_id, PeerBehavior::Good, 1, start);
+        reputations.disconnected(peer_id, start);
+        assert_eq!(
+            reputations.connected(peer_id, start + Duration::from_secs(11)),
+            Admission::Allowed(0)
+        );
+    }
+
+    #[test]
+    fn never_connected_peers_forgotten_after_retention() {
+        let mut reputations = Reputations::new(Config {
+            retention: Duration::from_secs(10),
+            ..Default::default()
+        });
+        let (connected, relayed) = (PeerId::random(), PeerId::random());
+        let start = Instant::now();
+
+        reputations.connected(connected, start);
+        reputations.report(connected, PeerBehavior::BadMessage, 5, start);
+        reputations.report(relayed, PeerBehavior::BadMessage, 5, start);
+
+        reputations.forget_expired(start + Duration::from_secs(11));
+        assert!(reputations.peers.contains_key(&connected));
+        assert!(!reputations.peers.contains_key(&relayed));
+    }
+
+    #[test]
+    fn banned_peers_denied_until_ban_ends() {
+        let mut bans = Bans::default();
+        let peer_id = PeerId::random();
+        let start = Instant::now();
+
+        assert!(bans.check(peer_id, start).is_ok());
+        bans.ban(peer_id, start + Duration::from_secs(60));
+        assert!(bans
+            .check(peer_id, start + Duration::from_secs(59))
+            .is_err());
+        assert!(bans.check(peer_id, start + Duration::from_secs(60)).is_ok());
+        assert!(bans.banned_until.is_empty());
+    }
+}
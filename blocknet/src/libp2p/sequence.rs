@@ -0,0 +1,311 @@
+use super::{AnyMessage, PeerId};
+use libp2p::gossipsub::TopicHash;
+use lru::LruCache;
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    ops::Range,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MAX_TRACKED_ORIGINS: usize = 1024;
+/// Out of order messages buffered per origin, beyond which the missing
+/// sequences are given up on without waiting for the timeout.
+const MAX_BUFFERED_MESSAGES: usize = 64;
+
+/// Replay protection of sequenced broadcasts. Only messages with a sequence
+/// strictly above the highest seen of their origin on the topic pass.
+pub(crate) struct SequenceFilter {
+    highest: LruCache<(PeerId, TopicHash), u64>,
+}
+
+impl SequenceFilter {
+    pub fn new() -> Self {
+        Self {
+            highest: LruCache::new(
+                NonZeroUsize::new(MAX_TRACKED_ORIGINS)
+                    .expect("max tracked origins is not zero; qed"),
+            ),
+        }
+    }
+
+    /// Whether to accept a message, recording its sequence if so.
+    pub fn accept(&mut self, origin: PeerId, topic: TopicHash, sequence: u64) -> bool {
+        match self.highest.get_mut(&(origin, topic.clone())) {
+            Some(highest) if *highest >= sequence => false,
+            Some(highest) => {
+                *highest = sequence;
+                true
+            }
+            None => {
+                self.highest.put((origin, topic), sequence);
+                true
+            }
+        }
+    }
+}
+
+/// Sequences of an ordered topic that never arrived from an origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub origin: PeerId,
+    pub topic: String,
+    /// The missing sequences.
+    pub missing: Range<u64>,
+}
+
+/// What an ordered topic delivers, in order.
+#[derive(Debug)]
+pub(crate) enum Delivery {
+    Message(AnyMessage),
+    /// Sequences skipped over, as they did not arrive in time.
+    Gap(Range<u64>),
+}
+
+struct OriginBuffer {
+    /// Sequence expected next, or None while the first arrivals are
+    /// buffered.
+    next: Option<u64>,
+    buffered: BTreeMap<u64, AnyMessage>,
+    /// Since when `next` is waited for, while later sequences are buffered,
+    /// or since the first arrival.
+    waiting_since: Option<Instant>,
+}
+
+impl OriginBuffer {
+    /// Deliver the buffered messages following on from `next`.
+    fn drain(&mut self, now: Instant, deliveries: &mut Vec<Delivery>) {
+        let Some(start) = self.next else {
+            return;
+        };
+        let mut next = start;
+        while let Some(message) = self.buffered.remove(&next) {
+            deliveries.push(Delivery::Message(message));
+            next += 1;
+        }
+        self.next = Some(next);
+
+        if self.buffered.is_empty() {
+            self.waiting_since = None;
+        } else if self.waiting_since.is_none() || next != start {
+            self.waiting_since = Some(now);
+        }
+    }
+
+    /// Give up on the sequences missing before the first buffered one, or
+    /// start from it if none was delivered yet.
+    fn skip_gap(&mut self, now: Instant, deliveries: &mut Vec<Delivery>) {
+        if let Some(&first) = self.buffered.keys().next() {
+            if let Some(next) = self.next {
+                deliveries.push(Delivery::Gap(next..first));
+            }
+            self.next = Some(first);
+            self.drain(now, deliveries);
+        }
+    }
+}
+
+/// Delivery in sequence order of the broadcasts of each origin on an ordered
+/// topic. Messages arriving ahead of a missing sequence are buffered until it
+/// arrives, or until the gap timeout, when it is skipped over.
+///
+/// The first arrivals of an origin are buffered for the gap timeout, so that
+/// its sequences start from the lowest seen, rather than from whichever
+/// arrived first.
+pub(crate) struct ReorderBuffer {
+    gap_timeout: Duration,
+    origins: LruCache<PeerId, OriginBuffer>,
+}
+
+impl ReorderBuffer {
+    pub fn new(gap_timeout: Duration) -> Self {
+        Self {
+            gap_timeout,
+            origins: LruCache::new(
+                NonZeroUsize::new(MAX_TRACKED_ORIGINS)
+                    .expect("max tracked origins is not zero; qed"),
+            ),
+        }
+    }
+
+    /// Buffer a message, returning what is deliverable in order. Stale and
+    /// duplicate sequences are dropped.
+    pub fn push(
+        &mut self,
+        origin: PeerId,
+        sequence: u64,
+        message: AnyMessage,
+        now: Instant,
+    ) -> Vec<Delivery> {
+        let origin = self.origins.get_or_insert_mut(origin, || OriginBuffer {
+            next: None,
+            buffered: BTreeMap::new(),
+            waiting_since: Some(now),
+        });
+        if origin.next.map_or(false, |next| sequence < next)
+            || origin.buffered.contains_key(&sequence)
+        {
+            return Vec::new();
+        }
+
+        let mut deliveries = Vec::new();
+        origin.buffered.insert(sequence, message);
+        origin.drain(now, &mut deliveries);
+        while origin.buffered.len() > MAX_BUFFERED_MESSAGES {
+            origin.skip_gap(now, &mut deliveries);
+        }
+        deliveries
+    }
+
+    /// Skip over the gaps waited on for longer than the timeout, returning
+    /// what became deliverable for each origin.
+    pub fn expire(&mut self, now: Instant) -> Vec<(PeerId, Vec<Delivery>)> {
+        let mut expired = Vec::new();
+        for (origin, buffer) in self.origins.iter_mut() {
+            let mut deliveries = Vec::new();
+            while buffer
+                .waiting_since
+                .map_or(false, |since| now.duration_since(since) >= self.gap_timeout)
+            {
+                buffer.skip_gap(now, &mut deliveries);
+            }
+            if !deliveries.is_empty() {
+                expired.push((*origin, deliveries));
+            }
+        }
+        expired
+    }
+}
+
+/// First sequence of a sequenced topic. Sequences start from the current time
+/// in milliseconds, so that they keep increasing across restarts.
+pub(crate) fn initial_sequence() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::gossipsub::IdentTopic;
+
+    #[test]
+    fn stale_sequences_dropped() {
+        let mut filter = SequenceFilter::new();
+        let origin = PeerId::random();
+        let topic = IdentTopic::new("announce").hash();
+
+        assert!(filter.accept(origin, topic.clone(), 2));
+        // Replayed.
+        assert!(!filter.accept(origin, topic.clone(), 2));
+        // Regressed.
+        assert!(!filter.accept(origin, topic.clone(), 1));
+        assert!(filter.accept(origin, topic.clone(), 5));
+        assert!(!filter.accept(origin, topic.clone(), 4));
+
+        // Other origins and topics are tracked separately.
+        assert!(filter.accept(PeerId::random(), topic, 1));
+        assert!(filter.accept(origin, IdentTopic::new("other").hash(), 1));
+    }
+
+    fn message(sequence: u64) -> AnyMessage {
+        AnyMessage {
+            topic: "announce".to_string(),
+            sequence: Some(sequence),
+            codec: crate::libp2p::WireCodec::Json.tag(),
+            serialized: Vec::new(),
+        }
+    }
+
+    fn delivered(deliveries: Vec<Delivery>) -> Vec<Result<u64, Range<u64>>> {
+        deliveries
+            .into_iter()
+            .map(|delivery| match delivery {
+                Delivery::Message(message) => Ok(message.sequence.unwrap()),
+                Delivery::Gap(missing) => Err(missing),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_arrivals_start_from_lowest() {
+        let timeout = Duration::from_secs(1);
+        let mut buffer = ReorderBuffer::new(timeout);
+        let origin = PeerId::random();
+        let start = Instant::now();
+
+        // Sequence 3 arrives late, but within the timeout.
+        assert!(buffer.push(origin, 5, message(5), start).is_empty());
+        assert!(buffer.push(origin, 3, message(3), start).is_empty());
+        assert!(buffer.expire(start + timeout / 2).is_empty());
+        let expired = buffer.expire(start + timeout);
+        assert_eq!(delivered(expired.into_iter().next().unwrap().1), [Ok(3)]);
+
+        let now = start + timeout;
+        assert_eq!(
+            delivered(buffer.push(origin, 4, message(4), now)),
+            [Ok(4), Ok(5)]
+        );
+        // Stale once started.
+        assert!(buffer.push(origin, 2, message(2), now).is_empty());
+    }
+
+    #[test]
+    fn reorders_and_skips_gaps() {
+        let timeout = Duration::from_secs(1);
+        let mut buffer = ReorderBuffer::new(timeout);
+        let origin = PeerId::random();
+        let started = Instant::now();
+        buffer.push(origin, 1, message(1), started);
+        let start = started + timeout;
+        assert_eq!(
+            delivered(buffer.expire(start).into_iter().next().unwrap().1),
+            [Ok(1)]
+        );
+        let mut push =
+            |sequence, now| delivered(buffer.push(origin, sequence, message(sequence), now));
+
+        assert_eq!(push(3, start), []);
+        assert_eq!(push(4, start), []);
+        assert_eq!(push(2, start), [Ok(2), Ok(3), Ok(4)]);
+        // Stale and duplicate.
+        assert_eq!(push(2, start), []);
+        assert_eq!(push(7, start), []);
+        assert_eq!(push(7, start), []);
+
+        assert!(buffer.expire(start + timeout / 2).is_empty());
+        let expired = buffer.expire(start + timeout);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, origin);
+        assert_eq!(
+            delivered(expired.into_iter().next().unwrap().1),
+            [Err(5..7), Ok(7)]
+        );
+        assert_eq!(
+            delivered(buffer.push(origin, 8, message(8), start + timeout)),
+            [Ok(8)]
+        );
+    }
+
+    #[test]
+    fn full_buffer_skips_gap() {
+        let timeout = Duration::from_secs(60);
+        let mut buffer = ReorderBuffer::new(timeout);
+        let origin = PeerId::random();
+        let started = Instant::now();
+        let now = started + timeout;
+
+        buffer.push(origin, 0, message(0), started);
+        assert_eq!(buffer.expire(now).len(), 1);
+        for sequence in 2..2 + MAX_BUFFERED_MESSAGES as u64 {
+            assert!(buffer
+                .push(origin, sequence, message(sequence), now)
+                .is_empty());
+        }
+        let deliveries = delivered(buffer.push(origin, 100, message(100), now));
+        assert_eq!(deliveries[0], Err(1..2));
+        assert_eq!(deliveries.len(), 1 + MAX_BUFFERED_MESSAGES);
+    }
+}
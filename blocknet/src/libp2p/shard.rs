@@ -0,0 +1,66 @@
+use libp2p::gossipsub::IdentTopic;
+
+/// Mapping of many logical broadcast topics onto a bounded set of physical
+/// gossipsub topics, such as for per-core or per-validator topics.
+///
+/// A logical topic is assigned to a shard by a hash of its name, stable
+/// across nodes and versions, so that all the nodes of a network agree on
+/// it. Broadcasts carry their logical topic, for listeners to filter the
+/// shared physical topic on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopicShards {
+    shards: u32,
+}
+
+impl TopicShards {
+    /// Map logical topics onto `shards` physical topics, at least one.
+    pub fn new(shards: u32) -> Self {
+        Self {
+            shards: shards.max(1),
+        }
+    }
+
+    /// Number of physical topics.
+    pub fn shards(&self) -> u32 {
+        self.shards
+    }
+
+    /// Shard of the logical topic, by the FNV-1a hash of its name.
+    pub fn shard(&self, topic: &str) -> u32 {
+        let hash = topic.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+        (hash % u64::from(self.shards)) as u32
+    }
+
+    /// Name of the physical topic of the logical topic.
+    pub fn physical_topic(&self, topic: &str) -> String {
+        format!("shard/{}", self.shard(topic))
+    }
+}
+
+/// Gossipsub topic of a logical topic, under the sharding if any.
+pub(crate) fn gossipsub_topic(shards: Option<TopicShards>, topic: &str) -> IdentTopic {
+    match shards {
+        Some(shards) => IdentTopic::new(shards.physical_topic(topic)),
+        None => IdentTopic::new(topic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shards_are_stable_and_bounded() {
+        let shards = TopicShards::new(4);
+        assert_eq!(shards.shard(""), 0xcbf29ce484222325u64 as u32 % 4);
+        for core in 0..64 {
+            let topic = format!("core/{core}");
+            assert!(shards.shard(&topic) < 4);
+            assert_eq!(shards.shard(&topic), shards.shard(&topic));
+        }
+        assert_eq!(TopicShards::new(0).shards(), 1);
+        assert_eq!(TopicShards::new(1).physical_topic("vote"), "shard/0");
+    }
+}
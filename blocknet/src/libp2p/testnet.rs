@@ -0,0 +1,240 @@
+//! In-process networks of workers over the memory transport, for integration
+//! tests of the protocols built on the services, such as sync and consensus.
+//!
+//! The workers are spawned on the ambient tokio runtime.
+
+use super::{Error, PeerId, Service, WorkerBuilder};
+use futures::future;
+use libp2p::{identity::Keypair, multiaddr::Protocol, Multiaddr};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+/// Memory transport ports are process-wide, so that networks of concurrent
+/// tests must not share any.
+static NEXT_MEMORY_PORT: AtomicU64 = AtomicU64::new(1);
+
+/// How the nodes of a test network are connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// Every node to every other.
+    FullMesh,
+    /// Every node to the next one.
+    Line,
+    /// Every node to the first one.
+    Star,
+}
+
+impl Topology {
+    /// Nodes connected to a node, among the `nodes` first.
+    fn neighbours(self, node: usize, nodes: usize) -> Vec<usize> {
+        match self {
+            Topology::FullMesh => (0..nodes).filter(|other| *other != node).collect(),
+            Topology::Line => [
+                node.checked_sub(1),
+                Some(node + 1).filter(|next| *next < nodes),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            Topology::Star if node == 0 => (1..nodes).collect(),
+            Topology::Star => vec![0],
+        }
+    }
+}
+
+/// Builder of a [`TestNet`].
+pub struct TestNetBuilder<PeerInfo> {
+    topology: Topology,
+    workers: Vec<WorkerBuilder<PeerInfo>>,
+}
+
+impl<PeerInfo> TestNetBuilder<PeerInfo>
+where
+    PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Add a node with the default worker configuration.
+    pub fn with_node(self, local_info: PeerInfo) -> Self {
+        self.with_worker(WorkerBuilder::new(local_info))
+    }
+
+    /// Add a node with a custom worker configuration. Its identity, mdns,
+    /// listen addresses and bootstrap peers are set by the network.
+    pub fn with_worker(mut self, worker: WorkerBuilder<PeerInfo>) -> Self {
+        self.workers.push(worker);
+        self
+    }
+
+    /// Build and spawn the workers, each dialing its neighbours added before
+    /// it, so that they already listen.
+    pub fn build(self) -> Result<TestNet<PeerInfo>, Error> {
+        let nodes = self.workers.len();
+        let mut net = TestNet {
+            topology: self.topology,
+            peer_ids: Vec::with_capacity(nodes),
+            addrs: Vec::with_capacity(nodes),
+            services: Vec::with_capacity(nodes),
+            handles: Vec::with_capacity(nodes),
+        };
+
+        for (node, worker) in self.workers.into_iter().enumerate() {
+            let keypair = Keypair::generate_ed25519();
+            let peer_id = keypair.public().to_peer_id();
+            let port = NEXT_MEMORY_PORT.fetch_add(1, Ordering::Relaxed);
+            let addr = Multiaddr::empty().with(Protocol::Memory(port));
+            let bootstrap = self
+                .topology
+                .neighbours(node, nodes)
+                .into_iter()
+                .filter(|neighbour| *neighbour < node)
+                .map(|neighbour| {
+                    net.addrs[neighbour]
+                        .clone()
+                        .with(Protocol::P2p(net.peer_ids[neighbour]))
+                })
+                .collect::<Vec<_>>();
+
+            let worker = worker
+                .with_keypair(keypair)
+                .with_mdns(false)
+                .with_memory_transport()
+                .with_listen_addrs([addr.clone()])
+                .with_bootstrap(bootstrap)
+                .build()?;
+            net.services.push(worker.service());
+            net.handles.push(tokio::spawn(worker.run()));
+            net.peer_ids.push(peer_id);
+            net.addrs.push(addr);
+        }
+
+        Ok(net)
+    }
+}
+
+/// A network of workers in the same process, connected in a [`Topology`].
+/// The workers stop when the network is shut down or dropped.
+pub struct TestNet<PeerInfo> {
+    topology: Topology,
+    peer_ids: Vec<PeerId>,
+    addrs: Vec<Multiaddr>,
+    services: Vec<Service<PeerInfo>>,
+    handles: Vec<JoinHandle<Result<std::convert::Infallible, super::FatalRunError>>>,
+}
+
+impl<PeerInfo> TestNet<PeerInfo>
+where
+    PeerInfo: Debug + Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Start building a network of the topology.
+    pub fn builder(topology: Topology) -> TestNetBuilder<PeerInfo> {
+        TestNetBuilder {
+            topology,
+            workers: Vec::new(),
+        }
+    }
+
+    /// Build a network of nodes with the default worker configuration, one
+    /// per local info.
+    pub fn new(
+        topology: Topology,
+        local_infos: impl IntoIterator<Item = PeerInfo>,
+    ) -> Result<Self, Error> {
+        local_infos
+            .into_iter()
+            .fold(Self::builder(topology), TestNetBuilder::with_node)
+            .build()
+    }
+
+    /// Service of the node.
+    pub fn service(&self, node: usize) -> Service<PeerInfo> {
+        self.services[node].clone()
+    }
+
+    /// Services of all the nodes, in the order they were added.
+    pub fn services(&self) -> &[Service<PeerInfo>] {
+        &self.services
+    }
+
+    /// Peer ids of all the nodes, in the order they were added.
+    pub fn peer_ids(&self) -> &[PeerId] {
+        &self.peer_ids
+    }
+
+    /// Listen address of the node, such as to connect another worker.
+    pub fn addr(&self, node: usize) -> Multiaddr {
+        self.addrs[node]
+            .clone()
+            .with(Protocol::P2p(self.peer_ids[node]))
+    }
+
+    /// Number of nodes.
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    /// Whether the network has no node.
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+
+    /// Wait until every node received the peer info of all its neighbours,
+    /// or fail with [`Error::ConnectTimeout`]. The nodes are woken by the
+    /// peer info as it arrives, all waiting at once.
+    pub async fn run_until_synced(&self, timeout: Duration) -> Result<(), Error> {
+        let waits = self
+            .services
+            .iter()
+            .enumerate()
+            .flat_map(|(node, service)| {
+                self.topology
+                    .neighbours(node, self.len())
+                    .into_iter()
+                    .map(move |neighbour| {
+                        let mut service = service.clone();
+                        let peer_id = self.peer_ids[neighbour];
+                        async move { service.wait_peer_info(peer_id, timeout).await }
+                    })
+            });
+        future::try_join_all(waits).await?;
+
+        Ok(())
+    }
+
+    /// Stop all the workers, and wait until they are gone.
+    pub async fn shutdown(mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+        for handle in self.handles.drain(..) {
+            let _ = handle.await;
+        }
+    }
+}
+
+impl<PeerInfo> Drop for TestNet<PeerInfo> {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topology_neighbours() {
+        assert_eq!(Topology::FullMesh.neighbours(1, 3), [0, 2]);
+        assert_eq!(Topology::Line.neighbours(0, 3), [1]);
+        assert_eq!(Topology::Line.neighbours(1, 3), [0, 2]);
+        assert_eq!(Topology::Line.neighbours(2, 3), [1]);
+        assert_eq!(Topology::Star.neighbours(0, 3), [1, 2]);
+        assert_eq!(Topology::Star.neighbours(2, 3), [0]);
+        assert!(Topology::Line.neighbours(0, 1).is_empty());
+    }
+}
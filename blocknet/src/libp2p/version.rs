@@ -0,0 +1,107 @@
+use std::{fmt, str::FromStr};
+
+/// Version of the blocknet protocol, advertised as the identify protocol
+/// version, such as `/blocknet/v0.1` or `/blocknet/<network id>/v0.1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolVersion {
+    pub network_id: Option<String>,
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.network_id {
+            Some(network_id) => {
+                write!(f, "/blocknet/{}/v{}.{}", network_id, self.major, self.minor)
+            }
+            None => write!(f, "/blocknet/v{}.{}", self.major, self.minor),
+        }
+    }
+}
+
+/// The protocol version string is not a valid blocknet version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidProtocolVersion(pub String);
+
+impl FromStr for ProtocolVersion {
+    type Err = InvalidProtocolVersion;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidProtocolVersion(s.to_string());
+
+        let mut parts = s
+            .strip_prefix("/blocknet/")
+            .ok_or_else(invalid)?
+            .rsplitn(2, '/');
+        let version = parts.next().ok_or_else(invalid)?;
+        let network_id = parts.next().map(|network_id| network_id.to_string());
+
+        let (major, minor) = version
+            .strip_prefix('v')
+            .and_then(|version| version.split_once('.'))
+            .ok_or_else(invalid)?;
+
+        Ok(Self {
+            network_id,
+            major: major.parse().map_err(|_| invalid())?,
+            minor: minor.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Which remote protocol versions are compatible with the local one. Peers of
+/// another network are never compatible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionPolicy {
+    /// Major and minor versions must match.
+    Exact,
+    /// Only major versions must match.
+    SameMajor,
+}
+
+impl VersionPolicy {
+    pub fn is_compatible(&self, local: &ProtocolVersion, remote: &ProtocolVersion) -> bool {
+        local.network_id == remote.network_id
+            && local.major == remote.major
+            && match self {
+                VersionPolicy::Exact => local.minor == remote.minor,
+                VersionPolicy::SameMajor => true,
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(network_id: Option<&str>, major: u32, minor: u32) -> ProtocolVersion {
+        ProtocolVersion {
+            network_id: network_id.map(Into::into),
+            major,
+            minor,
+        }
+    }
+
+    #[test]
+    fn parse_roundtrip() {
+        for version in [version(None, 0, 1), version(Some("testnet"), 2, 13)] {
+            assert_eq!(version.to_string().parse::<ProtocolVersion>(), Ok(version));
+        }
+
+        assert!("/ipfs/0.1.0".parse::<ProtocolVersion>().is_err());
+        assert!("/blocknet/v1".parse::<ProtocolVersion>().is_err());
+        assert!("/blocknet/vx.1".parse::<ProtocolVersion>().is_err());
+    }
+
+    #[test]
+    fn policies() {
+        let local = version(None, 0, 1);
+
+        assert!(VersionPolicy::Exact.is_compatible(&local, &version(None, 0, 1)));
+        assert!(!VersionPolicy::Exact.is_compatible(&local, &version(None, 0, 2)));
+        assert!(VersionPolicy::SameMajor.is_compatible(&local, &version(None, 0, 2)));
+        assert!(!VersionPolicy::SameMajor.is_compatible(&local, &version(None, 1, 1)));
+        assert!(!VersionPolicy::SameMajor.is_compatible(&local, &version(Some("other"), 0, 1)));
+    }
+}
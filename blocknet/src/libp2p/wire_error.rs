@@ -0,0 +1,79 @@
+use super::Error;
+use serde::{Deserialize, Serialize};
+
+/// Category of a [`WireError`]. The serialized names are stable, so that
+/// remote consumers can match on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireErrorKind {
+    Codec,
+    ChannelSend,
+    GossipsubSubscription,
+    GossipsubPublish,
+    Noise,
+    Multiaddr,
+    Transport,
+    Build,
+    OutboundRequest,
+    RequestCanceled,
+    ResponseChannelClosed,
+    WouldBlock,
+    BroadcastDropped,
+    InsufficientPeers,
+    ConnectTimeout,
+    TooManySubscriptions,
+    Shutdown,
+    WorkerDisconnected,
+    RecordStore,
+    UnknownOriginBroadcast,
+}
+
+/// Serializable representation of an [`Error`], such as to report it to a
+/// monitoring service or an RPC client. The inner libp2p errors are only kept
+/// as the message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WireError {
+    pub kind: WireErrorKind,
+    pub message: String,
+}
+
+impl Error {
+    /// Serializable representation of the error.
+    pub fn to_wire(&self) -> WireError {
+        let kind = match self {
+            Error::Codec(_) => WireErrorKind::Codec,
+            Error::ChannelSend(_) => WireErrorKind::ChannelSend,
+            Error::GossipsubSubscription(_) => WireErrorKind::GossipsubSubscription,
+            Error::GossipsubPublish(_) => WireErrorKind::GossipsubPublish,
+            Error::Noise(_) => WireErrorKind::Noise,
+            Error::Multiaddr(_) => WireErrorKind::Multiaddr,
+            Error::Transport(_) => WireErrorKind::Transport,
+            Error::Build(_) => WireErrorKind::Build,
+            Error::OutboundRequest(_) => WireErrorKind::OutboundRequest,
+            Error::RequestCanceled(_) => WireErrorKind::RequestCanceled,
+            Error::ResponseChannelClosed => WireErrorKind::ResponseChannelClosed,
+            Error::WouldBlock => WireErrorKind::WouldBlock,
+            Error::BroadcastDropped => WireErrorKind::BroadcastDropped,
+            Error::InsufficientPeers => WireErrorKind::InsufficientPeers,
+            Error::ConnectTimeout => WireErrorKind::ConnectTimeout,
+            Error::TooManySubscriptions => WireErrorKind::TooManySubscriptions,
+            Error::Shutdown => WireErrorKind::Shutdown,
+            Error::WorkerDisconnected => WireErrorKind::WorkerDisconnected,
+            Error::RecordStore(_) => WireErrorKind::RecordStore,
+            Error::UnknownOriginBroadcast(_) => WireErrorKind::UnknownOriginBroadcast,
+        };
+
+        // The top-level message alone is too vague to act on, such as "Noise
+        // error", so the inner error is appended.
+        let message = match std::error::Error::source(self) {
+            Some(source) => format!("{}: {}", self, source),
+            None => match self {
+                Error::Codec(message) => format!("{}: {}", self, message),
+                Error::Build(source) => format!("{}: {}", self, source),
+                _ => self.to_string(),
+            },
+        };
+
+        WireError { kind, message }
+    }
+}
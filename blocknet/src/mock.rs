@@ -0,0 +1,439 @@
+//! In-process mock network. Every service joined to the same [`Network`] is
+//! connected to every other one. Messages are passed as values without
+//! serialization. Useful for testing.
+
+use crate::{
+    BroadcastService as BroadcastServiceT, Event as EventT, Message as MessageT,
+    Request as RequestT, RequestService as RequestServiceT, Service as ServiceT,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{self, FutureExt},
+    select,
+    stream::{Stream, StreamExt},
+};
+use futures_timer::Delay;
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use sync_extra::MutexExtra;
+use thiserror::Error;
+
+/// Peer id in a mock network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PeerId(usize);
+
+type AnySender = mpsc::UnboundedSender<(PeerId, Box<dyn Any + Send>)>;
+type AnyResponseSender = oneshot::Sender<Box<dyn Any + Send>>;
+type AnyRequestSender = mpsc::UnboundedSender<(PeerId, Box<dyn Any + Send>, AnyResponseSender)>;
+
+struct NetworkInner<PeerInfo> {
+    next_peer_id: usize,
+    peers: HashMap<PeerId, PeerInfo>,
+    subscriptions: HashMap<String, Vec<(PeerId, AnySender)>>,
+    /// Callers of `broadcast_when_ready` to wake on the next subscription.
+    subscription_waiters: Vec<oneshot::Sender<()>>,
+    request_listeners: HashMap<(PeerId, TypeId), Vec<AnyRequestSender>>,
+}
+
+/// A mock network.
+pub struct Network<PeerInfo> {
+    inner: Arc<Mutex<NetworkInner<PeerInfo>>>,
+}
+
+impl<PeerInfo> Clone for Network<PeerInfo> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<PeerInfo> Default for Network<PeerInfo> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<PeerInfo> Network<PeerInfo> {
+    /// Create a new empty network.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(NetworkInner {
+                next_peer_id: 0,
+                peers: HashMap::new(),
+                subscriptions: HashMap::new(),
+                subscription_waiters: Vec::new(),
+                request_listeners: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Join a new peer to the network, and get its service.
+    pub fn join(&self, local_info: PeerInfo) -> Service<PeerInfo> {
+        let mut inner = self.inner.lock_unwrap();
+        let peer_id = PeerId(inner.next_peer_id);
+        inner.next_peer_id += 1;
+        inner.peers.insert(peer_id, local_info);
+
+        Service {
+            peer_id,
+            network: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Peer does not listen for the request")]
+    NoListener,
+    #[error("Request dropped without a response")]
+    NoResponse,
+    #[error("Not enough peers on the topic before the deadline")]
+    InsufficientPeers,
+}
+
+/// Service of a single peer in a mock network.
+pub struct Service<PeerInfo> {
+    peer_id: PeerId,
+    network: Network<PeerInfo>,
+}
+
+impl<PeerInfo> Clone for Service<PeerInfo> {
+    fn clone(&self) -> Self {
+        Self {
+            peer_id: self.peer_id,
+            network: self.network.clone(),
+        }
+    }
+}
+
+impl<PeerInfo> Service<PeerInfo> {
+    /// Peer id of the local peer.
+    pub fn local_peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Number of other peers listening on the topic.
+    pub fn topic_peers(&self, topic: &str) -> usize {
+        let mut inner = self.network.inner.lock_unwrap();
+        let Some(subscribers) = inner.subscriptions.get_mut(topic) else {
+            return 0;
+        };
+        subscribers.retain(|(_, sender)| !sender.is_closed());
+
+        subscribers
+            .iter()
+            .filter(|(peer_id, _)| *peer_id != self.peer_id)
+            .map(|(peer_id, _)| peer_id)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+}
+
+impl<PeerInfo> ServiceT for Service<PeerInfo>
+where
+    PeerInfo: Clone + Send,
+{
+    type PeerId = PeerId;
+    type PeerInfo = PeerInfo;
+    type Error = Error;
+
+    fn local_info(&self) -> PeerInfo {
+        self.network.inner.lock_unwrap().peers[&self.peer_id].clone()
+    }
+
+    fn set_local_info(&mut self, info: PeerInfo) {
+        self.network
+            .inner
+            .lock_unwrap()
+            .peers
+            .insert(self.peer_id, info);
+    }
+
+    fn update_local_info(&mut self, f: impl FnOnce(&mut PeerInfo)) {
+        let mut inner = self.network.inner.lock_unwrap();
+        f(inner
+            .peers
+            .get_mut(&self.peer_id)
+            .expect("peer joined the network; qed"));
+    }
+
+    fn peers(&self) -> impl IntoIterator<Item = (PeerId, PeerInfo)> {
+        self.network
+            .inner
+            .lock_unwrap()
+            .peers
+            .iter()
+            .filter(|(peer_id, _)| **peer_id != self.peer_id)
+            .map(|(peer_id, info)| (*peer_id, info.clone()))
+            .collect::<Vec<_>>()
+    }
+}
+
+#[derive(Debug)]
+pub struct Event<Value> {
+    origin: PeerId,
+    value: Value,
+}
+
+impl<Value> EventT for Event<Value> {
+    type Origin = PeerId;
+    type Value = Value;
+
+    fn origin(&self) -> impl Deref<Target = PeerId> {
+        &self.origin
+    }
+
+    fn value(&self) -> impl Deref<Target = Value> {
+        &self.value
+    }
+
+    fn into_value(self) -> Value {
+        self.value
+    }
+}
+
+impl<PeerInfo, Msg> BroadcastServiceT<Msg> for Service<PeerInfo>
+where
+    PeerInfo: Clone + Send,
+    Msg: MessageT + Clone + Send + 'static,
+    Msg::Topic: Into<String> + Send,
+{
+    type Event = Event<Msg>;
+
+    async fn listen(
+        &mut self,
+        topic: Msg::Topic,
+    ) -> Result<impl Stream<Item = Event<Msg>> + Send, Error> {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut inner = self.network.inner.lock_unwrap();
+        inner
+            .subscriptions
+            .entry(topic.into())
+            .or_default()
+            .push((self.peer_id, sender));
+        for waiter in inner.subscription_waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+        drop(inner);
+
+        // Messages of another type on the same topic are ignored.
+        Ok(receiver.filter_map(|(origin, message)| {
+            future::ready(message.downcast::<Msg>().ok().map(|value| Event {
+                origin,
+                value: *value,
+            }))
+        }))
+    }
+
+    async fn broadcast(&mut self, message: Msg) -> Result<(), Error> {
+        let mut inner = self.network.inner.lock_unwrap();
+        if let Some(subscribers) = inner.subscriptions.get_mut(&message.topic().into()) {
+            subscribers.retain(|(_, sender)| !sender.is_closed());
+
+            // Like gossip, a broadcast is not delivered back to its origin.
+            for (peer_id, sender) in subscribers.iter() {
+                if *peer_id != self.peer_id {
+                    let _ = sender.unbounded_send((self.peer_id, Box::new(message.clone())));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn broadcast_when_ready(
+        &mut self,
+        message: Msg,
+        min_peers: usize,
+        deadline: Duration,
+    ) -> Result<(), Error> {
+        let topic = message.topic().into();
+        let mut timeout = Delay::new(deadline).fuse();
+        loop {
+            // Registered before counting, so that a subscription in between
+            // still wakes the wait.
+            let (waiter, subscribed) = oneshot::channel();
+            {
+                let waiters = &mut self.network.inner.lock_unwrap().subscription_waiters;
+                // Drop the waiters that timed out.
+                waiters.retain(|waiter| !waiter.is_canceled());
+                waiters.push(waiter);
+            }
+            if self.topic_peers(&topic) >= min_peers {
+                break;
+            }
+
+            select! {
+                _ = subscribed.fuse() => (),
+                () = timeout => return Err(Error::InsufficientPeers),
+            }
+        }
+
+        BroadcastServiceT::<Msg>::broadcast(self, message).await
+    }
+
+    async fn unsubscribe(&mut self, topic: Msg::Topic) -> Result<(), Error> {
+        let mut inner = self.network.inner.lock_unwrap();
+        if let Some(subscribers) = inner.subscriptions.get_mut(&topic.into()) {
+            subscribers.retain(|(peer_id, _)| *peer_id != self.peer_id);
+        }
+
+        Ok(())
+    }
+}
+
+/// Channel to respond to an inbound request.
+pub struct Channel {
+    sender: AnyResponseSender,
+}
+
+impl<PeerInfo, Req> RequestServiceT<Req> for Service<PeerInfo>
+where
+    PeerInfo: Clone + Send,
+    Req: RequestT + Send + 'static,
+    Req::Response: Send + 'static,
+{
+    type Event = Event<Req>;
+    type Channel = Channel;
+
+    async fn listen(&mut self) -> Result<impl Stream<Item = (Channel, Event<Req>)> + Send, Error> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.network
+            .inner
+            .lock_unwrap()
+            .request_listeners
+            .entry((self.peer_id, TypeId::of::<Req>()))
+            .or_default()
+            .push(sender);
+
+        Ok(receiver.filter_map(|(origin, request, sender)| {
+            future::ready(request.downcast::<Req>().ok().map(|value| {
+                (
+                    Channel { sender },
+                    Event {
+                        origin,
+                        value: *value,
+                    },
+                )
+            }))
+        }))
+    }
+
+    async fn request(&mut self, peer: PeerId, request: Req) -> Result<Req::Response, Error> {
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut inner = self.network.inner.lock_unwrap();
+            let listeners = inner
+                .request_listeners
+                .get_mut(&(peer, TypeId::of::<Req>()))
+                .ok_or(Error::NoListener)?;
+            listeners.retain(|listener| !listener.is_closed());
+
+            // Like a real network, a request is handled by a single listener.
+            listeners
+                .first()
+                .ok_or(Error::NoListener)?
+                .unbounded_send((self.peer_id, Box::new(request), sender))
+                .map_err(|_| Error::NoListener)?;
+        }
+
+        let response = receiver.await.map_err(|_| Error::NoResponse)?;
+        response
+            .downcast::<Req::Response>()
+            .map(|response| *response)
+            .map_err(|_| Error::NoResponse)
+    }
+
+    async fn respond(&mut self, channel: Channel, response: Req::Response) -> Result<(), Error> {
+        channel
+            .sender
+            .send(Box::new(response))
+            .map_err(|_| Error::NoResponse)
+    }
+}
+
+/// Conformance backend joining each pair of peers to a new network.
+#[derive(Debug, Default)]
+pub struct Backend;
+
+impl crate::conformance::Backend for Backend {
+    type Service = Service<crate::conformance::PeerInfo>;
+
+    async fn pair(
+        &mut self,
+        first: crate::conformance::PeerInfo,
+        second: crate::conformance::PeerInfo,
+    ) -> (Self::Service, Self::Service) {
+        let network = Network::new();
+        (network.join(first), network.join(second))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance;
+
+    #[tokio::test]
+    async fn peers_reported() {
+        conformance::test_peers_reported(&mut Backend).await;
+    }
+
+    #[tokio::test]
+    async fn broadcast_roundtrip() {
+        conformance::test_broadcast_roundtrip(&mut Backend).await;
+    }
+
+    #[tokio::test]
+    async fn broadcast_unsubscribe() {
+        conformance::test_broadcast_unsubscribe(&mut Backend).await;
+    }
+
+    #[tokio::test]
+    async fn request_roundtrip() {
+        conformance::test_request_roundtrip(&mut Backend).await;
+    }
+
+    #[tokio::test]
+    async fn broadcast_when_ready_wakes_on_subscription() {
+        use conformance::{PeerInfo, Ping, PING_TOPIC};
+
+        let network = Network::new();
+        let mut sender = network.join(PeerInfo { id: 0 });
+        let err = sender
+            .broadcast_when_ready(Ping { value: 0 }, 1, Duration::from_millis(10))
+            .await
+            .expect_err("no peer is subscribed");
+        assert!(matches!(err, Error::InsufficientPeers));
+
+        let broadcast = tokio::spawn(async move {
+            sender
+                .broadcast_when_ready(Ping { value: 1 }, 2, Duration::from_secs(10))
+                .await
+        });
+        let mut first = network.join(PeerInfo { id: 1 });
+        let mut first = Box::pin(
+            BroadcastServiceT::<Ping>::listen(&mut first, PING_TOPIC)
+                .await
+                .unwrap(),
+        );
+        tokio::task::yield_now().await;
+        assert!(!broadcast.is_finished(), "waits for the second peer");
+
+        let mut second = network.join(PeerInfo { id: 2 });
+        let _second = BroadcastServiceT::<Ping>::listen(&mut second, PING_TOPIC)
+            .await
+            .unwrap();
+        broadcast.await.unwrap().unwrap();
+        assert_eq!(
+            first.next().await.map(|event| event.value),
+            Some(Ping { value: 1 })
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use futures::stream::Stream;
-use std::{future::Future, ops::Deref};
+use futures::stream::{Stream, StreamExt};
+use futures_timer::Delay;
+use std::{collections::HashSet, future::Future, hash::Hash, ops::Deref, time::Duration};
 
 pub trait Service: Send {
     type PeerId;
@@ -8,7 +9,30 @@ pub trait Service: Send {
 
     fn local_info(&self) -> Self::PeerInfo;
     fn set_local_info(&mut self, info: Self::PeerInfo);
+    /// Update the local info in place and push it to peers, atomically with
+    /// respect to other clones of the service.
+    fn update_local_info(&mut self, f: impl FnOnce(&mut Self::PeerInfo));
     fn peers(&self) -> impl IntoIterator<Item = (Self::PeerId, Self::PeerInfo)>;
+
+    /// Update and push the local info on every notification, such as a new
+    /// best block being imported, until the stream ends.
+    fn follow_local_info<N, S, F>(
+        &mut self,
+        notifications: S,
+        mut update: F,
+    ) -> impl Future<Output = ()> + Send
+    where
+        N: Send,
+        S: Stream<Item = N> + Send,
+        F: FnMut(&mut Self::PeerInfo, N) + Send,
+    {
+        async move {
+            let mut notifications = Box::pin(notifications);
+            while let Some(notification) = notifications.next().await {
+                self.update_local_info(|info| update(info, notification));
+            }
+        }
+    }
 }
 
 pub trait Event {
@@ -20,12 +44,46 @@ pub trait Event {
     fn into_value(self) -> Self::Value;
 }
 
+/// Priority class of a broadcast message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    /// Time-critical messages, such as block announcements.
+    High,
+    #[default]
+    Normal,
+    /// Messages that can be lost, such as gossip.
+    Low,
+}
+
 pub trait Message {
     type Topic;
 
+    /// Whether only the latest message of each origin matters, such as for
+    /// best block announcements. Backends may then drop a message not yet
+    /// received by a listener once a newer one from the same origin arrives.
+    /// Messages can arrive out of order, so topics of such messages are
+    /// best sequenced, if the backend supports it.
+    const KEEP_LATEST: bool = false;
+
+    /// Priority class of the message. Backends may send messages of a
+    /// higher class first, and drop messages of the [`Priority::Low`] class
+    /// under pressure, failing their broadcast.
+    const PRIORITY: Priority = Priority::Normal;
+
     fn topic(&self) -> Self::Topic;
 }
 
+/// A request or response message carrying a correlation id, so that a
+/// response can be matched to its request, such as the responses of
+/// [`BroadcastService::scatter_gather`] sharing a reply topic with the
+/// responses to other requests.
+pub trait Correlated {
+    type CorrelationId: PartialEq;
+
+    /// Id of the request, copied by its responses.
+    fn correlation_id(&self) -> Self::CorrelationId;
+}
+
 pub trait BroadcastService<Msg: Message>: Service {
     type Event: Event<Origin = Self::PeerId, Value = Msg>;
 
@@ -34,6 +92,63 @@ pub trait BroadcastService<Msg: Message>: Service {
         topic: Msg::Topic,
     ) -> impl Future<Output = Result<impl Stream<Item = Self::Event> + Send, Self::Error>> + Send;
     fn broadcast(&mut self, message: Msg) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Broadcast once at least `min_peers` peers are subscribed to the topic
+    /// of the message, such as for consensus messages that would be lost in
+    /// an empty mesh. Fails if that does not happen before the deadline.
+    fn broadcast_when_ready(
+        &mut self,
+        message: Msg,
+        min_peers: usize,
+        deadline: Duration,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Stop listening on the topic. All listener streams of the topic end once
+    /// this returns, and no later broadcast is received on them.
+    fn unsubscribe(
+        &mut self,
+        topic: Msg::Topic,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Broadcast a request, and gather the responses broadcast on the reply
+    /// topic until the deadline. Only the responses with the correlation id
+    /// of the request are kept, the first one of each peer.
+    fn scatter_gather<Resp>(
+        &mut self,
+        request: Msg,
+        reply_topic: Resp::Topic,
+        deadline: Duration,
+    ) -> impl Future<Output = Result<Vec<(Self::PeerId, Resp)>, Self::Error>> + Send
+    where
+        Self: BroadcastService<Resp> + Clone,
+        Self::PeerId: Clone + Eq + Hash + Send,
+        Msg: Correlated + Send,
+        Msg::CorrelationId: Send,
+        Resp: Message + Correlated<CorrelationId = Msg::CorrelationId> + Send,
+        Resp::Topic: Send,
+        <Self as BroadcastService<Resp>>::Event: Send,
+    {
+        async move {
+            let correlation_id = request.correlation_id();
+            // Listen before broadcasting, so that no early response is missed.
+            let mut listener = self.clone();
+            let responses = BroadcastService::<Resp>::listen(&mut listener, reply_topic).await?;
+            BroadcastService::<Msg>::broadcast(self, request).await?;
+
+            let mut responses = Box::pin(responses.take_until(Delay::new(deadline)));
+            let mut seen = HashSet::new();
+            let mut gathered = Vec::new();
+            while let Some(event) = responses.next().await {
+                if event.value().correlation_id() != correlation_id {
+                    continue;
+                }
+                let origin = event.origin().clone();
+                if seen.insert(origin.clone()) {
+                    gathered.push((origin, event.into_value()));
+                }
+            }
+
+            Ok(gathered)
+        }
+    }
 }
 
 pub trait NotifyService<Not>: Service {
@@ -51,6 +166,11 @@ pub trait NotifyService<Not>: Service {
 
 pub trait Request {
     type Response;
+
+    /// Identifier of the request type on the wire. It must be the same on
+    /// every build of a node speaking the protocol, and unique among its
+    /// request types.
+    const PROTOCOL_ID: &'static str;
 }
 
 pub trait RequestService<Req: Request>: Service {
@@ -0,0 +1,2218 @@
+//! Tests of the libp2p worker, over localhost.
+
+#[cfg(feature = "testnet")]
+use blocknet::libp2p::testnet::{TestNet, Topology};
+use blocknet::{
+    conformance,
+    libp2p::{
+        self as blocknet_libp2p, request_protocol_id, EventLog, EventRecorder, PeerBehavior,
+        RecordedEvent, TransportKind, VersionPolicy, WireCodec, WorkerBuilder,
+    },
+    BroadcastService, Event, Message, Priority, Request, RequestService, Service,
+};
+use futures::{FutureExt, StreamExt};
+use libp2p::{identity::Keypair, multiaddr::Protocol, Multiaddr};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PeerInfo {
+    best_block: u64,
+}
+
+fn local_addr() -> Multiaddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind succeeds");
+    let port = listener.local_addr().expect("has local addr").port();
+
+    format!("/ip4/127.0.0.1/tcp/{}", port)
+        .parse()
+        .expect("address is valid")
+}
+
+#[tokio::test]
+async fn minimal_worker_connects_to_bootstrap() {
+    let bootstrap_key = Keypair::generate_ed25519();
+    let bootstrap_peer_id = bootstrap_key.public().to_peer_id();
+    let bootstrap_addr = local_addr();
+
+    let bootstrap = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(bootstrap_key)
+        .with_mdns(false)
+        .with_ping()
+        .with_listen_addrs([bootstrap_addr.clone()])
+        .build()
+        .expect("bootstrap worker builds");
+    tokio::spawn(bootstrap.run());
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 2 })
+        .with_mdns(false)
+        .with_ping()
+        .with_listen_addrs([])
+        .with_bootstrap([bootstrap_addr.with(Protocol::P2p(bootstrap_peer_id))])
+        .build()
+        .expect("worker builds");
+    let service = worker.service();
+    tokio::spawn(worker.run());
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let peers = service.peers().into_iter().collect::<Vec<_>>();
+            if let Some((_, info)) = peers.iter().find(|(peer, _)| *peer == bootstrap_peer_id) {
+                assert_eq!(info.best_block, 1);
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("connects to the bootstrap peer");
+}
+
+#[tokio::test]
+async fn concurrent_local_info_updates_are_not_lost() {
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .build()
+        .expect("worker builds");
+    let service = worker.service();
+    tokio::spawn(worker.run());
+
+    let updaters = (0..2)
+        .map(|_| {
+            let mut service = service.clone();
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    service.update_local_info(|info| info.best_block += 1);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for updater in updaters {
+        updater.join().expect("updater does not panic");
+    }
+
+    assert_eq!(service.local_info().best_block, 2000);
+}
+
+#[tokio::test]
+async fn local_info_update_survives_full_action_channel() {
+    let bootstrap_key = Keypair::generate_ed25519();
+    let bootstrap_peer_id = bootstrap_key.public().to_peer_id();
+    let bootstrap_addr = local_addr();
+
+    let bootstrap = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_keypair(bootstrap_key)
+        .with_mdns(false)
+        .with_listen_addrs([bootstrap_addr.clone()])
+        .build()
+        .expect("bootstrap worker builds");
+    let bootstrap_service = bootstrap.service();
+    tokio::spawn(bootstrap.run());
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_bootstrap([bootstrap_addr.with(Protocol::P2p(bootstrap_peer_id))])
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+
+    // Fill the action channel before the worker runs, then update the local
+    // info: the update must still reach the peers.
+    let offender = Keypair::generate_ed25519().public().to_peer_id();
+    for _ in 0..100 {
+        let _ = service
+            .report_peer(offender, PeerBehavior::Good, 1)
+            .now_or_never();
+    }
+    service.update_local_info(|info| info.best_block = 1);
+    tokio::spawn(worker.run());
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !bootstrap_service
+            .peers()
+            .into_iter()
+            .any(|(_, info)| info.best_block == 1)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("bootstrap peer sees the update");
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GetBlock(u64);
+
+impl Request for GetBlock {
+    type Response = Option<u64>;
+
+    const PROTOCOL_ID: &'static str = "/test/get_block/1";
+}
+
+#[tokio::test]
+async fn pending_requests_counted_until_timeout() {
+    let responder_key = Keypair::generate_ed25519();
+    let responder_peer_id = responder_key.public().to_peer_id();
+    let responder_addr = local_addr();
+
+    let responder = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(responder_key)
+        .with_mdns(false)
+        .with_listen_addrs([responder_addr.clone()])
+        .build()
+        .expect("responder worker builds");
+    let mut responder_service = responder.service();
+    tokio::spawn(responder.run());
+
+    // Receive requests, but never respond.
+    let (ready_sender, ready_receiver) = tokio::sync::oneshot::channel();
+    let (received_sender, mut received_receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let inbound = RequestService::<GetBlock>::listen(&mut responder_service)
+            .await
+            .expect("listen succeeds");
+        let _ = ready_sender.send(());
+
+        let mut inbound = Box::pin(inbound);
+        while let Some((channel, _)) = inbound.next().await {
+            let _ = received_sender.send(channel);
+        }
+    });
+    ready_receiver.await.expect("responder listens");
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 2 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_request_timeout(Duration::from_secs(2))
+        .with_bootstrap([responder_addr.with(Protocol::P2p(responder_peer_id))])
+        .build()
+        .expect("worker builds");
+    let service = worker.service();
+    tokio::spawn(worker.run());
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !service
+            .peers()
+            .into_iter()
+            .any(|(peer, _)| peer == responder_peer_id)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("connects to the responder");
+
+    let requests = (0..2)
+        .map(|number| {
+            let mut service = service.clone();
+            tokio::spawn(async move { service.request(responder_peer_id, GetBlock(number)).await })
+        })
+        .collect::<Vec<_>>();
+
+    let protocol_id = request_protocol_id::<GetBlock>();
+    assert_eq!(protocol_id, "/test/get_block/1");
+    let mut channels = Vec::new();
+    for _ in 0..2 {
+        channels.push(received_receiver.recv().await.expect("request is received"));
+    }
+    assert_eq!(service.pending_requests().get(&protocol_id), Some(&2));
+
+    for request in requests {
+        assert!(request.await.expect("request task does not panic").is_err());
+    }
+    assert!(service.pending_requests().is_empty());
+}
+
+#[tokio::test]
+async fn shutdown_cancels_pending_requests() {
+    let responder_key = Keypair::generate_ed25519();
+    let responder_peer_id = responder_key.public().to_peer_id();
+    let responder_addr = local_addr();
+
+    let responder = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(responder_key)
+        .with_mdns(false)
+        .with_listen_addrs([responder_addr.clone()])
+        .build()
+        .expect("responder worker builds");
+    let mut responder_service = responder.service();
+    tokio::spawn(responder.run());
+
+    // Receive the request, but never respond.
+    let (ready_sender, ready_receiver) = tokio::sync::oneshot::channel();
+    let (received_sender, mut received_receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let inbound = RequestService::<GetBlock>::listen(&mut responder_service)
+            .await
+            .expect("listen succeeds");
+        let _ = ready_sender.send(());
+
+        let mut inbound = Box::pin(inbound);
+        while let Some((channel, _)) = inbound.next().await {
+            let _ = received_sender.send(channel);
+        }
+    });
+    ready_receiver.await.expect("responder listens");
+
+    // The request would only time out after a minute.
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 2 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_request_timeout(Duration::from_secs(60))
+        .with_bootstrap([responder_addr.with(Protocol::P2p(responder_peer_id))])
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+    let (shutdown, shutdown_receiver) = futures::channel::oneshot::channel::<()>();
+    let worker = tokio::spawn(worker.run_until(async move {
+        let _ = shutdown_receiver.await;
+    }));
+
+    service
+        .wait_connected(responder_peer_id, Duration::from_secs(10))
+        .await
+        .expect("connects to the responder");
+    let request = {
+        let mut service = service.clone();
+        tokio::spawn(async move { service.request(responder_peer_id, GetBlock(1)).await })
+    };
+    let _channel = received_receiver.recv().await.expect("request is received");
+    assert_eq!(service.pending_requests().len(), 1);
+
+    shutdown.send(()).expect("worker runs");
+    let response = tokio::time::timeout(Duration::from_secs(5), request)
+        .await
+        .expect("request resolves promptly")
+        .expect("request task does not panic");
+    assert!(matches!(response, Err(blocknet_libp2p::Error::Shutdown)));
+    assert!(service.pending_requests().is_empty());
+    worker
+        .await
+        .expect("worker task does not panic")
+        .expect("worker stops cleanly");
+}
+
+#[tokio::test]
+async fn request_uses_common_codec() {
+    let responder_key = Keypair::generate_ed25519();
+    let responder_peer_id = responder_key.public().to_peer_id();
+    let responder_addr = local_addr();
+
+    let responder = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(responder_key)
+        .with_mdns(false)
+        .with_codecs([WireCodec::Json])
+        .with_listen_addrs([responder_addr.clone()])
+        .build()
+        .expect("responder worker builds");
+    let mut responder_service = responder.service();
+    tokio::spawn(responder.run());
+
+    let (ready_sender, ready_receiver) = tokio::sync::oneshot::channel();
+    let (codec_sender, codec_receiver) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let mut listener = responder_service.clone();
+        let inbound = RequestService::<GetBlock>::listen(&mut listener)
+            .await
+            .expect("listen succeeds");
+        let _ = ready_sender.send(());
+
+        let mut inbound = Box::pin(inbound);
+        if let Some((channel, event)) = inbound.next().await {
+            let _ = codec_sender.send(channel.codec());
+            let number = event.into_value().0;
+            RequestService::<GetBlock>::respond(&mut responder_service, channel, Some(number + 1))
+                .await
+                .expect("respond succeeds");
+        }
+    });
+    ready_receiver.await.expect("responder listens");
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 2 })
+        .with_mdns(false)
+        .with_codecs([WireCodec::Scale, WireCodec::Json])
+        .with_listen_addrs([])
+        .with_bootstrap([responder_addr.with(Protocol::P2p(responder_peer_id))])
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+    tokio::spawn(worker.run());
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !service
+            .peers()
+            .into_iter()
+            .any(|(peer, _)| peer == responder_peer_id)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("connects to the responder");
+
+    assert_eq!(
+        service.negotiated_codec(&responder_peer_id),
+        WireCodec::Json
+    );
+    let response = service
+        .request(responder_peer_id, GetBlock(41))
+        .await
+        .expect("request succeeds");
+    assert_eq!(response, Some(42));
+    assert_eq!(
+        codec_receiver.await.expect("request is received"),
+        WireCodec::Json
+    );
+}
+
+#[tokio::test]
+async fn with_codec_prefers_codec_for_requests() {
+    let responder_key = Keypair::generate_ed25519();
+    let responder_peer_id = responder_key.public().to_peer_id();
+    let responder_addr = local_addr();
+
+    let responder = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(responder_key)
+        .with_mdns(false)
+        .with_codec(WireCodec::Scale)
+        .with_listen_addrs([responder_addr.clone()])
+        .build()
+        .expect("responder worker builds");
+    let mut responder_service = responder.service();
+    tokio::spawn(responder.run());
+
+    let (ready_sender, ready_receiver) = tokio::sync::oneshot::channel();
+    let (codec_sender, codec_receiver) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let mut listener = responder_service.clone();
+        let inbound = RequestService::<GetBlock>::listen(&mut listener)
+            .await
+            .expect("listen succeeds");
+        let _ = ready_sender.send(());
+
+        let mut inbound = Box::pin(inbound);
+        if let Some((channel, event)) = inbound.next().await {
+            let _ = codec_sender.send(channel.codec());
+            let number = event.into_value().0;
+            RequestService::<GetBlock>::respond(&mut responder_service, channel, Some(number + 1))
+                .await
+                .expect("respond succeeds");
+        }
+    });
+    ready_receiver.await.expect("responder listens");
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 2 })
+        .with_mdns(false)
+        .with_codec(WireCodec::Scale)
+        .with_listen_addrs([])
+        .with_bootstrap([responder_addr.with(Protocol::P2p(responder_peer_id))])
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+    tokio::spawn(worker.run());
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !service
+            .peers()
+            .into_iter()
+            .any(|(peer, _)| peer == responder_peer_id)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("connects to the responder");
+
+    assert_eq!(
+        service.negotiated_codec(&responder_peer_id),
+        WireCodec::Scale
+    );
+    let response = service
+        .request(responder_peer_id, GetBlock(41))
+        .await
+        .expect("request succeeds");
+    assert_eq!(response, Some(42));
+    assert_eq!(
+        codec_receiver.await.expect("request is received"),
+        WireCodec::Scale
+    );
+}
+
+struct Libp2pBackend;
+
+impl conformance::Backend for Libp2pBackend {
+    type Service = blocknet_libp2p::Service<conformance::PeerInfo>;
+
+    async fn pair(
+        &mut self,
+        first: conformance::PeerInfo,
+        second: conformance::PeerInfo,
+    ) -> (Self::Service, Self::Service) {
+        let second_key = Keypair::generate_ed25519();
+        let second_peer_id = second_key.public().to_peer_id();
+        let second_addr = local_addr();
+
+        let second = WorkerBuilder::new(second)
+            .with_keypair(second_key)
+            .with_mdns(false)
+            .with_listen_addrs([second_addr.clone()])
+            .build()
+            .expect("worker builds");
+        let second_service = second.service();
+        tokio::spawn(second.run());
+
+        let first = WorkerBuilder::new(first)
+            .with_mdns(false)
+            .with_listen_addrs([])
+            .with_bootstrap([second_addr.with(Protocol::P2p(second_peer_id))])
+            .build()
+            .expect("worker builds");
+        let first_service = first.service();
+        tokio::spawn(first.run());
+
+        (first_service, second_service)
+    }
+}
+
+#[tokio::test]
+async fn conformance_peers_reported() {
+    conformance::test_peers_reported(&mut Libp2pBackend).await;
+}
+
+#[tokio::test]
+async fn conformance_broadcast_roundtrip() {
+    conformance::test_broadcast_roundtrip(&mut Libp2pBackend).await;
+}
+
+#[tokio::test]
+async fn conformance_broadcast_unsubscribe() {
+    conformance::test_broadcast_unsubscribe(&mut Libp2pBackend).await;
+}
+
+#[tokio::test]
+async fn conformance_request_roundtrip() {
+    conformance::test_request_roundtrip(&mut Libp2pBackend).await;
+}
+
+#[tokio::test]
+async fn peer_observes_rising_best_block() {
+    let observer_key = Keypair::generate_ed25519();
+    let observer_peer_id = observer_key.public().to_peer_id();
+    let observer_addr = local_addr();
+
+    let observer = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_keypair(observer_key)
+        .with_mdns(false)
+        .with_listen_addrs([observer_addr.clone()])
+        .build()
+        .expect("observer worker builds");
+    let observer_service = observer.service();
+    tokio::spawn(observer.run());
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_bootstrap([observer_addr.with(Protocol::P2p(observer_peer_id))])
+        .build()
+        .expect("worker builds");
+    let service = worker.service();
+    tokio::spawn(worker.run());
+
+    // Blocks imported by the chain are notified as new best block numbers.
+    let (import_sender, import_notifications) = futures::channel::mpsc::unbounded();
+    let mut follow_service = service.clone();
+    tokio::spawn(async move {
+        follow_service
+            .follow_local_info(import_notifications, |info, best_block| {
+                info.best_block = best_block
+            })
+            .await
+    });
+
+    let observed_best_block = || {
+        observer_service
+            .peers()
+            .into_iter()
+            .next()
+            .map(|(_, info)| info.best_block)
+    };
+
+    let mut observed = Vec::new();
+    tokio::time::timeout(Duration::from_secs(20), async {
+        for best_block in 2..=4 {
+            // Wait for the previous best block to be observed before importing
+            // the next one.
+            while observed_best_block() != Some(best_block - 1) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            observed.push(best_block - 1);
+
+            import_sender
+                .unbounded_send(best_block)
+                .expect("follower is running");
+        }
+
+        while observed_best_block() != Some(4) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        observed.push(4);
+    })
+    .await
+    .expect("observer sees every best block");
+
+    assert_eq!(observed, vec![1, 2, 3, 4]);
+    assert_eq!(service.local_info().best_block, 4);
+}
+
+#[tokio::test]
+async fn info_push_keeps_advertised_codecs() {
+    let observer_key = Keypair::generate_ed25519();
+    let observer_peer_id = observer_key.public().to_peer_id();
+    let observer_addr = local_addr();
+
+    let observer = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_keypair(observer_key)
+        .with_mdns(false)
+        .with_codecs([WireCodec::Scale, WireCodec::Json])
+        .with_listen_addrs([observer_addr.clone()])
+        .build()
+        .expect("observer worker builds");
+    let observer_service = observer.service();
+    tokio::spawn(observer.run());
+
+    let worker_key = Keypair::generate_ed25519();
+    let worker_peer_id = worker_key.public().to_peer_id();
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(worker_key)
+        .with_mdns(false)
+        .with_codecs([WireCodec::Scale, WireCodec::Json])
+        .with_listen_addrs([])
+        .with_bootstrap([observer_addr.with(Protocol::P2p(observer_peer_id))])
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+    tokio::spawn(worker.run());
+
+    let observed_best_block = || {
+        observer_service
+            .peers()
+            .into_iter()
+            .find(|(peer, _)| *peer == worker_peer_id)
+            .map(|(_, info)| info.best_block)
+    };
+    let wait_for_best_block = |best_block| async move {
+        while observed_best_block() != Some(best_block) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(10), wait_for_best_block(1))
+        .await
+        .expect("observer identifies the worker");
+    assert_eq!(
+        observer_service.negotiated_codec(&worker_peer_id),
+        WireCodec::Scale
+    );
+
+    // Only the info changed, so the push leaves the codecs out, and the
+    // observer keeps those it identified.
+    service.set_local_info(PeerInfo { best_block: 2 });
+    tokio::time::timeout(Duration::from_secs(10), wait_for_best_block(2))
+        .await
+        .expect("observer receives the push");
+    assert_eq!(
+        observer_service.negotiated_codec(&worker_peer_id),
+        WireCodec::Scale
+    );
+}
+
+/// Connect a v0.2 node to a v0.1 node under the policy, and get whether they
+/// stay connected.
+async fn versions_stay_connected(policy: VersionPolicy) -> bool {
+    let old_key = Keypair::generate_ed25519();
+    let old_peer_id = old_key.public().to_peer_id();
+    let old_addr = local_addr();
+
+    let old = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(old_key)
+        .with_mdns(false)
+        .with_protocol_version(0, 1)
+        .with_version_policy(policy)
+        .with_listen_addrs([old_addr.clone()])
+        .build()
+        .expect("old worker builds");
+    let old_service = old.service();
+    tokio::spawn(old.run());
+
+    let new = WorkerBuilder::new(PeerInfo { best_block: 2 })
+        .with_mdns(false)
+        .with_protocol_version(0, 2)
+        .with_version_policy(policy)
+        .with_listen_addrs([])
+        .with_bootstrap([old_addr.with(Protocol::P2p(old_peer_id))])
+        .build()
+        .expect("new worker builds");
+    let new_service = new.service();
+    tokio::spawn(new.run());
+
+    // Identify completes well within this on localhost.
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let new_sees_old = new_service
+        .peers()
+        .into_iter()
+        .any(|(peer, _)| peer == old_peer_id);
+    let old_sees_new = old_service.peers().into_iter().next().is_some();
+    assert_eq!(new_sees_old, old_sees_new);
+
+    new_sees_old
+}
+
+#[tokio::test]
+async fn incompatible_versions_disconnect() {
+    assert!(!versions_stay_connected(VersionPolicy::Exact).await);
+}
+
+#[tokio::test]
+async fn same_major_versions_stay_connected() {
+    assert!(versions_stay_connected(VersionPolicy::SameMajor).await);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Announce(u64);
+
+impl Message for Announce {
+    type Topic = &'static str;
+
+    fn topic(&self) -> &'static str {
+        "announce"
+    }
+}
+
+#[tokio::test]
+async fn try_broadcast_sheds_load_when_saturated() {
+    // The worker is not run, so nothing drains the action channel.
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+    assert_eq!(service.action_channel_pressure(), 0.0);
+
+    let mut sent = 0;
+    let err = loop {
+        match service.try_broadcast(Announce(sent)) {
+            Ok(()) => sent += 1,
+            Err(err) => break err,
+        }
+        assert!(sent < 1000, "channel never saturates");
+    };
+
+    assert!(matches!(err, blocknet_libp2p::Error::WouldBlock));
+    assert!(sent > 0);
+    assert!(service.action_channel_pressure() >= 0.9);
+    drop(worker);
+}
+
+/// Broadcast from the service until the listener receives the message.
+async fn broadcast_until_received(
+    service: &mut blocknet_libp2p::Service<PeerInfo>,
+    listener: &mut (impl futures::Stream<Item = blocknet_libp2p::Event<Announce>> + Unpin),
+    value: u64,
+) {
+    tokio::time::timeout(Duration::from_secs(20), async {
+        loop {
+            service
+                .broadcast(Announce(value))
+                .await
+                .expect("broadcast succeeds");
+            let received = tokio::time::timeout(Duration::from_millis(200), async {
+                // Skip repeats of earlier broadcasts.
+                while let Some(event) = listener.next().await {
+                    if event.value().0 == value {
+                        return true;
+                    }
+                }
+                false
+            })
+            .await;
+            if received == Ok(true) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("listener receives the broadcast")
+}
+
+#[tokio::test]
+async fn listener_resumes_after_peers_reconnect() {
+    let listener_key = Keypair::generate_ed25519();
+    let listener_peer_id = listener_key.public().to_peer_id();
+    let listener_addr = local_addr();
+
+    let listener_worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(listener_key)
+        .with_mdns(false)
+        .with_listen_addrs([listener_addr.clone()])
+        .build()
+        .expect("listener worker builds");
+    let listener_service = listener_worker.service();
+    tokio::spawn(listener_worker.run());
+    let mut listening = listener_service.clone();
+    let mut listener = Box::pin(
+        BroadcastService::<Announce>::listen(&mut listening, "announce")
+            .await
+            .expect("listen succeeds"),
+    );
+
+    let bootstrap = listener_addr.with(Protocol::P2p(listener_peer_id));
+    let sender_key = Keypair::generate_ed25519();
+    let sender_peer_id = sender_key.public().to_peer_id();
+    let sender_worker = |best_block| {
+        WorkerBuilder::new(PeerInfo { best_block })
+            .with_keypair(sender_key.clone())
+            .with_mdns(false)
+            .with_listen_addrs([])
+            .with_bootstrap([bootstrap.clone()])
+            .build()
+            .expect("sender worker builds")
+    };
+
+    let worker = sender_worker(2);
+    let mut sender = worker.service();
+    let running = tokio::spawn(worker.run());
+    let mut sender_listening = sender.clone();
+    let _sender_listener = BroadcastService::<Announce>::listen(&mut sender_listening, "announce")
+        .await
+        .expect("listen succeeds");
+    broadcast_until_received(&mut sender, &mut listener, 1).await;
+
+    // The only peer goes away, and the listener loses the mesh.
+    running.abort();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while listener_service
+            .peers()
+            .into_iter()
+            .any(|(peer_id, _)| peer_id == sender_peer_id)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("sender disconnects");
+
+    let worker = sender_worker(3);
+    let mut sender = worker.service();
+    tokio::spawn(worker.run());
+    let mut sender_listening = sender.clone();
+    let _sender_listener = BroadcastService::<Announce>::listen(&mut sender_listening, "announce")
+        .await
+        .expect("listen succeeds");
+    broadcast_until_received(&mut sender, &mut listener, 2).await;
+}
+
+#[test]
+fn errors_map_to_distinct_wire_kinds() {
+    use blocknet_libp2p::{AnyMessage, Error, WireError};
+    use futures::channel::{mpsc, oneshot};
+    use libp2p::{gossipsub, kad, request_response, TransportError};
+    use std::collections::HashSet;
+
+    let (mut sender, receiver) = mpsc::channel::<()>(0);
+    drop(receiver);
+    let send_error = sender.try_send(()).unwrap_err().into_send_error();
+    let (canceled_sender, canceled) = oneshot::channel::<()>();
+    drop(canceled_sender);
+
+    let errors = vec![
+        Error::Codec("invalid json".to_string()),
+        Error::ChannelSend(send_error),
+        Error::GossipsubSubscription(gossipsub::SubscriptionError::NotAllowed),
+        Error::GossipsubPublish(gossipsub::PublishError::Duplicate),
+        Error::Noise(libp2p::noise::Error::BadSignature),
+        Error::Multiaddr("invalid".parse::<Multiaddr>().unwrap_err()),
+        Error::Transport(TransportError::MultiaddrNotSupported(local_addr())),
+        Error::Build(Box::new(std::io::Error::other("no transport"))),
+        Error::OutboundRequest(request_response::OutboundFailure::Timeout),
+        Error::RequestCanceled(futures::executor::block_on(canceled).unwrap_err()),
+        Error::ResponseChannelClosed,
+        Error::WouldBlock,
+        Error::BroadcastDropped,
+        Error::InsufficientPeers,
+        Error::ConnectTimeout,
+        Error::TooManySubscriptions,
+        Error::Shutdown,
+        Error::WorkerDisconnected,
+        Error::RecordStore(kad::store::Error::MaxRecords),
+        Error::UnknownOriginBroadcast(AnyMessage {
+            topic: "announce".to_string(),
+            sequence: None,
+            codec: WireCodec::Json.tag(),
+            serialized: Vec::new(),
+        }),
+    ];
+
+    let mut kinds = HashSet::new();
+    for error in &errors {
+        let wire = error.to_wire();
+        assert!(kinds.insert(wire.kind), "{:?} shares a wire kind", error);
+        assert!(wire.message.starts_with(&error.to_string()));
+
+        let serialized = serde_json::to_string(&wire).expect("serializes");
+        let deserialized: WireError = serde_json::from_str(&serialized).expect("deserializes");
+        assert_eq!(deserialized, wire);
+    }
+    assert_eq!(kinds.len(), errors.len());
+
+    let wire = Error::Build(Box::new(std::io::Error::other("no transport"))).to_wire();
+    assert_eq!(
+        serde_json::to_value(wire).expect("serializes"),
+        serde_json::json!({ "kind": "build", "message": "Build error: no transport" })
+    );
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BestBlock(u64);
+
+impl Message for BestBlock {
+    type Topic = &'static str;
+
+    const KEEP_LATEST: bool = true;
+
+    fn topic(&self) -> &'static str {
+        "best_block"
+    }
+}
+
+#[tokio::test]
+async fn keep_latest_listener_only_sees_final_update() {
+    let listener_key = Keypair::generate_ed25519();
+    let listener_peer_id = listener_key.public().to_peer_id();
+    let listener_addr = local_addr();
+
+    let listener_worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(listener_key)
+        .with_mdns(false)
+        .with_listen_addrs([listener_addr.clone()])
+        .build()
+        .expect("listener worker builds");
+    let mut listening = listener_worker.service();
+    tokio::spawn(listener_worker.run());
+    let mut listener = Box::pin(
+        BroadcastService::<BestBlock>::listen(&mut listening, "best_block")
+            .await
+            .expect("listen succeeds"),
+    );
+
+    // Sequenced, as gossipsub may deliver the updates out of order.
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 2 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_bootstrap([listener_addr.with(Protocol::P2p(listener_peer_id))])
+        .with_sequenced_topic("best_block")
+        .build()
+        .expect("worker builds");
+    let mut sender = worker.service();
+    tokio::spawn(worker.run());
+    let mut sender_listening = sender.clone();
+    let _sender_listener =
+        BroadcastService::<BestBlock>::listen(&mut sender_listening, "best_block")
+            .await
+            .expect("listen succeeds");
+
+    // Wait for the mesh to form.
+    tokio::time::timeout(Duration::from_secs(20), async {
+        loop {
+            sender
+                .broadcast(BestBlock(0))
+                .await
+                .expect("broadcast succeeds");
+            let received = tokio::time::timeout(Duration::from_millis(200), listener.next()).await;
+            if let Ok(Some(_)) = received {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("listener receives the broadcast");
+
+    // The listener is slow, and does not read while the updates arrive.
+    for best_block in 1..=10 {
+        sender
+            .broadcast(BestBlock(best_block))
+            .await
+            .expect("broadcast succeeds");
+    }
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let event = listener.next().await.expect("listener receives");
+    assert_eq!(event.value().0, 10);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), listener.next())
+            .await
+            .is_err(),
+        "superseded updates are dropped"
+    );
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Vote(u64);
+
+impl Message for Vote {
+    type Topic = &'static str;
+
+    fn topic(&self) -> &'static str {
+        "vote"
+    }
+}
+
+/// Listen for the votes of a worker not running, handling the listen with an
+/// empty replay.
+async fn listen_votes<'a>(
+    worker: &mut blocknet_libp2p::Worker<PeerInfo>,
+    listener: &'a mut blocknet_libp2p::Service<PeerInfo>,
+) -> impl futures::Stream<Item = blocknet_libp2p::Event<Vote>> + 'a {
+    let (votes, replayed) = futures::join!(
+        BroadcastService::<Vote>::listen(listener, "vote"),
+        worker.replay(EventLog {
+            entries: Vec::new()
+        }),
+    );
+    replayed.expect("replay succeeds");
+    votes.expect("listen succeeds")
+}
+
+/// Start a worker bootstrapping from the address.
+fn bootstrapped_service(bootstrap: Multiaddr) -> blocknet_libp2p::Service<PeerInfo> {
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_bootstrap([bootstrap])
+        .build()
+        .expect("worker builds");
+    let service = worker.service();
+    tokio::spawn(worker.run());
+    service
+}
+
+/// Wait until the service sees the number of peers on the vote topic.
+async fn wait_for_vote_peers(service: &blocknet_libp2p::Service<PeerInfo>, peers: usize) {
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while service.topic_peers("vote") < peers {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("peers subscribe")
+}
+
+#[tokio::test]
+async fn mesh_peers_include_each_other_after_heartbeat() {
+    let first_key = Keypair::generate_ed25519();
+    let first_peer_id = first_key.public().to_peer_id();
+    let first_addr = local_addr();
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(first_key)
+        .with_mdns(false)
+        .with_listen_addrs([first_addr.clone()])
+        .build()
+        .expect("worker builds");
+    let mut first = worker.service();
+    tokio::spawn(worker.run());
+    let second_key = Keypair::generate_ed25519();
+    let second_peer_id = second_key.public().to_peer_id();
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(second_key)
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_bootstrap([first_addr.with(Protocol::P2p(first_peer_id))])
+        .build()
+        .expect("worker builds");
+    let mut second = worker.service();
+    tokio::spawn(worker.run());
+    assert!(first.mesh_peers("vote").await.unwrap().is_empty());
+
+    let (mut first_listener, mut second_listener) = (first.clone(), second.clone());
+    let _first_votes = BroadcastService::<Vote>::listen(&mut first_listener, "vote")
+        .await
+        .expect("listen succeeds");
+    let _second_votes = BroadcastService::<Vote>::listen(&mut second_listener, "vote")
+        .await
+        .expect("listen succeeds");
+
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while first.mesh_peers("vote").await.unwrap() != [second_peer_id]
+            || second.mesh_peers("vote").await.unwrap() != [first_peer_id]
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("peers join the mesh");
+    assert!(first.mesh_peers("other").await.unwrap().is_empty());
+}
+
+#[cfg(feature = "testnet")]
+#[tokio::test]
+async fn testnet_mesh_delivers_broadcast() {
+    let net = TestNet::new(
+        Topology::FullMesh,
+        (0..3).map(|best_block| PeerInfo { best_block }),
+    )
+    .expect("net builds");
+    net.run_until_synced(Duration::from_secs(20))
+        .await
+        .expect("net syncs");
+    for (node, service) in net.services().iter().enumerate() {
+        let mut best_blocks = service
+            .peers()
+            .into_iter()
+            .map(|(_, info)| info.best_block)
+            .collect::<Vec<_>>();
+        best_blocks.sort();
+        assert_eq!(
+            best_blocks,
+            (0..3)
+                .filter(|other| *other != node as u64)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    let mut listeners = [net.service(1), net.service(2)];
+    let mut receivers = Vec::new();
+    for listener in &mut listeners {
+        receivers.push(Box::pin(
+            BroadcastService::<Vote>::listen(listener, "vote")
+                .await
+                .expect("listen succeeds"),
+        ));
+    }
+    net.service(0)
+        .broadcast_when_ready(Vote(1), 2, Duration::from_secs(20))
+        .await
+        .expect("broadcast succeeds");
+    for mut receiver in receivers {
+        let event = tokio::time::timeout(Duration::from_secs(20), receiver.next())
+            .await
+            .expect("vote is delivered in time")
+            .expect("vote is delivered");
+        assert_eq!(*event.origin(), net.peer_ids()[0]);
+        assert_eq!(event.value().0, 1);
+    }
+
+    let mut service = net.service(0);
+    net.shutdown().await;
+    assert!(service.mesh_peers("vote").await.is_err());
+}
+
+/// A message on the logical topic of its core.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CoreMessage {
+    core: u32,
+}
+
+impl Message for CoreMessage {
+    type Topic = String;
+
+    fn topic(&self) -> String {
+        format!("core/{}", self.core)
+    }
+}
+
+#[cfg(feature = "testnet")]
+#[tokio::test]
+async fn sharded_topics_route_to_logical_listeners() {
+    let worker = || {
+        WorkerBuilder::new(PeerInfo { best_block: 0 })
+            .with_topic_shards(2)
+            .with_max_subscriptions(2)
+    };
+    let net = TestNet::builder(Topology::FullMesh)
+        .with_worker(worker())
+        .with_worker(worker())
+        .build()
+        .expect("net builds");
+    net.run_until_synced(Duration::from_secs(20))
+        .await
+        .expect("net syncs");
+
+    // More logical topics than the subscription cap share the two shards.
+    let cores = 0..6;
+    let mut listeners = vec![net.service(1); cores.len()];
+    let mut receivers = Vec::new();
+    for (core, listener) in listeners.iter_mut().enumerate() {
+        receivers.push(Box::pin(
+            BroadcastService::<CoreMessage>::listen(listener, format!("core/{core}"))
+                .await
+                .expect("listen succeeds"),
+        ));
+    }
+
+    let mut sender = net.service(0);
+    for core in cores {
+        sender
+            .broadcast_when_ready(CoreMessage { core }, 1, Duration::from_secs(20))
+            .await
+            .expect("broadcast succeeds");
+    }
+    for (core, receiver) in receivers.iter_mut().enumerate() {
+        let event = tokio::time::timeout(Duration::from_secs(20), receiver.next())
+            .await
+            .expect("message is delivered in time")
+            .expect("message is delivered");
+        assert_eq!(event.value().core, core as u32);
+    }
+    for receiver in &mut receivers {
+        assert!(
+            tokio::time::timeout(Duration::from_millis(500), receiver.next())
+                .await
+                .is_err(),
+            "only the logical topic is delivered"
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscription_cap_fails_extra_listens() {
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_max_subscriptions(1)
+        .build()
+        .expect("worker builds");
+    let (mut vote_listener, mut core_listener) = (worker.service(), worker.service());
+    tokio::spawn(worker.run());
+
+    let _votes = BroadcastService::<Vote>::listen(&mut vote_listener, "vote")
+        .await
+        .expect("listen succeeds");
+    let result =
+        BroadcastService::<CoreMessage>::listen(&mut core_listener, "core/0".to_string()).await;
+    assert!(matches!(
+        result.map(|_| ()),
+        Err(blocknet_libp2p::Error::TooManySubscriptions)
+    ));
+    // Listening on a topic already subscribed to is within the cap.
+    let _more_votes = BroadcastService::<Vote>::listen(&mut core_listener, "vote")
+        .await
+        .expect("listen succeeds");
+}
+
+#[cfg(feature = "testnet")]
+#[tokio::test]
+async fn repeated_bad_behavior_disconnects_peer() {
+    let net = TestNet::builder(Topology::Line)
+        .with_worker(
+            WorkerBuilder::new(PeerInfo { best_block: 0 })
+                .with_peer_bans(-10, Duration::from_secs(60)),
+        )
+        .with_node(PeerInfo { best_block: 1 })
+        .build()
+        .expect("net builds");
+    net.run_until_synced(Duration::from_secs(20))
+        .await
+        .expect("net syncs");
+    let offender = net.peer_ids()[1];
+    let is_connected = |net: &TestNet<PeerInfo>| {
+        net.service(0)
+            .peers()
+            .into_iter()
+            .any(|(peer_id, _)| peer_id == offender)
+    };
+
+    // Good behavior offsets some of the bad, until the reputation falls
+    // below the threshold.
+    let mut reporter = net.service(0);
+    let reports = [
+        (PeerBehavior::BadBlock, 2),
+        (PeerBehavior::Good, 2),
+        (PeerBehavior::BadMessage, 4),
+        (PeerBehavior::BadBlock, 2),
+    ];
+    for (behavior, weight) in reports {
+        reporter
+            .report_peer(offender, behavior, weight)
+            .await
+            .expect("report is sent");
+    }
+    // Reports are handled in order, so the peer is still connected at -10.
+    reporter
+        .wait_connected(offender, Duration::from_secs(5))
+        .await
+        .expect("peer stays connected");
+    assert!(is_connected(&net));
+
+    reporter
+        .report_peer(offender, PeerBehavior::BadMessage, 1)
+        .await
+        .expect("report is sent");
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while is_connected(&net) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("peer is disconnected in time");
+}
+
+#[tokio::test]
+async fn broadcast_when_ready_waits_for_min_peers() {
+    let sender_key = Keypair::generate_ed25519();
+    let sender_peer_id = sender_key.public().to_peer_id();
+    let sender_addr = local_addr();
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(sender_key)
+        .with_mdns(false)
+        .with_listen_addrs([sender_addr.clone()])
+        .build()
+        .expect("worker builds");
+    let mut sender = worker.service();
+    tokio::spawn(worker.run());
+    let bootstrap = sender_addr.with(Protocol::P2p(sender_peer_id));
+
+    let mut first_service = bootstrapped_service(bootstrap.clone());
+    let mut first = Box::pin(
+        BroadcastService::<Vote>::listen(&mut first_service, "vote")
+            .await
+            .expect("listen succeeds"),
+    );
+    wait_for_vote_peers(&sender, 1).await;
+
+    let err = sender
+        .broadcast_when_ready(Vote(0), 2, Duration::from_millis(200))
+        .await
+        .expect_err("a single peer is not enough");
+    assert!(matches!(err, blocknet_libp2p::Error::InsufficientPeers));
+
+    let mut broadcasting = sender.clone();
+    let broadcast = tokio::spawn(async move {
+        broadcasting
+            .broadcast_when_ready(Vote(1), 2, Duration::from_secs(20))
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!broadcast.is_finished(), "waits for the second peer");
+
+    let mut second_service = bootstrapped_service(bootstrap);
+    let mut second = Box::pin(
+        BroadcastService::<Vote>::listen(&mut second_service, "vote")
+            .await
+            .expect("listen succeeds"),
+    );
+    broadcast
+        .await
+        .expect("task completes")
+        .expect("broadcast succeeds once two peers subscribed");
+    assert!(sender.topic_peers("vote") >= 2);
+
+    // Both peers are now subscribed, but may not be grafted in the mesh yet.
+    let received = tokio::time::timeout(Duration::from_secs(20), async {
+        loop {
+            if let Ok(Some(event)) =
+                tokio::time::timeout(Duration::from_millis(200), second.next()).await
+            {
+                return event.value().0;
+            }
+            sender.broadcast(Vote(2)).await.expect("broadcast succeeds");
+        }
+    })
+    .await
+    .expect("second peer receives votes");
+    assert!(received >= 1);
+    assert!(first.next().await.is_some());
+}
+
+#[tokio::test]
+async fn peers_filtered_by_advertised_protocol() {
+    let central_key = Keypair::generate_ed25519();
+    let central_peer_id = central_key.public().to_peer_id();
+    let central_addr = local_addr();
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_keypair(central_key)
+        .with_mdns(false)
+        .with_listen_addrs([central_addr.clone()])
+        .build()
+        .expect("worker builds");
+    let central = worker.service();
+    tokio::spawn(worker.run());
+    let bootstrap = central_addr.with(Protocol::P2p(central_peer_id));
+
+    // Only the first peer supports ping.
+    for (best_block, ping) in [(1, true), (2, false)] {
+        let mut builder = WorkerBuilder::new(PeerInfo { best_block })
+            .with_mdns(false)
+            .with_listen_addrs([])
+            .with_bootstrap([bootstrap.clone()]);
+        if ping {
+            builder = builder.with_ping();
+        }
+        let worker = builder.build().expect("worker builds");
+        tokio::spawn(worker.run());
+    }
+
+    let ping = libp2p::StreamProtocol::new("/ipfs/ping/1.0.0");
+    let identify = libp2p::StreamProtocol::new("/ipfs/id/1.0.0");
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while central.peers_supporting(&identify).len() < 2 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("peers are identified");
+
+    let supporting = central.peers_supporting(&ping);
+    assert_eq!(supporting.len(), 1);
+    assert_eq!(supporting[0].1.best_block, 1);
+    assert!(central
+        .peers_supporting(&libp2p::StreamProtocol::new("/unknown/1.0.0"))
+        .is_empty());
+}
+
+#[tokio::test]
+async fn falls_back_to_tcp_when_quic_unavailable() {
+    // Holding the UDP port makes listening over QUIC fail.
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").expect("bind succeeds");
+    let port = socket.local_addr().expect("has local addr").port();
+    let quic_addr: Multiaddr = format!("/ip4/127.0.0.1/udp/{}/quic-v1", port)
+        .parse()
+        .expect("address is valid");
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([quic_addr.clone(), local_addr()])
+        .build()
+        .expect("worker builds over TCP");
+    assert_eq!(
+        worker.service().active_transports(),
+        vec![TransportKind::Tcp]
+    );
+
+    // An in-process listener is enough too.
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_memory_transport()
+        .with_listen_addrs([
+            quic_addr.clone(),
+            Multiaddr::empty().with(Protocol::Memory(0)),
+        ])
+        .build()
+        .expect("worker builds in process");
+    assert_eq!(
+        worker.service().active_transports(),
+        vec![TransportKind::Memory]
+    );
+
+    // Without another transport, the QUIC failure is an error.
+    let result = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([quic_addr])
+        .build();
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn wait_connected_resolves_on_connection() {
+    let listener_key = Keypair::generate_ed25519();
+    let listener_peer_id = listener_key.public().to_peer_id();
+    let listener_addr = local_addr();
+
+    let listener = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_keypair(listener_key)
+        .with_mdns(false)
+        .with_listen_addrs([listener_addr.clone()])
+        .build()
+        .expect("listener worker builds");
+    tokio::spawn(listener.run());
+
+    let dialer = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_bootstrap([listener_addr.with(Protocol::P2p(listener_peer_id))])
+        .build()
+        .expect("dialer worker builds");
+    let mut service = dialer.service();
+    tokio::spawn(dialer.run());
+
+    service
+        .wait_connected(listener_peer_id, Duration::from_secs(20))
+        .await
+        .expect("listener connects");
+    // Already connected, so it resolves without waiting for a new connection.
+    service
+        .wait_connected(listener_peer_id, Duration::from_secs(1))
+        .await
+        .expect("listener is connected");
+
+    let unknown = Keypair::generate_ed25519().public().to_peer_id();
+    let err = service
+        .wait_connected(unknown, Duration::from_millis(100))
+        .await
+        .expect_err("unknown peer never connects");
+    assert!(matches!(err, blocknet_libp2p::Error::ConnectTimeout));
+}
+
+#[tokio::test]
+async fn replay_reconstructs_recorded_session() {
+    let path = std::env::temp_dir().join(format!("blocknet-events-{}.jsonl", std::process::id()));
+    let recorded_key = Keypair::generate_ed25519();
+    let recorded_peer_id = recorded_key.public().to_peer_id();
+    let recorded_addr = local_addr();
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_keypair(recorded_key)
+        .with_mdns(false)
+        .with_listen_addrs([recorded_addr.clone()])
+        .with_event_recorder(EventRecorder::create(&path).expect("log file is created"))
+        .build()
+        .expect("worker builds");
+    let recorded = worker.service();
+    let handle = tokio::spawn(worker.run());
+    let mut listener = recorded.clone();
+    let mut votes = Box::pin(
+        BroadcastService::<Vote>::listen(&mut listener, "vote")
+            .await
+            .expect("listen succeeds"),
+    );
+
+    let mut sender = bootstrapped_service(recorded_addr.with(Protocol::P2p(recorded_peer_id)));
+    let delivered = tokio::time::timeout(Duration::from_secs(20), async {
+        let mut delivered = Vec::new();
+        let mut vote = 0;
+        while delivered.len() < 3 {
+            vote += 1;
+            sender
+                .broadcast_when_ready(Vote(vote), 1, Duration::from_secs(20))
+                .await
+                .expect("broadcast succeeds");
+            if let Ok(Some(event)) =
+                tokio::time::timeout(Duration::from_millis(200), votes.next()).await
+            {
+                delivered.push((*event.origin(), event.value().0));
+            }
+        }
+        while recorded.peers().into_iter().next().is_none() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        delivered
+    })
+    .await
+    .expect("votes are delivered");
+    let peers = recorded
+        .peers()
+        .into_iter()
+        .map(|(peer_id, info)| (peer_id, info.best_block))
+        .collect::<Vec<_>>();
+
+    // Dropping the worker waits for the recorder to write the log.
+    handle.abort();
+    let _ = handle.await;
+    let log = EventLog::<PeerInfo>::read(std::fs::File::open(&path).expect("log file exists"))
+        .expect("log is valid");
+    let _ = std::fs::remove_file(&path);
+
+    let mut worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .build()
+        .expect("worker builds");
+    let replayed = worker.service();
+    let mut listener = replayed.clone();
+    let mut replayed_votes = Box::pin(listen_votes(&mut worker, &mut listener).await);
+    worker.replay(log).await.expect("replay succeeds");
+
+    assert_eq!(
+        replayed
+            .peers()
+            .into_iter()
+            .map(|(peer_id, info)| (peer_id, info.best_block))
+            .collect::<Vec<_>>(),
+        peers
+    );
+    // Votes still in flight at the end of the recording may follow.
+    for (origin, vote) in delivered {
+        let event = replayed_votes.next().await.expect("vote is replayed");
+        assert_eq!((*event.origin(), event.value().0), (origin, vote));
+    }
+}
+
+#[tokio::test]
+async fn ordered_topic_delivers_in_sequence_and_reports_gaps() {
+    let mut worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_ordered_topic("vote", Duration::from_millis(200))
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+    let mut listener = service.clone();
+    let mut votes = Box::pin(listen_votes(&mut worker, &mut listener).await);
+    let mut gaps = Box::pin(service.listen_gaps("vote").await.expect("listen succeeds"));
+
+    // Replayed arrivals, so that their order is deterministic.
+    let origin = libp2p::PeerId::random();
+    let entries = [1, 3, 2, 4, 6, 7]
+        .into_iter()
+        .map(|sequence| blocknet_libp2p::LogEntry {
+            timestamp_ms: 0,
+            event: RecordedEvent::Message {
+                source: Some(origin),
+                topic: libp2p::gossipsub::IdentTopic::new("vote")
+                    .hash()
+                    .into_string(),
+                data: blocknet_libp2p::AnyMessage {
+                    topic: "vote".to_string(),
+                    sequence: Some(sequence),
+                    codec: WireCodec::Json.tag(),
+                    serialized: serde_json::to_vec(&Vote(sequence)).unwrap(),
+                }
+                .encode()
+                .unwrap(),
+            },
+        })
+        .collect();
+    worker
+        .replay(EventLog { entries })
+        .await
+        .expect("replay succeeds");
+
+    // The first arrivals of the origin wait for the gap timeout, in case an
+    // earlier sequence arrives late.
+    assert!(votes.next().now_or_never().is_none());
+    tokio::spawn(worker.run());
+    let mut delivered = Vec::new();
+    for _ in 0..4 {
+        let event = tokio::time::timeout(Duration::from_secs(5), votes.next())
+            .await
+            .expect("vote is delivered in time")
+            .expect("vote is delivered");
+        assert_eq!(*event.origin(), origin);
+        delivered.push(event.value().0);
+    }
+    assert_eq!(delivered, [1, 2, 3, 4]);
+
+    // 5 never arrives, so 6 and 7 wait for the gap timeout.
+    let gap = tokio::time::timeout(Duration::from_secs(5), gaps.next())
+        .await
+        .expect("gap is reported in time")
+        .expect("gap is reported");
+    assert_eq!(gap.origin, origin);
+    assert_eq!(gap.topic, "vote");
+    assert_eq!(gap.missing, 5..6);
+    for expected in [6, 7] {
+        let event = tokio::time::timeout(Duration::from_secs(5), votes.next())
+            .await
+            .expect("vote is delivered in time")
+            .expect("vote is delivered");
+        assert_eq!(event.value().0, expected);
+    }
+}
+
+#[tokio::test]
+async fn ordered_topic_with_zero_gap_timeout_delivers() {
+    let mut worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_ordered_topic("vote", Duration::ZERO)
+        .build()
+        .expect("worker builds");
+    let mut listener = worker.service();
+    let mut votes = Box::pin(listen_votes(&mut worker, &mut listener).await);
+
+    let origin = libp2p::PeerId::random();
+    let entries = [2, 1]
+        .into_iter()
+        .map(|sequence| blocknet_libp2p::LogEntry {
+            timestamp_ms: 0,
+            event: RecordedEvent::Message {
+                source: Some(origin),
+                topic: libp2p::gossipsub::IdentTopic::new("vote")
+                    .hash()
+                    .into_string(),
+                data: blocknet_libp2p::AnyMessage {
+                    topic: "vote".to_string(),
+                    sequence: Some(sequence),
+                    codec: WireCodec::Json.tag(),
+                    serialized: serde_json::to_vec(&Vote(sequence)).unwrap(),
+                }
+                .encode()
+                .unwrap(),
+            },
+        })
+        .collect();
+    worker
+        .replay(EventLog { entries })
+        .await
+        .expect("replay succeeds");
+    tokio::spawn(worker.run());
+
+    for expected in [1, 2] {
+        let event = tokio::time::timeout(Duration::from_secs(5), votes.next())
+            .await
+            .expect("vote is delivered in time")
+            .expect("vote is delivered");
+        assert_eq!(event.value().0, expected);
+    }
+}
+
+#[tokio::test]
+async fn sequenced_topic_drops_replayed_broadcasts() {
+    let mut worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_sequenced_topic("vote")
+        .build()
+        .expect("worker builds");
+    let mut listener = worker.service();
+    let mut votes = Box::pin(listen_votes(&mut worker, &mut listener).await);
+
+    // Arrivals as received from gossipsub, with replays of each origin.
+    let (first, second) = (libp2p::PeerId::random(), libp2p::PeerId::random());
+    let entries = [
+        (first, 1),
+        (first, 2),
+        (first, 2),
+        (second, 1),
+        (first, 1),
+        (first, 3),
+    ]
+    .into_iter()
+    .map(|(origin, sequence)| blocknet_libp2p::LogEntry {
+        timestamp_ms: 0,
+        event: RecordedEvent::Message {
+            source: Some(origin),
+            topic: libp2p::gossipsub::IdentTopic::new("vote")
+                .hash()
+                .into_string(),
+            data: blocknet_libp2p::AnyMessage {
+                topic: "vote".to_string(),
+                sequence: Some(sequence),
+                codec: WireCodec::Json.tag(),
+                serialized: serde_json::to_vec(&Vote(sequence)).unwrap(),
+            }
+            .encode()
+            .unwrap(),
+        },
+    })
+    .collect();
+    worker
+        .replay(EventLog { entries })
+        .await
+        .expect("replay succeeds");
+
+    let mut delivered = Vec::new();
+    while let Some(Some(event)) = votes.next().now_or_never() {
+        delivered.push((*event.origin(), event.value().0));
+    }
+    assert_eq!(delivered, [(first, 1), (first, 2), (second, 1), (first, 3)]);
+}
+
+#[tokio::test]
+async fn dropped_listener_does_not_stop_deliveries() {
+    let mut worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .build()
+        .expect("worker builds");
+    let mut listener = worker.service();
+    let mut dropped_listener = listener.clone();
+    let dropped = listen_votes(&mut worker, &mut dropped_listener).await;
+    let mut votes = Box::pin(listen_votes(&mut worker, &mut listener).await);
+    drop(dropped);
+
+    let origin = libp2p::PeerId::random();
+    let entries = [1, 2]
+        .into_iter()
+        .map(|vote| blocknet_libp2p::LogEntry {
+            timestamp_ms: 0,
+            event: RecordedEvent::Message {
+                source: Some(origin),
+                topic: libp2p::gossipsub::IdentTopic::new("vote")
+                    .hash()
+                    .into_string(),
+                data: blocknet_libp2p::AnyMessage {
+                    topic: "vote".to_string(),
+                    sequence: None,
+                    codec: WireCodec::Json.tag(),
+                    serialized: serde_json::to_vec(&Vote(vote)).unwrap(),
+                }
+                .encode()
+                .unwrap(),
+            },
+        })
+        .collect();
+    worker
+        .replay(EventLog { entries })
+        .await
+        .expect("replay succeeds");
+
+    let mut delivered = Vec::new();
+    while let Some(Some(event)) = votes.next().now_or_never() {
+        delivered.push(event.value().0);
+    }
+    assert_eq!(delivered, [1, 2]);
+}
+
+#[tokio::test]
+async fn bare_broadcasts_are_delivered() {
+    let mut worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .build()
+        .expect("worker builds");
+    let mut listener = worker.service();
+    let mut votes = Box::pin(listen_votes(&mut worker, &mut listener).await);
+
+    // Peers predating the envelope publish the bare JSON of the message.
+    let origin = libp2p::PeerId::random();
+    let entries = vec![blocknet_libp2p::LogEntry {
+        timestamp_ms: 0,
+        event: RecordedEvent::Message {
+            source: Some(origin),
+            topic: libp2p::gossipsub::IdentTopic::new("vote")
+                .hash()
+                .into_string(),
+            data: serde_json::to_vec(&Vote(1)).unwrap(),
+        },
+    }];
+    worker
+        .replay(EventLog { entries })
+        .await
+        .expect("replay succeeds");
+
+    let event = votes
+        .next()
+        .now_or_never()
+        .flatten()
+        .expect("vote is delivered");
+    assert_eq!((*event.origin(), event.value().0), (origin, 1));
+}
+
+#[test]
+fn broadcast_envelope_is_binary() {
+    let message = blocknet_libp2p::AnyMessage {
+        topic: "block".to_string(),
+        sequence: Some(7),
+        codec: WireCodec::Scale.tag(),
+        serialized: vec![0xff; 1024],
+    };
+    let encoded = message.encode().expect("message encodes");
+    // The payload goes as is, beside a few bytes of topic, sequence and tag.
+    assert!(encoded.len() < 1024 + 32, "{} bytes", encoded.len());
+
+    let decoded = blocknet_libp2p::AnyMessage::decode(&encoded).expect("message decodes");
+    assert_eq!(decoded.topic, message.topic);
+    assert_eq!(decoded.sequence, message.sequence);
+    assert_eq!(decoded.codec, message.codec);
+    assert_eq!(decoded.serialized, message.serialized);
+    assert!(blocknet_libp2p::AnyMessage::decode(&encoded[..8]).is_err());
+}
+
+#[tokio::test]
+async fn mixed_codec_broadcasts_decode_by_tag() {
+    let mut worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_codecs([WireCodec::Scale, WireCodec::Json])
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+    let mut listener = service.clone();
+    let mut votes = Box::pin(listen_votes(&mut worker, &mut listener).await);
+    let mut unsupported = Box::pin(
+        service
+            .listen_unsupported_codecs()
+            .await
+            .expect("listen succeeds"),
+    );
+
+    // An origin on each codec, and one on a tag unknown here.
+    let (json_origin, scale_origin, unknown_origin) = (
+        libp2p::PeerId::random(),
+        libp2p::PeerId::random(),
+        libp2p::PeerId::random(),
+    );
+    let entries = [
+        (json_origin, WireCodec::Json.tag(), 1),
+        (unknown_origin, 7, 2),
+        (scale_origin, WireCodec::Scale.tag(), 3),
+        (unknown_origin, 7, 4),
+        (json_origin, WireCodec::Json.tag(), 5),
+    ]
+    .into_iter()
+    .map(|(origin, tag, vote)| blocknet_libp2p::LogEntry {
+        timestamp_ms: 0,
+        event: RecordedEvent::Message {
+            source: Some(origin),
+            topic: libp2p::gossipsub::IdentTopic::new("vote")
+                .hash()
+                .into_string(),
+            data: blocknet_libp2p::AnyMessage {
+                topic: "vote".to_string(),
+                sequence: None,
+                codec: tag,
+                serialized: WireCodec::from_tag(tag)
+                    .unwrap_or(WireCodec::Scale)
+                    .encode(&Vote(vote))
+                    .unwrap(),
+            }
+            .encode()
+            .unwrap(),
+        },
+    })
+    .collect();
+    worker
+        .replay(EventLog { entries })
+        .await
+        .expect("replay succeeds");
+
+    let mut delivered = Vec::new();
+    for _ in 0..3 {
+        let event = votes.next().await.expect("vote is delivered");
+        delivered.push((*event.origin(), event.value().0));
+    }
+    assert_eq!(
+        delivered,
+        [(json_origin, 1), (scale_origin, 3), (json_origin, 5)]
+    );
+
+    let report = unsupported.next().await.expect("codec is reported");
+    assert_eq!(
+        report,
+        blocknet_libp2p::UnsupportedCodec {
+            origin: unknown_origin,
+            topic: "vote".to_string(),
+            tag: 7,
+        }
+    );
+    // The second broadcast of the origin is dropped silently.
+    assert!(futures::FutureExt::now_or_never(unsupported.next()).is_none());
+}
+
+#[test]
+fn requests_without_codec_decode_as_json() {
+    let request: blocknet_libp2p::AnyRequest =
+        serde_json::from_str(r#"{"protocol_id":"/ping","serialized":[1,2]}"#)
+            .expect("request is valid");
+    assert_eq!(request.codec, WireCodec::Json);
+    assert_eq!(request.serialized, [1, 2]);
+
+    let response: blocknet_libp2p::AnyResponse =
+        serde_json::from_str(r#"{"protocol_id":"/ping","serialized":[3]}"#)
+            .expect("response is valid");
+    assert_eq!(response.codec, WireCodec::Json);
+    assert_eq!(response.serialized, [3]);
+}
+
+/// Gossipsub id of a vote, by its value rather than its sender.
+fn vote_message_id(data: &[u8]) -> libp2p::gossipsub::MessageId {
+    let message = blocknet_libp2p::AnyMessage::decode(data).expect("message is valid");
+    let vote: Vote = serde_json::from_slice(&message.serialized).expect("vote is valid");
+    libp2p::gossipsub::MessageId::new(&vote.0.to_be_bytes())
+}
+
+#[tokio::test]
+async fn custom_message_id_dedups_across_senders() {
+    let listener_key = Keypair::generate_ed25519();
+    let listener_peer_id = listener_key.public().to_peer_id();
+    let listener_addr = local_addr();
+
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_keypair(listener_key)
+        .with_mdns(false)
+        .with_listen_addrs([listener_addr.clone()])
+        .with_message_id_fn(vote_message_id)
+        .build()
+        .expect("worker builds");
+    let mut listener = worker.service();
+    tokio::spawn(worker.run());
+    let mut votes = Box::pin(
+        BroadcastService::<Vote>::listen(&mut listener, "vote")
+            .await
+            .expect("listen succeeds"),
+    );
+
+    let bootstrap = listener_addr.with(Protocol::P2p(listener_peer_id));
+    let sender = |key: Keypair| {
+        let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+            .with_keypair(key)
+            .with_mdns(false)
+            .with_listen_addrs([])
+            .with_bootstrap([bootstrap.clone()])
+            .with_message_id_fn(vote_message_id)
+            .build()
+            .expect("worker builds");
+        let service = worker.service();
+        tokio::spawn(worker.run());
+        service
+    };
+    let (first_key, second_key) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let (first_peer_id, second_peer_id) = (
+        first_key.public().to_peer_id(),
+        second_key.public().to_peer_id(),
+    );
+    let (mut first, mut second) = (sender(first_key), sender(second_key));
+
+    tokio::time::timeout(Duration::from_secs(20), async {
+        first
+            .broadcast_when_ready(Vote(7), 1, Duration::from_secs(20))
+            .await
+            .expect("broadcast succeeds");
+        let event = votes.next().await.expect("vote is delivered");
+        assert_eq!(*event.origin(), first_peer_id);
+        assert_eq!(event.value().0, 7);
+
+        // The same vote from another sender has the same id, so that it is
+        // dropped as a duplicate. Only the other vote is delivered, even
+        // given time for the duplicate to arrive out of order.
+        for vote in [7, 8] {
+            second
+                .broadcast_when_ready(Vote(vote), 1, Duration::from_secs(20))
+                .await
+                .expect("broadcast succeeds");
+        }
+        let event = votes.next().await.expect("vote is delivered");
+        assert_eq!(*event.origin(), second_peer_id);
+        assert_eq!(event.value().0, 8);
+    })
+    .await
+    .expect("votes are delivered");
+    assert!(
+        tokio::time::timeout(Duration::from_secs(2), votes.next())
+            .await
+            .is_err(),
+        "duplicate vote is dropped"
+    );
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Gossip(u64);
+
+impl Message for Gossip {
+    type Topic = &'static str;
+    const PRIORITY: Priority = Priority::Low;
+
+    fn topic(&self) -> &'static str {
+        "gossip"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlockAnnounce(u64);
+
+impl Message for BlockAnnounce {
+    type Topic = &'static str;
+    const PRIORITY: Priority = Priority::High;
+
+    fn topic(&self) -> &'static str {
+        "block"
+    }
+}
+
+/// Writer appending to a buffer shared with the test.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn high_priority_broadcast_sent_before_low_backlog() {
+    let buffer = SharedBuffer::default();
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_event_recorder(EventRecorder::new(buffer.clone()))
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+
+    // Queued before the worker runs, so that the whole backlog is pending.
+    let mut queued = 0;
+    for gossip in 0..100 {
+        match BroadcastService::broadcast(&mut service, Gossip(gossip)).await {
+            Ok(()) => queued += 1,
+            Err(blocknet_libp2p::Error::BroadcastDropped) => (),
+            Err(err) => panic!("low priority broadcast failed: {:?}", err),
+        }
+    }
+    assert!((64..100).contains(&queued));
+    assert_eq!(service.broadcast_queue_pressure(Priority::Low), 1.0);
+    assert!(matches!(
+        service.try_broadcast(Gossip(100)),
+        Err(blocknet_libp2p::Error::WouldBlock)
+    ));
+    service
+        .try_broadcast(BlockAnnounce(1))
+        .expect("high priority queue has room");
+    // Publishing fails without peers, which the worker only logs.
+    let handle = tokio::spawn(worker.run());
+
+    // Broadcasts are recorded as soon as they leave the queues.
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while service.broadcast_queue_pressure(Priority::Low) > 0.0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("backlog drains");
+
+    handle.abort();
+    let _ = handle.await;
+    let log = EventLog::<PeerInfo>::read(&buffer.0.lock().unwrap()[..]).expect("log is valid");
+    let topics = log
+        .entries
+        .into_iter()
+        .filter_map(|entry| match entry.event {
+            RecordedEvent::BroadcastSend { message } => Some(message.topic),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(topics[0], "block");
+    // Gossip beyond the capacity of the low priority queue was dropped, and
+    // reported as such.
+    let gossip = topics.iter().filter(|topic| *topic == "gossip").count();
+    assert_eq!(gossip, queued);
+}
+
+#[tokio::test]
+async fn listener_ends_with_error_when_worker_is_gone() {
+    let worker = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .build()
+        .expect("worker builds");
+    let mut service = worker.service();
+    let handle = tokio::spawn(worker.run());
+
+    // Unsubscribing ends the stream cleanly.
+    let unsubscribed = service
+        .listen_broadcasts::<Vote>("vote")
+        .await
+        .expect("listen succeeds");
+    BroadcastService::<Vote>::unsubscribe(&mut service, "vote")
+        .await
+        .expect("unsubscribe succeeds");
+    let ended = tokio::time::timeout(Duration::from_secs(5), Box::pin(unsubscribed).next())
+        .await
+        .expect("stream ends promptly");
+    assert!(ended.is_none());
+
+    let mut listener = Box::pin(
+        service
+            .listen_broadcasts::<Vote>("vote")
+            .await
+            .expect("listen succeeds"),
+    );
+    handle.abort();
+    let _ = handle.await;
+
+    let last = tokio::time::timeout(Duration::from_secs(5), listener.next())
+        .await
+        .expect("stream ends promptly");
+    assert!(matches!(
+        last,
+        Some(Err(blocknet_libp2p::Error::WorkerDisconnected))
+    ));
+    assert!(listener.next().await.is_none());
+}
+
+#[tokio::test]
+async fn peers_identify_node_under_rotated_key() {
+    let observer_key = Keypair::generate_ed25519();
+    let observer_peer_id = observer_key.public().to_peer_id();
+    let observer_addr = local_addr();
+
+    let observer = WorkerBuilder::new(PeerInfo { best_block: 0 })
+        .with_keypair(observer_key)
+        .with_mdns(false)
+        .with_listen_addrs([observer_addr.clone()])
+        .build()
+        .expect("observer worker builds");
+    let observer_service = observer.service();
+    tokio::spawn(observer.run());
+
+    let old_key = Keypair::generate_ed25519();
+    let old_peer_id = old_key.public().to_peer_id();
+    let rotating_addr = local_addr();
+    let rotating = WorkerBuilder::new(PeerInfo { best_block: 1 })
+        .with_keypair(old_key)
+        .with_mdns(false)
+        .with_listen_addrs([rotating_addr.clone()])
+        .with_bootstrap([observer_addr.with(Protocol::P2p(observer_peer_id))])
+        .build()
+        .expect("rotating worker builds");
+    let mut service = rotating.service();
+    tokio::spawn(rotating.run());
+
+    let observed = |service: &blocknet_libp2p::Service<PeerInfo>| {
+        service
+            .peers()
+            .into_iter()
+            .map(|(peer_id, _)| peer_id)
+            .collect::<Vec<_>>()
+    };
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while observed(&observer_service) != [old_peer_id] {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("observer identifies the old key");
+
+    let new_key = Keypair::generate_ed25519();
+    let new_peer_id = new_key.public().to_peer_id();
+    assert_eq!(
+        service
+            .rotate_identity(new_key)
+            .await
+            .expect("rotation succeeds"),
+        new_peer_id
+    );
+
+    // The observer is dialed again, and sees the old peer id leave.
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while observed(&observer_service) != [new_peer_id] {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("observer identifies the new key");
+
+    // The listen address carries over, under the new peer id.
+    let dialer = WorkerBuilder::new(PeerInfo { best_block: 2 })
+        .with_mdns(false)
+        .with_listen_addrs([])
+        .with_bootstrap([rotating_addr.with(Protocol::P2p(new_peer_id))])
+        .build()
+        .expect("dialer worker builds");
+    let dialer_service = dialer.service();
+    tokio::spawn(dialer.run());
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while observed(&dialer_service) != [new_peer_id] {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("dialer identifies the new key");
+}
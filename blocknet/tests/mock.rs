@@ -0,0 +1,207 @@
+//! Tests of the service traits over the mock network.
+
+use blocknet::{
+    mock, BroadcastService, Coalescing, Correlated, Event, Message, Request, RequestService,
+};
+use futures::{channel::oneshot, stream::StreamExt};
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WhoHas {
+    request: u64,
+    block: u64,
+}
+
+impl Message for WhoHas {
+    type Topic = &'static str;
+
+    fn topic(&self) -> &'static str {
+        "who_has"
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Has {
+    request: u64,
+    block: u64,
+}
+
+impl Message for Has {
+    type Topic = &'static str;
+
+    fn topic(&self) -> &'static str {
+        "has"
+    }
+}
+
+impl Correlated for WhoHas {
+    type CorrelationId = u64;
+
+    fn correlation_id(&self) -> u64 {
+        self.request
+    }
+}
+
+impl Correlated for Has {
+    type CorrelationId = u64;
+
+    fn correlation_id(&self) -> u64 {
+        self.request
+    }
+}
+
+/// Spawn a responder answering every `WhoHas` request after the delay, with
+/// the request id shifted by `skew`, such as to answer an older request.
+async fn spawn_responder(service: mock::Service<()>, delay: Duration, replies: usize, skew: u64) {
+    let (ready_sender, ready_receiver) = oneshot::channel();
+
+    tokio::spawn(async move {
+        let mut listener = service.clone();
+        let mut requests = Box::pin(
+            BroadcastService::<WhoHas>::listen(&mut listener, "who_has")
+                .await
+                .expect("listen succeeds"),
+        );
+        let _ = ready_sender.send(());
+
+        let mut service = service;
+        while let Some(request) = requests.next().await {
+            tokio::time::sleep(delay).await;
+            for _ in 0..replies {
+                BroadcastService::<Has>::broadcast(
+                    &mut service,
+                    Has {
+                        request: request.value().request + skew,
+                        block: request.value().block,
+                    },
+                )
+                .await
+                .expect("broadcast succeeds");
+            }
+        }
+    });
+
+    ready_receiver.await.expect("responder is listening");
+}
+
+#[tokio::test]
+async fn scatter_gather_collects_responses_before_deadline() {
+    let network = mock::Network::new();
+    let mut requester = network.join(());
+
+    let fast = network.join(());
+    let twice = network.join(());
+    let slow = network.join(());
+    let stale = network.join(());
+    spawn_responder(fast.clone(), Duration::ZERO, 1, 0).await;
+    spawn_responder(twice.clone(), Duration::from_millis(10), 2, 0).await;
+    spawn_responder(slow.clone(), Duration::from_secs(5), 1, 0).await;
+    spawn_responder(stale, Duration::ZERO, 1, 1).await;
+
+    let request = WhoHas {
+        request: 1,
+        block: 42,
+    };
+    let mut responses = requester
+        .scatter_gather::<Has>(request, "has", Duration::from_millis(500))
+        .await
+        .expect("scatter gather succeeds");
+    responses.sort_by_key(|(peer_id, _)| *peer_id);
+
+    assert_eq!(
+        responses,
+        vec![
+            (
+                fast.local_peer_id(),
+                Has {
+                    request: 1,
+                    block: 42
+                }
+            ),
+            (
+                twice.local_peer_id(),
+                Has {
+                    request: 1,
+                    block: 42
+                }
+            ),
+        ]
+    );
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct GetBlock {
+    number: u64,
+}
+
+impl Request for GetBlock {
+    type Response = Has;
+
+    const PROTOCOL_ID: &'static str = "/test/get_block/1";
+}
+
+#[tokio::test]
+async fn identical_in_flight_requests_coalesced() {
+    let network = mock::Network::new();
+    let requester = network.join(());
+    let responder = network.join(());
+    let received = Arc::new(AtomicUsize::new(0));
+
+    let peer = responder.local_peer_id();
+    let (ready_sender, ready_receiver) = oneshot::channel();
+    let counter = received.clone();
+    tokio::spawn(async move {
+        let mut listener = responder.clone();
+        let mut requests = Box::pin(
+            RequestService::<GetBlock>::listen(&mut listener)
+                .await
+                .expect("listen succeeds"),
+        );
+        let _ = ready_sender.send(());
+
+        let mut service = responder;
+        while let Some((channel, request)) = requests.next().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            // Give the other requests time to join the first one.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let response = Has {
+                request: 0,
+                block: request.value().number,
+            };
+            RequestService::<GetBlock>::respond(&mut service, channel, response)
+                .await
+                .expect("respond succeeds");
+        }
+    });
+
+    ready_receiver.await.expect("responder is listening");
+
+    let coalescing = Coalescing::new(requester);
+    let request = |number| {
+        let mut service = coalescing.clone();
+        async move { service.request(peer, GetBlock { number }).await }
+    };
+    let (first, second, third) = futures::future::join3(request(7), request(7), request(7)).await;
+
+    assert_eq!(received.load(Ordering::SeqCst), 1);
+    for response in [first, second, third] {
+        assert_eq!(
+            response.expect("request succeeds"),
+            Has {
+                request: 0,
+                block: 7
+            }
+        );
+    }
+
+    // Once completed, the same request goes over the wire again.
+    request(7).await.expect("request succeeds");
+    assert_eq!(received.load(Ordering::SeqCst), 2);
+}
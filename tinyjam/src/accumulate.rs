@@ -0,0 +1,271 @@
+//! # Accumulate.
+//!
+//! The accumulate stage integrates work reports, once available, into the
+//! relay chain state. It is metered by gas, so that a block can only include
+//! a bounded amount of work. Reports that do not fit in a block are deferred
+//! to the next one, in order, and reports that do not fit in any block are
+//! rejected. Reports failing to accumulate are moved aside, so that the
+//! following ones go on, until retried.
+//!
+//! A report carries outputs for services. The [`ServiceAccumulator`]
+//! dispatches each of them to the accumulate logic of its destination service,
+//! found in a [`ServiceStore`].
+
+use crate::core_seal::WorkReport;
+use std::{collections::VecDeque, convert::Infallible, marker::PhantomData};
+
+/// Amount of gas.
+pub type Gas = u64;
+
+/// Accumulation of work reports into the state.
+pub trait Accumulate {
+    /// Error type for accumulation.
+    type Error;
+
+    /// An available work report.
+    type WorkReport;
+
+    /// Gas the report needs to be accumulated. A report is only accumulated
+    /// if the remaining budget of the block covers it.
+    fn gas_required(&self, report: &Self::WorkReport) -> Gas;
+    /// Accumulate a work report, given the remaining gas budget of the block.
+    /// Returns the gas consumed, which must not exceed the budget. A report
+    /// failing to accumulate is moved aside by the queue.
+    fn accumulate(
+        &mut self,
+        report: &Self::WorkReport,
+        gas_remaining: Gas,
+    ) -> Result<Gas, Self::Error>;
+}
+
+/// Result of accumulating a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockAccumulation<E> {
+    /// Number of reports accumulated.
+    pub accumulated: usize,
+    /// Gas consumed.
+    pub gas_used: Gas,
+    /// Errors of the reports that failed to accumulate, and were moved
+    /// aside, in order.
+    pub errors: Vec<E>,
+}
+
+/// Queue of available work reports awaiting accumulation, driving the
+/// per-block gas limit.
+#[derive(Debug, Clone)]
+pub struct AccumulateQueue<Report> {
+    pending: VecDeque<Report>,
+    /// Reports that failed to accumulate, in order, until retried.
+    failed: Vec<Report>,
+    gas_limit: Gas,
+}
+
+impl<Report> AccumulateQueue<Report> {
+    /// Create a new empty queue, for blocks with the given gas limit.
+    pub fn new(gas_limit: Gas) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            failed: Vec::new(),
+            gas_limit,
+        }
+    }
+
+    /// The gas limit of a block.
+    pub fn gas_limit(&self) -> Gas {
+        self.gas_limit
+    }
+
+    /// Queue an available report. A report needing more gas than a whole
+    /// block has would never be accumulated, and hold back all the following
+    /// ones, so it is given back instead.
+    pub fn push<A>(&mut self, accumulator: &A, report: Report) -> Result<(), Report>
+    where
+        A: Accumulate<WorkReport = Report>,
+    {
+        if accumulator.gas_required(&report) > self.gas_limit {
+            return Err(report);
+        }
+
+        self.pending.push_back(report);
+        Ok(())
+    }
+
+    /// Reports moved aside for failing to accumulate, in order.
+    pub fn failed(&self) -> impl Iterator<Item = &Report> {
+        self.failed.iter()
+    }
+
+    /// Queue the failed reports again, ahead of the deferred ones, such as
+    /// once the cause of their failure is fixed.
+    pub fn retry_failed(&mut self) {
+        for report in self.failed.drain(..).rev() {
+            self.pending.push_front(report);
+        }
+    }
+
+    /// Remove the failed reports, such as to give up on them for good.
+    pub fn take_failed(&mut self) -> Vec<Report> {
+        std::mem::take(&mut self.failed)
+    }
+
+    /// Reports deferred to later blocks, in order.
+    pub fn pending(&self) -> impl Iterator<Item = &Report> {
+        self.pending.iter()
+    }
+
+    /// Number of deferred reports.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether no report is deferred.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Accumulate reports in order, for a block. Stops at the first report
+    /// that does not fit the remaining budget, which stays queued with all
+    /// the following ones. Reports failing to accumulate are moved aside,
+    /// and the following ones accumulated in the same block.
+    pub fn accumulate_block<A>(&mut self, accumulator: &mut A) -> BlockAccumulation<A::Error>
+    where
+        A: Accumulate<WorkReport = Report>,
+    {
+        let mut result = BlockAccumulation {
+            accumulated: 0,
+            gas_used: 0,
+            errors: Vec::new(),
+        };
+
+        while let Some(report) = self.pending.front() {
+            let gas_remaining = self.gas_limit - result.gas_used;
+            if accumulator.gas_required(report) > gas_remaining {
+                break;
+            }
+
+            let accumulated = accumulator.accumulate(report, gas_remaining);
+            let report = self.pending.pop_front().expect("front report exists; qed");
+            let consumed = match accumulated {
+                Ok(consumed) => consumed,
+                Err(error) => {
+                    self.failed.push(report);
+                    result.errors.push(error);
+                    continue;
+                }
+            };
+            result.gas_used += consumed.min(gas_remaining);
+            result.accumulated += 1;
+        }
+
+        result
+    }
+}
+
+/// Identifier of a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServiceId(pub u32);
+
+/// A work report carrying outputs for services.
+pub trait ReportOutputs: WorkReport {
+    /// Output of the report for a service.
+    type Output;
+
+    /// Outputs of the report, with their destination service and the gas
+    /// their accumulation needs, in order. The report needs the gas of all
+    /// its outputs.
+    fn outputs(&self) -> Vec<(ServiceId, Gas, Self::Output)>;
+}
+
+/// Accumulate logic of a service, writing outputs into its state.
+pub trait AccumulateService {
+    /// Output the service accumulates.
+    type Output;
+
+    /// Accumulate an output, given the remaining gas budget of the block.
+    /// Returns the gas consumed, which is clamped to `gas_remaining`.
+    fn accumulate(&mut self, output: Self::Output, gas_remaining: Gas) -> Gas;
+}
+
+/// Store of the services of the state.
+pub trait ServiceStore {
+    /// A service.
+    type Service: AccumulateService;
+
+    /// Get a service to accumulate into, if it exists.
+    fn service_mut(&mut self, id: ServiceId) -> Option<&mut Self::Service>;
+}
+
+/// Error of accumulating a single output. Other outputs of the report are
+/// still accumulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputError {
+    /// The destination service does not exist.
+    UnknownService(ServiceId),
+}
+
+/// Default accumulation, dispatching each output of a report to its
+/// destination service in the store. Outputs that cannot be accumulated are
+/// collected as errors rather than failing the report.
+#[derive(Debug)]
+pub struct ServiceAccumulator<Store, Report> {
+    store: Store,
+    errors: Vec<OutputError>,
+    _marker: PhantomData<Report>,
+}
+
+impl<Store, Report> ServiceAccumulator<Store, Report> {
+    /// Create a new accumulator over the store.
+    pub fn new(store: Store) -> Self {
+        Self {
+            store,
+            errors: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// The store.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Take the errors collected since the last call, in order.
+    pub fn take_errors(&mut self) -> Vec<OutputError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Get back the store.
+    pub fn into_store(self) -> Store {
+        self.store
+    }
+}
+
+impl<Store, Report> Accumulate for ServiceAccumulator<Store, Report>
+where
+    Store: ServiceStore,
+    Report: ReportOutputs<Output = <Store::Service as AccumulateService>::Output>,
+{
+    type Error = Infallible;
+    type WorkReport = Report;
+
+    fn gas_required(&self, report: &Report) -> Gas {
+        report
+            .outputs()
+            .iter()
+            .fold(0, |gas, (_, output_gas, _)| gas.saturating_add(*output_gas))
+    }
+
+    fn accumulate(&mut self, report: &Report, gas_remaining: Gas) -> Result<Gas, Infallible> {
+        let mut gas_used: Gas = 0;
+        for (service_id, _, output) in report.outputs() {
+            let Some(service) = self.store.service_mut(service_id) else {
+                self.errors.push(OutputError::UnknownService(service_id));
+                continue;
+            };
+
+            let consumed = service.accumulate(output, gas_remaining - gas_used);
+            gas_used = gas_used.saturating_add(consumed).min(gas_remaining);
+        }
+
+        Ok(gas_used)
+    }
+}
@@ -0,0 +1,163 @@
+use super::{AuthorizerHash, ExecutionOutcome, Executor, WorkPackage};
+use crate::accumulate::Gas;
+use std::collections::HashMap;
+
+/// Default gas limit of executing authorizer code.
+pub const DEFAULT_AUTHORIZATION_GAS_LIMIT: Gas = 1_000_000;
+
+/// Authorizer logic, validating the authorization token of a work package
+/// against its code and state.
+pub trait Authorizer<Package> {
+    /// Error of an invalid token.
+    type Error;
+
+    /// Validate the token carried by the package.
+    fn authorize(&self, package: &Package, token: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Error of authorizing a work package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthorizationError<E> {
+    /// The authorizer of the package is not in the pool of the core.
+    UnauthorizedCode(AuthorizerHash),
+    /// The package carries no authorization token.
+    MissingToken,
+    /// The authorizer rejected the token.
+    InvalidToken(E),
+}
+
+/// Error of executing authorizer code, rejecting the package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionError {
+    /// The code exited with a nonzero status.
+    Exit(u32),
+    /// The code trapped.
+    Trap,
+    /// The code exceeded the gas limit of authorization.
+    OutOfGas,
+}
+
+/// Authorization pool of a core, holding the authorizers currently allowed on
+/// it, keyed by their code hashes.
+///
+/// Authorizers are either native [`Authorizer`] logic, or code run by an
+/// [`Executor`] within the gas limit of the pool.
+#[derive(Debug, Clone)]
+pub struct AuthorizationPool<A> {
+    authorizers: HashMap<AuthorizerHash, A>,
+    gas_limit: Gas,
+}
+
+impl<A> Default for AuthorizationPool<A> {
+    fn default() -> Self {
+        Self {
+            authorizers: HashMap::new(),
+            gas_limit: DEFAULT_AUTHORIZATION_GAS_LIMIT,
+        }
+    }
+}
+
+impl<A> AuthorizationPool<A> {
+    /// Create a new empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the gas limit of executing authorizer code.
+    pub fn with_gas_limit(mut self, gas_limit: Gas) -> Self {
+        self.gas_limit = gas_limit;
+        self
+    }
+
+    /// Gas limit of executing authorizer code.
+    pub fn gas_limit(&self) -> Gas {
+        self.gas_limit
+    }
+
+    /// Allow an authorizer on the core, replacing the one with the same
+    /// code hash.
+    pub fn insert(&mut self, hash: AuthorizerHash, authorizer: A) -> Option<A> {
+        self.authorizers.insert(hash, authorizer)
+    }
+
+    /// Remove an authorizer from the core.
+    pub fn remove(&mut self, hash: &AuthorizerHash) -> Option<A> {
+        self.authorizers.remove(hash)
+    }
+
+    /// Whether the authorizer is allowed on the core.
+    pub fn contains(&self, hash: &AuthorizerHash) -> bool {
+        self.authorizers.contains_key(hash)
+    }
+
+    /// Authorize a work package, passing its token to the authorizer it
+    /// names.
+    pub fn authorize<P>(&self, package: &P) -> Result<(), AuthorizationError<A::Error>>
+    where
+        P: WorkPackage,
+        A: Authorizer<P>,
+    {
+        let (authorizer, token) = self.lookup(package)?;
+        authorizer
+            .authorize(package, token)
+            .map_err(AuthorizationError::InvalidToken)
+    }
+
+    /// Authorize a work package by executing the code of the authorizer it
+    /// names, within the gas limit of the pool. The input is the length of
+    /// the token as 4 little-endian bytes, the token, then the encoded
+    /// package. Only a zero exit status authorizes the package.
+    pub fn execute<P, E>(
+        &self,
+        executor: &E,
+        package: &P,
+    ) -> Result<Gas, AuthorizationError<ExecutionError>>
+    where
+        P: WorkPackage,
+        A: AsRef<[u8]>,
+        E: Executor,
+    {
+        let (code, token) = self.lookup(package)?;
+
+        let mut input = (token.len() as u32).to_le_bytes().to_vec();
+        input.extend_from_slice(token);
+        input.extend(package.encode());
+
+        let error = match executor.execute(code.as_ref(), &input, self.gas_limit) {
+            // Also checked here, so that a misbehaving executor can not
+            // exceed the limit.
+            ExecutionOutcome::Exit { gas_used, .. } if gas_used > self.gas_limit => {
+                ExecutionError::OutOfGas
+            }
+            ExecutionOutcome::Exit {
+                status: 0,
+                gas_used,
+            } => return Ok(gas_used),
+            ExecutionOutcome::Exit { status, .. } => ExecutionError::Exit(status),
+            ExecutionOutcome::Trap => ExecutionError::Trap,
+            ExecutionOutcome::OutOfGas => ExecutionError::OutOfGas,
+        };
+
+        Err(AuthorizationError::InvalidToken(error))
+    }
+
+    /// Get the authorizer named by the package, and its token.
+    fn lookup<'a, P, E>(
+        &'a self,
+        package: &'a P,
+    ) -> Result<(&'a A, &'a [u8]), AuthorizationError<E>>
+    where
+        P: WorkPackage,
+    {
+        let hash = package.authorizer();
+        let authorizer = self
+            .authorizers
+            .get(&hash)
+            .ok_or(AuthorizationError::UnauthorizedCode(hash))?;
+        let token = package
+            .authorization_token()
+            .ok_or(AuthorizationError::MissingToken)?;
+
+        Ok((authorizer, token))
+    }
+}
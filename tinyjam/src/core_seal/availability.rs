@@ -0,0 +1,165 @@
+use super::{AvailabilityStatus, ReportStore, ValidatorSet, WorkReportId};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    time::Duration,
+};
+
+/// Event of the availability subsystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AvailabilityEvent {
+    /// The report reached the assurance threshold, and is now available.
+    Available {
+        /// The report.
+        report_id: WorkReportId,
+    },
+    /// The report did not reach the assurance threshold before its deadline,
+    /// such as when assurers are offline. It must be guaranteed again, on a
+    /// fresh validator set.
+    ReassignmentNeeded {
+        /// The report.
+        report_id: WorkReportId,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct PendingAvailability<Assurer> {
+    deadline: Duration,
+    assurers: HashSet<Assurer>,
+}
+
+/// Availability subsystem, counting assurances of guaranteed reports.
+///
+/// A guaranteed report becomes available once `threshold` distinct assurers
+/// assured it. If that does not happen within the availability timeout, the
+/// report stops being tracked and needs reassignment. Time is passed in by the
+/// caller, as measured by its [`Timer`](super::Timer), so that tests can drive
+/// it with a virtual clock.
+#[derive(Debug, Clone)]
+pub struct Availability<Assurer> {
+    threshold: usize,
+    timeout: Duration,
+    pending: HashMap<WorkReportId, PendingAvailability<Assurer>>,
+}
+
+impl<Assurer: Eq + Hash> Availability<Assurer> {
+    /// Create a new availability subsystem, with the number of assurances a
+    /// report needs, and the time it has to get them.
+    pub fn new(threshold: usize, timeout: Duration) -> Self {
+        Self {
+            threshold,
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Create a new availability subsystem for the validator set, needing
+    /// its [availability threshold](ValidatorSet::availability_threshold)
+    /// of assurances.
+    pub fn for_validators<V>(validators: &ValidatorSet<V>, timeout: Duration) -> Self {
+        Self::new(validators.availability_threshold(), timeout)
+    }
+
+    /// Number of assurances a report needs.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Time a report has to reach the threshold.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Whether the report is waiting for assurances.
+    pub fn is_pending(&self, report_id: &WorkReportId) -> bool {
+        self.pending.contains_key(report_id)
+    }
+
+    /// Number of distinct assurers of a report waiting for assurances. Zero
+    /// if the report is not pending.
+    pub fn assurances(&self, report_id: &WorkReportId) -> usize {
+        self.pending
+            .get(report_id)
+            .map_or(0, |pending| pending.assurers.len())
+    }
+
+    /// The reports waiting for assurances, with their deadline and assurers.
+    pub(super) fn pending(
+        &self,
+    ) -> impl Iterator<Item = (WorkReportId, Duration, &HashSet<Assurer>)> {
+        self.pending
+            .iter()
+            .map(|(report_id, pending)| (*report_id, pending.deadline, &pending.assurers))
+    }
+
+    /// Wait for assurances of a report again, with its deadline and the
+    /// assurers so far, such as from a [`SealSnapshot`](super::SealSnapshot).
+    pub(super) fn restore(
+        &mut self,
+        report_id: WorkReportId,
+        deadline: Duration,
+        assurers: HashSet<Assurer>,
+    ) {
+        self.pending
+            .insert(report_id, PendingAvailability { deadline, assurers });
+    }
+
+    /// Start waiting for assurances of a report guaranteed at `now`. If the
+    /// report is already pending, such as after a reassignment, its
+    /// assurances are reset and its deadline restarts.
+    pub fn guarantee(&mut self, report_id: WorkReportId, now: Duration) {
+        self.pending.insert(
+            report_id,
+            PendingAvailability {
+                deadline: now + self.timeout,
+                assurers: HashSet::new(),
+            },
+        );
+    }
+
+    /// Note an assurance of the report. Once the threshold is reached, the
+    /// report is marked available in the store. Assurances of reports not
+    /// pending, and repeated assurances, are ignored.
+    pub fn assure<Report, BlockId>(
+        &mut self,
+        store: &mut ReportStore<Report, BlockId>,
+        report_id: &WorkReportId,
+        assurer: Assurer,
+    ) -> Option<AvailabilityEvent> {
+        let pending = self.pending.get_mut(report_id)?;
+        pending.assurers.insert(assurer);
+        if pending.assurers.len() < self.threshold {
+            return None;
+        }
+
+        self.pending.remove(report_id);
+        if let Some(entry) = store.get_mut(report_id) {
+            entry.availability = AvailabilityStatus::Available;
+        }
+
+        Some(AvailabilityEvent::Available {
+            report_id: *report_id,
+        })
+    }
+
+    /// Stop tracking reports past their deadline at `now`, and get them as
+    /// needing reassignment.
+    pub fn check_deadlines(&mut self, now: Duration) -> Vec<AvailabilityEvent> {
+        let mut expired = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(report_id, _)| *report_id)
+            .collect::<Vec<_>>();
+        expired.sort();
+
+        for report_id in &expired {
+            self.pending.remove(report_id);
+        }
+
+        expired
+            .into_iter()
+            .map(|report_id| AvailabilityEvent::ReassignmentNeeded { report_id })
+            .collect()
+    }
+}
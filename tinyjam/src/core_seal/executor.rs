@@ -0,0 +1,23 @@
+use crate::accumulate::Gas;
+
+/// Outcome of executing code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionOutcome {
+    /// The code exited with a status, zero on success.
+    Exit {
+        /// Exit status.
+        status: u32,
+        /// Gas consumed.
+        gas_used: Gas,
+    },
+    /// The code trapped, such as on an invalid instruction.
+    Trap,
+    /// The code ran out of gas.
+    OutOfGas,
+}
+
+/// Executor of service and authorizer code, such as a PVM interpreter.
+pub trait Executor {
+    /// Execute the code on the input, stopping once it consumed `gas_limit`.
+    fn execute(&self, code: &[u8], input: &[u8], gas_limit: Gas) -> ExecutionOutcome;
+}
@@ -0,0 +1,351 @@
+use super::{
+    Availability, CoreSealHandle, CoreSealWorker, ReportStore, SealSnapshot, Spawn, Timer,
+    WorkPackage, WorkPackageId, WorkReportId, WorkerError,
+};
+use futures::{
+    channel::mpsc,
+    stream::{Stream, StreamExt},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// Identifier of a core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CoreId(pub u32);
+
+/// A work package assigned to a core.
+pub trait CoreAssigned {
+    /// The core the package is assigned to.
+    fn core(&self) -> CoreId;
+}
+
+/// Event of a core, in the unified stream of the manager.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreEvent<E> {
+    /// A work package was refined and its report attested.
+    Attested {
+        /// The core processing the package.
+        core: CoreId,
+        /// The work package.
+        package: WorkPackageId,
+        /// The attested report.
+        report: WorkReportId,
+    },
+    /// A work package failed to refine, or its report failed to attest on
+    /// every attempt, with [`WorkerError::AttestationFailed`].
+    Failed {
+        /// The core processing the package.
+        core: CoreId,
+        /// The work package.
+        package: WorkPackageId,
+        /// Why it failed.
+        error: WorkerError<E>,
+    },
+}
+
+/// Error of submitting a work package to the manager. The package is handed
+/// back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitError<P> {
+    /// The package is assigned to a core not managed.
+    UnknownCore(P),
+    /// The worker of the core has stopped.
+    Stopped(P),
+}
+
+impl<P> SubmitError<P> {
+    fn map<Q>(self, f: impl FnOnce(P) -> Q) -> SubmitError<Q> {
+        match self {
+            Self::UnknownCore(work) => SubmitError::UnknownCore(f(work)),
+            Self::Stopped(work) => SubmitError::Stopped(f(work)),
+        }
+    }
+}
+
+/// A work package in flight in the manager, queued or being processed by the
+/// worker of its core.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InFlight<P, R> {
+    /// The package is to be refined, or being refined.
+    Refine(P),
+    /// The package is refined, and its report is being attested.
+    Attest {
+        /// The work package.
+        package: P,
+        /// The report to attest.
+        report: R,
+        /// Number of attempts failed so far.
+        failed: usize,
+    },
+}
+
+impl<P, R> InFlight<P, R> {
+    /// The work package.
+    pub fn package(&self) -> &P {
+        match self {
+            Self::Refine(package) | Self::Attest { package, .. } => package,
+        }
+    }
+
+    /// Take the work package.
+    pub fn into_package(self) -> P {
+        match self {
+            Self::Refine(package) | Self::Attest { package, .. } => package,
+        }
+    }
+}
+
+/// State shared by the manager and its workers.
+struct Shared<P, R> {
+    /// Work submitted and not yet taken by the worker of its core, in
+    /// submission order, along with the worker it is queued for.
+    queue: Vec<(u64, InFlight<P, R>)>,
+    /// Work taken by each worker and not yet done.
+    current: BTreeMap<u64, InFlight<P, R>>,
+}
+
+impl<P, R> Default for Shared<P, R> {
+    fn default() -> Self {
+        Self {
+            queue: Vec::new(),
+            current: BTreeMap::new(),
+        }
+    }
+}
+
+type SharedState<P, R> = Arc<Mutex<Shared<P, R>>>;
+
+/// Work in flight of the workers of a handle.
+type Work<H> = InFlight<<H as CoreSealHandle>::WorkPackage, <H as CoreSealHandle>::WorkReport>;
+
+/// Coordinator of the in-core sealing workers of multiple cores.
+///
+/// Each core runs its worker on its own task, owning its handle and segment
+/// store, so that cores do not contend on shared state. The manager only
+/// keeps a channel to each of them, and routes work packages by their core
+/// assignment. Attestations and failures of all cores are aggregated into a
+/// single event stream.
+///
+/// The work packages a worker has not started yet are queued in the manager,
+/// and the package each worker is refining or attesting is tracked there
+/// along with its attestation attempts, so that they can be exported along
+/// with the report store and the availability subsystem with
+/// [`Self::export_state`].
+pub struct CoreSealManager<H: CoreSealHandle> {
+    cores: HashMap<CoreId, (u64, mpsc::UnboundedSender<()>)>,
+    events: mpsc::UnboundedSender<CoreEvent<H::Error>>,
+    shared: SharedState<H::WorkPackage, H::WorkReport>,
+    next_worker: u64,
+}
+
+impl<H> CoreSealManager<H>
+where
+    H: CoreSealHandle + Send + Sync + 'static,
+    H::WorkPackage: CoreAssigned + Clone + Send,
+    H::WorkReport: Clone + Send,
+    H::Error: Send,
+{
+    /// Create a new manager without any core, along with its event stream.
+    pub fn new() -> (Self, impl Stream<Item = CoreEvent<H::Error>>) {
+        let (events, receiver) = mpsc::unbounded();
+        let manager = Self {
+            cores: HashMap::new(),
+            events,
+            shared: Arc::default(),
+            next_worker: 0,
+        };
+
+        (manager, receiver)
+    }
+
+    /// Spawn the worker of a core. A worker already running for the core
+    /// stops once it processed the packages already submitted to it.
+    pub fn add_core<S, T>(&mut self, core: CoreId, worker: CoreSealWorker<H, S, T>)
+    where
+        S: Spawn + Clone + Send + 'static,
+        T: Timer + Send + 'static,
+    {
+        // Each submitted package wakes the worker once, to take it from the
+        // queue.
+        let (sender, mut wakes) = mpsc::unbounded::<()>();
+        let events = self.events.clone();
+        let shared = self.shared.clone();
+        let worker_id = self.next_worker;
+        self.next_worker += 1;
+
+        worker.spawn_with(|mut worker| async move {
+            while wakes.next().await.is_some() {
+                let Some(work) = take(&shared, worker_id) else {
+                    continue;
+                };
+                let package = work.package().id();
+                let result = async {
+                    let (report, failed) = match work {
+                        InFlight::Refine(work) => {
+                            let report = worker.refine(work.clone()).await?;
+                            lock(&shared).current.insert(
+                                worker_id,
+                                InFlight::Attest {
+                                    package: work,
+                                    report: report.clone(),
+                                    failed: 0,
+                                },
+                            );
+                            (report, 0)
+                        }
+                        InFlight::Attest { report, failed, .. } => (report, failed),
+                    };
+                    worker
+                        .attest_after(report, failed, |failed| {
+                            if let Some(InFlight::Attest {
+                                failed: current, ..
+                            }) = lock(&shared).current.get_mut(&worker_id)
+                            {
+                                *current = failed;
+                            }
+                        })
+                        .await
+                }
+                .await;
+                lock(&shared).current.remove(&worker_id);
+
+                let event = match result {
+                    Ok(report) => CoreEvent::Attested {
+                        core,
+                        package,
+                        report,
+                    },
+                    Err(error) => CoreEvent::Failed {
+                        core,
+                        package,
+                        error,
+                    },
+                };
+
+                // Keep processing even if no one listens to the events.
+                let _ = events.unbounded_send(event);
+            }
+        });
+
+        self.cores.insert(core, (worker_id, sender));
+    }
+
+    /// Stop the worker of a core, once it processed the packages already
+    /// submitted to it. Returns whether the core was managed.
+    pub fn remove_core(&mut self, core: CoreId) -> bool {
+        self.cores.remove(&core).is_some()
+    }
+
+    /// The managed cores.
+    pub fn cores(&self) -> impl Iterator<Item = CoreId> + '_ {
+        self.cores.keys().copied()
+    }
+
+    /// Route a work package to the worker of its assigned core.
+    pub fn submit(&self, work: H::WorkPackage) -> Result<(), SubmitError<H::WorkPackage>> {
+        self.enqueue(InFlight::Refine(work))
+            .map_err(|err| err.map(InFlight::into_package))
+    }
+
+    /// Queue work for the worker of the core of its package.
+    fn enqueue(&self, work: Work<H>) -> Result<(), SubmitError<Work<H>>> {
+        let Some((worker_id, sender)) = self.cores.get(&work.package().core()) else {
+            return Err(SubmitError::UnknownCore(work));
+        };
+
+        lock(&self.shared).queue.push((*worker_id, work));
+        sender.unbounded_send(()).map_err(|_| {
+            // The worker is gone, so the work just queued is its last one.
+            let queue = &mut lock(&self.shared).queue;
+            let index = queue
+                .iter()
+                .rposition(|(id, _)| id == worker_id)
+                .expect("work is just queued; qed");
+            SubmitError::Stopped(queue.remove(index).1)
+        })
+    }
+
+    /// Import the in-flight state into a new report store and availability
+    /// subsystem, at `now` on the clock of the restored node. The work
+    /// packages in flight are submitted again, to the cores added by then,
+    /// resuming the attestation of those already refined, and those failing
+    /// to are handed back.
+    #[allow(clippy::type_complexity)]
+    pub fn import_state<BlockId, Assurer>(
+        &self,
+        mut snapshot: SealSnapshot<H::WorkReport, BlockId, Assurer, Work<H>>,
+        now: Duration,
+    ) -> (
+        ReportStore<H::WorkReport, BlockId>,
+        Availability<Assurer>,
+        Vec<SubmitError<Work<H>>>,
+    )
+    where
+        BlockId: Clone,
+        Assurer: Eq + Hash + Clone,
+    {
+        let packages = snapshot.take_packages();
+        let (store, availability) = snapshot.import(now);
+        let rejected = packages
+            .into_iter()
+            .filter_map(|work| self.enqueue(work).err())
+            .collect();
+
+        (store, availability, rejected)
+    }
+}
+
+impl<H> CoreSealManager<H>
+where
+    H: CoreSealHandle,
+    H::WorkPackage: Clone,
+    H::WorkReport: Clone,
+{
+    /// Work packages in flight: those the workers are refining or attesting,
+    /// and then those not yet started, in submission order.
+    pub fn in_flight(&self) -> Vec<Work<H>> {
+        let shared = lock(&self.shared);
+        shared
+            .current
+            .values()
+            .chain(shared.queue.iter().map(|(_, work)| work))
+            .cloned()
+            .collect()
+    }
+
+    /// Export the in-flight state, at `now` on the clock of the availability
+    /// deadlines: the report store, the availability subsystem, and the work
+    /// packages in flight, with the failed attempts of the reports being
+    /// attested.
+    pub fn export_state<BlockId, Assurer>(
+        &self,
+        store: &ReportStore<H::WorkReport, BlockId>,
+        availability: &Availability<Assurer>,
+        now: Duration,
+    ) -> SealSnapshot<H::WorkReport, BlockId, Assurer, Work<H>>
+    where
+        BlockId: Clone,
+        Assurer: Eq + Hash + Clone,
+    {
+        SealSnapshot::export(store, availability, now).with_packages(self.in_flight())
+    }
+}
+
+fn lock<P, R>(shared: &SharedState<P, R>) -> MutexGuard<'_, Shared<P, R>> {
+    // Nothing panics while holding the lock.
+    shared.lock().expect("lock is never poisoned; qed")
+}
+
+/// Take the first work queued for the worker, as its current work.
+fn take<P: Clone, R: Clone>(shared: &SharedState<P, R>, worker_id: u64) -> Option<InFlight<P, R>> {
+    let mut shared = lock(shared);
+    let index = shared.queue.iter().position(|(id, _)| *id == worker_id)?;
+    let work = shared.queue.remove(index).1;
+    shared.current.insert(worker_id, work.clone());
+    Some(work)
+}
@@ -9,9 +9,10 @@
 //!   a work report.
 //! * It further ensures availability, and later handle disputes.
 //!
-//! The types of the in-core sealing algorithm defined here deals with only a
-//! single core (in another word, there's no core ID). It is expected that
-//! different cores will need their own worker thread anyway.
+//! The worker and its handle deal with a single core, and have no core ID,
+//! as each core needs its own worker thread anyway. The core assignment is
+//! owned by the [`CoreSealManager`], which runs one worker per core and
+//! routes work packages to them by their assigned core.
 //!
 //! We do not have the concept of a block in this module. The algorithm works
 //! through a *handle*, which acts as a state machine, with always-up-to-date
@@ -28,11 +29,49 @@
 //! ## Cycle of the worker
 //!
 //! The worker thread accepts a stream receiving work packages. Upon checking
-//! that the work package is authorized, it takes ownership of it, refines it to
-//! get the work report, and then attest it to publish on the relay chain.
+//! that the work package is within the size and gas limits of the core, and
+//! that it is authorized, it takes ownership of it, refines it to get the work
+//! report, and then attest it to publish on the relay chain.
 //!
 //! Another stream will receive a tuple of work packages and work reports
 //! already generated, and attest them.
+//!
+//! The worker does not use the ambient runtime or the wall clock. It spawns
+//! through a [`Spawn`] executor and measures timeouts through a [`Timer`], so
+//! that tests can drive it with a virtual clock.
+
+mod authorization;
+mod availability;
+mod executor;
+mod manager;
+mod report;
+mod segment;
+mod snapshot;
+mod validators;
+mod worker;
+
+pub use self::authorization::{
+    AuthorizationError, AuthorizationPool, Authorizer, ExecutionError,
+    DEFAULT_AUTHORIZATION_GAS_LIMIT,
+};
+pub use self::availability::{Availability, AvailabilityEvent};
+pub use self::executor::{ExecutionOutcome, Executor};
+pub use self::manager::{CoreAssigned, CoreEvent, CoreId, CoreSealManager, InFlight, SubmitError};
+pub use self::report::{
+    AvailabilityStatus, DisputeState, ReportEntry, ReportEvent, ReportStore, WorkReport,
+    WorkReportId,
+};
+pub use self::segment::{
+    AuthorizerHash, RefineError, Refined, Segment, SegmentRef, SegmentStore, WorkPackage,
+    WorkPackageId,
+};
+pub use self::snapshot::SealSnapshot;
+pub use self::validators::{ValidatorDirectory, ValidatorIndex, ValidatorSet};
+pub use self::worker::{
+    AdmissionError, CoreSealWorker, Spawn, Timer, WorkerError, DEFAULT_ATTEST_ATTEMPTS,
+    DEFAULT_ATTEST_BACKOFF, DEFAULT_MAX_ATTEST_BACKOFF, DEFAULT_MAX_PACKAGE_GAS,
+    DEFAULT_MAX_PACKAGE_SIZE, DEFAULT_REFINE_TIMEOUT,
+};
 
 use std::future::Future;
 
@@ -46,17 +85,20 @@ pub trait CoreSealHandle {
     type Error;
 
     /// A work package, pre-refine.
-    type WorkPackage;
+    type WorkPackage: WorkPackage;
     /// A work report from a work package, post-refine.
-    type WorkReport;
+    type WorkReport: WorkReport;
 
-    /// Whether the work package is authorized on the current core.
+    /// Whether the work package is authorized on the current core, such as
+    /// through the [`AuthorizationPool`] of the core.
     fn is_authorized(&self, work: &Self::WorkPackage) -> bool;
-    /// Refine from a work package into a work report.
+    /// Refine from a work package into a work report, given the segments it
+    /// imports. See [`SegmentStore::refine`] to resolve them.
     fn refine(
         &self,
         work: Self::WorkPackage,
-    ) -> impl Future<Output = Result<Self::WorkReport, Self::Error>> + Send;
+        imports: Vec<Segment>,
+    ) -> impl Future<Output = Result<Refined<Self::WorkReport>, Self::Error>> + Send;
 
     /// Attest to a work report and submit it.
     fn attest(
@@ -0,0 +1,274 @@
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use blockchain::{ForkTreeBest, Identified};
+use std::collections::HashMap;
+
+/// Identifier of a work report, the Blake2b-256 hash of its encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorkReportId(pub [u8; 32]);
+
+/// A work report, post-refine.
+pub trait WorkReport {
+    /// Canonical encoding of the report. Two reports with the same encoding
+    /// are the same report.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Identifier of the report.
+    fn id(&self) -> WorkReportId {
+        WorkReportId(Blake2b::<U32>::digest(self.encode()).into())
+    }
+}
+
+/// Availability status of a work report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AvailabilityStatus {
+    /// Guaranteed, but not yet available.
+    #[default]
+    Pending,
+    /// Enough assurances have been received.
+    Available,
+}
+
+/// Dispute state of a work report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisputeState {
+    /// No dispute.
+    #[default]
+    None,
+    /// A dispute is open.
+    Open,
+    /// The dispute is resolved.
+    Resolved {
+        /// Whether the report was judged valid.
+        valid: bool,
+    },
+}
+
+/// Event of the report store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportEvent {
+    /// The report was not included in a relay chain block within the
+    /// inclusion window, and is no longer tracked. The guarantor can stop
+    /// tracking it too.
+    Expired {
+        /// The report.
+        report_id: WorkReportId,
+    },
+}
+
+/// A work report tracked by the in-core sealing subsystems.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReportEntry<Report, BlockId> {
+    /// The work report.
+    pub report: Report,
+    /// The relay chain block the report was guaranteed in.
+    pub guaranteed_in: Option<BlockId>,
+    /// Slot the report was guaranteed at, from which its inclusion window
+    /// starts.
+    pub guaranteed_at: Option<u64>,
+    /// Availability status.
+    pub availability: AvailabilityStatus,
+    /// Whether the report has been audited.
+    pub audited: bool,
+    /// Dispute state.
+    pub dispute: DisputeState,
+}
+
+impl<Report, BlockId> ReportEntry<Report, BlockId> {
+    /// Whether a dispute is open, or has judged the report invalid.
+    pub fn is_disputed(&self) -> bool {
+        !matches!(
+            self.dispute,
+            DisputeState::None | DisputeState::Resolved { valid: true }
+        )
+    }
+}
+
+/// Store of work reports, keyed by their ids. Availability, auditing and
+/// disputes all track their state of a report in its single entry.
+#[derive(Debug, Clone)]
+pub struct ReportStore<Report, BlockId> {
+    entries: HashMap<WorkReportId, ReportEntry<Report, BlockId>>,
+    finality_confirmations: usize,
+    inclusion_window: Option<u64>,
+}
+
+impl<Report, BlockId> ReportStore<Report, BlockId> {
+    /// Create a new empty store. Availability of a report is only final once
+    /// `finality_confirmations` relay chain blocks build on its guaranteeing
+    /// block.
+    pub fn new(finality_confirmations: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            finality_confirmations,
+            inclusion_window: None,
+        }
+    }
+
+    /// Evict the reports guaranteed at a slot that are still not included in
+    /// a relay chain block `slots` slots later, with
+    /// [`Self::check_inclusion`].
+    pub fn with_inclusion_window(mut self, slots: u64) -> Self {
+        self.inclusion_window = Some(slots);
+        self
+    }
+
+    /// Number of confirming blocks needed for availability to be final.
+    pub fn finality_confirmations(&self) -> usize {
+        self.finality_confirmations
+    }
+
+    /// Number of slots a guaranteed report has to be included, if limited.
+    pub fn inclusion_window(&self) -> Option<u64> {
+        self.inclusion_window
+    }
+
+    /// Get the entry of a report.
+    pub fn get(&self, id: &WorkReportId) -> Option<&ReportEntry<Report, BlockId>> {
+        self.entries.get(id)
+    }
+
+    /// Get the mutable entry of a report.
+    pub fn get_mut(&mut self, id: &WorkReportId) -> Option<&mut ReportEntry<Report, BlockId>> {
+        self.entries.get_mut(id)
+    }
+
+    /// Whether the report is in the store.
+    pub fn contains(&self, id: &WorkReportId) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    /// Number of reports in the store.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over all entries.
+    pub fn iter(&self) -> impl Iterator<Item = (&WorkReportId, &ReportEntry<Report, BlockId>)> {
+        self.entries.iter()
+    }
+
+    /// Evict the reports past their inclusion window at slot `now` that are
+    /// still not included, and get them as expired. Reports inserted
+    /// without a guarantee slot never expire.
+    pub fn check_inclusion(&mut self, now: u64) -> Vec<ReportEvent> {
+        let Some(window) = self.inclusion_window else {
+            return Vec::new();
+        };
+
+        let mut expired = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.guaranteed_in.is_none()
+                    && entry
+                        .guaranteed_at
+                        .map_or(false, |slot| now >= slot.saturating_add(window))
+            })
+            .map(|(report_id, _)| *report_id)
+            .collect::<Vec<_>>();
+        expired.sort();
+
+        for report_id in &expired {
+            self.entries.remove(report_id);
+        }
+
+        expired
+            .into_iter()
+            .map(|report_id| ReportEvent::Expired { report_id })
+            .collect()
+    }
+}
+
+impl<Report: WorkReport, BlockId> ReportStore<Report, BlockId> {
+    /// Insert a new report, and get its id. If the report is already in the
+    /// store, its existing entry is kept.
+    pub fn insert(&mut self, report: Report) -> WorkReportId {
+        self.insert_entry(report, None)
+    }
+
+    /// Insert a new report guaranteed at the slot, and get its id, so that it
+    /// expires if not included within the inclusion window. If the report is
+    /// already in the store, its existing entry is kept.
+    pub fn insert_guaranteed(&mut self, report: Report, slot: u64) -> WorkReportId {
+        self.insert_entry(report, Some(slot))
+    }
+
+    fn insert_entry(&mut self, report: Report, guaranteed_at: Option<u64>) -> WorkReportId {
+        let id = report.id();
+        self.entries.entry(id).or_insert_with(|| ReportEntry {
+            report,
+            guaranteed_in: None,
+            guaranteed_at,
+            availability: Default::default(),
+            audited: false,
+            dispute: Default::default(),
+        });
+        id
+    }
+}
+
+impl<Report, BlockId: Copy + Eq + core::hash::Hash> ReportStore<Report, BlockId> {
+    /// Whether availability of the report is final. That is, the report is
+    /// available, not disputed, and its guaranteeing block has at least
+    /// `finality_confirmations` descendants on the canonical chain ending at
+    /// the best block of the fork tree.
+    pub fn availability_finalized<F>(&self, id: &WorkReportId, fork_tree: &F) -> bool
+    where
+        F: ForkTreeBest,
+        F::Block: Identified<Identifier = BlockId>,
+    {
+        let Some(entry) = self.entries.get(id) else {
+            return false;
+        };
+
+        if entry.availability != AvailabilityStatus::Available || entry.is_disputed() {
+            return false;
+        }
+
+        let Some(guaranteed_in) = entry.guaranteed_in else {
+            return false;
+        };
+
+        let Ok(best) = fork_tree.best_id() else {
+            return false;
+        };
+        let (Ok(guaranteed_depth), Ok(best_depth)) = (
+            fork_tree.block_depth(&guaranteed_in),
+            fork_tree.block_depth(&best),
+        ) else {
+            return false;
+        };
+
+        best_depth >= guaranteed_depth + self.finality_confirmations
+            && fork_tree
+                .is_ancestor(&best, &guaranteed_in)
+                .unwrap_or(false)
+    }
+
+    /// Remove a report once its availability is final, with
+    /// [`Self::availability_finalized`], as it then no longer needs to be
+    /// tracked. Reports not yet final are kept, and None is returned.
+    pub fn remove_finalized<F>(
+        &mut self,
+        id: &WorkReportId,
+        fork_tree: &F,
+    ) -> Option<ReportEntry<Report, BlockId>>
+    where
+        F: ForkTreeBest,
+        F::Block: Identified<Identifier = BlockId>,
+    {
+        if !self.availability_finalized(id, fork_tree) {
+            return None;
+        }
+        self.entries.remove(id)
+    }
+}
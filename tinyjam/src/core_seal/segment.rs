@@ -0,0 +1,133 @@
+use super::CoreSealHandle;
+use crate::accumulate::Gas;
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use std::collections::HashMap;
+
+/// A segment of data, exported by a work package for later ones to import.
+pub type Segment = Vec<u8>;
+
+/// Identifier of a work package, the Blake2b-256 hash of its encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WorkPackageId(pub [u8; 32]);
+
+/// Hash of the code of an authorizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AuthorizerHash(pub [u8; 32]);
+
+/// A work package, pre-refine.
+pub trait WorkPackage {
+    /// Canonical encoding of the package.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Size of the canonical encoding. Encodes the package by default, so
+    /// packages knowing their size should override it.
+    fn encoded_size(&self) -> usize {
+        self.encode().len()
+    }
+
+    /// The authorizer the package is to be authorized by.
+    fn authorizer(&self) -> AuthorizerHash;
+
+    /// Token consumed by the authorizer, such as a signature. `None` if the
+    /// package carries no token.
+    fn authorization_token(&self) -> Option<&[u8]>;
+
+    /// Segments the package imports, in the order refine receives them.
+    fn imports(&self) -> Vec<SegmentRef>;
+
+    /// Gas the package declares for refine. Zero by default.
+    fn gas(&self) -> Gas {
+        0
+    }
+
+    /// Identifier of the package.
+    fn id(&self) -> WorkPackageId {
+        WorkPackageId(Blake2b::<U32>::digest(self.encode()).into())
+    }
+}
+
+/// Reference to a segment exported by a work package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentRef {
+    /// The exporting package.
+    pub package: WorkPackageId,
+    /// Index in the exports of the package.
+    pub index: usize,
+}
+
+/// Output of refining a work package.
+#[derive(Debug, Clone)]
+pub struct Refined<Report> {
+    /// The work report.
+    pub report: Report,
+    /// Segments exported by the package.
+    pub exports: Vec<Segment>,
+}
+
+/// Error of refining through a segment store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefineError<E> {
+    /// An imported segment is not available.
+    MissingSegment(SegmentRef),
+    /// The handle failed to refine.
+    Handle(E),
+}
+
+/// Store of segments exported by work packages.
+#[derive(Debug, Clone, Default)]
+pub struct SegmentStore {
+    exports: HashMap<WorkPackageId, Vec<Segment>>,
+}
+
+impl SegmentStore {
+    /// Create a new empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the exported segments of a package, replacing any previous
+    /// exports of it.
+    pub fn export(&mut self, package_id: WorkPackageId, segments: Vec<Segment>) {
+        self.exports.insert(package_id, segments);
+    }
+
+    /// Get an exported segment.
+    pub fn import(&self, segment_ref: &SegmentRef) -> Option<Segment> {
+        self.exports
+            .get(&segment_ref.package)?
+            .get(segment_ref.index)
+            .cloned()
+    }
+
+    /// Remove all exports of a package, once no later package can import
+    /// them.
+    pub fn remove(&mut self, package_id: &WorkPackageId) -> Option<Vec<Segment>> {
+        self.exports.remove(package_id)
+    }
+
+    /// Refine a work package. Its imports are resolved before the handle
+    /// refines it, and its exports are registered after.
+    pub async fn refine<H: CoreSealHandle>(
+        &mut self,
+        handle: &H,
+        work: H::WorkPackage,
+    ) -> Result<H::WorkReport, RefineError<H::Error>> {
+        let package_id = work.id();
+        let imports = work
+            .imports()
+            .iter()
+            .map(|segment_ref| {
+                self.import(segment_ref)
+                    .ok_or(RefineError::MissingSegment(*segment_ref))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let refined = handle
+            .refine(work, imports)
+            .await
+            .map_err(RefineError::Handle)?;
+        self.export(package_id, refined.exports);
+
+        Ok(refined.report)
+    }
+}
@@ -0,0 +1,147 @@
+use super::{Availability, ReportEntry, ReportStore, WorkReport, WorkReportId};
+use std::{hash::Hash, time::Duration};
+
+/// Assurances of a report still waiting for them, in a [`SealSnapshot`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct PendingSnapshot<Assurer> {
+    report_id: WorkReportId,
+    /// Time left before the deadline, as the clock of the restored node
+    /// starts anew.
+    remaining: Duration,
+    assurers: Vec<Assurer>,
+}
+
+/// In-flight state of the in-core sealing subsystems of a node: the tracked
+/// reports with their availability, audit and dispute state, and the
+/// assurances counted so far, so that a node restarted or migrated resumes
+/// tracking them rather than losing them.
+///
+/// Only the state of the subsystems is kept, without any secret, as none of
+/// them holds keys. Snapshots of a
+/// [`CoreSealManager`](super::CoreSealManager) also keep the work packages
+/// submitted to it but not yet attested, along with the reports being
+/// attested and their failed attempts, so that they are submitted again.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SealSnapshot<Report, BlockId, Assurer, Package = ()> {
+    finality_confirmations: usize,
+    inclusion_window: Option<u64>,
+    /// Reports by id, sorted.
+    reports: Vec<(WorkReportId, ReportEntry<Report, BlockId>)>,
+    threshold: usize,
+    timeout: Duration,
+    /// Pending availability by report id, sorted.
+    pending: Vec<PendingSnapshot<Assurer>>,
+    /// Work packages in flight, in submission order.
+    packages: Vec<Package>,
+}
+
+impl<Report, BlockId, Assurer> SealSnapshot<Report, BlockId, Assurer>
+where
+    Report: WorkReport + Clone,
+    BlockId: Clone,
+    Assurer: Eq + Hash + Clone,
+{
+    /// Export the state of the report store and the availability subsystem,
+    /// at `now` on the clock of the availability deadlines.
+    pub fn export(
+        store: &ReportStore<Report, BlockId>,
+        availability: &Availability<Assurer>,
+        now: Duration,
+    ) -> Self {
+        let mut reports = store
+            .iter()
+            .map(|(report_id, entry)| (*report_id, entry.clone()))
+            .collect::<Vec<_>>();
+        reports.sort_by_key(|(report_id, _)| *report_id);
+
+        let mut pending = availability
+            .pending()
+            .map(|(report_id, deadline, assurers)| PendingSnapshot {
+                report_id,
+                remaining: deadline.saturating_sub(now),
+                assurers: assurers.iter().cloned().collect(),
+            })
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|pending| pending.report_id);
+
+        Self {
+            finality_confirmations: store.finality_confirmations(),
+            inclusion_window: store.inclusion_window(),
+            reports,
+            threshold: availability.threshold(),
+            timeout: availability.timeout(),
+            pending,
+            packages: Vec::new(),
+        }
+    }
+
+    /// Keep the work packages in flight.
+    pub fn with_packages<Package>(
+        self,
+        packages: Vec<Package>,
+    ) -> SealSnapshot<Report, BlockId, Assurer, Package> {
+        SealSnapshot {
+            finality_confirmations: self.finality_confirmations,
+            inclusion_window: self.inclusion_window,
+            reports: self.reports,
+            threshold: self.threshold,
+            timeout: self.timeout,
+            pending: self.pending,
+            packages,
+        }
+    }
+}
+
+impl<Report, BlockId, Assurer, Package> SealSnapshot<Report, BlockId, Assurer, Package>
+where
+    Report: WorkReport + Clone,
+    BlockId: Clone,
+    Assurer: Eq + Hash + Clone,
+{
+    /// The work packages in flight.
+    pub fn packages(&self) -> &[Package] {
+        &self.packages
+    }
+
+    /// Take the work packages in flight out of the snapshot.
+    pub fn take_packages(&mut self) -> Vec<Package> {
+        std::mem::take(&mut self.packages)
+    }
+
+    /// Import the state into a new report store and availability subsystem,
+    /// at `now` on the clock of the restored node, from which the deadlines
+    /// left restart. The work packages are left to the caller, see
+    /// [`Self::take_packages`].
+    pub fn import(self, now: Duration) -> (ReportStore<Report, BlockId>, Availability<Assurer>) {
+        let mut store = ReportStore::new(self.finality_confirmations);
+        if let Some(slots) = self.inclusion_window {
+            store = store.with_inclusion_window(slots);
+        }
+        for (_, entry) in self.reports {
+            let report_id = match entry.guaranteed_at {
+                Some(slot) => store.insert_guaranteed(entry.report, slot),
+                None => store.insert(entry.report),
+            };
+            let restored = store
+                .get_mut(&report_id)
+                .expect("report is just inserted; qed");
+            restored.guaranteed_in = entry.guaranteed_in;
+            restored.availability = entry.availability;
+            restored.audited = entry.audited;
+            restored.dispute = entry.dispute;
+        }
+
+        let mut availability = Availability::new(self.threshold, self.timeout);
+        for pending in self.pending {
+            availability.restore(
+                pending.report_id,
+                now + pending.remaining,
+                pending.assurers.into_iter().collect(),
+            );
+        }
+
+        (store, availability)
+    }
+}
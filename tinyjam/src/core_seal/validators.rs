@@ -0,0 +1,140 @@
+use std::{collections::HashMap, hash::Hash};
+
+/// Position of a validator in the [`ValidatorSet`] of the epoch.
+pub type ValidatorIndex = usize;
+
+/// Set of validators of the current epoch.
+///
+/// The Byzantine thresholds of the subsystems are derived from it, so that
+/// they agree on them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatorSet<V> {
+    validators: Vec<V>,
+}
+
+impl<V> ValidatorSet<V> {
+    /// Create a new validator set.
+    pub fn new(validators: Vec<V>) -> Self {
+        Self { validators }
+    }
+
+    /// The validators, in order.
+    pub fn validators(&self) -> &[V] {
+        &self.validators
+    }
+
+    /// Number of validators.
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    /// Smallest number of validators that is more than two thirds of the
+    /// set, `⌊2n/3⌋ + 1`. Any two supermajorities share an honest validator
+    /// as long as fewer than a third is faulty.
+    pub fn supermajority_threshold(&self) -> usize {
+        2 * self.len() / 3 + 1
+    }
+
+    /// Number of assurances for a work report to be available. A
+    /// supermajority, `⌊2n/3⌋ + 1`, so that enough honest validators hold its
+    /// chunks to recover it.
+    pub fn availability_threshold(&self) -> usize {
+        self.supermajority_threshold()
+    }
+}
+
+impl<V: PartialEq> ValidatorSet<V> {
+    /// Whether the validator is in the set.
+    pub fn contains(&self, validator: &V) -> bool {
+        self.validators.contains(validator)
+    }
+}
+
+/// Resolution between the validators of the epoch, by index, and the network
+/// peers operating them.
+///
+/// Peers advertise their session key, such as in their network peer info,
+/// and a peer is the validator whose key it advertised. Advertisements
+/// outlive epochs, so that a peer advertising the key of the next epoch is
+/// resolved as soon as it rotates in. A validator without an advertising
+/// peer resolves to `None`, for the caller to fall back to discovery.
+#[derive(Debug, Clone)]
+pub struct ValidatorDirectory<V, P> {
+    validators: ValidatorSet<V>,
+    indices: HashMap<V, ValidatorIndex>,
+    keys: HashMap<P, V>,
+    peers: HashMap<V, P>,
+}
+
+impl<V, P> ValidatorDirectory<V, P>
+where
+    V: Clone + Eq + Hash,
+    P: Clone + Eq + Hash,
+{
+    /// Create a new directory of the validator set, with no advertisement.
+    pub fn new(validators: ValidatorSet<V>) -> Self {
+        let mut directory = Self {
+            validators: ValidatorSet::new(Vec::new()),
+            indices: HashMap::new(),
+            keys: HashMap::new(),
+            peers: HashMap::new(),
+        };
+        directory.rotate(validators);
+        directory
+    }
+
+    /// The validator set of the epoch.
+    pub fn validators(&self) -> &ValidatorSet<V> {
+        &self.validators
+    }
+
+    /// Switch to the validator set of a new epoch, keeping the
+    /// advertisements.
+    pub fn rotate(&mut self, validators: ValidatorSet<V>) {
+        self.indices = validators
+            .validators()
+            .iter()
+            .enumerate()
+            .map(|(index, key)| (key.clone(), index))
+            .collect();
+        self.validators = validators;
+    }
+
+    /// Record the session key advertised by a peer. It replaces the key the
+    /// peer advertised before, and the peer that advertised the key before.
+    pub fn advertise(&mut self, peer: P, key: V) {
+        if let Some(previous) = self.keys.insert(peer.clone(), key.clone()) {
+            if self.peers.get(&previous) == Some(&peer) {
+                self.peers.remove(&previous);
+            }
+        }
+        if let Some(previous) = self.peers.insert(key, peer.clone()) {
+            if previous != peer {
+                self.keys.remove(&previous);
+            }
+        }
+    }
+
+    /// Forget the advertisement of a peer, such as when it disconnects.
+    pub fn remove(&mut self, peer: &P) {
+        if let Some(key) = self.keys.remove(peer) {
+            self.peers.remove(&key);
+        }
+    }
+
+    /// Peer operating the validator, if it advertised its key.
+    pub fn peer_for(&self, index: ValidatorIndex) -> Option<&P> {
+        self.peers.get(self.validators.validators().get(index)?)
+    }
+
+    /// Index of the validator the peer operates, if it advertised the key of
+    /// a validator of the epoch.
+    pub fn index_for(&self, peer: &P) -> Option<ValidatorIndex> {
+        self.indices.get(self.keys.get(peer)?).copied()
+    }
+}
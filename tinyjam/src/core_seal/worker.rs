@@ -0,0 +1,326 @@
+use super::{CoreSealHandle, RefineError, SegmentStore, WorkPackage, WorkReport, WorkReportId};
+use crate::accumulate::Gas;
+use futures::{
+    channel::oneshot,
+    future::{self, Either},
+    stream::{Stream, StreamExt},
+};
+use std::{
+    collections::hash_map::RandomState, future::Future, hash::BuildHasher, pin::pin, pin::Pin,
+    time::Duration,
+};
+
+/// Default time a refine can take before it is abandoned.
+pub const DEFAULT_REFINE_TIMEOUT: Duration = Duration::from_secs(6);
+/// Default number of attempts to attest a report before giving up.
+pub const DEFAULT_ATTEST_ATTEMPTS: usize = 3;
+/// Default delay before the first retry of a failed attestation.
+pub const DEFAULT_ATTEST_BACKOFF: Duration = Duration::from_millis(500);
+/// Default maximum delay between attestation retries, before jitter.
+pub const DEFAULT_MAX_ATTEST_BACKOFF: Duration = Duration::from_secs(30);
+/// Default maximum size of an encoded work package, in bytes.
+pub const DEFAULT_MAX_PACKAGE_SIZE: usize = 12 * 1024 * 1024;
+/// Default maximum gas a work package can declare for refine.
+pub const DEFAULT_MAX_PACKAGE_GAS: Gas = 5_000_000_000;
+
+/// Executor the worker spawns onto.
+pub trait Spawn {
+    /// Spawn a future to run in the background.
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>);
+}
+
+/// Clock the worker measures timeouts with.
+pub trait Timer {
+    /// Future completing once a delay elapsed.
+    type Delay: Future<Output = ()> + Send;
+
+    /// Get a future completing after `duration`.
+    fn delay(&self, duration: Duration) -> Self::Delay;
+}
+
+/// Error of admitting a work package to a core, checked before authorization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionError {
+    /// The encoded package is larger than the core accepts.
+    TooLarge {
+        /// Size of the encoded package.
+        size: usize,
+        /// Maximum size of the core.
+        max: usize,
+    },
+    /// The package declares more gas than the core accepts.
+    OverGas {
+        /// Gas declared by the package.
+        gas: Gas,
+        /// Maximum gas of the core.
+        max: Gas,
+    },
+}
+
+/// Error of processing a work package in the worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkerError<E> {
+    /// The work package exceeds the limits of the core.
+    Inadmissible(AdmissionError),
+    /// The work package is not authorized on the core.
+    Unauthorized,
+    /// Refine did not complete before the timeout.
+    Timeout,
+    /// Refine failed.
+    Refine(RefineError<E>),
+    /// Attesting the refined report failed on every attempt.
+    AttestationFailed {
+        /// The report that was not attested.
+        report: WorkReportId,
+        /// Number of attempts made.
+        attempts: usize,
+        /// Error of the last attempt.
+        error: E,
+    },
+}
+
+/// The in-core sealing worker of a single core. It is generic over the
+/// executor and the clock, so that tests can drive it deterministically.
+pub struct CoreSealWorker<H, S, T> {
+    handle: H,
+    spawner: S,
+    timer: T,
+    segments: SegmentStore,
+    refine_timeout: Duration,
+    attest_attempts: usize,
+    attest_backoff: Duration,
+    max_attest_backoff: Duration,
+    max_package_size: usize,
+    max_package_gas: Gas,
+    /// Per-worker randomness of the retry jitter, so that validators retrying
+    /// the same report do not retry in lockstep.
+    jitter: RandomState,
+}
+
+impl<H: CoreSealHandle, S: Spawn, T: Timer> CoreSealWorker<H, S, T> {
+    /// Create a new worker.
+    pub fn new(handle: H, spawner: S, timer: T) -> Self {
+        Self {
+            handle,
+            spawner,
+            timer,
+            segments: SegmentStore::new(),
+            refine_timeout: DEFAULT_REFINE_TIMEOUT,
+            attest_attempts: DEFAULT_ATTEST_ATTEMPTS,
+            attest_backoff: DEFAULT_ATTEST_BACKOFF,
+            max_attest_backoff: DEFAULT_MAX_ATTEST_BACKOFF,
+            max_package_size: DEFAULT_MAX_PACKAGE_SIZE,
+            max_package_gas: DEFAULT_MAX_PACKAGE_GAS,
+            jitter: RandomState::new(),
+        }
+    }
+
+    /// Set the time a refine can take before it is abandoned.
+    pub fn with_refine_timeout(mut self, refine_timeout: Duration) -> Self {
+        self.refine_timeout = refine_timeout;
+        self
+    }
+
+    /// Set the number of attempts to attest a report, at least one, and the
+    /// delay before the first retry. The delay doubles on each retry, up to
+    /// the maximum attest backoff, with up to half of it added as jitter.
+    pub fn with_attest_retries(mut self, attempts: usize, backoff: Duration) -> Self {
+        self.attest_attempts = attempts.max(1);
+        self.attest_backoff = backoff;
+        self
+    }
+
+    /// Set the maximum delay between attestation retries, before jitter.
+    pub fn with_max_attest_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_attest_backoff = max_backoff;
+        self
+    }
+
+    /// Set the maximum size of an encoded work package, and the maximum gas
+    /// it can declare, for it to be admitted.
+    pub fn with_admission_limits(mut self, max_size: usize, max_gas: Gas) -> Self {
+        self.max_package_size = max_size;
+        self.max_package_gas = max_gas;
+        self
+    }
+
+    /// Get the handle.
+    pub fn handle(&self) -> &H {
+        &self.handle
+    }
+
+    /// Get the segment store.
+    pub fn segments(&self) -> &SegmentStore {
+        &self.segments
+    }
+
+    /// Check a work package against the size and gas limits of the core,
+    /// cheaply rejecting it before authorization and refine.
+    pub fn admit(&self, work: &H::WorkPackage) -> Result<(), AdmissionError> {
+        let gas = work.gas();
+        if gas > self.max_package_gas {
+            return Err(AdmissionError::OverGas {
+                gas,
+                max: self.max_package_gas,
+            });
+        }
+
+        let size = work.encoded_size();
+        if size > self.max_package_size {
+            return Err(AdmissionError::TooLarge {
+                size,
+                max: self.max_package_size,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Refine an admitted and authorized work package, within the refine
+    /// timeout.
+    pub async fn refine(
+        &mut self,
+        work: H::WorkPackage,
+    ) -> Result<H::WorkReport, WorkerError<H::Error>> {
+        self.admit(&work).map_err(WorkerError::Inadmissible)?;
+        if !self.handle.is_authorized(&work) {
+            return Err(WorkerError::Unauthorized);
+        }
+
+        let refine = pin!(self.segments.refine(&self.handle, work));
+        let timeout = pin!(self.timer.delay(self.refine_timeout));
+        match future::select(refine, timeout).await {
+            Either::Left((result, _)) => result.map_err(WorkerError::Refine),
+            Either::Right(((), _)) => Err(WorkerError::Timeout),
+        }
+    }
+
+    /// Refine an authorized work package, and attest the report. Returns the
+    /// id of the attested report.
+    pub async fn refine_and_attest(
+        &mut self,
+        work: H::WorkPackage,
+    ) -> Result<WorkReportId, WorkerError<H::Error>>
+    where
+        H::WorkReport: Clone,
+    {
+        let report = self.refine(work).await?;
+        self.attest(report).await
+    }
+
+    /// Attest a report, retrying with backoff until an attempt succeeds or
+    /// all attempts failed.
+    pub async fn attest(
+        &mut self,
+        report: H::WorkReport,
+    ) -> Result<WorkReportId, WorkerError<H::Error>>
+    where
+        H::WorkReport: Clone,
+    {
+        self.attest_after(report, 0, |_| ()).await
+    }
+
+    /// Attest a report already attempted `failed` times, such as before a
+    /// restart, right away and then with backoff. `on_failure` is told the
+    /// number of failed attempts so far after each failure that is retried.
+    pub(super) async fn attest_after<F>(
+        &mut self,
+        report: H::WorkReport,
+        failed: usize,
+        mut on_failure: F,
+    ) -> Result<WorkReportId, WorkerError<H::Error>>
+    where
+        H::WorkReport: Clone,
+        F: FnMut(usize),
+    {
+        let report_id = report.id();
+        let mut attempt = failed + 1;
+        loop {
+            match self.handle.attest(report.clone()).await {
+                Ok(()) => return Ok(report_id),
+                Err(error) if attempt >= self.attest_attempts => {
+                    return Err(WorkerError::AttestationFailed {
+                        report: report_id,
+                        attempts: attempt,
+                        error,
+                    })
+                }
+                Err(_) => {
+                    on_failure(attempt);
+                    self.timer
+                        .delay(self.retry_delay(&report_id, attempt))
+                        .await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Delay after the failed attempt, starting at the attest backoff and
+    /// doubling up to the maximum attest backoff, plus a jitter of up to half
+    /// of it.
+    fn retry_delay(&self, report: &WorkReportId, attempt: usize) -> Duration {
+        let exponent = u32::try_from(attempt - 1).unwrap_or(u32::MAX);
+        let delay = self
+            .attest_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_attest_backoff);
+
+        let permille = (self.jitter.hash_one((report, attempt)) % 1000) as u32;
+        delay.saturating_add(delay / 2000 * permille)
+    }
+
+    /// Refine and attest work packages from the stream, until it ends. Work
+    /// packages failing to refine are dropped. Returns the error of the first
+    /// attestation failing on every attempt.
+    pub async fn run<P>(mut self, packages: P) -> Result<(), H::Error>
+    where
+        P: Stream<Item = H::WorkPackage>,
+        H::WorkReport: Clone,
+    {
+        let mut packages = pin!(packages);
+        while let Some(work) = packages.next().await {
+            if let Err(WorkerError::AttestationFailed { error, .. }) =
+                self.refine_and_attest(work).await
+            {
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<H, S, T> CoreSealWorker<H, S, T>
+where
+    H: CoreSealHandle + Send + Sync + 'static,
+    H::WorkPackage: Send,
+    H::WorkReport: Clone + Send,
+    H::Error: Send,
+    S: Spawn + Clone + Send + 'static,
+    T: Timer + Send + 'static,
+{
+    /// Run the worker on its own executor. See [`Self::run`]. Returns a
+    /// receiver of its result, canceled if the executor drops the worker.
+    pub fn spawn<P>(self, packages: P) -> oneshot::Receiver<Result<(), H::Error>>
+    where
+        P: Stream<Item = H::WorkPackage> + Send + 'static,
+    {
+        let (sender, result) = oneshot::channel();
+        self.spawn_with(|worker| async move {
+            // No one may wait for the result.
+            let _ = sender.send(worker.run(packages).await);
+        });
+        result
+    }
+
+    /// Run a task driving the worker on its own executor.
+    pub(super) fn spawn_with<F, Fut>(self, task: F)
+    where
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let spawner = self.spawner.clone();
+        spawner.spawn(Box::pin(task(self)));
+    }
+}
@@ -14,6 +14,7 @@
 //! the block production algorithm to simple Aura. Or you can disable
 //! map-reduce, so that the chain notes raw blobs without any functionality.
 
+pub mod accumulate;
 pub mod core_seal;
 
 pub struct State<Consensus> {
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use tinyjam::accumulate::{
+    Accumulate, AccumulateQueue, AccumulateService, BlockAccumulation, Gas, OutputError,
+    ReportOutputs, ServiceAccumulator, ServiceId, ServiceStore,
+};
+use tinyjam::core_seal::WorkReport;
+
+/// A report with the gas it needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Report {
+    package: u32,
+    gas: Gas,
+}
+
+#[derive(Default)]
+struct Counter {
+    accumulated: Vec<u32>,
+    /// Package failing to accumulate, if any.
+    failing: Option<u32>,
+}
+
+impl Accumulate for Counter {
+    type Error = ();
+    type WorkReport = Report;
+
+    fn gas_required(&self, report: &Report) -> Gas {
+        report.gas
+    }
+
+    fn accumulate(&mut self, report: &Report, gas_remaining: Gas) -> Result<Gas, ()> {
+        assert!(report.gas <= gas_remaining);
+        if self.failing == Some(report.package) {
+            return Err(());
+        }
+        self.accumulated.push(report.package);
+        Ok(report.gas)
+    }
+}
+
+/// A queue for blocks of 100 gas, with reports numbered in order.
+fn queue(counter: &Counter, gas: &[Gas]) -> AccumulateQueue<Report> {
+    let mut queue = AccumulateQueue::new(100);
+    for (package, gas) in gas.iter().enumerate() {
+        queue
+            .push(
+                counter,
+                Report {
+                    package: package as u32,
+                    gas: *gas,
+                },
+            )
+            .unwrap();
+    }
+    queue
+}
+
+fn pending(queue: &AccumulateQueue<Report>) -> Vec<u32> {
+    queue.pending().map(|report| report.package).collect()
+}
+
+#[test]
+fn overflow_reports_deferred() {
+    let mut counter = Counter::default();
+    let mut queue = queue(&counter, &[40, 30, 20, 10, 50]);
+
+    assert_eq!(
+        queue.accumulate_block(&mut counter),
+        BlockAccumulation {
+            accumulated: 4,
+            gas_used: 100,
+            errors: vec![],
+        }
+    );
+    assert_eq!(counter.accumulated, vec![0, 1, 2, 3]);
+    assert_eq!(pending(&queue), [4]);
+
+    assert_eq!(
+        queue.accumulate_block(&mut counter),
+        BlockAccumulation {
+            accumulated: 1,
+            gas_used: 50,
+            errors: vec![],
+        }
+    );
+    assert_eq!(counter.accumulated, vec![0, 1, 2, 3, 4]);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn order_kept_when_report_does_not_fit() {
+    let mut counter = Counter::default();
+    let mut queue = queue(&counter, &[60, 50, 10]);
+
+    // The small third report is not accumulated ahead of the second one.
+    let result = queue.accumulate_block(&mut counter);
+    assert_eq!(result.accumulated, 1);
+    assert_eq!(result.gas_used, 60);
+    assert_eq!(pending(&queue), [1, 2]);
+
+    let result = queue.accumulate_block(&mut counter);
+    assert_eq!(result.accumulated, 2);
+    assert_eq!(counter.accumulated, vec![0, 1, 2]);
+}
+
+#[test]
+fn reports_over_block_limit_rejected() {
+    let counter = Counter::default();
+    let mut queue = queue(&counter, &[100]);
+
+    let oversized = Report {
+        package: 1,
+        gas: 101,
+    };
+    assert_eq!(queue.push(&counter, oversized.clone()), Err(oversized));
+    assert_eq!(pending(&queue), [0]);
+}
+
+#[test]
+fn failing_report_moved_aside() {
+    let mut counter = Counter {
+        failing: Some(1),
+        ..Counter::default()
+    };
+    let mut other = queue(&counter, &[10, 20]);
+    let mut queue = queue(&counter, &[10, 20, 30, 40]);
+
+    // The reports after it do not wait for it, even within the block.
+    assert_eq!(
+        queue.accumulate_block(&mut counter),
+        BlockAccumulation {
+            accumulated: 3,
+            gas_used: 80,
+            errors: vec![()],
+        }
+    );
+    assert!(queue.is_empty());
+    assert_eq!(
+        queue
+            .failed()
+            .map(|report| report.package)
+            .collect::<Vec<_>>(),
+        [1]
+    );
+    assert_eq!(counter.accumulated, vec![0, 2, 3]);
+
+    // Retried, it is accumulated first.
+    counter.failing = None;
+    queue
+        .push(
+            &counter,
+            Report {
+                package: 4,
+                gas: 10,
+            },
+        )
+        .unwrap();
+    queue.retry_failed();
+    assert_eq!(pending(&queue), [1, 4]);
+    assert_eq!(queue.accumulate_block(&mut counter).accumulated, 2);
+    assert_eq!(counter.accumulated, vec![0, 2, 3, 1, 4]);
+
+    // Or it is given up on for good.
+    counter.failing = Some(0);
+    assert_eq!(other.accumulate_block(&mut counter).errors, vec![()]);
+    let failed = other.take_failed();
+    assert_eq!(
+        failed
+            .iter()
+            .map(|report| report.package)
+            .collect::<Vec<_>>(),
+        [0]
+    );
+    assert_eq!(other.failed().count(), 0);
+    assert!(other.is_empty());
+}
+
+/// A report adding to the balances of services.
+struct Transfers(Vec<(ServiceId, u64)>);
+
+impl WorkReport for Transfers {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for (service, amount) in &self.0 {
+            encoded.extend(service.0.to_le_bytes());
+            encoded.extend(amount.to_le_bytes());
+        }
+        encoded
+    }
+}
+
+impl ReportOutputs for Transfers {
+    type Output = u64;
+
+    fn outputs(&self) -> Vec<(ServiceId, Gas, u64)> {
+        self.0
+            .iter()
+            .map(|(service, amount)| (*service, 1, *amount))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Balance(u64);
+
+impl AccumulateService for Balance {
+    type Output = u64;
+
+    fn accumulate(&mut self, output: u64, _gas_remaining: Gas) -> Gas {
+        self.0 += output;
+        1
+    }
+}
+
+#[derive(Default)]
+struct Services(HashMap<ServiceId, Balance>);
+
+impl ServiceStore for Services {
+    type Service = Balance;
+
+    fn service_mut(&mut self, id: ServiceId) -> Option<&mut Balance> {
+        self.0.get_mut(&id)
+    }
+}
+
+#[test]
+fn outputs_dispatched_to_services() {
+    let mut services = Services::default();
+    services.0.insert(ServiceId(1), Balance(0));
+    services.0.insert(ServiceId(2), Balance(10));
+    let mut accumulator = ServiceAccumulator::new(services);
+
+    let mut queue = AccumulateQueue::new(100);
+    for transfers in [
+        vec![(ServiceId(1), 5), (ServiceId(3), 7), (ServiceId(2), 1)],
+        vec![(ServiceId(1), 2)],
+    ] {
+        assert!(queue.push(&accumulator, Transfers(transfers)).is_ok());
+    }
+
+    let result = queue.accumulate_block(&mut accumulator);
+    assert_eq!(result.accumulated, 2);
+    // The output for the missing service consumed no gas.
+    assert_eq!(result.gas_used, 3);
+    assert_eq!(
+        accumulator.take_errors(),
+        vec![OutputError::UnknownService(ServiceId(3))]
+    );
+    assert!(accumulator.take_errors().is_empty());
+
+    let services = accumulator.into_store();
+    assert_eq!(services.0[&ServiceId(1)], Balance(7));
+    assert_eq!(services.0[&ServiceId(2)], Balance(11));
+    assert!(!services.0.contains_key(&ServiceId(3)));
+}
+
+/// A service claiming to consume more gas than it was given.
+struct Greedy;
+
+impl AccumulateService for Greedy {
+    type Output = u64;
+
+    fn accumulate(&mut self, _output: u64, _gas_remaining: Gas) -> Gas {
+        Gas::MAX
+    }
+}
+
+impl ServiceStore for Greedy {
+    type Service = Greedy;
+
+    fn service_mut(&mut self, _id: ServiceId) -> Option<&mut Greedy> {
+        Some(self)
+    }
+}
+
+#[test]
+fn over_consumption_clamped_to_budget() {
+    let mut accumulator = ServiceAccumulator::new(Greedy);
+    let mut queue = AccumulateQueue::new(100);
+    assert!(queue
+        .push(
+            &accumulator,
+            Transfers(vec![(ServiceId(1), 5), (ServiceId(2), 5)])
+        )
+        .is_ok());
+
+    let result = queue.accumulate_block(&mut accumulator);
+    assert_eq!(result.accumulated, 1);
+    assert_eq!(result.gas_used, 100);
+}
@@ -0,0 +1,182 @@
+use tinyjam::accumulate::Gas;
+use tinyjam::core_seal::{
+    AuthorizationError, AuthorizationPool, Authorizer, AuthorizerHash, ExecutionError,
+    ExecutionOutcome, Executor, SegmentRef, WorkPackage,
+};
+
+#[derive(Debug, Clone)]
+struct Package {
+    name: u8,
+    authorizer: AuthorizerHash,
+    token: Option<Vec<u8>>,
+}
+
+impl WorkPackage for Package {
+    fn encode(&self) -> Vec<u8> {
+        vec![self.name]
+    }
+
+    fn authorizer(&self) -> AuthorizerHash {
+        self.authorizer
+    }
+
+    fn authorization_token(&self) -> Option<&[u8]> {
+        self.token.as_deref()
+    }
+
+    fn imports(&self) -> Vec<SegmentRef> {
+        Vec::new()
+    }
+}
+
+/// Accepts tokens of the package name prefixed by its secret.
+struct SecretAuthorizer {
+    secret: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BadSecret;
+
+impl Authorizer<Package> for SecretAuthorizer {
+    type Error = BadSecret;
+
+    fn authorize(&self, package: &Package, token: &[u8]) -> Result<(), BadSecret> {
+        if token == [self.secret, package.name] {
+            Ok(())
+        } else {
+            Err(BadSecret)
+        }
+    }
+}
+
+const AUTHORIZER: AuthorizerHash = AuthorizerHash([1; 32]);
+
+fn pool() -> AuthorizationPool<SecretAuthorizer> {
+    let mut pool = AuthorizationPool::new();
+    pool.insert(AUTHORIZER, SecretAuthorizer { secret: 42 });
+    pool
+}
+
+fn package(token: Option<Vec<u8>>) -> Package {
+    Package {
+        name: 7,
+        authorizer: AUTHORIZER,
+        token,
+    }
+}
+
+#[test]
+fn valid_token_accepted() {
+    assert_eq!(pool().authorize(&package(Some(vec![42, 7]))), Ok(()));
+}
+
+#[test]
+fn invalid_token_rejected() {
+    assert_eq!(
+        pool().authorize(&package(Some(vec![41, 7]))),
+        Err(AuthorizationError::InvalidToken(BadSecret))
+    );
+}
+
+#[test]
+fn missing_token_rejected() {
+    assert_eq!(
+        pool().authorize(&package(None)),
+        Err(AuthorizationError::MissingToken)
+    );
+}
+
+#[test]
+fn authorizer_outside_pool_rejected() {
+    let mut pool = pool();
+    let mut package = package(Some(vec![42, 7]));
+    package.authorizer = AuthorizerHash([2; 32]);
+    assert_eq!(
+        pool.authorize(&package),
+        Err(AuthorizationError::UnauthorizedCode(AuthorizerHash(
+            [2; 32]
+        )))
+    );
+
+    // Rotated out of the core.
+    package.authorizer = AUTHORIZER;
+    pool.remove(&AUTHORIZER);
+    assert_eq!(
+        pool.authorize(&package),
+        Err(AuthorizationError::UnauthorizedCode(AUTHORIZER))
+    );
+}
+
+/// Runs code that accepts tokens starting with the code bytes, consuming one
+/// gas per input byte.
+struct PatternExecutor;
+
+impl Executor for PatternExecutor {
+    fn execute(&self, code: &[u8], input: &[u8], gas_limit: Gas) -> ExecutionOutcome {
+        let gas_used = input.len() as Gas;
+        if gas_used > gas_limit {
+            return ExecutionOutcome::OutOfGas;
+        }
+
+        let Some(len) = input.get(..4) else {
+            return ExecutionOutcome::Trap;
+        };
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let token = &input[4..4 + len];
+        ExecutionOutcome::Exit {
+            status: if token.starts_with(code) { 0 } else { 1 },
+            gas_used,
+        }
+    }
+}
+
+fn code_pool() -> AuthorizationPool<Vec<u8>> {
+    let mut pool = AuthorizationPool::new();
+    pool.insert(AUTHORIZER, vec![0xca, 0xfe]);
+    pool
+}
+
+#[test]
+fn executed_authorizer_matches_pattern() {
+    let pool = code_pool();
+
+    // 4 bytes of length, 3 of token and 1 of package.
+    assert_eq!(
+        pool.execute(&PatternExecutor, &package(Some(vec![0xca, 0xfe, 1]))),
+        Ok(8)
+    );
+    assert_eq!(
+        pool.execute(&PatternExecutor, &package(Some(vec![0xca, 0xfa, 1]))),
+        Err(AuthorizationError::InvalidToken(ExecutionError::Exit(1)))
+    );
+    assert_eq!(
+        pool.execute(&PatternExecutor, &package(None)),
+        Err(AuthorizationError::MissingToken)
+    );
+}
+
+#[test]
+fn executed_authorizer_out_of_gas_rejected() {
+    let pool = code_pool().with_gas_limit(7);
+    assert_eq!(
+        pool.execute(&PatternExecutor, &package(Some(vec![0xca, 0xfe, 1]))),
+        Err(AuthorizationError::InvalidToken(ExecutionError::OutOfGas))
+    );
+
+    /// Reports more gas than allowed.
+    struct Overspending;
+
+    impl Executor for Overspending {
+        fn execute(&self, _code: &[u8], _input: &[u8], gas_limit: Gas) -> ExecutionOutcome {
+            ExecutionOutcome::Exit {
+                status: 0,
+                gas_used: gas_limit + 1,
+            }
+        }
+    }
+
+    assert_eq!(
+        pool.execute(&Overspending, &package(Some(vec![0xca, 0xfe]))),
+        Err(AuthorizationError::InvalidToken(ExecutionError::OutOfGas))
+    );
+}
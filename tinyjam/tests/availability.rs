@@ -0,0 +1,77 @@
+use std::time::Duration;
+use tinyjam::core_seal::{
+    Availability, AvailabilityEvent, AvailabilityStatus, ReportStore, WorkReport,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Report {
+    package: u32,
+}
+
+impl WorkReport for Report {
+    fn encode(&self) -> Vec<u8> {
+        self.package.to_le_bytes().to_vec()
+    }
+}
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn stalled_assurances_need_reassignment() {
+    let mut store = ReportStore::<_, ()>::new(0);
+    let mut availability = Availability::new(3, TIMEOUT);
+    let report_id = store.insert(Report { package: 1 });
+    availability.guarantee(report_id, secs(0));
+
+    // Two of the assurers are offline, and one assures twice.
+    assert_eq!(availability.assure(&mut store, &report_id, 1), None);
+    assert_eq!(availability.assure(&mut store, &report_id, 1), None);
+    assert!(availability.check_deadlines(secs(29)).is_empty());
+
+    assert_eq!(
+        availability.check_deadlines(secs(30)),
+        vec![AvailabilityEvent::ReassignmentNeeded { report_id }]
+    );
+    assert!(!availability.is_pending(&report_id));
+    assert_eq!(
+        store.get(&report_id).unwrap().availability,
+        AvailabilityStatus::Pending
+    );
+
+    // Late assurances are ignored, and the deadline fires once.
+    assert_eq!(availability.assure(&mut store, &report_id, 2), None);
+    assert!(availability.check_deadlines(secs(60)).is_empty());
+
+    // Re-guaranteed on a fresh validator set, the deadline restarts.
+    availability.guarantee(report_id, secs(60));
+    assert!(availability.check_deadlines(secs(89)).is_empty());
+    assert_eq!(
+        availability.check_deadlines(secs(90)),
+        vec![AvailabilityEvent::ReassignmentNeeded { report_id }]
+    );
+}
+
+#[test]
+fn threshold_in_time_makes_available() {
+    let mut store = ReportStore::<_, ()>::new(0);
+    let mut availability = Availability::new(2, TIMEOUT);
+    let report_id = store.insert(Report { package: 1 });
+    availability.guarantee(report_id, secs(0));
+
+    assert_eq!(availability.assure(&mut store, &report_id, 1), None);
+    assert_eq!(
+        availability.assure(&mut store, &report_id, 2),
+        Some(AvailabilityEvent::Available { report_id })
+    );
+    assert_eq!(
+        store.get(&report_id).unwrap().availability,
+        AvailabilityStatus::Available
+    );
+
+    assert!(availability.check_deadlines(secs(30)).is_empty());
+    assert!(availability.check_deadlines(secs(300)).is_empty());
+}
@@ -0,0 +1,221 @@
+use blockchain::memory::MemoryForkTree;
+use blockchain::{ForkTreeBest, ForkTreeMut, Identified};
+use tinyjam::core_seal::{AvailabilityStatus, DisputeState, ReportEvent, ReportStore, WorkReport};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Report {
+    package: u32,
+    output: Vec<u8>,
+}
+
+impl WorkReport for Report {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = self.package.to_le_bytes().to_vec();
+        encoded.extend(&self.output);
+        encoded
+    }
+}
+
+/// Block `number` of fork `fork`, which branches off fork 0 after block
+/// `branch`.
+#[derive(Debug, Clone)]
+struct Block {
+    id: (u32, u32),
+    parent: Option<(u32, u32)>,
+}
+
+impl Block {
+    fn new(number: u32) -> Self {
+        Self::fork(0, number, 0)
+    }
+
+    fn fork(fork: u32, number: u32, branch: u32) -> Self {
+        let parent = number
+            .checked_sub(1)
+            .map(|parent| (if parent <= branch { 0 } else { fork }, parent));
+        Self {
+            id: (fork, number),
+            parent,
+        }
+    }
+}
+
+impl Identified for Block {
+    type Identifier = (u32, u32);
+
+    fn id(&self) -> (u32, u32) {
+        self.id
+    }
+
+    fn parent_id(&self) -> Option<(u32, u32)> {
+        self.parent
+    }
+}
+
+fn report(package: u32) -> Report {
+    Report {
+        package,
+        output: vec![package as u8; 4],
+    }
+}
+
+#[test]
+fn id_is_stable() {
+    assert_eq!(report(1).id(), report(1).id());
+    assert_eq!(report(1).id(), report(1).clone().id());
+    assert_ne!(report(1).id(), report(2).id());
+}
+
+#[test]
+fn subsystems_share_entry() {
+    let mut store = ReportStore::<_, ()>::new(0);
+    let id = store.insert(report(1));
+    store.insert(report(2));
+
+    // Availability and disputes only know the report itself.
+    store
+        .get_mut(&report(1).id())
+        .expect("report was inserted")
+        .availability = AvailabilityStatus::Available;
+    store
+        .get_mut(&report(1).id())
+        .expect("report was inserted")
+        .dispute = DisputeState::Open;
+
+    let entry = store.get(&id).expect("report was inserted");
+    assert_eq!(entry.report, report(1));
+    assert_eq!(entry.availability, AvailabilityStatus::Available);
+    assert_eq!(entry.dispute, DisputeState::Open);
+
+    let other = store.get(&report(2).id()).expect("report was inserted");
+    assert_eq!(other.availability, AvailabilityStatus::Pending);
+    assert_eq!(other.dispute, DisputeState::None);
+}
+
+#[test]
+fn duplicate_insert_keeps_entry() {
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert(Block::new(0)).unwrap();
+
+    let mut store = ReportStore::new(0);
+    let id = store.insert(report(1));
+    let entry = store.get_mut(&id).expect("report was inserted");
+    entry.audited = true;
+    entry.guaranteed_in = Some((0, 0));
+    entry.availability = AvailabilityStatus::Available;
+
+    assert_eq!(store.insert(report(1)), id);
+    assert_eq!(store.len(), 1);
+    assert!(store.get(&id).expect("report was inserted").audited);
+
+    assert!(store.remove_finalized(&id, &fork_tree).is_some());
+    assert!(store.is_empty());
+    assert!(store.get(&id).is_none());
+}
+
+#[test]
+fn remove_finalized_keeps_reports_not_final() {
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert(Block::new(0)).unwrap();
+
+    let mut store = ReportStore::new(2);
+    let id = store.insert(report(1));
+    store
+        .get_mut(&id)
+        .expect("report was inserted")
+        .guaranteed_in = Some((0, 0));
+
+    // Neither while pending, nor while short of confirmations.
+    assert!(store.remove_finalized(&id, &fork_tree).is_none());
+    store.get_mut(&id).expect("report is kept").availability = AvailabilityStatus::Available;
+    fork_tree.insert(Block::new(1)).unwrap();
+    assert!(store.remove_finalized(&id, &fork_tree).is_none());
+    assert!(store.contains(&id));
+
+    fork_tree.insert(Block::new(2)).unwrap();
+    assert!(store.remove_finalized(&id, &fork_tree).is_some());
+    assert!(!store.contains(&id));
+}
+
+#[test]
+fn availability_finalized_after_confirmations() {
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert(Block::new(0)).unwrap();
+    fork_tree.insert(Block::new(1)).unwrap();
+
+    let mut store = ReportStore::new(3);
+    let id = store.insert(report(1));
+    let entry = store.get_mut(&id).expect("report was inserted");
+    entry.guaranteed_in = Some((0, 1));
+    entry.availability = AvailabilityStatus::Available;
+
+    for number in 1..4 {
+        assert!(!store.availability_finalized(&id, &fork_tree));
+        fork_tree.insert(Block::new(number + 1)).unwrap();
+    }
+    assert!(store.availability_finalized(&id, &fork_tree));
+
+    // Not once a fork without the guaranteeing block becomes the best.
+    for number in 1..6 {
+        fork_tree.insert(Block::fork(1, number, 0)).unwrap();
+    }
+    assert_eq!(fork_tree.best_id().unwrap(), (1, 5));
+    assert!(!store.availability_finalized(&id, &fork_tree));
+}
+
+#[test]
+fn availability_not_finalized_when_disputed() {
+    let mut fork_tree = MemoryForkTree::new();
+    fork_tree.insert(Block::new(0)).unwrap();
+
+    let mut store = ReportStore::new(3);
+    let id = store.insert(report(1));
+    let entry = store.get_mut(&id).expect("report was inserted");
+    entry.guaranteed_in = Some((0, 0));
+    entry.availability = AvailabilityStatus::Available;
+
+    for number in 1..3 {
+        fork_tree.insert(Block::new(number)).unwrap();
+    }
+
+    store.get_mut(&id).expect("report was inserted").dispute = DisputeState::Open;
+
+    for number in 3..6 {
+        fork_tree.insert(Block::new(number)).unwrap();
+        assert!(!store.availability_finalized(&id, &fork_tree));
+    }
+
+    store.get_mut(&id).expect("report was inserted").dispute =
+        DisputeState::Resolved { valid: false };
+    assert!(!store.availability_finalized(&id, &fork_tree));
+
+    store.get_mut(&id).expect("report was inserted").dispute =
+        DisputeState::Resolved { valid: true };
+    assert!(store.availability_finalized(&id, &fork_tree));
+}
+
+#[test]
+fn unincluded_report_expires_after_window() {
+    let mut store = ReportStore::<_, u32>::new(0).with_inclusion_window(5);
+    let expiring = store.insert_guaranteed(report(1), 10);
+    let included = store.insert_guaranteed(report(2), 10);
+    let untimed = store.insert(report(3));
+    store
+        .get_mut(&included)
+        .expect("report was inserted")
+        .guaranteed_in = Some(0);
+
+    assert!(store.check_inclusion(14).is_empty());
+    assert!(store.contains(&expiring));
+
+    assert_eq!(
+        store.check_inclusion(15),
+        [ReportEvent::Expired {
+            report_id: expiring
+        }]
+    );
+    assert!(!store.contains(&expiring));
+    assert!(store.contains(&included));
+    assert!(store.contains(&untimed));
+    assert!(store.check_inclusion(100).is_empty());
+}
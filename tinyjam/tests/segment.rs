@@ -0,0 +1,131 @@
+use futures::executor::block_on;
+use tinyjam::core_seal::{
+    AuthorizerHash, CoreSealHandle, RefineError, Refined, Segment, SegmentRef, SegmentStore,
+    WorkPackage, WorkReport,
+};
+
+#[derive(Debug, Clone)]
+struct Package {
+    name: u8,
+    imports: Vec<SegmentRef>,
+    exports: Vec<Segment>,
+}
+
+impl WorkPackage for Package {
+    fn encode(&self) -> Vec<u8> {
+        vec![self.name]
+    }
+
+    fn imports(&self) -> Vec<SegmentRef> {
+        self.imports.clone()
+    }
+
+    fn authorizer(&self) -> AuthorizerHash {
+        AuthorizerHash([0; 32])
+    }
+
+    fn authorization_token(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// A report holding the imported segments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Report {
+    name: u8,
+    imported: Vec<Segment>,
+}
+
+impl WorkReport for Report {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = vec![self.name];
+        encoded.extend(self.imported.concat());
+        encoded
+    }
+}
+
+struct Handle;
+
+impl CoreSealHandle for Handle {
+    type Error = ();
+    type WorkPackage = Package;
+    type WorkReport = Report;
+
+    fn is_authorized(&self, _work: &Package) -> bool {
+        true
+    }
+
+    async fn refine(&self, work: Package, imports: Vec<Segment>) -> Result<Refined<Report>, ()> {
+        Ok(Refined {
+            report: Report {
+                name: work.name,
+                imported: imports,
+            },
+            exports: work.exports,
+        })
+    }
+
+    async fn attest(&mut self, _report: Report) -> Result<(), ()> {
+        Ok(())
+    }
+
+    async fn dispute(&mut self, _own: Report, _other: Report) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn imports_segment_of_previous_package() {
+    let mut store = SegmentStore::new();
+    let a = Package {
+        name: 1,
+        imports: Vec::new(),
+        exports: vec![vec![10], vec![11]],
+    };
+    let b = Package {
+        name: 2,
+        imports: vec![SegmentRef {
+            package: a.id(),
+            index: 1,
+        }],
+        exports: Vec::new(),
+    };
+
+    block_on(store.refine(&Handle, a)).unwrap();
+    let report = block_on(store.refine(&Handle, b.clone())).unwrap();
+
+    assert_eq!(report.imported, vec![vec![11]]);
+    assert_eq!(store.import(&b.imports[0]), Some(vec![11]));
+}
+
+#[test]
+fn missing_segment_fails_refine() {
+    let mut store = SegmentStore::new();
+    let a = Package {
+        name: 1,
+        imports: Vec::new(),
+        exports: vec![vec![10]],
+    };
+    let missing = SegmentRef {
+        package: a.id(),
+        index: 0,
+    };
+    let b = Package {
+        name: 2,
+        imports: vec![missing],
+        exports: vec![vec![20]],
+    };
+
+    assert_eq!(
+        block_on(store.refine(&Handle, b.clone())),
+        Err(RefineError::MissingSegment(missing))
+    );
+    // Nothing is exported by a package failing to refine.
+    assert_eq!(
+        store.import(&SegmentRef {
+            package: b.id(),
+            index: 0,
+        }),
+        None
+    );
+}
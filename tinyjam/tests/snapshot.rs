@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tinyjam::core_seal::{
+    Availability, AvailabilityEvent, AvailabilityStatus, DisputeState, ReportStore, SealSnapshot,
+    WorkReport,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Report {
+    package: u32,
+}
+
+impl WorkReport for Report {
+    fn encode(&self) -> Vec<u8> {
+        self.package.to_le_bytes().to_vec()
+    }
+}
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn in_flight_reports_survive_restart() {
+    let mut store = ReportStore::<_, u32>::new(2).with_inclusion_window(10);
+    let mut availability = Availability::new(3, TIMEOUT);
+
+    let pending_id = store.insert_guaranteed(Report { package: 1 }, 5);
+    availability.guarantee(pending_id, secs(0));
+    availability.assure(&mut store, &pending_id, 1u16);
+    availability.assure(&mut store, &pending_id, 2);
+
+    let disputed_id = store.insert(Report { package: 2 });
+    let disputed = store.get_mut(&disputed_id).unwrap();
+    disputed.guaranteed_in = Some(7);
+    disputed.dispute = DisputeState::Open;
+
+    // Exported 10 seconds into the availability timeout.
+    let json =
+        serde_json::to_string(&SealSnapshot::export(&store, &availability, secs(10))).unwrap();
+    let snapshot = serde_json::from_str::<SealSnapshot<Report, u32, u16>>(&json).unwrap();
+    // Imported on a fresh node, whose clock starts back at 0.
+    let (mut store, mut availability) = snapshot.import(secs(0));
+
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.finality_confirmations(), 2);
+    assert_eq!(store.inclusion_window(), Some(10));
+    let pending = store.get(&pending_id).unwrap();
+    assert_eq!(pending.report, Report { package: 1 });
+    assert_eq!(pending.guaranteed_at, Some(5));
+    assert_eq!(pending.availability, AvailabilityStatus::Pending);
+    let disputed = store.get(&disputed_id).unwrap();
+    assert_eq!(disputed.guaranteed_in, Some(7));
+    assert_eq!(disputed.dispute, DisputeState::Open);
+
+    assert!(availability.is_pending(&pending_id));
+    assert_eq!(availability.assurances(&pending_id), 2);
+    assert_eq!(availability.threshold(), 3);
+    // The deadline keeps the 20 seconds left, and the assurers so far count.
+    assert!(availability.check_deadlines(secs(19)).is_empty());
+    assert_eq!(availability.assure(&mut store, &pending_id, 2), None);
+    assert_eq!(
+        availability.assure(&mut store, &pending_id, 3),
+        Some(AvailabilityEvent::Available {
+            report_id: pending_id
+        })
+    );
+    assert_eq!(
+        store.get(&pending_id).unwrap().availability,
+        AvailabilityStatus::Available
+    );
+}
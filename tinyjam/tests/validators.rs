@@ -0,0 +1,56 @@
+use std::time::Duration;
+use tinyjam::core_seal::{Availability, ValidatorDirectory, ValidatorSet};
+
+fn validators(n: u32) -> ValidatorSet<u32> {
+    ValidatorSet::new((0..n).collect())
+}
+
+#[test]
+fn thresholds_by_validator_count() {
+    // (n, supermajority)
+    let expected = [(1, 1), (2, 2), (3, 3), (4, 3), (10, 7), (100, 67)];
+
+    for (n, supermajority) in expected {
+        let set = validators(n);
+        assert_eq!(set.supermajority_threshold(), supermajority, "n = {n}");
+        assert_eq!(set.availability_threshold(), supermajority, "n = {n}");
+    }
+}
+
+#[test]
+fn availability_uses_validator_threshold() {
+    for n in [1, 4, 10, 100] {
+        let set = validators(n);
+        let availability = Availability::<u32>::for_validators(&set, Duration::from_secs(1));
+        assert_eq!(availability.threshold(), set.availability_threshold());
+    }
+}
+
+#[test]
+fn directory_resolves_advertised_validators() {
+    // Session keys 10 to 13 for validators 0 to 3, operated by peers "a" to
+    // "c", and "d" advertising a key of the next epoch.
+    let mut directory = ValidatorDirectory::new(ValidatorSet::new(vec![10, 11, 12, 13]));
+    directory.advertise("a", 10);
+    directory.advertise("b", 12);
+    directory.advertise("c", 13);
+    directory.advertise("d", 20);
+
+    assert_eq!(directory.peer_for(2), Some(&"b"));
+    assert_eq!(directory.index_for(&"b"), Some(2));
+    assert_eq!(directory.peer_for(1), None);
+    assert_eq!(directory.peer_for(4), None);
+    assert_eq!(directory.index_for(&"d"), None);
+
+    // A peer taking over a key replaces its previous advertiser.
+    directory.advertise("e", 13);
+    assert_eq!(directory.peer_for(3), Some(&"e"));
+    assert_eq!(directory.index_for(&"c"), None);
+    directory.remove(&"a");
+    assert_eq!(directory.peer_for(0), None);
+
+    directory.rotate(ValidatorSet::new(vec![20, 12]));
+    assert_eq!(directory.peer_for(0), Some(&"d"));
+    assert_eq!(directory.index_for(&"b"), Some(1));
+    assert_eq!(directory.index_for(&"e"), None);
+}
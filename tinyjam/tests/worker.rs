@@ -0,0 +1,703 @@
+use futures::{
+    channel::mpsc, executor::block_on, executor::LocalPool, future, stream, task::Spawn as _,
+    StreamExt,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use tinyjam::accumulate::Gas;
+use tinyjam::core_seal::{
+    AdmissionError, AuthorizerHash, Availability, CoreAssigned, CoreEvent, CoreId, CoreSealHandle,
+    CoreSealManager, CoreSealWorker, InFlight, Refined, ReportStore, SealSnapshot, Segment,
+    SegmentRef, Spawn, SubmitError, Timer, WorkPackage, WorkReport, WorkerError,
+    DEFAULT_ATTEST_ATTEMPTS,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Package {
+    core: u32,
+    name: u8,
+    /// Whether refine never completes.
+    stuck: bool,
+    /// Declared gas.
+    gas: Gas,
+    /// Bytes of the encoding beyond the core and the name.
+    padding: usize,
+}
+
+impl WorkPackage for Package {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = vec![self.core as u8, self.name];
+        encoded.resize(self.encoded_size(), 0);
+        encoded
+    }
+
+    fn encoded_size(&self) -> usize {
+        2 + self.padding
+    }
+
+    fn gas(&self) -> Gas {
+        self.gas
+    }
+
+    fn imports(&self) -> Vec<SegmentRef> {
+        Vec::new()
+    }
+
+    fn authorizer(&self) -> AuthorizerHash {
+        AuthorizerHash([0; 32])
+    }
+
+    fn authorization_token(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl CoreAssigned for Package {
+    fn core(&self) -> CoreId {
+        CoreId(self.core)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Report {
+    name: u8,
+}
+
+impl WorkReport for Report {
+    fn encode(&self) -> Vec<u8> {
+        vec![self.name]
+    }
+}
+
+#[derive(Default)]
+struct Handle {
+    attested: Arc<Mutex<Vec<u8>>>,
+    /// Number of calls to attest.
+    attempts: Arc<Mutex<usize>>,
+    /// Number of next calls to attest failing.
+    failures: Arc<Mutex<usize>>,
+}
+
+impl CoreSealHandle for Handle {
+    type Error = ();
+    type WorkPackage = Package;
+    type WorkReport = Report;
+
+    fn is_authorized(&self, _work: &Package) -> bool {
+        true
+    }
+
+    async fn refine(&self, work: Package, _imports: Vec<Segment>) -> Result<Refined<Report>, ()> {
+        if work.stuck {
+            future::pending::<()>().await;
+        }
+
+        Ok(Refined {
+            report: Report { name: work.name },
+            exports: Vec::new(),
+        })
+    }
+
+    async fn attest(&mut self, report: Report) -> Result<(), ()> {
+        *self.attempts.lock().unwrap() += 1;
+        let mut failures = self.failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            return Err(());
+        }
+        self.attested.lock().unwrap().push(report.name);
+        Ok(())
+    }
+
+    async fn dispute(&mut self, _own: Report, _other: Report) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Spawner of the executor.
+#[derive(Clone)]
+struct Spawner(mpsc::UnboundedSender<Task>);
+
+impl Spawn for Spawner {
+    fn spawn(&self, future: Task) {
+        self.0.unbounded_send(future).expect("executor is alive");
+    }
+}
+
+/// A single-threaded executor, only running when told to.
+struct Executor {
+    pool: LocalPool,
+    spawned: mpsc::UnboundedReceiver<Task>,
+    spawner: Spawner,
+}
+
+impl Executor {
+    fn new() -> Self {
+        let (sender, spawned) = mpsc::unbounded();
+        Self {
+            pool: LocalPool::new(),
+            spawned,
+            spawner: Spawner(sender),
+        }
+    }
+
+    /// Run all tasks until none can make progress.
+    fn run_until_stalled(&mut self) {
+        loop {
+            let mut any_spawned = false;
+            while let Ok(Some(task)) = self.spawned.try_next() {
+                self.pool
+                    .spawner()
+                    .spawn_obj(task.into())
+                    .expect("pool is alive");
+                any_spawned = true;
+            }
+            self.pool.run_until_stalled();
+            if !any_spawned {
+                break;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ClockState {
+    now: Duration,
+    wakers: Vec<Waker>,
+}
+
+/// A clock only advancing when told to.
+#[derive(Clone, Default)]
+struct VirtualClock(Arc<Mutex<ClockState>>);
+
+impl VirtualClock {
+    fn advance(&self, duration: Duration) {
+        let mut state = self.0.lock().unwrap();
+        state.now += duration;
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+struct Delay {
+    clock: VirtualClock,
+    deadline: Duration,
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut state = self.clock.0.lock().unwrap();
+        if state.now >= self.deadline {
+            Poll::Ready(())
+        } else {
+            state.wakers.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Timer for VirtualClock {
+    type Delay = Delay;
+
+    fn delay(&self, duration: Duration) -> Delay {
+        Delay {
+            clock: self.clone(),
+            deadline: self.0.lock().unwrap().now + duration,
+        }
+    }
+}
+
+#[test]
+fn refine_times_out_on_virtual_clock() {
+    let mut executor = Executor::new();
+    let clock = VirtualClock::default();
+    let mut worker =
+        CoreSealWorker::new(Handle::default(), executor.spawner.clone(), clock.clone())
+            .with_refine_timeout(Duration::from_secs(6));
+
+    let result = Arc::new(Mutex::new(None));
+    let task_result = result.clone();
+    executor.spawner.spawn(Box::pin(async move {
+        let refined = worker
+            .refine(Package {
+                core: 0,
+                name: 1,
+                stuck: true,
+                gas: 0,
+                padding: 0,
+            })
+            .await;
+        *task_result.lock().unwrap() = Some(refined);
+    }));
+
+    executor.run_until_stalled();
+    assert_eq!(*result.lock().unwrap(), None);
+
+    clock.advance(Duration::from_secs(5));
+    executor.run_until_stalled();
+    assert_eq!(*result.lock().unwrap(), None);
+
+    clock.advance(Duration::from_secs(1));
+    executor.run_until_stalled();
+    assert_eq!(*result.lock().unwrap(), Some(Err(WorkerError::Timeout)));
+}
+
+#[test]
+fn spawned_worker_skips_timed_out_package() {
+    let mut executor = Executor::new();
+    let clock = VirtualClock::default();
+    let handle = Handle::default();
+    let attested = handle.attested.clone();
+    let worker = CoreSealWorker::new(handle, executor.spawner.clone(), clock.clone());
+
+    let mut result = worker.spawn(stream::iter([
+        Package {
+            core: 0,
+            name: 1,
+            stuck: true,
+            gas: 0,
+            padding: 0,
+        },
+        Package {
+            core: 0,
+            name: 2,
+            stuck: false,
+            gas: 0,
+            padding: 0,
+        },
+    ]));
+
+    executor.run_until_stalled();
+    assert!(attested.lock().unwrap().is_empty());
+
+    clock.advance(tinyjam::core_seal::DEFAULT_REFINE_TIMEOUT);
+    executor.run_until_stalled();
+    assert_eq!(*attested.lock().unwrap(), vec![2]);
+    assert_eq!(result.try_recv(), Ok(Some(Ok(()))));
+}
+
+/// Refine and attest a package on a spawned task, advancing the clock past
+/// any retry delay until it completes.
+fn refine_and_attest_with_retries(
+    handle: Handle,
+    attempts: usize,
+) -> Result<tinyjam::core_seal::WorkReportId, WorkerError<()>> {
+    let mut executor = Executor::new();
+    let clock = VirtualClock::default();
+    let mut worker = CoreSealWorker::new(handle, executor.spawner.clone(), clock.clone())
+        .with_attest_retries(attempts, Duration::from_secs(1));
+
+    let result = Arc::new(Mutex::new(None));
+    let task_result = result.clone();
+    executor.spawner.spawn(Box::pin(async move {
+        let attested = worker
+            .refine_and_attest(Package {
+                core: 0,
+                name: 1,
+                stuck: false,
+                gas: 0,
+                padding: 0,
+            })
+            .await;
+        *task_result.lock().unwrap() = Some(attested);
+    }));
+
+    loop {
+        executor.run_until_stalled();
+        if let Some(attested) = result.lock().unwrap().take() {
+            return attested;
+        }
+        clock.advance(Duration::from_secs(60));
+    }
+}
+
+#[test]
+fn attest_retries_transient_failures() {
+    let handle = Handle::default();
+    *handle.failures.lock().unwrap() = 2;
+    let (attested, attempts) = (handle.attested.clone(), handle.attempts.clone());
+
+    assert_eq!(
+        refine_and_attest_with_retries(handle, DEFAULT_ATTEST_ATTEMPTS),
+        Ok(Report { name: 1 }.id())
+    );
+    assert_eq!(*attempts.lock().unwrap(), 3);
+    assert_eq!(*attested.lock().unwrap(), vec![1]);
+}
+
+#[test]
+fn attest_gives_up_after_attempts() {
+    let handle = Handle::default();
+    *handle.failures.lock().unwrap() = usize::MAX;
+    let (attested, attempts) = (handle.attested.clone(), handle.attempts.clone());
+
+    assert_eq!(
+        refine_and_attest_with_retries(handle, 4),
+        Err(WorkerError::AttestationFailed {
+            report: Report { name: 1 }.id(),
+            attempts: 4,
+            error: (),
+        })
+    );
+    assert_eq!(*attempts.lock().unwrap(), 4);
+    assert!(attested.lock().unwrap().is_empty());
+}
+
+#[test]
+fn attest_backoff_is_capped() {
+    let mut executor = Executor::new();
+    let clock = VirtualClock::default();
+    let handle = Handle::default();
+    *handle.failures.lock().unwrap() = usize::MAX;
+    let attempts = handle.attempts.clone();
+
+    // Doubling such a backoff would overflow without the cap.
+    let mut worker = CoreSealWorker::new(handle, executor.spawner.clone(), clock.clone())
+        .with_attest_retries(40, Duration::from_secs(u64::MAX / 2))
+        .with_max_attest_backoff(Duration::from_secs(10));
+
+    let result = Arc::new(Mutex::new(None));
+    let task_result = result.clone();
+    executor.spawner.spawn(Box::pin(async move {
+        let attested = worker
+            .refine_and_attest(Package {
+                core: 0,
+                name: 1,
+                stuck: false,
+                gas: 0,
+                padding: 0,
+            })
+            .await;
+        *task_result.lock().unwrap() = Some(attested);
+    }));
+
+    // Each retry is due within the maximum backoff plus half of it.
+    for retries in 0..39 {
+        executor.run_until_stalled();
+        assert_eq!(*attempts.lock().unwrap(), retries + 1);
+        assert_eq!(*result.lock().unwrap(), None);
+        clock.advance(Duration::from_secs(15));
+    }
+    executor.run_until_stalled();
+    assert!(matches!(
+        *result.lock().unwrap(),
+        Some(Err(WorkerError::AttestationFailed { attempts: 40, .. }))
+    ));
+}
+
+#[test]
+fn spawned_worker_returns_attestation_failure() {
+    let mut executor = Executor::new();
+    let handle = Handle::default();
+    *handle.failures.lock().unwrap() = usize::MAX;
+    let worker = CoreSealWorker::new(handle, executor.spawner.clone(), VirtualClock::default())
+        .with_attest_retries(1, Duration::from_secs(1));
+
+    let mut result = worker.spawn(stream::iter([Package {
+        core: 0,
+        name: 1,
+        stuck: false,
+        gas: 0,
+        padding: 0,
+    }]));
+
+    executor.run_until_stalled();
+    assert_eq!(result.try_recv(), Ok(Some(Err(()))));
+}
+
+#[test]
+fn manager_routes_packages_to_their_cores() {
+    let mut executor = Executor::new();
+    let clock = VirtualClock::default();
+    let (mut manager, events) = CoreSealManager::<Handle>::new();
+
+    let mut attested = Vec::new();
+    for core in 0..2 {
+        let handle = Handle::default();
+        attested.push(handle.attested.clone());
+        let worker = CoreSealWorker::new(handle, executor.spawner.clone(), clock.clone());
+        manager.add_core(CoreId(core), worker);
+    }
+
+    let package = |core, name| Package {
+        core,
+        name,
+        stuck: false,
+        gas: 0,
+        padding: 0,
+    };
+    manager.submit(package(0, 1)).unwrap();
+    manager.submit(package(1, 2)).unwrap();
+    manager.submit(package(0, 3)).unwrap();
+    assert_eq!(
+        manager.submit(package(7, 4)),
+        Err(SubmitError::UnknownCore(package(7, 4)))
+    );
+
+    executor.run_until_stalled();
+    assert_eq!(*attested[0].lock().unwrap(), vec![1, 3]);
+    assert_eq!(*attested[1].lock().unwrap(), vec![2]);
+    assert!(manager.in_flight().is_empty());
+
+    // Dropping the manager stops the workers, ending the event stream.
+    drop(manager);
+    executor.run_until_stalled();
+    let mut per_core = block_on(events.collect::<Vec<_>>())
+        .into_iter()
+        .map(|event| match event {
+            CoreEvent::Attested {
+                core,
+                package,
+                report,
+            } => (core, package, report),
+            CoreEvent::Failed { error, .. } => panic!("package failed: {error:?}"),
+        })
+        .collect::<Vec<_>>();
+    per_core.sort_by_key(|(core, _, _)| *core);
+
+    let expected = [(0, 1), (0, 3), (1, 2)]
+        .into_iter()
+        .map(|(core, name)| (CoreId(core), package(core, name).id(), Report { name }.id()))
+        .collect::<Vec<_>>();
+    assert_eq!(per_core, expected);
+}
+
+#[test]
+fn manager_state_survives_restart() {
+    let mut executor = Executor::new();
+    let clock = VirtualClock::default();
+    let (mut manager, _events) = CoreSealManager::<Handle>::new();
+    manager.add_core(
+        CoreId(0),
+        CoreSealWorker::new(Handle::default(), executor.spawner.clone(), clock.clone()),
+    );
+
+    let stuck = Package {
+        core: 0,
+        name: 1,
+        stuck: true,
+        gas: 0,
+        padding: 0,
+    };
+    let queued = Package {
+        name: 2,
+        stuck: false,
+        ..stuck.clone()
+    };
+    manager.submit(stuck.clone()).unwrap();
+    manager.submit(queued.clone()).unwrap();
+    executor.run_until_stalled();
+    // The stuck package is being refined, and the other one is queued.
+    let in_flight = vec![
+        InFlight::Refine(stuck.clone()),
+        InFlight::Refine(queued.clone()),
+    ];
+    assert_eq!(manager.in_flight(), in_flight);
+
+    let mut store = ReportStore::<Report, u32>::new(2);
+    let mut availability = Availability::<u16>::new(3, Duration::from_secs(30));
+    let report_id = store.insert_guaranteed(Report { name: 7 }, 5);
+    availability.guarantee(report_id, Duration::from_secs(0));
+    availability.assure(&mut store, &report_id, 1);
+    availability.assure(&mut store, &report_id, 2);
+
+    let snapshot = manager.export_state(&store, &availability, Duration::from_secs(10));
+    assert_eq!(snapshot.packages(), in_flight);
+    drop(manager);
+
+    // Restored on a fresh node, with its own workers.
+    let mut executor = Executor::new();
+    let handle = Handle::default();
+    let attested = handle.attested.clone();
+    let (mut manager, _events) = CoreSealManager::<Handle>::new();
+    manager.add_core(
+        CoreId(0),
+        CoreSealWorker::new(handle, executor.spawner.clone(), clock.clone()),
+    );
+    let (store, availability, rejected) = manager.import_state(snapshot, Duration::from_secs(0));
+    assert!(rejected.is_empty());
+
+    assert_eq!(store.get(&report_id).unwrap().report, Report { name: 7 });
+    assert!(availability.is_pending(&report_id));
+    assert_eq!(availability.assurances(&report_id), 2);
+
+    // Both packages are submitted again, in order, and the stuck one is
+    // refined anew until it times out.
+    executor.run_until_stalled();
+    assert_eq!(manager.in_flight(), in_flight);
+    clock.advance(tinyjam::core_seal::DEFAULT_REFINE_TIMEOUT);
+    executor.run_until_stalled();
+    assert_eq!(*attested.lock().unwrap(), vec![2]);
+    assert!(manager.in_flight().is_empty());
+}
+
+#[test]
+fn manager_resumes_attestation_after_restart() {
+    let mut executor = Executor::new();
+    let clock = VirtualClock::default();
+    let (mut manager, _events) = CoreSealManager::<Handle>::new();
+    let handle = Handle::default();
+    *handle.failures.lock().unwrap() = usize::MAX;
+    manager.add_core(
+        CoreId(0),
+        CoreSealWorker::new(handle, executor.spawner.clone(), clock.clone())
+            .with_attest_retries(3, Duration::from_secs(1)),
+    );
+
+    let package = Package {
+        core: 0,
+        name: 1,
+        stuck: false,
+        gas: 0,
+        padding: 0,
+    };
+    manager.submit(package.clone()).unwrap();
+    executor.run_until_stalled();
+    clock.advance(Duration::from_secs(2));
+    executor.run_until_stalled();
+    // Two attempts failed, and the worker waits to retry.
+    let attesting = InFlight::Attest {
+        package,
+        report: Report { name: 1 },
+        failed: 2,
+    };
+    assert_eq!(manager.in_flight(), vec![attesting.clone()]);
+
+    let snapshot = manager.export_state(
+        &ReportStore::<Report, u32>::new(2),
+        &Availability::<u16>::new(3, Duration::from_secs(30)),
+        Duration::from_secs(0),
+    );
+    assert_eq!(snapshot.packages(), [attesting]);
+    drop(manager);
+
+    // The restored node attests right away, with the attempts left.
+    let mut executor = Executor::new();
+    let handle = Handle::default();
+    *handle.failures.lock().unwrap() = 1;
+    let attempts = handle.attempts.clone();
+    let (mut manager, events) = CoreSealManager::<Handle>::new();
+    manager.add_core(
+        CoreId(0),
+        CoreSealWorker::new(handle, executor.spawner.clone(), clock.clone())
+            .with_attest_retries(3, Duration::from_secs(1)),
+    );
+    let (_, _, rejected) = manager.import_state(snapshot, Duration::from_secs(0));
+    assert!(rejected.is_empty());
+
+    executor.run_until_stalled();
+    assert_eq!(*attempts.lock().unwrap(), 1);
+    assert!(manager.in_flight().is_empty());
+    drop(manager);
+    executor.run_until_stalled();
+    assert!(matches!(
+        block_on(events.collect::<Vec<_>>())[..],
+        [CoreEvent::Failed {
+            error: WorkerError::AttestationFailed { attempts: 3, .. },
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn manager_hands_back_unknown_cores_on_import() {
+    let (manager, _events) = CoreSealManager::<Handle>::new();
+    let package = Package {
+        core: 3,
+        name: 1,
+        stuck: false,
+        gas: 0,
+        padding: 0,
+    };
+    let snapshot = SealSnapshot::export(
+        &ReportStore::<Report, u32>::new(2),
+        &Availability::<u16>::new(3, Duration::from_secs(30)),
+        Duration::from_secs(0),
+    )
+    .with_packages(vec![InFlight::Refine(package.clone())]);
+
+    let (_, _, rejected) = manager.import_state(snapshot, Duration::from_secs(0));
+    assert_eq!(
+        rejected,
+        vec![SubmitError::UnknownCore(InFlight::Refine(package))]
+    );
+}
+
+#[test]
+fn packages_over_limits_rejected_at_admission() {
+    let executor = Executor::new();
+    let worker = CoreSealWorker::new(
+        Handle::default(),
+        executor.spawner.clone(),
+        VirtualClock::default(),
+    )
+    .with_admission_limits(64, 1_000);
+    let package = |gas, padding| Package {
+        core: 0,
+        name: 1,
+        stuck: false,
+        gas,
+        padding,
+    };
+
+    assert_eq!(
+        worker.admit(&package(0, 63)),
+        Err(AdmissionError::TooLarge { size: 65, max: 64 })
+    );
+    assert_eq!(
+        worker.admit(&package(1_001, 0)),
+        Err(AdmissionError::OverGas {
+            gas: 1_001,
+            max: 1_000
+        })
+    );
+    assert_eq!(worker.admit(&package(1_000, 62)), Ok(()));
+}
+
+#[test]
+fn spawned_worker_drops_inadmissible_packages() {
+    let mut executor = Executor::new();
+    let handle = Handle::default();
+    let attested = handle.attested.clone();
+    let worker = CoreSealWorker::new(handle, executor.spawner.clone(), VirtualClock::default())
+        .with_admission_limits(64, 1_000);
+
+    let mut result = worker.spawn(stream::iter([
+        Package {
+            core: 0,
+            name: 1,
+            stuck: false,
+            gas: 0,
+            padding: 100,
+        },
+        Package {
+            core: 0,
+            name: 2,
+            stuck: true,
+            gas: 2_000,
+            padding: 0,
+        },
+        Package {
+            core: 0,
+            name: 3,
+            stuck: false,
+            gas: 1_000,
+            padding: 0,
+        },
+    ]));
+
+    // The stuck package is rejected before refine, so nothing times out.
+    executor.run_until_stalled();
+    assert_eq!(*attested.lock().unwrap(), vec![3]);
+    assert_eq!(result.try_recv(), Ok(Some(Ok(()))));
+}